@@ -1,7 +1,9 @@
 extern crate serde_derive;
 extern crate toml;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
 //use std::env;
 
 /*  :TODO:
@@ -23,26 +25,110 @@ pub struct Conf {
     out_dir: String,
 
     init_protos: Vec<String>,
+    id_manifest: String,
+    #[serde(default)]
+    pto_shuffle_seed: Option<u64>,
+    #[serde(default)]
+    pto_dir_packages: bool,
+    #[serde(default)]
+    handler_dir: Option<String>,
 
     // tcp service
     tcp_serv_addr: String,
+    #[serde(default)]
+    tcp_idle_timeout: Option<u64>,
+    #[serde(default)]
+    reconnect_grace: Option<u64>,
+    #[serde(default)]
+    tcp_rate_limit: Option<RateLimitConf>,
+    #[serde(default)]
+    tcp_backpressure: Option<BackpressureConf>,
+    #[serde(default)]
+    tcp_max_message_len: Option<usize>,
+    #[serde(default)]
+    tcp_compress_threshold: Option<usize>,
+    #[serde(default)]
+    tcp_encrypt: bool,
+    #[serde(default)]
+    tcp_tls: Option<TlsConf>,
+    #[serde(default)]
+    ws_serv_addr: Option<String>,
 
     // http service
     http_serv_addr: String,
+    #[serde(default)]
+    http_tls: Option<TlsConf>,
 
     // rpc service
     rpc_serv_addr: String,
+    #[serde(default)]
+    rpc_backpressure: Option<BackpressureConf>,
+    #[serde(default)]
+    rpc_max_message_len: Option<usize>,
+    #[serde(default)]
+    rpc_compress_threshold: Option<usize>,
+    #[serde(default)]
+    rpc_tls: Option<TlsConf>,
 
     // rpc db service
     db_host_id: u64,
     rpc_db_serv_addr: String,
 }
 
+// 令牌桶: rate 为每秒补充的协议包个数, burst 为桶的容量
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy)]
+pub struct BucketConf {
+    pub rate: u32,
+    pub burst: u32,
+}
+
+// 客户端连接的流量限制, 见 conf.toml 的 [tcp_rate_limit]
+#[derive(serde_derive::Deserialize, Debug, Clone)]
+pub struct RateLimitConf {
+    pub action: String,
+    #[serde(default)]
+    pub max_violations: u32,
+    #[serde(default)]
+    pub global: Option<BucketConf>,
+    #[serde(default)]
+    pub protos: HashMap<String, BucketConf>,
+}
+
+// 连接发送队列的背压策略, 见 conf.toml 的 [tcp_backpressure]
+#[derive(serde_derive::Deserialize, Debug, Clone)]
+pub struct BackpressureConf {
+    pub capacity: usize,
+    pub normal: String,
+    pub critical: String,
+    #[serde(default)]
+    pub critical_protos: Vec<String>,
+    #[serde(default)]
+    pub block_timeout_ms: u64,
+}
+
+// tls 的证书和私钥(PEM 文件), 见 conf.toml 的 [tcp_tls], [rpc_tls], [http_tls]
+#[derive(serde_derive::Deserialize, Debug, Clone)]
+pub struct TlsConf {
+    pub cert: String,
+    pub key: String,
+    #[serde(default)]
+    pub ca: Option<String>,
+    #[serde(default)]
+    pub verify_peer: bool,
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
 impl Conf {
     pub fn new() -> Conf {
         //println!("{:?}",env::current_dir().unwrap());
-        let fname = "conf/conf.toml";
-        let mut file = match File::open(fname) {
+        Conf::from_file("conf/conf.toml")
+    }
+
+    // 指定配置文件路径, 比如在 build.rs 里读取工作区的配置
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Conf {
+        let fname = path.as_ref().display();
+        let mut file = match File::open(path.as_ref()) {
             Ok(f) => f,
             Err(e) => panic!("open {} error: {}", fname, e),
         };
@@ -96,18 +182,90 @@ impl Conf {
         &self.init_protos
     }
 
+    pub fn get_id_manifest(&self) -> &str {
+        &self.id_manifest
+    }
+
+    pub fn get_pto_shuffle_seed(&self) -> Option<u64> {
+        self.pto_shuffle_seed
+    }
+
+    pub fn get_pto_dir_packages(&self) -> bool {
+        self.pto_dir_packages
+    }
+
+    pub fn get_handler_dir(&self) -> Option<&str> {
+        self.handler_dir.as_deref()
+    }
+
     pub fn get_tcp_serv_addr(&self) -> &str {
         &self.tcp_serv_addr
     }
 
+    pub fn get_tcp_idle_timeout(&self) -> Option<u64> {
+        self.tcp_idle_timeout
+    }
+
+    pub fn get_reconnect_grace(&self) -> Option<u64> {
+        self.reconnect_grace
+    }
+
+    pub fn get_tcp_rate_limit(&self) -> Option<&RateLimitConf> {
+        self.tcp_rate_limit.as_ref()
+    }
+
+    pub fn get_tcp_backpressure(&self) -> Option<&BackpressureConf> {
+        self.tcp_backpressure.as_ref()
+    }
+
+    pub fn get_tcp_max_message_len(&self) -> Option<usize> {
+        self.tcp_max_message_len
+    }
+
+    pub fn get_tcp_compress_threshold(&self) -> Option<usize> {
+        self.tcp_compress_threshold
+    }
+
+    pub fn get_tcp_encrypt(&self) -> bool {
+        self.tcp_encrypt
+    }
+
+    pub fn get_tcp_tls(&self) -> Option<&TlsConf> {
+        self.tcp_tls.as_ref()
+    }
+
+    pub fn get_ws_serv_addr(&self) -> Option<&str> {
+        self.ws_serv_addr.as_deref()
+    }
+
     pub fn get_http_serv_addr(&self) -> &str {
         &self.http_serv_addr
     }
 
+    pub fn get_http_tls(&self) -> Option<&TlsConf> {
+        self.http_tls.as_ref()
+    }
+
     pub fn get_rpc_serv_addr(&self) -> &str {
         &self.rpc_serv_addr
     }
 
+    pub fn get_rpc_backpressure(&self) -> Option<&BackpressureConf> {
+        self.rpc_backpressure.as_ref()
+    }
+
+    pub fn get_rpc_max_message_len(&self) -> Option<usize> {
+        self.rpc_max_message_len
+    }
+
+    pub fn get_rpc_compress_threshold(&self) -> Option<usize> {
+        self.rpc_compress_threshold
+    }
+
+    pub fn get_rpc_tls(&self) -> Option<&TlsConf> {
+        self.rpc_tls.as_ref()
+    }
+
     pub fn get_db_host_id(&self) -> u64 {
         self.db_host_id
     }
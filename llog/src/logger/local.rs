@@ -1,4 +1,4 @@
-use super::mgr;
+use super::{logobj, mgr};
 
 use chrono::Local;
 use std::rc::Rc;
@@ -20,7 +20,7 @@ pub fn can_log_error() -> bool {
     LOGOBJ_MGR.with(|f| f.can_log_error())
 }
 
-pub fn debug<'a>(fname: &'a str, logstr: &str) {
+pub fn debug(fname: &str, logstr: &str) {
     LOGOBJ_MGR.with(|f| {
         let lv = f.get_log_level();
         let datetime = Local::now();
@@ -31,13 +31,13 @@ pub fn debug<'a>(fname: &'a str, logstr: &str) {
         {
             println!("[debug]: {:?}", err);
         } else {
-            let cur_date = datetime.date().and_hms_milli(0, 0, 0, 0).timestamp();
+            let cur_date = logobj::day_start(&datetime);
             f.check_file_roll(fname, cur_date);
         }
     });
 }
 
-pub fn warning<'a>(fname: &'a str, logstr: &str) {
+pub fn warning(fname: &str, logstr: &str) {
     LOGOBJ_MGR.with(|f| {
         let lv = f.get_log_level();
         let datetime = Local::now();
@@ -48,13 +48,13 @@ pub fn warning<'a>(fname: &'a str, logstr: &str) {
         {
             println!("[warning]: {:?}", err);
         } else {
-            let cur_date = datetime.date().and_hms_milli(0, 0, 0, 0).timestamp();
+            let cur_date = logobj::day_start(&datetime);
             f.check_file_roll(fname, cur_date);
         }
     })
 }
 
-pub fn info<'a>(fname: &'a str, logstr: &str) {
+pub fn info(fname: &str, logstr: &str) {
     LOGOBJ_MGR.with(|f| {
         let lv = f.get_log_level();
         let datetime = Local::now();
@@ -65,13 +65,13 @@ pub fn info<'a>(fname: &'a str, logstr: &str) {
         {
             println!("[info]: {:?}", err);
         } else {
-            let cur_date = datetime.date().and_hms_milli(0, 0, 0, 0).timestamp();
+            let cur_date = logobj::day_start(&datetime);
             f.check_file_roll(fname, cur_date);
         }
     })
 }
 
-pub fn error<'a>(fname: &'a str, logstr: &str) {
+pub fn error(fname: &str, logstr: &str) {
     LOGOBJ_MGR.with(|f| {
         let lv = f.get_log_level();
         let datetime = Local::now();
@@ -82,7 +82,7 @@ pub fn error<'a>(fname: &'a str, logstr: &str) {
         {
             println!("[error]: {:?}", err);
         } else {
-            let cur_date = datetime.date().and_hms_milli(0, 0, 0, 0).timestamp();
+            let cur_date = logobj::day_start(&datetime);
             f.check_file_roll(fname, cur_date);
         }
     })
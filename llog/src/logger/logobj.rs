@@ -1,9 +1,21 @@
-use chrono::{Local, TimeZone};
+use chrono::{DateTime, Local, TimeZone};
 use std::cmp::Eq;
 use std::fs::File;
 use std::hash::Hash;
 use std::io::{Result, Write};
 
+// 当天零点的时间戳
+pub fn day_start(datetime: &DateTime<Local>) -> i64 {
+    datetime
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_local_timezone(Local)
+        .earliest()
+        .unwrap()
+        .timestamp()
+}
+
 #[derive(Debug, PartialEq, PartialOrd, Eq, Hash, Clone, Copy)]
 pub enum LevelType {
     Debug,
@@ -39,7 +51,7 @@ impl Logger {
         Logger {
             _fname: fname.to_owned(),
             path: path.to_owned(),
-            create_date: Local::today().and_hms_milli(0, 0, 0, 0).timestamp(),
+            create_date: day_start(&Local::now()),
             fh,
             fsize: 0,
             roll_num: 1,
@@ -53,7 +65,7 @@ impl Logger {
     pub fn update_file_roll(&mut self, fh: File) {
         self.fh = fh;
         self.fsize = 0;
-        self.create_date = Local::today().and_hms_milli(0, 0, 0, 0).timestamp();
+        self.create_date = day_start(&Local::now());
     }
 
     pub fn get_path(&self) -> String {
@@ -61,7 +73,7 @@ impl Logger {
     }
 
     pub fn get_create_date(&self) -> String {
-        let dt = Local.timestamp(self.create_date, 0);
+        let dt = Local.timestamp_opt(self.create_date, 0).unwrap();
         dt.format("%Y-%m-%d").to_string()
     }
 
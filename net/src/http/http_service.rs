@@ -1,7 +1,9 @@
 use super::{ChanHttpProtoSenderOp, HttpProtoType};
-use serde::{Deserialize, Serialize};
+use crate::tls::{self, TlsAcceptor};
 use std::future::Future;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
 
 use std::convert::Infallible;
 use tokio::sync::oneshot;
@@ -16,8 +18,10 @@ fn with_sender(
     warp::any().map(move || sender.clone())
 }
 
+// tls: 配置了证书时使用 https
 pub async fn start_service(
     addr: SocketAddr,
+    tls: Option<Arc<TlsAcceptor>>,
     shutdown: impl Future,
     chan_out: ChanHttpProtoSenderOp,
 ) {
@@ -48,8 +52,20 @@ pub async fn start_service(
         .or(handler_req_server)
         .or(handler_gm_add_item);
 
+    let server = warp::serve(routes);
+    let run = async move {
+        match tls {
+            Some(tls) => {
+                let listener = TcpListener::bind(addr).await.unwrap();
+                server
+                    .run_incoming(tls::incoming(listener, tls, LOG_NAME))
+                    .await
+            }
+            None => server.run(addr).await,
+        }
+    };
     tokio::select! {
-        _ = warp::serve(routes).run(addr) => {
+        _ = run => {
             llog::error!(LOG_NAME,"http.run closed.");
         }
         _ = shutdown => {
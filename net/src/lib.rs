@@ -1,17 +1,22 @@
+use std::net::SocketAddr;
 use tokio::sync::mpsc::{Receiver, Sender};
 
 pub mod http;
 pub mod rpc;
 pub mod tcp;
+pub mod tls;
 pub mod utils;
+pub mod ws;
 
 pub use proto::allptos::{self, ProtoType};
+use proto::{Direction, MessageDescriptor};
 // vfd,proto_id,pto
 pub type ProtoMsgType = (u64, u32, ProtoType);
 
 pub use tcp::{
-    connection::{ConnReader, ConnWriter},
+    connection::{ConnOptions, ConnReader, ConnWriter},
     mailbox::MailBox,
+    outbound::{ProtoReceiver, ProtoSender},
 };
 
 #[derive(Debug)]
@@ -26,28 +31,59 @@ pub enum ServiceType {
     Rpc,
 }
 
-// for tcp proto
-pub type ProtoSender = Sender<ProtoMsgType>;
-pub type ProtoReceiver = Receiver<ProtoMsgType>;
+impl ServiceType {
+    // 监听的连接可以接收的协议: 玩家连接只接收客户端发给服务器的协议, rpc 连接只接收服务器之间的协议
+    pub fn accepts(&self, desc: &MessageDescriptor) -> bool {
+        match self {
+            ServiceType::Tcp => desc.direction == Direction::ToServer,
+            ServiceType::Rpc => desc.direction == Direction::Rpc,
+        }
+    }
+}
+
+// for tcp proto, 连接的发送队列见 tcp::outbound
 pub type ChanProtoSender = Sender<(u64, ProtoSender)>;
 pub type ChanProtoReceiver = Receiver<(u64, ProtoSender)>;
+// 连接的生命周期事件与协议消息走同一个 mailbox
+pub type EventSender = Sender<NetEvent>;
+pub type EventReceiver = Receiver<NetEvent>;
 // for http proto
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T> = std::result::Result<T, Error>;
 
+// 连接断开的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisconnectReason {
+    Closed,        // 对端正常关闭
+    Error(String), // 读取出错, 比如协议包不合法或者连接被重置
+    Shutdown,      // 服务停止
+    IdleTimeout,   // 超过读空闲时间没有收到协议, 比如客户端掉线后留下的半开连接
+    RateLimited,   // 超出流量限制的次数太多
+    SlowConsumer,  // 发送队列满了, 按背压策略断开
+}
+
+// ConnReader 投递给服务循环的事件.
+// 同一个连接的 Connected 一定在它的协议消息之前, Disconnected 一定是最后一个.
+#[derive(Debug)]
+pub enum NetEvent {
+    Proto(ProtoMsgType),
+    Connected { vfd: u64, peer_addr: SocketAddr },
+    Disconnected { vfd: u64, reason: DisconnectReason },
+}
+
 #[derive(Debug)]
-pub struct ServiceState<S, T>
+pub struct ServiceState<S>
 where
-    S: Communicate<T>,
+    S: Communicate,
 {
     pub entity: S,
-    pub mailbox: MailBox<T>,
+    pub mailbox: MailBox<NetEvent>,
 }
 
-impl<S, T> ServiceState<S, T>
+impl<S> ServiceState<S>
 where
-    S: Communicate<T>,
+    S: Communicate,
 {
     pub fn new(entity: S, bounded_size: usize) -> Self {
         ServiceState {
@@ -57,8 +93,8 @@ where
     }
 }
 
-pub trait Communicate<T> {
-    fn register(&mut self, identity: u64, sender: Sender<T>);
+pub trait Communicate {
+    fn register(&mut self, identity: u64, sender: ProtoSender);
     fn unregister(&mut self, identity: u64);
-    fn get(&mut self, identity: u64) -> Option<&Sender<T>>;
+    fn get(&mut self, identity: u64) -> Option<&ProtoSender>;
 }
@@ -1,16 +1,38 @@
+use crate::tcp::compress::Compression;
+use crate::tcp::connection::WriteStream;
+use crate::tls::TlsConnector;
 use crate::{ConnWriter, ProtoReceiver};
+use std::sync::Arc;
+use tokio::io;
 use tokio::net::TcpStream;
 extern crate llog;
 
-pub async fn start_service<'a>(
+// tls: 对端的 rpc 监听开启了 tls 时, 先用 addr 里的主机名(或者配置的 server_name)完成握手
+pub async fn start_service(
     stream: TcpStream,
-    log_name: &'a str,
+    addr: &str,
+    log_name: &str,
     identity: u64,
     proto_rx: ProtoReceiver,
+    compression: Option<Arc<Compression>>,
+    tls: Option<Arc<TlsConnector>>,
 ) -> crate::Result<()> {
-    let (_read_stream, write_stream) = stream.into_split();
+    let write_stream: WriteStream = match tls {
+        Some(tls) => {
+            let stream = tls.connect(addr, stream).await.map_err(|err| {
+                llog::error!(log_name, "[tls.connect]: identity={},{}", identity, err);
+                err
+            })?;
+            // 只发送不接收, 读的一端直接丢弃
+            Box::new(io::split(stream).1)
+        }
+        None => Box::new(stream.into_split().1),
+    };
 
-    let mut writer = ConnWriter::new(identity, write_stream, proto_rx);
+    let mut writer = ConnWriter::with_stream(identity, write_stream, proto_rx);
+    if let Some(compression) = compression {
+        writer.set_compression(compression);
+    }
     llog::info!(log_name, "new connection,identity={}", identity);
 
     if let Err(err) = writer.run(log_name).await {
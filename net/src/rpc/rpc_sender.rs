@@ -1,16 +1,24 @@
 use super::client_send_only;
+use crate::tcp::compress::{CompressStats, Compression};
+use crate::tcp::outbound::{self, OutboundPolicy, OutboundStats};
+use crate::tls::TlsConnector;
 use crate::{utils, Communicate, ProtoMsgType, ProtoSender, ProtoType};
 use conf::conf::Conf;
-use std::collections::HashMap;
-use tokio::sync::mpsc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
 
 #[derive(Debug)]
 pub struct RpcSender {
     conf: Conf,
     pub chan_map: HashMap<u64, ProtoSender>,
+    outbound: Arc<OutboundPolicy>, // 所有 rpc 连接共用的背压策略
+    compression: Option<Arc<Compression>>,
+    tls: Option<Arc<TlsConnector>>, // 连接开启了 tls 的 rpc 监听
+    pending: HashMap<u64, VecDeque<ProtoMsgType>>, // <host_id,等待 flush 发送的协议>
 }
 
-impl Communicate<ProtoMsgType> for RpcSender {
+impl Communicate for RpcSender {
     fn register(&mut self, vfd: u64, sender: ProtoSender) {
         self.chan_map.insert(vfd, sender);
     }
@@ -24,12 +32,38 @@ impl Communicate<ProtoMsgType> for RpcSender {
 
 impl RpcSender {
     pub fn new(conf: Conf) -> RpcSender {
+        let outbound = match conf.get_rpc_backpressure() {
+            Some(bp) => OutboundPolicy::from_conf("rpc_backpressure", bp)
+                .unwrap_or_else(|err| panic!("{}", err)),
+            None => OutboundPolicy::new(1000),
+        };
+        let compression = conf
+            .get_rpc_compress_threshold()
+            .map(|threshold| Arc::new(Compression::new(threshold)));
+        let tls = conf.get_rpc_tls().map(|tls| {
+            let connector =
+                TlsConnector::from_conf("rpc_tls", tls).unwrap_or_else(|err| panic!("{}", err));
+            Arc::new(connector)
+        });
         RpcSender {
             conf,
             chan_map: HashMap::new(),
+            outbound: Arc::new(outbound),
+            compression,
+            tls,
+            pending: HashMap::new(),
         }
     }
 
+    pub fn outbound_stats(&self) -> OutboundStats {
+        self.outbound.stats()
+    }
+
+    // 没有开启压缩时返回 None
+    pub fn compress_stats(&self) -> Option<CompressStats> {
+        self.compression.as_ref().map(|c| c.stats())
+    }
+
     fn new_connection(&mut self, host_id: u64, addr: &str) -> std::io::Result<()> {
         println!(
             "start a new rpc connection,host_id={},addr={}",
@@ -41,58 +75,107 @@ impl RpcSender {
         std_stream.set_nonblocking(true)?;
         let stream = tokio::net::TcpStream::from_std(std_stream)?;
 
-        let (tx, rx) = mpsc::channel(1000);
+        let (tx, rx) = outbound::channel(self.outbound.clone());
         self.chan_map.insert(host_id, tx);
+        let (compression, tls) = (self.compression.clone(), self.tls.clone());
+        let addr = addr.to_owned();
         tokio::spawn(async move {
             let log_name = format!("client_send_only_host_id_{}.log", host_id);
-            let _ = client_send_only::start_service(stream, &log_name, host_id, rx).await;
+            let _ = client_send_only::start_service(
+                stream,
+                &addr,
+                &log_name,
+                host_id,
+                rx,
+                compression,
+                tls,
+            )
+            .await;
         });
         Ok(())
     }
 
+    // 配置了 Block 策略的协议先放到 pending 里, 由 flush 等待发送. 这个 host 还有等待的协议时, 后面的协议也排在后面, 保证顺序
     pub fn send2host(&mut self, host_id: u64, proto_id: u32, pto: ProtoType) {
         let cur_host_id = self.conf.get_host_id();
         if host_id == cur_host_id {
             return;
         }
-        let pto = if let Some(tx) = self.chan_map.get(&host_id) {
-            let pto = match utils::try_send("rpc_sender.log", tx, host_id, proto_id, pto) {
-                None => {
-                    return;
-                }
-                Some((1, _)) => {
-                    println!(
-                        "[send2host]: chan_full,host_id={},proto_id:{}",
-                        host_id, proto_id
-                    );
-                    return;
-                }
-                Some((_, pto)) => pto, // need new connection
-            };
-            pto
-        } else {
-            pto
+        if self.outbound.is_blocking(proto_id) || self.pending.contains_key(&host_id) {
+            let pending = self.pending.entry(host_id).or_default();
+            pending.push_back((host_id, proto_id, pto));
+            return;
+        }
+        let tx = match self.connect(host_id) {
+            Some(tx) => tx,
+            None => return,
         };
+        if let Some((1, _)) = utils::try_send("rpc_sender.log", tx, host_id, proto_id, pto) {
+            println!(
+                "[send2host]: chan_full,host_id={},proto_id:{}",
+                host_id, proto_id
+            );
+        }
+    }
 
-        let addr = if host_id == self.conf.get_db_host_id() {
-            self.conf.get_rpc_db_serv_addr().to_owned()
-        } else {
-            // :TODO: get addr by host_id
-            let addr = format!("127.0.0.1:8083");
-            addr
-        };
-        match self.new_connection(host_id, &addr) {
-            Ok(_) => {
-                let tx = self.chan_map.get(&host_id).unwrap();
-                utils::try_send("rpc_sender.log", tx, host_id, proto_id, pto);
+    // 等待发送 pending 里的协议, 服务循环处理完每个事件后调用.
+    // 每个协议最多等待 block_timeout, 超时后这个 host 剩下的协议也丢弃, 不会一直挡住服务循环
+    pub async fn flush(&mut self) {
+        for (host_id, msgs) in std::mem::take(&mut self.pending) {
+            let tx = match self.connect(host_id) {
+                Some(tx) => tx,
+                None => continue,
+            };
+            let mut msgs = msgs.into_iter();
+            for msg in msgs.by_ref() {
+                let proto_id = msg.1;
+                match tx.send_timeout(msg).await {
+                    Ok(()) => continue,
+                    Err(TrySendError::Full(_)) => llog::error!(
+                        "rpc_sender.log",
+                        "[flush]: timeout: host_id={},proto_id={}",
+                        host_id,
+                        proto_id
+                    ),
+                    Err(TrySendError::Closed(_)) => llog::error!(
+                        "rpc_sender.log",
+                        "[flush]: channel close: host_id={},proto_id={}",
+                        host_id,
+                        proto_id
+                    ),
+                }
+                break;
             }
-            Err(err) => {
+            let dropped = msgs.count();
+            if dropped > 0 {
+                llog::error!(
+                    "rpc_sender.log",
+                    "[flush]: host_id={},dropped {} protocols",
+                    host_id,
+                    dropped
+                );
+            }
+        }
+    }
+
+    // 没有连接或者连接已经关闭时新建连接
+    fn connect(&mut self, host_id: u64) -> Option<&ProtoSender> {
+        if self.chan_map.get(&host_id).is_none_or(|tx| tx.is_closed()) {
+            let addr = if host_id == self.conf.get_db_host_id() {
+                self.conf.get_rpc_db_serv_addr().to_owned()
+            } else {
+                // :TODO: get addr by host_id
+                "127.0.0.1:8083".to_string()
+            };
+            if let Err(err) = self.new_connection(host_id, &addr) {
                 println!(
                     "[send2host]: host_id={},{},connection failed: {}",
                     host_id, addr, err
                 );
+                return None;
             }
         }
+        self.chan_map.get(&host_id)
     }
 
     pub fn send2db(&mut self, proto_id: u32, pto: ProtoType) {
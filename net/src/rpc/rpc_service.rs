@@ -6,22 +6,25 @@
 //  2). rpc 数据发送到对端时,会触发一个新的 tcp connection (如果不存在该 connection 的情况下).
 
 use crate::{
-    tcp, ChanProtoReceiver, ChanProtoSender, Communicate, MailBox, ProtoMsgType, ProtoSender,
-    ServiceType,
+    tcp, ChanProtoReceiver, ChanProtoSender, Communicate, ConnOptions, EventSender, MailBox,
+    NetEvent, ServiceType,
 };
 use std::future::Future;
 use tokio::sync::mpsc;
 
 pub async fn start_service(
+    opts: ConnOptions,
     addr: &str,
     shutdown: impl Future,
     chan_out_tx: ChanProtoSender,
-    pto_out_sender: ProtoSender,
+    pto_out_sender: EventSender,
 ) {
     let serv_type = ServiceType::Rpc;
     let log_name = "rpc.log";
+    // rpc 连接只在服务器之间, 一般不检查读空闲, 也不限流
     tcp::tcp_service::start_service(
         serv_type,
+        opts,
         log_name,
         addr,
         shutdown,
@@ -33,8 +36,8 @@ pub async fn start_service(
 
 pub async fn start_service_handler(
     chan_out_rx: ChanProtoReceiver,
-    entity: impl Communicate<ProtoMsgType>,
-    mailbox: MailBox<ProtoMsgType>,
+    entity: impl Communicate,
+    mailbox: MailBox<NetEvent>,
     shutdown_notify_rx: mpsc::Receiver<()>,
 ) {
     let log_name = "rpc_handler.log";
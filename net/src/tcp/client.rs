@@ -1,5 +1,8 @@
 // 这个模块主要是用来辅助测试
-use crate::{ChanProtoSender, ConnReader, ConnWriter, ProtoMsgType, ProtoSender};
+use super::connection::{ReadStream, WriteStream};
+use super::crypto::{self, Role};
+use super::outbound::{self, OutboundPolicy};
+use crate::{ChanProtoSender, ConnReader, ConnWriter, EventSender};
 use std::future::Future;
 use std::sync::Arc;
 use tokio::net::TcpStream;
@@ -13,28 +16,50 @@ pub async fn run(
     shutdown: impl Future,
     identity: u64,
     chan_out: ChanProtoSender,
-    out_sender: ProtoSender,
+    out_sender: EventSender,
+) -> crate::Result<()> {
+    run_with(addr, false, shutdown, identity, chan_out, out_sender).await
+}
+
+// encrypt: 连接服务端开启了 encrypt 的 listener, 先握手再加密传输
+pub async fn run_with(
+    addr: String,
+    encrypt: bool,
+    shutdown: impl Future,
+    identity: u64,
+    chan_out: ChanProtoSender,
+    out_sender: EventSender,
 ) -> crate::Result<()> {
     let vfd = identity;
     let log_name = LOG_NAME;
 
-    let stream = TcpStream::connect(addr).await.unwrap();
-    let (conn_tx, conn_rx) = mpsc::channel::<ProtoMsgType>(100);
-    let (read_stream, write_stream) = stream.into_split();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let peer_addr = stream.peer_addr().ok();
+    let (conn_tx, conn_rx) = outbound::channel(Arc::new(OutboundPolicy::new(100)));
+    let (read_stream, write_stream): (ReadStream, WriteStream) = if encrypt {
+        let session = crypto::handshake(&mut stream, Role::Client).await?;
+        let (read_half, write_half) = stream.into_split();
+        let (reader, writer) = session.split(read_half, write_half);
+        (Box::new(reader), Box::new(writer))
+    } else {
+        let (read_half, write_half) = stream.into_split();
+        (Box::new(read_half), Box::new(write_half))
+    };
 
     let (notify_shutdown, _) = broadcast::channel(1);
     let limit_connections = Arc::new(Semaphore::new(1));
     let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
 
-    let mut reader = ConnReader::new(
+    let mut reader = ConnReader::with_stream(
         vfd,
         read_stream,
+        peer_addr,
         out_sender.clone(),
         limit_connections.clone(),
         shutdown_complete_tx.clone(),
     );
 
-    let mut writer = ConnWriter::new(vfd, write_stream, conn_rx);
+    let mut writer = ConnWriter::with_stream(vfd, write_stream, conn_rx);
 
     // 暴露自己的消息输入端给外界
     chan_out.send((identity, conn_tx)).await?;
@@ -0,0 +1,113 @@
+// 协议包压缩.
+// ConnWriter 把不小于 threshold 的协议包用 lz4 压缩, 压缩后更小时才使用, 消息头的协议包长度带上 PROTO_COMPRESS_FLAG.
+// 压缩后的内容: 原始长度(u32, 小端) + lz4 block. ConnReader 总是能解压, 所以压缩只需要在发送的一端开启.
+// 计数器在同一个服务的所有连接之间共享, 用于观察压缩率.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// lz4 block 最多把每个输入字节展开成 255 个字节, 原始长度超过这个值的包一定是伪造的
+const MAX_EXPANSION: usize = 255;
+
+// 压缩的次数和字节数, 所有连接累计
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CompressStats {
+    pub compressed: u64,
+    pub skipped: u64, // 压缩后没有变小, 按原始内容发送
+    pub raw_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl CompressStats {
+    // 压缩后的字节数 / 原始字节数, 越小越好
+    pub fn ratio(&self) -> f64 {
+        if self.raw_bytes == 0 {
+            1.0
+        } else {
+            self.compressed_bytes as f64 / self.raw_bytes as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    compressed: AtomicU64,
+    skipped: AtomicU64,
+    raw_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct Compression {
+    threshold: usize,
+    counters: Counters,
+}
+
+impl Compression {
+    pub fn new(threshold: usize) -> Self {
+        Compression {
+            threshold,
+            counters: Counters::default(),
+        }
+    }
+
+    // 协议包太小或者压缩后没有变小时返回 None
+    pub fn compress(&self, body: &[u8]) -> Option<Vec<u8>> {
+        if body.len() < self.threshold {
+            return None;
+        }
+        let out = encode(body);
+        let counters = &self.counters;
+        if out.len() >= body.len() {
+            counters.skipped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        counters.compressed.fetch_add(1, Ordering::Relaxed);
+        let (raw, compressed) = (body.len() as u64, out.len() as u64);
+        counters.raw_bytes.fetch_add(raw, Ordering::Relaxed);
+        counters
+            .compressed_bytes
+            .fetch_add(compressed, Ordering::Relaxed);
+        Some(out)
+    }
+
+    pub fn stats(&self) -> CompressStats {
+        CompressStats {
+            compressed: self.counters.compressed.load(Ordering::Relaxed),
+            skipped: self.counters.skipped.load(Ordering::Relaxed),
+            raw_bytes: self.counters.raw_bytes.load(Ordering::Relaxed),
+            compressed_bytes: self.counters.compressed_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// 原始长度 + lz4 block
+pub fn encode(src: &[u8]) -> Vec<u8> {
+    lz4_flex::block::compress_prepend_size(src)
+}
+
+// 解压 encode 的结果, 原始长度超过 max_len 时返回错误
+pub fn decode(buf: &[u8], max_len: usize) -> crate::Result<Vec<u8>> {
+    if buf.len() < 4 {
+        return Err("[compress.decode]: missing raw length".into());
+    }
+    let raw_len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    if raw_len > max_len {
+        return Err(format!("[compress.decode]: exceed max_message_len,{}", raw_len).into());
+    }
+    let block = &buf[4..];
+    // 先按压缩后的长度检查, 避免很小的包让我们分配 max_len 的内存
+    if raw_len > block.len().saturating_mul(MAX_EXPANSION) {
+        return Err(format!("[compress.decode]: invalid raw length,{}", raw_len).into());
+    }
+    let mut out = vec![0u8; raw_len];
+    let n = lz4_flex::block::decompress_into(block, &mut out)
+        .map_err(|e| format!("[compress.decode]: {}", e))?;
+    if n != raw_len {
+        return Err(format!(
+            "[compress.decode]: raw length mismatch, expect {}, got {}",
+            raw_len, n
+        )
+        .into());
+    }
+    Ok(out)
+}
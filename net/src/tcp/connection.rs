@@ -1,34 +1,84 @@
-use crate::ProtoMsgType;
-use crate::{ProtoReceiver, ProtoSender};
-use proto::allptos;
+use super::compress::{self, Compression};
+use super::outbound::OutboundPolicy;
+use super::rate_limit::{RateLimitConfig, RateLimiter, Verdict};
+use crate::tls::TlsAcceptor;
+use crate::{utils, ProtoReceiver, ProtoSender, ServiceType};
+use crate::{DisconnectReason, EventSender, NetEvent, ProtoMsgType, ProtoType};
+use proto::{allptos, consts};
 use std::io;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::sync::{
     broadcast,
     mpsc::{self, error::TrySendError},
     Semaphore,
 };
+use tokio::time::{self, Duration, Instant};
 
 extern crate llog;
 
-//一个完整的协议头部,包括: 协议id(u32) + 协议包总长度(u32), 满足8个字节
-const PROTO_HEADER_LEN: usize = 8;
+//一个完整的协议头部,包括: 协议id(u32) + 协议包总长度(u32), 满足8个字节. 长度限制在协议源文件里声明, 与客户端共用
+const PROTO_HEADER_LEN: usize = consts::PROTO_HEADER_LEN as usize;
 const INIT_PROTO_TOTAL_LEN: usize = 1024;
-const PROTO_BODY_MAX_LEN: usize = 64 * 1024 - PROTO_HEADER_LEN;
+const PROTO_BODY_MAX_LEN: usize = consts::PROTO_BODY_MAX_LEN as usize;
+// 超过 PROTO_BODY_MAX_LEN 的协议包拆成分片, 除了最后一个分片, 协议包长度都带上这个标记
+const PROTO_CHUNK_FLAG: u32 = consts::PROTO_CHUNK_FLAG;
+// 协议包是压缩的内容, 见 compress
+const PROTO_COMPRESS_FLAG: u32 = consts::PROTO_COMPRESS_FLAG;
+const PROTO_CHUNK_LEN: usize = PROTO_BODY_MAX_LEN - 1;
+// 读完大的协议包后, 缓存超过这个容量就缩回 INIT_PROTO_TOTAL_LEN
+const SHRINK_BUFFER_LEN: usize = PROTO_HEADER_LEN + PROTO_BODY_MAX_LEN;
+
+// 连接的读写两端, 可以是 socket 本身, 也可以是包装在 socket 上的一层, 比如 crypto 的加密传输
+pub type ReadStream = Box<dyn AsyncRead + Send + Unpin>;
+pub type WriteStream = Box<dyn AsyncWrite + Send + Unpin>;
+
+// 连接的可选设置, listener 把它们应用到每个新连接上
+#[derive(Debug, Clone, Default)]
+pub struct ConnOptions {
+    pub idle_timeout: Option<Duration>, // 见 ConnReader::set_idle_timeout
+    pub rate_limit: Option<Arc<RateLimitConfig>>, // 见 ConnReader::set_rate_limit
+    pub outbound: Option<Arc<OutboundPolicy>>, // 发送队列的背压策略
+    pub max_message_len: Option<usize>, // 见 ConnReader::set_max_message_len
+    pub compression: Option<Arc<Compression>>, // 见 ConnWriter::set_compression
+    pub encrypt: bool,                  // 先握手再加密传输, 见 crypto
+    pub tls: Option<Arc<TlsAcceptor>>,  // 见 tls
+    // 连接数上限, 共用一个服务循环的传输(tcp 和 websocket)传同一个; 没有时每个服务各自 MAX_CONNECTIONS
+    pub limit_connections: Option<Arc<Semaphore>>,
+}
+
+// parse_frame 的结果
+enum Frame {
+    Proto(ProtoMsgType),
+    // 协议包完整, 但字段不满足协议源文件里的约束, 协议包已经从缓存里移除
+    Invalid(u32, proto::Error),
+    // 读到一个分片, 协议包还没有拼接完整
+    Chunk,
+    // 还需要从 stream 继续读取
+    Incomplete,
+}
 
-#[derive(Debug)]
 pub struct ConnReader {
     vfd: u64,
-    stream: BufReader<OwnedReadHalf>,
-    proto_tx: ProtoSender, // tcp msg send to outer service
+    stream: BufReader<ReadStream>,
+    peer_addr: Option<SocketAddr>,
+    event_tx: EventSender,            // tcp msg/events send to outer service
+    feedback_tx: Option<ProtoSender>, // 直接回复给对端的消息, 比如 c_errors
+    serv_type: Option<ServiceType>,   // 只接收这个服务类型的协议
+    idle_timeout: Option<Duration>,   // 读空闲超时
+    rate_limiter: Option<RateLimiter>,
+    max_message_len: usize,         // 拼接分片后协议包的最大长度
+    chunks: Option<(u32, Vec<u8>)>, // 正在拼接的协议id和已经读到的分片内容
     limit_connections: Arc<Semaphore>,
     _shutdown_complete: mpsc::Sender<()>,
     shutdown: bool,
     buffer: Vec<u8>,
     proto_id: u32,
     proto_len: usize,
+    proto_more: bool,       // 后面还有分片
+    proto_compressed: bool, // 协议包是压缩的内容
     is_header_decode: bool,
     readnum: u64,
 }
@@ -43,35 +93,169 @@ impl ConnReader {
     pub fn new(
         vfd: u64,
         stream: OwnedReadHalf,
-        proto_tx: ProtoSender,
+        event_tx: EventSender,
+        limit_connections: Arc<Semaphore>,
+        _shutdown_complete: mpsc::Sender<()>,
+    ) -> ConnReader {
+        let peer_addr = stream.peer_addr().ok();
+        Self::with_stream(
+            vfd,
+            Box::new(stream),
+            peer_addr,
+            event_tx,
+            limit_connections,
+            _shutdown_complete,
+        )
+    }
+
+    // stream 不是 socket 本身时, 由调用者提供对端地址
+    pub fn with_stream(
+        vfd: u64,
+        stream: ReadStream,
+        peer_addr: Option<SocketAddr>,
+        event_tx: EventSender,
         limit_connections: Arc<Semaphore>,
         _shutdown_complete: mpsc::Sender<()>,
     ) -> ConnReader {
         ConnReader {
             vfd,
             stream: BufReader::new(stream),
-            proto_tx,
+            peer_addr,
+            event_tx,
+            feedback_tx: None,
+            serv_type: None,
+            idle_timeout: None,
+            rate_limiter: None,
+            max_message_len: PROTO_BODY_MAX_LEN,
+            chunks: None,
             limit_connections,
             _shutdown_complete,
             shutdown: false,
             buffer: Vec::with_capacity(INIT_PROTO_TOTAL_LEN),
             proto_id: 0,
             proto_len: 0,
+            proto_more: false,
+            proto_compressed: false,
             is_header_decode: false,
             readnum: 0,
         }
     }
 
+    // 收到不满足字段约束的协议时, 通过 sender 回复对端 c_errors(id 为请求的协议id, param 为原因).
+    // 没有设置时只记录日志. 两种情况下连接都会保持, 丢弃的只是这一个协议包.
+    // sender 的发送队列按背压策略断开时, 连接也随之断开.
+    pub fn set_feedback(&mut self, sender: ProtoSender) {
+        self.feedback_tx = Some(sender);
+    }
+
+    // 只接收 serv_type 可以接收的协议(见 ServiceType::accepts), 其他协议在读取协议包之前就断开连接.
+    // 没有设置时接收所有协议.
+    pub fn set_service_type(&mut self, serv_type: ServiceType) {
+        self.serv_type = Some(serv_type);
+    }
+
+    // 超过 timeout 没有读到完整的协议包(包括心跳 s_ping)就断开连接. 没有设置时不检查.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = Some(timeout);
+    }
+
+    // 按 config 限制这个连接读取协议包的速率, 心跳也计算在内. 没有设置时不限制.
+    pub fn set_rate_limit(&mut self, config: Arc<RateLimitConfig>) {
+        self.rate_limiter = Some(RateLimiter::new(config));
+    }
+
+    // 协议包(拼接分片后)的最大长度, 超过就断开连接. 默认 PROTO_BODY_MAX_LEN.
+    pub fn set_max_message_len(&mut self, len: usize) {
+        self.max_message_len = len;
+    }
+
+    // listener 给新连接应用 ConnOptions
+    pub fn set_options(&mut self, opts: &ConnOptions) {
+        if let Some(timeout) = opts.idle_timeout {
+            self.set_idle_timeout(timeout);
+        }
+        if let Some(config) = &opts.rate_limit {
+            self.set_rate_limit(config.clone());
+        }
+        if let Some(len) = opts.max_message_len {
+            self.set_max_message_len(len);
+        }
+    }
+
+    // 连接开始时投递 Connected, 结束时(无论什么原因)投递 Disconnected
     pub async fn run(
         &mut self,
         log_name: &'static str,
-        mut notify: broadcast::Receiver<()>,
+        notify: broadcast::Receiver<()>,
     ) -> crate::Result<()> {
+        let res = self.serve(log_name, notify).await;
+        let reason = match &res {
+            Ok(reason) => reason.clone(),
+            Err(err) => DisconnectReason::Error(err.to_string()),
+        };
+        // 生命周期事件不能丢弃, 所以这里用 send 等待. 服务循环发给连接的消息都是 try_send, 不会互相等待.
+        let event = NetEvent::Disconnected {
+            vfd: self.vfd,
+            reason,
+        };
+        if self.event_tx.send(event).await.is_err() {
+            llog::info!(log_name, "[ConnReader]: event_tx close: vfd={}", self.vfd);
+        }
+        res.map(|_| ())
+    }
+
+    async fn serve(
+        &mut self,
+        log_name: &'static str,
+        mut notify: broadcast::Receiver<()>,
+    ) -> crate::Result<DisconnectReason> {
+        let peer_addr = self.peer_addr.ok_or("[ConnReader]: unknown peer address")?;
+        let event = NetEvent::Connected {
+            vfd: self.vfd,
+            peer_addr,
+        };
+        if self.event_tx.send(event).await.is_err() {
+            return Ok(DisconnectReason::Shutdown);
+        }
+
+        let feedback = self.feedback_tx.clone();
         while !self.shutdown {
+            // 每读到一个协议包重新计时
+            let idle_deadline = Instant::now() + self.idle_timeout.unwrap_or_default();
             tokio::select! {
-                res = self.read_frame() => {
+                res = self.read_frame(log_name) => {
                     if let Some(pto) = res? {
-                        println!("recv: vfd={},proto_id={},readnum={}",pto.0,pto.1,self.readnum);
+                        if let Some(limiter) = &mut self.rate_limiter {
+                            match limiter.check(pto.1) {
+                                Verdict::Pass => {}
+                                Verdict::Drop => continue,
+                                // 暂停读取, 对端的发送会被 tcp 的流量控制挡住. 暂停期间仍然响应关闭
+                                Verdict::Delay(wait) => tokio::select! {
+                                    _ = time::sleep(wait) => {}
+                                    _ = async { feedback.as_ref().unwrap().kicked().await }, if feedback.is_some() => {
+                                        self.shutdown = true;
+                                        return Ok(DisconnectReason::SlowConsumer);
+                                    },
+                                    _ = notify.recv() => {
+                                        self.shutdown = true;
+                                        return Ok(DisconnectReason::Shutdown);
+                                    },
+                                },
+                                Verdict::Disconnect => {
+                                    self.shutdown = true;
+                                    return Ok(DisconnectReason::RateLimited);
+                                }
+                            }
+                        }
+                        // 心跳直接回复, 不转发给服务循环
+                        if let ProtoType::s_ping(ping) = &pto.2 {
+                            if let Some(sender) = &self.feedback_tx {
+                                utils::pong(log_name, sender, self.vfd, ping.seq);
+                            }
+                            continue;
+                        }
+                        let (vfd, proto_id) = (pto.0, pto.1);
+                        println!("recv: vfd={},proto_id={},readnum={}",vfd,proto_id,self.readnum);
 
                         // 注意, 如果这里使用 send 发送会产生阻塞,而对端的消息处理完毕后也可能会有消息返回也是通过 send.
                         // 如果这边的 send 出现阻塞, 对端返回的 send 也同样出现阻塞, 这时候会导致两端的协程产生 deadlock.
@@ -82,64 +266,122 @@ impl ConnReader {
                         // :TODO: 对于 rpc 的发送, 后续是通过 spawn 一个协程来发送呢,还是有其他更好的办法.
                         // 这里暂时的做法是把未发送成功的协议记录下来,通过日志的错误提示,再寻求扩大队列还是其他更好的办法.
 
-                        //self.proto_tx.send(pto).await?; // would block
-                        if let Err(err) = self.proto_tx.try_send(pto) {
+                        //self.event_tx.send(pto).await?; // would block
+                        if let Err(err) = self.event_tx.try_send(NetEvent::Proto(pto)) {
                             match err {
-                                TrySendError::Full(err) => {
-                                    llog::error!(log_name,"[ConnReader]: proto_tx send failed: vfd={},proto_id={}",err.0,err.1);
+                                TrySendError::Full(_err) => {
+                                    llog::error!(log_name,"[ConnReader]: event_tx send failed: vfd={},proto_id={}",vfd,proto_id);
                                 },
                                 TrySendError::Closed(_err) =>{
-                                    llog::error!(log_name,"[ConnReader]: proto_tx close: vfd={}",self.vfd);
+                                    llog::error!(log_name,"[ConnReader]: event_tx close: vfd={}",self.vfd);
                                     self.shutdown = true;
-                                    break;
+                                    return Ok(DisconnectReason::Shutdown);
                                 }
                             }
                         }
                     } else {
                         llog::info!(log_name,"[ConnReader]: tcp connection close: vfd={}",self.vfd);
                         self.shutdown = true;
-                        break;
+                        return Ok(DisconnectReason::Closed);
                     }
                 }
+                // 断开原因由处理 Disconnected 的服务循环记录
+                _ = time::sleep_until(idle_deadline), if self.idle_timeout.is_some() => {
+                    self.shutdown = true;
+                    return Ok(DisconnectReason::IdleTimeout);
+                },
+                _ = async { feedback.as_ref().unwrap().kicked().await }, if feedback.is_some() => {
+                    self.shutdown = true;
+                    return Ok(DisconnectReason::SlowConsumer);
+                },
                 _ = notify.recv() => {
                     llog::info!(log_name,"[ConnReader]: notify connection close: vfd={}",self.vfd);
                     self.shutdown = true;
-                    break;
+                    return Ok(DisconnectReason::Shutdown);
                 },
             };
         }
-        Ok(())
+        Ok(DisconnectReason::Shutdown)
     }
 
-    pub async fn read_frame(&mut self) -> crate::Result<Option<ProtoMsgType>> {
+    pub async fn read_frame(
+        &mut self,
+        log_name: &'static str,
+    ) -> crate::Result<Option<ProtoMsgType>> {
         loop {
-            if let Some(pto) = self.parse_frame()? {
-                self.readnum += 1;
-                //println!("[read_frame]: readnum={},proto_id={}",self.readnum,self.proto_id);
-                self.is_header_decode = false;
-                self.proto_id = 0;
-                self.proto_len = 0;
-                return Ok(Some(pto));
-            }
-            // :TODO: the capacity of this buffer will grow..when to shrink the buffer?
-            if 0 == self.stream.read_buf(&mut self.buffer).await? {
-                if self.buffer.is_empty() {
-                    return Ok(None);
-                } else {
-                    return Err("connection reset by peer".into());
+            match self.parse_frame()? {
+                Frame::Proto(pto) => {
+                    self.readnum += 1;
+                    //println!("[read_frame]: readnum={},proto_id={}",self.readnum,self.proto_id);
+                    self.shrink_buffer();
+                    return Ok(Some(pto));
+                }
+                // 缓存里可能还有完整的协议包, 继续解析
+                Frame::Invalid(proto_id, err) => {
+                    self.shrink_buffer();
+                    self.reject(log_name, proto_id, err)
+                }
+                Frame::Chunk => {}
+                Frame::Incomplete => {
+                    if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                        if self.buffer.is_empty() {
+                            return Ok(None);
+                        } else {
+                            return Err("connection reset by peer".into());
+                        }
+                    }
                 }
             }
         }
     }
 
-    // return Ok(None) means continue read from stream.
-    fn parse_frame(&mut self) -> crate::Result<Option<ProtoMsgType>> {
+    // 读大的协议包时缓存会变大, 读完后缩回去
+    fn shrink_buffer(&mut self) {
+        if self.buffer.capacity() > SHRINK_BUFFER_LEN && self.buffer.len() <= INIT_PROTO_TOTAL_LEN {
+            self.buffer.shrink_to(INIT_PROTO_TOTAL_LEN);
+        }
+    }
+
+    fn reject(&self, log_name: &'static str, proto_id: u32, err: proto::Error) {
+        llog::info!(
+            log_name,
+            "[ConnReader]: invalid proto dropped: vfd={},proto_id={},{}",
+            self.vfd,
+            proto_id,
+            err
+        );
+        if let Some(sender) = &self.feedback_tx {
+            utils::feekback(log_name, sender, self.vfd, proto_id, err.to_string());
+        }
+    }
+
+    // 从缓存里移除已经解析的协议包, 准备解析下一个消息头
+    fn consume(&mut self, protolen: usize) {
+        let buflen = self.buffer.len();
+        //把 buffer 剩余的内容往前拷贝
+        let leftlen = buflen - protolen;
+        if leftlen != 0 {
+            // 等于0就不用拷贝了
+            self.buffer.copy_within(protolen..buflen, 0);
+        }
+        //设置当前 len
+        unsafe {
+            self.buffer.set_len(leftlen);
+        };
+        self.is_header_decode = false;
+        self.proto_id = 0;
+        self.proto_len = 0;
+        self.proto_more = false;
+        self.proto_compressed = false;
+    }
+
+    fn parse_frame(&mut self) -> crate::Result<Frame> {
         //缓存里还剩余的数据长度
         let buflen = self.buffer.len();
         // buffer 必须满足一整个协议包的内容空间大小之后才开始协议对象的序列化
         // protoid(4bytes) + packlen(4bytes) + body(packlen bytes)
         if buflen < PROTO_HEADER_LEN {
-            return Ok(None);
+            return Ok(Frame::Incomplete);
         }
         if !self.is_header_decode {
             let mut proto_id = 0u32;
@@ -163,8 +405,23 @@ impl ConnReader {
             //     println!("r parse proto_len: {},{},{}",i,(self.buffer[i]),proto_len);
             // }
             self.proto_id = proto_id;
-            self.proto_len = proto_len as usize;
+            self.proto_more = proto_len & PROTO_CHUNK_FLAG != 0;
+            self.proto_compressed = proto_len & PROTO_COMPRESS_FLAG != 0;
+            self.proto_len = (proto_len & !(PROTO_CHUNK_FLAG | PROTO_COMPRESS_FLAG)) as usize;
             self.is_header_decode = true;
+
+            // 比如玩家发来 c_xxx 或 db_xxx, rpc 连接收到 s_xxx
+            if let Some(serv_type) = self.serv_type {
+                let accepted =
+                    allptos::descriptor_by_id(proto_id).is_some_and(|d| serv_type.accepts(d));
+                if !accepted {
+                    return Err(format!(
+                        "[parse_frame]: {:?} connection does not accept proto_id={}",
+                        serv_type, proto_id
+                    )
+                    .into());
+                }
+            }
         }
         //协议长度超出最大上限
         if self.proto_len >= PROTO_BODY_MAX_LEN {
@@ -172,50 +429,109 @@ impl ConnReader {
                 format!("[parse_fram]: exceed PROTO_BODY_MAX_LEN,{}", self.proto_len).into(),
             );
         }
+        let chunked = self.chunks.as_ref().map_or(0, |(_, chunks)| chunks.len());
+        if chunked + self.proto_len > self.max_message_len {
+            return Err(format!(
+                "[parse_frame]: exceed max_message_len,{}",
+                chunked + self.proto_len
+            )
+            .into());
+        }
         let protolen = self.proto_len + PROTO_HEADER_LEN;
         //剩余缓存数据长度还未满足协议数据所需长度,我们认为是接收字节流未完成
         if buflen < protolen {
-            return Ok(None);
+            return Ok(Frame::Incomplete);
         }
         //println!("proto_id={},protolen={},buflen={},bufcap={},header={:?}",self.proto_id,self.proto_len,buflen,self.buffer.capacity(),&self.buffer[0..PROTO_HEADER_LEN]);
-        match allptos::parse_proto(self.proto_id, &self.buffer, PROTO_HEADER_LEN, protolen) {
+        if self.proto_more || self.chunks.is_some() {
+            return self.parse_chunk(protolen);
+        }
+        let proto_id = self.proto_id;
+        if self.proto_compressed {
+            let body = compress::decode(
+                &self.buffer[PROTO_HEADER_LEN..protolen],
+                self.max_message_len,
+            )?;
+            self.consume(protolen);
+            return self.parse_body(proto_id, &body);
+        }
+        match allptos::parse_proto(proto_id, &self.buffer, PROTO_HEADER_LEN, protolen) {
             Ok(ptoobj) => {
-                //把 buffer 剩余的内容往前拷贝
-                let leftlen = buflen - protolen;
-                if leftlen != 0 {
-                    // 等于0就不用拷贝了
-                    self.buffer.copy_within(protolen..buflen, 0);
-                }
-                //设置当前 len
-                unsafe {
-                    self.buffer.set_len(leftlen);
-                };
-                Ok(Some((self.vfd, self.proto_id, ptoobj)))
+                self.consume(protolen);
+                Ok(Frame::Proto((self.vfd, proto_id, ptoobj)))
+            }
+            Err(err @ proto::Error::Validation(_)) => {
+                self.consume(protolen);
+                Ok(Frame::Invalid(proto_id, err))
             }
             Err(err) => Err(err.into()),
         }
     }
+
+    // 解析已经从缓存里移除的协议包(拼接的分片或者解压后的内容)
+    fn parse_body(&self, proto_id: u32, body: &[u8]) -> crate::Result<Frame> {
+        match allptos::parse_proto(proto_id, body, 0, body.len()) {
+            Ok(ptoobj) => Ok(Frame::Proto((self.vfd, proto_id, ptoobj))),
+            Err(err @ proto::Error::Validation(_)) => Ok(Frame::Invalid(proto_id, err)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // 分片的内容拼接起来, 读到最后一个分片后再解析整个协议包
+    fn parse_chunk(&mut self, protolen: usize) -> crate::Result<Frame> {
+        let (proto_id, more, compressed) = (self.proto_id, self.proto_more, self.proto_compressed);
+        let (chunk_id, chunks) = self.chunks.get_or_insert_with(|| (proto_id, Vec::new()));
+        if *chunk_id != proto_id {
+            return Err(format!(
+                "[parse_frame]: chunk of proto_id={} interleaved with proto_id={}",
+                chunk_id, proto_id
+            )
+            .into());
+        }
+        chunks.extend_from_slice(&self.buffer[PROTO_HEADER_LEN..protolen]);
+        self.consume(protolen);
+        if more {
+            return Ok(Frame::Chunk);
+        }
+        // 拼接用的缓存随之释放
+        let (_, chunks) = self.chunks.take().unwrap_or_default();
+        if compressed {
+            let body = compress::decode(&chunks, self.max_message_len)?;
+            return self.parse_body(proto_id, &body);
+        }
+        self.parse_body(proto_id, &chunks)
+    }
 }
 
-#[derive(Debug)]
 pub struct ConnWriter {
     vfd: u64,
-    stream: BufWriter<OwnedWriteHalf>,
+    stream: BufWriter<WriteStream>,
     proto_rx: ProtoReceiver,
+    compression: Option<Arc<Compression>>,
     writenum: u64,
 }
 
 impl ConnWriter {
     pub fn new(vfd: u64, stream: OwnedWriteHalf, proto_rx: ProtoReceiver) -> ConnWriter {
+        Self::with_stream(vfd, Box::new(stream), proto_rx)
+    }
+
+    pub fn with_stream(vfd: u64, stream: WriteStream, proto_rx: ProtoReceiver) -> ConnWriter {
         ConnWriter {
             vfd,
             stream: BufWriter::new(stream),
             proto_rx,
+            compression: None,
             writenum: 0,
         }
     }
 
-    pub async fn run<'a>(&mut self, log_name: &'a str) -> crate::Result<()> {
+    // 不小于 compression 阈值的协议包压缩后发送, 压缩后没有变小就发送原始内容. 没有设置时不压缩.
+    pub fn set_compression(&mut self, compression: Arc<Compression>) {
+        self.compression = Some(compression);
+    }
+
+    pub async fn run(&mut self, log_name: &str) -> crate::Result<()> {
         while let Some((from_vfd, proto_id, pto)) = self.proto_rx.recv().await {
             if self.vfd != from_vfd {
                 llog::info!(
@@ -240,7 +556,29 @@ impl ConnWriter {
             self.vfd, proto_id, self.writenum
         );
 
-        let buflen = buf.len() as u32;
+        let compressed = self.compression.as_ref().and_then(|c| c.compress(buf));
+        let (buf, flag) = match &compressed {
+            Some(body) => (body.as_slice(), PROTO_COMPRESS_FLAG),
+            None => (buf, 0),
+        };
+
+        // 超过 PROTO_BODY_MAX_LEN 的协议包拆成分片, 除了最后一个分片都带上 PROTO_CHUNK_FLAG
+        if buf.len() >= PROTO_BODY_MAX_LEN {
+            let mut chunks = buf.chunks(PROTO_CHUNK_LEN).peekable();
+            while let Some(chunk) = chunks.next() {
+                let flag = if chunks.peek().is_some() {
+                    flag | PROTO_CHUNK_FLAG
+                } else {
+                    flag
+                };
+                let header = proto_id as u64 | ((chunk.len() as u32 | flag) as u64) << 32;
+                self.stream.write_u64_le(header).await?;
+                self.stream.write_all(chunk).await?;
+            }
+            return self.stream.flush().await;
+        }
+
+        let buflen = buf.len() as u32 | flag;
         // little-endian
         let mut header = 0u64;
         header |= proto_id as u64;
@@ -260,7 +598,7 @@ impl ConnWriter {
         //self.stream.write(&header2).await?;
 
         self.stream.write_u64_le(header).await?;
-        self.stream.write_all(&buf).await?;
+        self.stream.write_all(buf).await?;
 
         // Ensure the encoded frame is written to the socket. The calls above
         // are to the buffered stream and writes. Calling `flush` writes the
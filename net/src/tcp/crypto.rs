@@ -0,0 +1,300 @@
+// 客户端连接的加密传输.
+// 连接建立后先握手: 双方各自生成临时的 X25519 密钥并交换公钥, 再用 HKDF-SHA256 从共享密钥派生两个方向各自的密钥.
+// 之后每个方向都是一串 ChaCha20-Poly1305 加密的记录: 密文长度(u32, 小端) + 密文 + tag.
+// nonce 是这个方向上记录的序号, 长度作为附加数据一起认证, 所以记录被篡改, 重放或者调换顺序都会解密失败, 连接随之断开.
+// EncryptedReader/EncryptedWriter 位于 socket 和 ConnReader/ConnWriter 之间, 协议头和协议包的格式不变.
+// 注意: 临时密钥没有身份认证, 只能防止窃听和篡改, 不能防止中间人.
+
+use openssl::derive::Deriver;
+use openssl::md::Md;
+use openssl::pkey::{Id, PKey};
+use openssl::pkey_ctx::PkeyCtx;
+use openssl::symm::{self, Cipher};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::Duration;
+
+// 握手消息: 魔数(含版本号) + 公钥
+const HANDSHAKE_MAGIC: &[u8; 4] = b"RNE1";
+const PUBLIC_KEY_LEN: usize = 32;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const RECORD_HEADER_LEN: usize = 4;
+// 一条记录的最大明文长度, 足够放下一个完整的协议头和协议包(包括分片)
+pub const MAX_RECORD_LEN: usize = 64 * 1024 + 64;
+// listener 等待客户端完成握手的时间
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_CHUNK_LEN: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+// 一个方向的密钥和记录序号
+struct Key {
+    key: [u8; KEY_LEN],
+    seq: u64,
+}
+
+impl Key {
+    fn nonce(&mut self) -> io::Result<[u8; 12]> {
+        // 序号用完之前连接早就该断开了, 这里只是保证 nonce 不会重复
+        if self.seq == u64::MAX {
+            return Err(io::Error::other("[crypto]: record sequence exhausted"));
+        }
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.seq.to_le_bytes());
+        self.seq += 1;
+        Ok(nonce)
+    }
+
+    // 加密 plain, 把记录追加到 out
+    fn seal(&mut self, plain: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        let nonce = self.nonce()?;
+        let header = (plain.len() as u32).to_le_bytes();
+        let mut tag = [0u8; TAG_LEN];
+        let cipher = Cipher::chacha20_poly1305();
+        let sealed = symm::encrypt_aead(cipher, &self.key, Some(&nonce), &header, plain, &mut tag)
+            .map_err(io::Error::other)?;
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&sealed);
+        out.extend_from_slice(&tag);
+        Ok(())
+    }
+
+    // 解密一条完整的记录(不含记录头)
+    fn open(&mut self, header: &[u8], record: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = self.nonce()?;
+        let (sealed, tag) = record.split_at(record.len() - TAG_LEN);
+        let cipher = Cipher::chacha20_poly1305();
+        symm::decrypt_aead(cipher, &self.key, Some(&nonce), header, sealed, tag).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "[crypto]: record authentication failed",
+            )
+        })
+    }
+}
+
+// 握手得到的两个方向的密钥
+pub struct Session {
+    send: Key,
+    recv: Key,
+}
+
+impl Session {
+    // 用这个会话的密钥包装 socket 的读写两端
+    pub fn split<R, W>(self, reader: R, writer: W) -> (EncryptedReader<R>, EncryptedWriter<W>) {
+        let reader = EncryptedReader {
+            inner: reader,
+            key: self.recv,
+            raw: Vec::with_capacity(READ_CHUNK_LEN),
+            plain: Vec::new(),
+            pos: 0,
+        };
+        let writer = EncryptedWriter {
+            inner: writer,
+            key: self.send,
+            plain: Vec::new(),
+            out: Vec::new(),
+            written: 0,
+        };
+        (reader, writer)
+    }
+}
+
+// 双方同时发出自己的公钥再读取对方的, 不需要等待对方先发
+pub async fn handshake<S>(stream: &mut S, role: Role) -> crate::Result<Session>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let private = PKey::generate_x25519()?;
+    let public = private.raw_public_key()?;
+    let mut hello = HANDSHAKE_MAGIC.to_vec();
+    hello.extend_from_slice(&public);
+    stream.write_all(&hello).await?;
+    stream.flush().await?;
+
+    let mut peer_hello = [0u8; HANDSHAKE_MAGIC.len() + PUBLIC_KEY_LEN];
+    stream.read_exact(&mut peer_hello).await?;
+    let (magic, peer_public) = peer_hello.split_at(HANDSHAKE_MAGIC.len());
+    if magic != HANDSHAKE_MAGIC {
+        return Err("[crypto.handshake]: bad handshake magic".into());
+    }
+    let peer = PKey::public_key_from_raw_bytes(peer_public, Id::X25519)?;
+    let mut deriver = Deriver::new(&private)?;
+    deriver.set_peer(&peer)?;
+    let shared = deriver.derive_to_vec()?;
+
+    // 双方的公钥作为 salt, 密钥和这次握手绑定
+    let (server_public, client_public) = match role {
+        Role::Server => (public.as_slice(), peer_public),
+        Role::Client => (peer_public, public.as_slice()),
+    };
+    let salt = [server_public, client_public].concat();
+    let to_server = hkdf(&shared, &salt, b"client to server")?;
+    let to_client = hkdf(&shared, &salt, b"server to client")?;
+    let (send, recv) = match role {
+        Role::Server => (to_client, to_server),
+        Role::Client => (to_server, to_client),
+    };
+    Ok(Session {
+        send: Key { key: send, seq: 0 },
+        recv: Key { key: recv, seq: 0 },
+    })
+}
+
+fn hkdf(secret: &[u8], salt: &[u8], info: &[u8]) -> crate::Result<[u8; KEY_LEN]> {
+    let mut ctx = PkeyCtx::new_id(Id::HKDF)?;
+    ctx.derive_init()?;
+    ctx.set_hkdf_md(Md::sha256())?;
+    ctx.set_hkdf_key(secret)?;
+    ctx.set_hkdf_salt(salt)?;
+    ctx.add_hkdf_info(info)?;
+    let mut key = [0u8; KEY_LEN];
+    ctx.derive(Some(&mut key))?;
+    Ok(key)
+}
+
+pub struct EncryptedReader<R> {
+    inner: R,
+    key: Key,
+    raw: Vec<u8>,   // 还没有凑成完整记录的密文
+    plain: Vec<u8>, // 解密后还没有被读走的明文
+    pos: usize,
+}
+
+impl<R> EncryptedReader<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    // 缓存里有完整的记录时解密到 plain
+    fn open_record(&mut self) -> io::Result<bool> {
+        if self.raw.len() < RECORD_HEADER_LEN {
+            return Ok(false);
+        }
+        let len = u32::from_le_bytes([self.raw[0], self.raw[1], self.raw[2], self.raw[3]]) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("[crypto]: record too large,{}", len),
+            ));
+        }
+        let total = RECORD_HEADER_LEN + len + TAG_LEN;
+        if self.raw.len() < total {
+            return Ok(false);
+        }
+        let (header, record) = self.raw[..total].split_at(RECORD_HEADER_LEN);
+        self.plain = self.key.open(header, record)?;
+        self.pos = 0;
+        self.raw.drain(..total);
+        Ok(true)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for EncryptedReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.pos < this.plain.len() {
+                let n = buf.remaining().min(this.plain.len() - this.pos);
+                buf.put_slice(&this.plain[this.pos..this.pos + n]);
+                this.pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            if this.open_record()? {
+                continue;
+            }
+            let mut chunk = [0u8; READ_CHUNK_LEN];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            let filled = chunk_buf.filled();
+            if filled.is_empty() {
+                // 对端在记录的中间关闭连接
+                if !this.raw.is_empty() {
+                    return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                }
+                return Poll::Ready(Ok(()));
+            }
+            this.raw.extend_from_slice(filled);
+        }
+    }
+}
+
+// 写入的明文先缓存起来, flush 时加密成一条记录. ConnWriter 每写完一个协议包 flush 一次,
+// 所以通常一个协议包就是一条记录; 明文超过 MAX_RECORD_LEN 时提前加密.
+pub struct EncryptedWriter<W> {
+    inner: W,
+    key: Key,
+    plain: Vec<u8>,
+    out: Vec<u8>, // 已经加密, 还没有写到 inner 的记录
+    written: usize,
+}
+
+impl<W> EncryptedWriter<W> {
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    fn seal(&mut self) -> io::Result<()> {
+        if !self.plain.is_empty() {
+            self.key.seal(&self.plain, &mut self.out)?;
+            self.plain.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> EncryptedWriter<W> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.out.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.out.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for EncryptedWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        if this.plain.len() >= MAX_RECORD_LEN {
+            this.seal()?;
+            ready!(this.poll_drain(cx))?;
+        }
+        let n = buf.len().min(MAX_RECORD_LEN - this.plain.len());
+        this.plain.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.seal()?;
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -1,48 +1,146 @@
-use crate::{ChanProtoSender, ProtoSender};
+use super::connection::{ReadStream, WriteStream};
+use super::crypto::{self, Role};
+use super::outbound::{self, OutboundPolicy};
+use crate::{ChanProtoSender, EventSender};
 use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio::io::{self, AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio::sync::{broadcast, mpsc, OwnedSemaphorePermit, Semaphore};
 use tokio::time::{self, Duration};
 extern crate llog;
-use crate::{ConnReader, ConnWriter, ProtoMsgType, ServiceType};
+use crate::{ConnOptions, ConnReader, ConnWriter, ServiceType};
 
-// tcp socket 最大连接数量上限
-const MAX_CONNECTIONS: usize = 10000;
+// 最大连接数量上限
+pub const MAX_CONNECTIONS: usize = 10000;
+// 来自其他传输层(比如 websocket)的玩家连接, vfd 从这里开始, 和 tcp 连接共用一个服务循环时不会重复
+const TRANSPORT_VFD_BASE: u64 = 1 << 40;
+
+// 其他传输层已经建立好的连接, 读写的是和 tcp 一样的字节流
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+// permit: 传输层在握手之前从 run_transport 的 limit_connections 占用的连接数
+pub type TransportStream = (Box<dyn Transport>, SocketAddr, OwnedSemaphorePermit);
+
+// 连接的来源
+#[derive(Debug)]
+enum Source {
+    Tcp(TcpListener),
+    Transport(mpsc::Receiver<TransportStream>),
+}
+
+enum Accepted {
+    Tcp(TcpStream),
+    Transport((Box<dyn Transport>, SocketAddr)),
+}
 
 #[derive(Debug)]
 pub struct Listener {
-    listener: TcpListener,
+    source: Source,
     limit_connections: Arc<Semaphore>,
     notify_shutdown: broadcast::Sender<()>,
     shutdown_complete_rx: mpsc::Receiver<()>,
     shutdown_complete_tx: mpsc::Sender<()>,
     counter: u64,
     serv_type: ServiceType,
+    opts: ConnOptions,
+    outbound: Arc<OutboundPolicy>,
 }
 
 pub async fn run(
     serv_type: ServiceType,
+    opts: ConnOptions,
     log_name: &'static str,
     listener: TcpListener,
     shutdown: impl Future,
     chan_out: ChanProtoSender,
-    out_sender: ProtoSender,
+    out_sender: EventSender,
+) {
+    let source = Source::Tcp(listener);
+    let limit_connections = limit_connections(&opts);
+    serve(
+        source,
+        limit_connections,
+        serv_type,
+        opts,
+        log_name,
+        shutdown,
+        chan_out,
+        out_sender,
+    )
+    .await;
+}
+
+// opts 指定的连接数上限, 没有时新建一个
+pub(crate) fn limit_connections(opts: &ConnOptions) -> Arc<Semaphore> {
+    opts.limit_connections
+        .clone()
+        .unwrap_or_else(|| Arc::new(Semaphore::new(MAX_CONNECTIONS)))
+}
+
+// transports 里的玩家连接和 tcp 连接一样处理: 连接数限制, ConnOptions(除了 tls, 由传输层自己处理), 注册到 chan_out, 事件投递到 out_sender.
+// limit_connections 由传输层从 opts 取得, 握手之前先占用计数, 慢速的握手也会受连接数上限限制
+pub async fn run_transport(
+    opts: ConnOptions,
+    limit_connections: Arc<Semaphore>,
+    log_name: &'static str,
+    transports: mpsc::Receiver<TransportStream>,
+    shutdown: impl Future,
+    chan_out: ChanProtoSender,
+    out_sender: EventSender,
+) {
+    let source = Source::Transport(transports);
+    let serv_type = ServiceType::Tcp;
+    serve(
+        source,
+        limit_connections,
+        serv_type,
+        opts,
+        log_name,
+        shutdown,
+        chan_out,
+        out_sender,
+    )
+    .await;
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn serve(
+    source: Source,
+    limit_connections: Arc<Semaphore>,
+    serv_type: ServiceType,
+    opts: ConnOptions,
+    log_name: &'static str,
+    shutdown: impl Future,
+    chan_out: ChanProtoSender,
+    out_sender: EventSender,
 ) {
     let (notify_shutdown, _) = broadcast::channel(1);
     let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
     let mut listener = Listener {
-        listener,
-        limit_connections: Arc::new(Semaphore::new(MAX_CONNECTIONS)),
+        source,
+        limit_connections,
         notify_shutdown,
         shutdown_complete_tx,
         shutdown_complete_rx,
         counter: 0,
         serv_type,
+        // 没有配置背压策略时根据服务类型决定队列大小
+        outbound: opts.outbound.clone().unwrap_or_else(|| {
+            Arc::new(OutboundPolicy::new(match serv_type {
+                ServiceType::Tcp => 100,
+                ServiceType::Rpc => 2000,
+            }))
+        }),
+        opts,
     };
-    let base_counter = match listener.serv_type {
-        ServiceType::Tcp => 10000u64, // 来自游戏客户端的连接,vfd 从 10000 开始标识
-        ServiceType::Rpc => 0u64,
+    let base_counter = match (&listener.source, listener.serv_type) {
+        (Source::Transport(_), _) => TRANSPORT_VFD_BASE,
+        (Source::Tcp(_), ServiceType::Tcp) => 10000u64, // 来自游戏客户端的连接,vfd 从 10000 开始标识
+        (Source::Tcp(_), ServiceType::Rpc) => 0u64,
     };
     listener.counter = base_counter;
 
@@ -74,7 +172,7 @@ impl Listener {
         &mut self,
         log_name: &'static str,
         chan_out: ChanProtoSender,
-        out_sender: ProtoSender,
+        out_sender: EventSender,
     ) -> crate::Result<()> {
         llog::info!(log_name, "[run]: accepting connections");
 
@@ -82,65 +180,189 @@ impl Listener {
             // 给每个新连接一个自增的id
             self.counter += 1;
             let vfd = self.counter;
-            let stream = self.accept(log_name).await?;
-            let (read_stream, write_stream) = stream.into_split();
-            let mut reader = ConnReader::new(
-                vfd,
-                read_stream,
-                out_sender.clone(),
-                self.limit_connections.clone(),
-                self.shutdown_complete_tx.clone(),
-            );
-
-            // 根据服务类型决定 channel 队列大小
-            let ch_bound_size = match self.serv_type {
-                ServiceType::Tcp => 100,
-                ServiceType::Rpc => 2000,
-            };
-            let (conn_tx, conn_rx) = mpsc::channel::<ProtoMsgType>(ch_bound_size);
-            let mut writer = ConnWriter::new(vfd, write_stream, conn_rx);
+            let conn = self.accept(log_name).await?;
 
-            // 在 reader 被 drop 时归还计数
-            self.limit_connections.acquire().await.unwrap().forget();
-
-            // 暴露自己的消息输入端给外界, :TODO: 注意这里会产生阻塞
-            chan_out.send((vfd, conn_tx)).await?;
-
-            // 开启 socket 消息写循环
-            tokio::spawn(async move {
-                if let Err(err) = writer.run(log_name).await {
-                    llog::error!(log_name, "[ConnWriter]: error: vfd={},{:?}", vfd, err);
-                } else {
-                    llog::info!(log_name, "[ConnWriter]: return,vfd={}", vfd);
-                }
-            });
+            // 在 reader 被 drop 时归还计数, 其他传输层的连接在 accept 里已经占用
+            if let Accepted::Tcp(_) = conn {
+                self.limit_connections.acquire().await.unwrap().forget();
+            }
 
-            // 开启socket 消息读循环
-            let nofity = self.notify_shutdown.subscribe();
+            // 加密握手要等待客户端, 所以连接的初始化放到新协程里, 不影响接收其他连接
+            let incoming = Incoming {
+                vfd,
+                serv_type: self.serv_type,
+                opts: self.opts.clone(),
+                outbound: self.outbound.clone(),
+                limit_connections: self.limit_connections.clone(),
+                shutdown_complete_tx: self.shutdown_complete_tx.clone(),
+                notify: self.notify_shutdown.subscribe(),
+                chan_out: chan_out.clone(),
+                out_sender: out_sender.clone(),
+            };
             tokio::spawn(async move {
-                if let Err(err) = reader.run(log_name, nofity).await {
-                    llog::error!(log_name, "[ConnReader]: error: vfd={},{:?}", vfd, err);
-                } else {
-                    llog::info!(log_name, "[ConnReader]: return,vfd={}", vfd);
+                if let Err(err) = incoming.run(log_name, conn).await {
+                    llog::error!(
+                        log_name,
+                        "[listener]: connection setup failed: vfd={},{:?}",
+                        vfd,
+                        err
+                    );
                 }
             });
         }
     }
 
-    async fn accept(&mut self, log_name: &'static str) -> crate::Result<TcpStream> {
-        let mut backoff = 1;
-        loop {
-            match self.listener.accept().await {
-                Ok((socket, _)) => return Ok(socket),
-                Err(err) => {
-                    if backoff > 64 {
-                        return Err(err.into());
+    async fn accept(&mut self, log_name: &'static str) -> crate::Result<Accepted> {
+        let listener = match &mut self.source {
+            Source::Tcp(listener) => listener,
+            Source::Transport(transports) => {
+                return match transports.recv().await {
+                    Some((stream, peer_addr, permit)) => {
+                        permit.forget();
+                        Ok(Accepted::Transport((stream, peer_addr)))
                     }
+                    None => Err("[listener.accept]: transport closed".into()),
+                };
+            }
+        };
+        let (socket, _) = accept(listener, log_name).await?;
+        Ok(Accepted::Tcp(socket))
+    }
+}
+
+// 接收 tcp 连接, 出错时(比如文件描述符用完)按 1, 2, 4 ... 64 秒退避后重试, 一直失败才返回错误
+pub(crate) async fn accept(
+    listener: &TcpListener,
+    log_name: &'static str,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    let mut backoff = 1;
+    loop {
+        match listener.accept().await {
+            Ok(conn) => return Ok(conn),
+            Err(err) => {
+                if backoff > 64 {
+                    return Err(err);
                 }
             }
-            time::sleep(Duration::from_secs(backoff)).await;
-            backoff *= 2;
-            llog::info!(log_name, "[listener.accept]: backoff: {}", backoff);
         }
+        time::sleep(Duration::from_secs(backoff)).await;
+        backoff *= 2;
+        llog::info!(log_name, "[listener.accept]: backoff: {}", backoff);
+    }
+}
+
+// 刚接收的连接, 初始化完成后开启读写循环
+struct Incoming {
+    vfd: u64,
+    serv_type: ServiceType,
+    opts: ConnOptions,
+    outbound: Arc<OutboundPolicy>,
+    limit_connections: Arc<Semaphore>,
+    shutdown_complete_tx: mpsc::Sender<()>,
+    notify: broadcast::Receiver<()>,
+    chan_out: ChanProtoSender,
+    out_sender: EventSender,
+}
+
+impl Incoming {
+    async fn run(self, log_name: &'static str, conn: Accepted) -> crate::Result<()> {
+        let vfd = self.vfd;
+        // tls 和加密握手都要等待对端
+        let (peer_addr, res) = match conn {
+            Accepted::Tcp(stream) => {
+                let peer_addr = stream.peer_addr().ok();
+                let secure = self.secure(stream);
+                (
+                    peer_addr,
+                    time::timeout(crypto::HANDSHAKE_TIMEOUT, secure).await,
+                )
+            }
+            Accepted::Transport((stream, peer_addr)) => {
+                let secure = split(stream, self.opts.encrypt);
+                (
+                    Some(peer_addr),
+                    time::timeout(crypto::HANDSHAKE_TIMEOUT, secure).await,
+                )
+            }
+        };
+        let (read_stream, write_stream) = match res {
+            Ok(Ok(streams)) => streams,
+            // 还没有 reader, 这里自己归还计数
+            Ok(Err(err)) => {
+                self.limit_connections.add_permits(1);
+                return Err(err);
+            }
+            Err(_) => {
+                self.limit_connections.add_permits(1);
+                return Err("[listener]: handshake timeout".into());
+            }
+        };
+
+        let mut reader = ConnReader::with_stream(
+            vfd,
+            read_stream,
+            peer_addr,
+            self.out_sender,
+            self.limit_connections,
+            self.shutdown_complete_tx,
+        );
+        let (conn_tx, conn_rx) = outbound::channel(self.outbound);
+        let mut writer = ConnWriter::with_stream(vfd, write_stream, conn_rx);
+        if let Some(compression) = &self.opts.compression {
+            writer.set_compression(compression.clone());
+        }
+        reader.set_service_type(self.serv_type);
+        reader.set_options(&self.opts);
+        // 客户端的请求不满足字段约束时回复 c_errors
+        if let ServiceType::Tcp = self.serv_type {
+            reader.set_feedback(conn_tx.clone());
+        }
+
+        // 暴露自己的消息输入端给外界, :TODO: 注意这里会产生阻塞
+        // 注册在 reader 投递 Connected 之前, 服务循环优先处理 chan_out 就能保证收到事件时连接已注册
+        self.chan_out.send((vfd, conn_tx)).await?;
+
+        // 开启 socket 消息写循环
+        tokio::spawn(async move {
+            if let Err(err) = writer.run(log_name).await {
+                llog::error!(log_name, "[ConnWriter]: error: vfd={},{:?}", vfd, err);
+            } else {
+                llog::info!(log_name, "[ConnWriter]: return,vfd={}", vfd);
+            }
+        });
+
+        // socket 消息读循环
+        if let Err(err) = reader.run(log_name, self.notify).await {
+            llog::error!(log_name, "[ConnReader]: error: vfd={},{:?}", vfd, err);
+        } else {
+            llog::info!(log_name, "[ConnReader]: return,vfd={}", vfd);
+        }
+        Ok(())
+    }
+    // 按 ConnOptions 在 socket 上包装 tls 和加密传输
+    async fn secure(&self, stream: TcpStream) -> crate::Result<(ReadStream, WriteStream)> {
+        match &self.opts.tls {
+            Some(tls) => split(tls.accept(stream).await?, self.opts.encrypt).await,
+            None if self.opts.encrypt => split(stream, true).await,
+            None => {
+                let (read_half, write_half) = stream.into_split();
+                Ok((Box::new(read_half), Box::new(write_half)))
+            }
+        }
+    }
+}
+
+async fn split<S>(mut stream: S, encrypt: bool) -> crate::Result<(ReadStream, WriteStream)>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    if encrypt {
+        let session = crypto::handshake(&mut stream, Role::Server).await?;
+        let (read_half, write_half) = io::split(stream);
+        let (reader, writer) = session.split(read_half, write_half);
+        Ok((Box::new(reader), Box::new(writer)))
+    } else {
+        let (read_half, write_half) = io::split(stream);
+        Ok((Box::new(read_half), Box::new(write_half)))
     }
 }
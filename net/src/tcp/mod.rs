@@ -1,5 +1,9 @@
 pub mod client;
+pub mod compress;
 pub mod connection;
+pub mod crypto;
 pub mod listener;
 pub mod mailbox;
+pub mod outbound;
+pub mod rate_limit;
 pub mod tcp_service;
@@ -0,0 +1,364 @@
+// 连接的发送队列.
+// 服务循环通过 ProtoSender::try_send 把协议放进队列, ConnWriter 按顺序取出写到 socket.
+// 队列满了之后按协议的优先级选择背压策略: 高优先级的协议先挤掉队列里最旧的低优先级协议,
+// 没有可以挤掉的再按这个优先级配置的策略处理. 同一个服务的所有连接共用一个 OutboundPolicy 和它的计数器.
+// 服务循环里的 try_send 从不等待; Block 策略只对能够等待的调用者(send_timeout, 比如 RpcSender::flush)生效.
+
+use crate::{allptos, ProtoMsgType};
+use conf::conf::BackpressureConf;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::Notify;
+use tokio::time::{self, Instant};
+
+// 协议的优先级
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Normal,
+    Critical, // 比如 c_login, c_errors, 不能被频繁的状态同步挤掉
+}
+
+// 队列满了之后的处理
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    DropNewest, // 丢弃新协议
+    DropOldest, // 丢弃队列里最旧的同优先级协议
+    Disconnect, // 断开消费太慢的连接
+    Block, // send_timeout 等待空位, 超过 block_timeout 后丢弃新协议; try_send 不能等待, 和 DropNewest 一样
+}
+
+// 各种处理的次数, 所有连接累计
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OutboundStats {
+    pub dropped_newest: u64,
+    pub dropped_oldest: u64, // 包括被高优先级协议挤掉的
+    pub timed_out: u64,
+    pub disconnected: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    dropped_newest: AtomicU64,
+    dropped_oldest: AtomicU64,
+    timed_out: AtomicU64,
+    disconnected: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct OutboundPolicy {
+    capacity: usize,
+    block_timeout: Duration,
+    critical: HashSet<u32>,
+    normal_policy: Policy,
+    critical_policy: Policy,
+    counters: Counters,
+}
+
+impl OutboundPolicy {
+    // 默认所有协议都是 DropNewest, 与 tokio 的 mpsc 队列 try_send 失败时一样
+    pub fn new(capacity: usize) -> Self {
+        OutboundPolicy {
+            capacity: capacity.max(1),
+            block_timeout: Duration::ZERO,
+            critical: HashSet::new(),
+            normal_policy: Policy::DropNewest,
+            critical_policy: Policy::DropNewest,
+            counters: Counters::default(),
+        }
+    }
+
+    // 读取 conf.toml 的 [tcp_backpressure] 或 [rpc_backpressure], 协议名换成协议id
+    pub fn from_conf(section: &str, conf: &BackpressureConf) -> crate::Result<Self> {
+        let parse = |name: &str| match name {
+            "drop_newest" => Ok(Policy::DropNewest),
+            "drop_oldest" => Ok(Policy::DropOldest),
+            "disconnect" => Ok(Policy::Disconnect),
+            "block" => Ok(Policy::Block),
+            other => Err(format!("[{}]: unknown policy '{}'", section, other)),
+        };
+        let mut policy = OutboundPolicy::new(conf.capacity)
+            .policy(Priority::Normal, parse(&conf.normal)?)
+            .policy(Priority::Critical, parse(&conf.critical)?)
+            .block_timeout(Duration::from_millis(conf.block_timeout_ms));
+        for name in &conf.critical_protos {
+            let desc = allptos::descriptor_by_name(name)
+                .ok_or_else(|| format!("[{}]: no such protocol '{}'", section, name))?;
+            policy = policy.critical(desc.id);
+        }
+        Ok(policy)
+    }
+
+    pub fn policy(mut self, priority: Priority, policy: Policy) -> Self {
+        match priority {
+            Priority::Normal => self.normal_policy = policy,
+            Priority::Critical => self.critical_policy = policy,
+        }
+        self
+    }
+
+    pub fn critical(mut self, proto_id: u32) -> Self {
+        self.critical.insert(proto_id);
+        self
+    }
+
+    pub fn block_timeout(mut self, timeout: Duration) -> Self {
+        self.block_timeout = timeout;
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn priority(&self, proto_id: u32) -> Priority {
+        if self.critical.contains(&proto_id) {
+            Priority::Critical
+        } else {
+            Priority::Normal
+        }
+    }
+
+    pub fn stats(&self) -> OutboundStats {
+        OutboundStats {
+            dropped_newest: self.counters.dropped_newest.load(Ordering::Relaxed),
+            dropped_oldest: self.counters.dropped_oldest.load(Ordering::Relaxed),
+            timed_out: self.counters.timed_out.load(Ordering::Relaxed),
+            disconnected: self.counters.disconnected.load(Ordering::Relaxed),
+        }
+    }
+
+    // 这个协议在队列满了时是否等待空位
+    pub fn is_blocking(&self, proto_id: u32) -> bool {
+        self.policy_of(self.priority(proto_id)) == Policy::Block
+    }
+
+    fn policy_of(&self, priority: Priority) -> Policy {
+        match priority {
+            Priority::Normal => self.normal_policy,
+            Priority::Critical => self.critical_policy,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    queue: VecDeque<(Priority, ProtoMsgType)>,
+    senders: usize,
+    closed: bool, // ConnWriter 已经退出
+    kicked: bool, // 因为消费太慢被断开
+}
+
+#[derive(Debug)]
+struct Shared {
+    policy: Arc<OutboundPolicy>,
+    state: Mutex<State>,
+    readable: Notify, // ConnWriter 等待协议
+    space: Notify,    // send_timeout 等待空位
+    kick: Notify,     // ConnReader 等待断开
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+pub fn channel(policy: Arc<OutboundPolicy>) -> (ProtoSender, ProtoReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(policy.capacity),
+            senders: 1,
+            closed: false,
+            kicked: false,
+        }),
+        policy,
+        readable: Notify::new(),
+        space: Notify::new(),
+        kick: Notify::new(),
+    });
+    (
+        ProtoSender {
+            shared: shared.clone(),
+        },
+        ProtoReceiver { shared },
+    )
+}
+
+#[derive(Debug)]
+pub struct ProtoSender {
+    shared: Arc<Shared>,
+}
+
+impl Clone for ProtoSender {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        ProtoSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for ProtoSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.readable.notify_one();
+        }
+    }
+}
+
+impl ProtoSender {
+    // 不会等待, 失败时返回 Full(被丢弃的是新协议) 或者 Closed(连接已经关闭或者因为消费太慢被断开).
+    pub fn try_send(&self, msg: ProtoMsgType) -> Result<(), TrySendError<ProtoMsgType>> {
+        self.push(msg, false)
+    }
+
+    // 这个协议的优先级配置了 Block 策略时, 队列满了就等待 ConnWriter 取走协议, 超过 block_timeout 后丢弃新协议(返回 Full).
+    // 其他策略和 try_send 一样. 同时等待的多个 send_timeout 之间不保证顺序, 需要顺序时一个一个地等待
+    pub async fn send_timeout(
+        &self,
+        mut msg: ProtoMsgType,
+    ) -> Result<(), TrySendError<ProtoMsgType>> {
+        let shared = &*self.shared;
+        let deadline = Instant::now() + shared.policy.block_timeout;
+        loop {
+            // 先登记再检查队列, 不会错过检查之后的通知
+            let space = shared.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+            msg = match self.push(msg, true) {
+                Err(TrySendError::Full(msg)) if shared.policy.is_blocking(msg.1) => msg,
+                res => return res,
+            };
+            if time::timeout_at(deadline, space).await.is_err() {
+                let counters = &shared.policy.counters;
+                counters.timed_out.fetch_add(1, Ordering::Relaxed);
+                return Err(TrySendError::Full(msg));
+            }
+        }
+    }
+
+    // wait: 调用者会等待空位, Block 策略时直接返回 Full, 不计数
+    fn push(&self, msg: ProtoMsgType, wait: bool) -> Result<(), TrySendError<ProtoMsgType>> {
+        let shared = &*self.shared;
+        let policy = &*shared.policy;
+        let counters = &policy.counters;
+        let priority = policy.priority(msg.1);
+        let mut state = shared.lock();
+        if state.closed || state.kicked {
+            return Err(TrySendError::Closed(msg));
+        }
+        if state.queue.len() >= policy.capacity {
+            if let Some(idx) = state.queue.iter().position(|(p, _)| *p < priority) {
+                state.queue.remove(idx);
+                counters.dropped_oldest.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if state.queue.len() >= policy.capacity {
+            match policy.policy_of(priority) {
+                Policy::Block if wait => return Err(TrySendError::Full(msg)),
+                Policy::DropNewest | Policy::Block => {
+                    counters.dropped_newest.fetch_add(1, Ordering::Relaxed);
+                    return Err(TrySendError::Full(msg));
+                }
+                Policy::DropOldest => {
+                    let idx = state.queue.iter().position(|(p, _)| *p == priority);
+                    match idx {
+                        Some(idx) => {
+                            state.queue.remove(idx);
+                            counters.dropped_oldest.fetch_add(1, Ordering::Relaxed);
+                        }
+                        None => {
+                            counters.dropped_newest.fetch_add(1, Ordering::Relaxed);
+                            return Err(TrySendError::Full(msg));
+                        }
+                    }
+                }
+                Policy::Disconnect => {
+                    state.kicked = true;
+                    state.queue.clear();
+                    counters.disconnected.fetch_add(1, Ordering::Relaxed);
+                    drop(state);
+                    shared.readable.notify_one();
+                    shared.space.notify_waiters();
+                    shared.kick.notify_waiters();
+                    return Err(TrySendError::Closed(msg));
+                }
+            }
+        }
+        state.queue.push_back((priority, msg));
+        drop(state);
+        shared.readable.notify_one();
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let state = self.shared.lock();
+        state.closed || state.kicked
+    }
+
+    // 等到连接因为消费太慢被断开
+    pub async fn kicked(&self) {
+        loop {
+            let notified = self.shared.kick.notified();
+            if self.shared.lock().kicked {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn policy(&self) -> &Arc<OutboundPolicy> {
+        &self.shared.policy
+    }
+}
+
+#[derive(Debug)]
+pub struct ProtoReceiver {
+    shared: Arc<Shared>,
+}
+
+impl Drop for ProtoReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        state.queue.clear();
+        drop(state);
+        self.shared.space.notify_waiters();
+    }
+}
+
+impl ProtoReceiver {
+    // 所有 ProtoSender 都被 drop 或者连接被断开时返回 None
+    pub async fn recv(&mut self) -> Option<ProtoMsgType> {
+        loop {
+            {
+                let mut state = self.shared.lock();
+                if state.kicked {
+                    return None;
+                }
+                if let Some((_, msg)) = state.queue.pop_front() {
+                    drop(state);
+                    self.shared.space.notify_waiters();
+                    return Some(msg);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            self.shared.readable.notified().await;
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<ProtoMsgType> {
+        let msg = self.shared.lock().queue.pop_front().map(|(_, msg)| msg);
+        if msg.is_some() {
+            self.shared.space.notify_waiters();
+        }
+        msg
+    }
+}
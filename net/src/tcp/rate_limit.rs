@@ -0,0 +1,218 @@
+// 客户端连接的流量限制.
+// 每个连接有一个所有协议共用的令牌桶, 以及按协议id单独配置的令牌桶, 每读到一个协议包从两个桶里各取一个令牌.
+// 令牌不够时按配置处理: 丢弃, 延迟读取, 或者丢弃并在累计一定次数后断开连接.
+// 计数器在所有连接之间共享, 用于观察服务器整体被限流的情况.
+
+use crate::allptos;
+use conf::conf::{BucketConf, RateLimitConf};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+
+// 超出限制时的处理
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitAction {
+    Drop,
+    Delay,
+    Disconnect(u32), // 丢弃, 累计超出这么多次后断开连接
+}
+
+// 一个协议包的检查结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Pass,
+    Drop,
+    Delay(Duration), // 等待这么久之后再处理(令牌已经预支)
+    Disconnect,
+}
+
+// 各种处理的次数, 所有连接累计
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RateLimitStats {
+    pub passed: u64,
+    pub dropped: u64,
+    pub delayed: u64,
+    pub disconnected: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    passed: AtomicU64,
+    dropped: AtomicU64,
+    delayed: AtomicU64,
+    disconnected: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct RateLimitConfig {
+    action: LimitAction,
+    global: Option<BucketConf>,
+    protos: HashMap<u32, BucketConf>,
+    counters: Counters,
+}
+
+impl RateLimitConfig {
+    pub fn new(action: LimitAction) -> Self {
+        RateLimitConfig {
+            action,
+            global: None,
+            protos: HashMap::new(),
+            counters: Counters::default(),
+        }
+    }
+
+    // 读取 conf.toml 的 [tcp_rate_limit], 协议名换成协议id
+    pub fn from_conf(conf: &RateLimitConf) -> crate::Result<Self> {
+        let action = match conf.action.as_str() {
+            "drop" => LimitAction::Drop,
+            "delay" => LimitAction::Delay,
+            "disconnect" => LimitAction::Disconnect(conf.max_violations.max(1)),
+            other => return Err(format!("[tcp_rate_limit]: unknown action '{}'", other).into()),
+        };
+        let mut config = RateLimitConfig::new(action);
+        if let Some(bucket) = conf.global {
+            config = config.global(bucket)?;
+        }
+        for (name, bucket) in &conf.protos {
+            let desc = allptos::descriptor_by_name(name)
+                .ok_or_else(|| format!("[tcp_rate_limit]: no such protocol '{}'", name))?;
+            config = config.proto(desc.id, *bucket)?;
+        }
+        Ok(config)
+    }
+
+    pub fn global(mut self, bucket: BucketConf) -> crate::Result<Self> {
+        self.global = Some(check_bucket(bucket)?);
+        Ok(self)
+    }
+
+    pub fn proto(mut self, proto_id: u32, bucket: BucketConf) -> crate::Result<Self> {
+        self.protos.insert(proto_id, check_bucket(bucket)?);
+        Ok(self)
+    }
+
+    pub fn stats(&self) -> RateLimitStats {
+        RateLimitStats {
+            passed: self.counters.passed.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            delayed: self.counters.delayed.load(Ordering::Relaxed),
+            disconnected: self.counters.disconnected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn check_bucket(bucket: BucketConf) -> crate::Result<BucketConf> {
+    if bucket.rate == 0 || bucket.burst == 0 {
+        return Err(format!("[tcp_rate_limit]: rate and burst must be > 0: {:?}", bucket).into());
+    }
+    Ok(bucket)
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64, // 预支令牌时可以是负数
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(conf: BucketConf, now: Instant) -> Self {
+        TokenBucket {
+            rate: conf.rate as f64,
+            burst: conf.burst as f64,
+            tokens: conf.burst as f64,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+
+    // 取到一个令牌还需要等待的时间
+    fn wait(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+        }
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+// 一个连接的限流状态
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: Arc<RateLimitConfig>,
+    global: Option<TokenBucket>,
+    protos: HashMap<u32, TokenBucket>,
+    violations: u32,
+}
+
+impl RateLimiter {
+    pub fn new(config: Arc<RateLimitConfig>) -> Self {
+        let now = Instant::now();
+        RateLimiter {
+            global: config.global.map(|conf| TokenBucket::new(conf, now)),
+            protos: config
+                .protos
+                .iter()
+                .map(|(id, conf)| (*id, TokenBucket::new(*conf, now)))
+                .collect(),
+            config,
+            violations: 0,
+        }
+    }
+
+    // 这个连接超出限制的次数
+    pub fn violations(&self) -> u32 {
+        self.violations
+    }
+
+    pub fn check(&mut self, proto_id: u32) -> Verdict {
+        self.check_at(proto_id, Instant::now())
+    }
+
+    pub fn check_at(&mut self, proto_id: u32, now: Instant) -> Verdict {
+        let mut wait = Duration::ZERO;
+        for bucket in self.buckets(proto_id) {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait());
+        }
+        let config = self.config.clone();
+        let counters = &config.counters;
+        if wait.is_zero() {
+            self.buckets(proto_id).for_each(TokenBucket::take);
+            counters.passed.fetch_add(1, Ordering::Relaxed);
+            return Verdict::Pass;
+        }
+
+        self.violations += 1;
+        match config.action {
+            LimitAction::Delay => {
+                self.buckets(proto_id).for_each(TokenBucket::take);
+                counters.delayed.fetch_add(1, Ordering::Relaxed);
+                Verdict::Delay(wait)
+            }
+            LimitAction::Disconnect(max) if self.violations >= max => {
+                counters.disconnected.fetch_add(1, Ordering::Relaxed);
+                Verdict::Disconnect
+            }
+            LimitAction::Drop | LimitAction::Disconnect(_) => {
+                counters.dropped.fetch_add(1, Ordering::Relaxed);
+                Verdict::Drop
+            }
+        }
+    }
+
+    fn buckets(&mut self, proto_id: u32) -> impl Iterator<Item = &mut TokenBucket> {
+        self.global.iter_mut().chain(self.protos.get_mut(&proto_id))
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
-    tcp::listener, ChanProtoReceiver, ChanProtoSender, Communicate, MailBox, ProtoMsgType,
-    ProtoSender, ServiceType,
+    tcp::listener, ChanProtoReceiver, ChanProtoSender, Communicate, ConnOptions, EventSender,
+    MailBox, NetEvent, ServiceType,
 };
 use llog;
 use std::future::Future;
@@ -10,18 +10,21 @@ use tokio::{
     time::{self, Duration},
 };
 
+// opts: 每个连接的可选设置, 比如读空闲超时和流量限制
 pub async fn start_service(
     serv_type: ServiceType,
+    opts: ConnOptions,
     log_name: &'static str,
     addr: &str,
     shutdown: impl Future,
     chan_out_tx: ChanProtoSender,
-    pto_out_sender: ProtoSender,
+    pto_out_sender: EventSender,
 ) {
     llog::info!(log_name, "service start: listening {}", addr);
     let listener = TcpListener::bind(addr).await.unwrap();
     listener::run(
         serv_type,
+        opts,
         log_name,
         listener,
         shutdown,
@@ -35,8 +38,8 @@ pub async fn start_service(
 pub async fn start_service_handler(
     log_name: &'static str,
     mut chan_out_rx: ChanProtoReceiver,
-    mut entity: impl Communicate<ProtoMsgType>,
-    mut mailbox: MailBox<ProtoMsgType>,
+    mut entity: impl Communicate,
+    mut mailbox: MailBox<NetEvent>,
     mut shutdown_notify_rx: mpsc::Receiver<()>,
 ) {
     let mut heart_beat = time::interval(Duration::from_millis(1000));
@@ -52,34 +55,52 @@ pub async fn start_service_handler(
                 }
             },
             res = mailbox.recv() => {
-                if let Some((vfd,proto_id,pto)) = res {
-                    //println!("service get proto: vfd={},proto_id={}",vfd,proto_id);
-                    match entity.get(vfd) {
-                        Some(ch) => {
-                            // :TODO: 处理 pto
+                // 先处理连接注册, 再处理这个连接的事件
+                while let Ok((vfd,sender)) = chan_out_rx.try_recv() {
+                    entity.register(vfd,sender);
+                    llog::info!(log_name,"new client connection channel: vfd={}",vfd);
+                }
+                let event = match res {
+                    Some(event) => event,
+                    None => {
+                        llog::error!(log_name,"server service receive close");
+                        break;
+                    }
+                };
+                match event {
+                    NetEvent::Connected { vfd, peer_addr } => {
+                        llog::info!(log_name,"connection established: vfd={},peer_addr={}",vfd,peer_addr);
+                    },
+                    NetEvent::Disconnected { vfd, reason } => {
+                        entity.unregister(vfd);
+                        llog::info!(log_name,"connection closed: vfd={},reason={:?}",vfd,reason);
+                    },
+                    NetEvent::Proto((vfd,proto_id,pto)) => {
+                        //println!("service get proto: vfd={},proto_id={}",vfd,proto_id);
+                        match entity.get(vfd) {
+                            Some(ch) => {
+                                // :TODO: 处理 pto
 
-                            // 这是发送给 socket 的消息, 可以考虑用 try_send, 直接丢弃队列溢出的消息
-                            if let Err(err) = ch.try_send((vfd,proto_id,pto)) {
-                                match err {
-                                    TrySendError::Full(err) => {
-                                        llog::error!(log_name,"service send proto to connection failed, chan full: vfd={},proto_id={}",err.0,err.1);
-                                    },
-                                    TrySendError::Closed(_err) =>{
-                                        llog::error!(log_name,"connection channel close: vfd={}",vfd);
-                                        // should remove ch
-                                        entity.unregister(vfd);
+                                // 这是发送给 socket 的消息, 可以考虑用 try_send, 直接丢弃队列溢出的消息
+                                if let Err(err) = ch.try_send((vfd,proto_id,pto)) {
+                                    match err {
+                                        TrySendError::Full(err) => {
+                                            llog::error!(log_name,"service send proto to connection failed, chan full: vfd={},proto_id={}",err.0,err.1);
+                                        },
+                                        TrySendError::Closed(_err) =>{
+                                            llog::error!(log_name,"connection channel close: vfd={}",vfd);
+                                            // should remove ch
+                                            entity.unregister(vfd);
+                                        }
                                     }
                                 }
+                            },
+                            None => {
+                                // ch 不存在
+                                llog::error!(log_name,"service send proto,connection doesn't exist: vfd={},proto_id={}",vfd,proto_id);
                             }
-                        },
-                        None => {
-                            // ch 不存在
-                            llog::error!(log_name,"service send proto,connection doesn't exist: vfd={},proto_id={}",vfd,proto_id);
                         }
                     }
-                } else {
-                    llog::error!(log_name,"server service receive close");
-                    break;
                 }
             }
             _ = heart_beat.tick() => {
@@ -0,0 +1,201 @@
+// tcp, rpc, http 监听和 rpc 主动连接的 tls, 用 rustls 实现.
+// 证书和私钥都是 PEM 文件, 由 conf.toml 的 [tcp_tls], [rpc_tls], [http_tls] 配置.
+
+use crate::tcp::listener;
+use conf::conf::TlsConf;
+use std::convert::Infallible;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::{self, Duration};
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+
+// 等待对端完成 tls 握手的时间
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// incoming 里同时进行的握手数量上限, 超过时暂停接收新连接
+const MAX_HANDSHAKES: usize = 256;
+
+// 服务端和客户端握手后都是这个类型, get_ref 返回底层 socket 和协商结果(比如对端的证书)
+pub type TlsStream<S> = tokio_rustls::TlsStream<S>;
+
+// 服务端的证书和私钥; 配置了 verify_peer 时还要求对端出示 ca 签发的证书(双向认证)
+pub struct TlsAcceptor {
+    acceptor: tokio_rustls::TlsAcceptor,
+}
+
+impl fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsAcceptor").finish_non_exhaustive()
+    }
+}
+
+impl TlsAcceptor {
+    pub fn from_conf(section: &str, conf: &TlsConf) -> crate::Result<Self> {
+        let certs = load_certs(section, &conf.cert)?;
+        let key = load_key(section, &conf.key)?;
+        let provider = provider();
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = if conf.verify_peer {
+            let ca = conf
+                .ca
+                .as_deref()
+                .ok_or_else(|| format!("[{}]: verify_peer requires ca", section))?;
+            let roots = load_roots(section, ca)?;
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| format!("[{}]: {}: {}", section, ca, e))?;
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| format!("[{}]: {}: {}", section, conf.key, e))?;
+        Ok(TlsAcceptor {
+            acceptor: Arc::new(config).into(),
+        })
+    }
+
+    pub async fn accept<S>(&self, stream: S) -> io::Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        Ok(self.acceptor.accept(stream).await?.into())
+    }
+}
+
+// 连接其他服务器时用 ca 校验对端的证书(没有配置时用系统的根证书), 证书里的名字要和 server_name(没有配置时是连接地址的主机名)一致.
+// 对端要求双向认证时出示自己的证书.
+pub struct TlsConnector {
+    connector: tokio_rustls::TlsConnector,
+    server_name: Option<String>,
+}
+
+impl fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConnector")
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsConnector {
+    pub fn from_conf(section: &str, conf: &TlsConf) -> crate::Result<Self> {
+        let roots = match &conf.ca {
+            Some(ca) => load_roots(section, ca)?,
+            None => {
+                let mut roots = RootCertStore::empty();
+                // 个别无法解析的系统证书直接跳过
+                let native = rustls_native_certs::load_native_certs();
+                roots.add_parsable_certificates(native.certs);
+                roots
+            }
+        };
+        let certs = load_certs(section, &conf.cert)?;
+        let key = load_key(section, &conf.key)?;
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)
+            .map_err(|e| format!("[{}]: {}: {}", section, conf.key, e))?;
+        Ok(TlsConnector {
+            connector: Arc::new(config).into(),
+            server_name: conf.server_name.clone(),
+        })
+    }
+
+    // addr: 连接的地址, 比如 "127.0.0.1:8083"
+    pub async fn connect<S>(&self, addr: &str, stream: S) -> io::Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let host = match &self.server_name {
+            Some(name) => name.as_str(),
+            None => addr.rsplit_once(':').map_or(addr, |(host, _)| host),
+        };
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(self.connector.connect(name, stream).await?.into())
+    }
+}
+
+// 接收连接并完成 tls 握手, 给 warp 的 run_incoming 使用. 握手失败的连接直接关闭.
+pub fn incoming(
+    listener: TcpListener,
+    acceptor: Arc<TlsAcceptor>,
+    log_name: &'static str,
+) -> impl futures_util::TryStream<Ok = TlsStream<TcpStream>, Error = Infallible> + Send {
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        let handshakes = Arc::new(Semaphore::new(MAX_HANDSHAKES));
+        loop {
+            let permit = handshakes.clone().acquire_owned().await.unwrap();
+            let stream = tokio::select! {
+                res = listener::accept(&listener, log_name) => match res {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        llog::error!(log_name, "[tls.incoming]: accept error: {:?}", err);
+                        break;
+                    }
+                },
+                _ = tx.closed() => break,
+            };
+            // 握手要等待对端, 不能挡住接收其他连接
+            let (tx, acceptor) = (tx.clone(), acceptor.clone());
+            tokio::spawn(async move {
+                let res = time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await;
+                drop(permit);
+                if let Ok(Ok(stream)) = res {
+                    let _ = tx.send(Ok(stream)).await;
+                }
+            });
+        }
+    });
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|stream| (stream, rx))
+    })
+}
+
+// 只用 ring 实现的算法, 不依赖进程级别的默认配置
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn open(section: &str, path: &str) -> crate::Result<BufReader<File>> {
+    let file = File::open(path).map_err(|e| format!("[{}]: {}: {}", section, path, e))?;
+    Ok(BufReader::new(file))
+}
+
+fn load_certs(section: &str, path: &str) -> crate::Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut open(section, path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("[{}]: {}: {}", section, path, e))?;
+    if certs.is_empty() {
+        return Err(format!("[{}]: {}: no certificate", section, path).into());
+    }
+    Ok(certs)
+}
+
+fn load_key(section: &str, path: &str) -> crate::Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut open(section, path)?)
+        .map_err(|e| format!("[{}]: {}: {}", section, path, e))?
+        .ok_or_else(|| format!("[{}]: {}: no private key", section, path).into())
+}
+
+fn load_roots(section: &str, path: &str) -> crate::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(section, path)? {
+        roots
+            .add(cert)
+            .map_err(|e| format!("[{}]: {}: {}", section, path, e))?;
+    }
+    Ok(roots)
+}
@@ -4,8 +4,8 @@ use tokio::sync::mpsc::error::TrySendError;
 
 // 必须是 try_send
 // return=0,ok; 1,channel full; 2,channel closed
-pub fn try_send<'a>(
-    log_name: &'a str,
+pub fn try_send(
+    log_name: &str,
     sender: &ProtoSender,
     vfd: u64,
     proto_id: u32,
@@ -31,13 +31,7 @@ pub fn try_send<'a>(
     None
 }
 
-pub fn feekback<'a>(
-    log_name: &'a str,
-    sender: &ProtoSender,
-    vfd: u64,
-    feedback_id: u32,
-    params: String,
-) {
+pub fn feekback(log_name: &str, sender: &ProtoSender, vfd: u64, feedback_id: u32, params: String) {
     let sendptoid = c_errors::c_errors::id();
     let c_errors = c_errors::c_errors {
         id: feedback_id,
@@ -46,3 +40,10 @@ pub fn feekback<'a>(
     let sendpto = ProtoType::c_errors(c_errors);
     let _ = try_send(log_name, sender, vfd, sendptoid, sendpto);
 }
+
+// 回复心跳, seq 为 s_ping 里的序号
+pub fn pong(log_name: &str, sender: &ProtoSender, vfd: u64, seq: u32) {
+    let sendptoid = c_pong::c_pong::id();
+    let sendpto = ProtoType::c_pong(c_pong::c_pong { seq });
+    let _ = try_send(log_name, sender, vfd, sendptoid, sendpto);
+}
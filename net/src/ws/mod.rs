@@ -0,0 +1,95 @@
+// 浏览器客户端的 websocket 传输.
+// 每条二进制消息里是一个或多个完整的帧(协议头 + 协议包), 格式和 tcp 相同. WsStream 把消息拼接成字节流交给 ConnReader,
+// ConnWriter 每写完一个协议包 flush 一次, 每次 flush 发送一条二进制消息, 所以通常一条消息就是一个协议包(分片的协议包在同一条消息里).
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use warp::ws::{Message, WebSocket};
+use warp::{Sink, Stream};
+
+pub mod ws_service;
+
+pub struct WsStream {
+    inner: WebSocket,
+    read: Vec<u8>, // 收到的消息里还没有被读走的内容
+    pos: usize,
+    write: Vec<u8>, // 下一次 flush 发送的内容
+}
+
+impl WsStream {
+    pub fn new(inner: WebSocket) -> Self {
+        WsStream {
+            inner,
+            read: Vec::new(),
+            pos: 0,
+            write: Vec::new(),
+        }
+    }
+}
+
+impl AsyncRead for WsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.pos < this.read.len() {
+                let n = buf.remaining().min(this.read.len() - this.pos);
+                buf.put_slice(&this.read[this.pos..this.pos + n]);
+                this.pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            let msg = match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(msg)) => msg,
+                Some(Err(err)) => return Poll::Ready(Err(io::Error::other(err))),
+                // 对端关闭
+                None => return Poll::Ready(Ok(())),
+            };
+            if msg.is_binary() {
+                this.read = msg.into_bytes();
+                this.pos = 0;
+            } else if msg.is_close() {
+                return Poll::Ready(Ok(()));
+            } else if msg.is_text() {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "[ws]: text message not supported",
+                )));
+            }
+            // ping/pong 由 websocket 自己回复
+        }
+    }
+}
+
+impl AsyncWrite for WsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().write.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut inner = Pin::new(&mut this.inner);
+        if !this.write.is_empty() {
+            ready!(inner.as_mut().poll_ready(cx)).map_err(io::Error::other)?;
+            let msg = Message::binary(std::mem::take(&mut this.write));
+            inner.as_mut().start_send(msg).map_err(io::Error::other)?;
+        }
+        inner.poll_flush(cx).map_err(io::Error::other)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner)
+            .poll_close(cx)
+            .map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,105 @@
+use super::WsStream;
+use crate::tcp::listener::{self, TransportStream};
+use crate::tls::{self, TlsAcceptor};
+use crate::{ChanProtoSender, ConnOptions, EventSender};
+use llog;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::{self, Duration};
+use warp::hyper::server::conn::Http;
+use warp::ws::{WebSocket, Ws};
+use warp::Filter;
+
+// 客户端连接的地址: ws://<addr>/ws, 配置了 tls 时是 wss://<addr>/ws
+const WS_PATH: &str = "ws";
+// 客户端一条消息的最大长度, 协议包的长度另外由 ConnReader 按 max_message_len 检查
+const MAX_WS_MESSAGE_LEN: usize = 1024 * 1024;
+// 等待客户端完成 websocket 升级的时间
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 和 tcp_service::start_service 一样, 连接注册到 chan_out_tx, 事件投递到 pto_out_sender,
+// 服务循环不需要区分 vfd 来自哪种传输. opts.tls 用在 http 升级之前(wss)
+pub async fn start_service(
+    opts: ConnOptions,
+    log_name: &'static str,
+    addr: &str,
+    shutdown: impl Future,
+    chan_out_tx: ChanProtoSender,
+    pto_out_sender: EventSender,
+) {
+    llog::info!(log_name, "service start: listening {}", addr);
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (transport_tx, transport_rx) = mpsc::channel(32);
+    let tls = opts.tls.clone();
+    // opts.limit_connections 和 tcp 服务共用时, 两种传输的连接合计不超过上限; 握手和升级期间就占用
+    let limit_connections = listener::limit_connections(&opts);
+    tokio::select! {
+        _ = listener::run_transport(opts, limit_connections.clone(), log_name, transport_rx, shutdown, chan_out_tx, pto_out_sender) => {}
+        _ = accept(listener, tls, limit_connections, log_name, transport_tx) => {}
+    }
+    llog::info!(log_name, "service stop");
+}
+
+async fn accept(
+    listener: TcpListener,
+    tls: Option<Arc<TlsAcceptor>>,
+    limit_connections: Arc<Semaphore>,
+    log_name: &'static str,
+    transport_tx: mpsc::Sender<TransportStream>,
+) {
+    loop {
+        let (stream, peer_addr) = match listener::accept(&listener, log_name).await {
+            Ok(conn) => conn,
+            Err(err) => {
+                llog::error!(log_name, "[ws.accept]: error: {:?}", err);
+                return;
+            }
+        };
+        // 升级失败或者超时时归还计数
+        let permit = limit_connections.clone().acquire_owned().await.unwrap();
+        // 握手和 http 升级都要等待对端, 不能挡住接收其他连接
+        let (tls, transport_tx) = (tls.clone(), transport_tx.clone());
+        tokio::spawn(async move {
+            let socket = match tls {
+                Some(tls) => {
+                    let res = time::timeout(tls::HANDSHAKE_TIMEOUT, tls.accept(stream)).await;
+                    match res {
+                        Ok(Ok(stream)) => time::timeout(UPGRADE_TIMEOUT, upgrade(stream)).await,
+                        _ => return,
+                    }
+                }
+                None => time::timeout(UPGRADE_TIMEOUT, upgrade(stream)).await,
+            };
+            if let Ok(Some(socket)) = socket {
+                let stream = Box::new(WsStream::new(socket));
+                let _ = transport_tx.send((stream, peer_addr, permit)).await;
+            }
+        });
+    }
+}
+
+// 处理这个连接上的 http 请求, 返回升级后的 websocket. 连接关闭时还没有升级返回 None
+async fn upgrade<S>(stream: S) -> Option<WebSocket>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (socket_tx, mut socket_rx) = mpsc::channel(1);
+    let route = warp::path(WS_PATH)
+        .and(warp::path::end())
+        .and(warp::ws())
+        .map(move |ws: Ws| {
+            let socket_tx = socket_tx.clone();
+            ws.max_message_size(MAX_WS_MESSAGE_LEN)
+                .on_upgrade(move |socket| async move {
+                    let _ = socket_tx.send(socket).await;
+                })
+        });
+    let _ = Http::new()
+        .serve_connection(stream, warp::service(route))
+        .with_upgrades()
+        .await;
+    socket_rx.recv().await
+}
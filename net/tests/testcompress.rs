@@ -0,0 +1,99 @@
+use net::tcp::compress::{self, CompressStats, Compression};
+
+// 简单的伪随机数, 生成压缩不了的内容
+fn noise(len: usize) -> Vec<u8> {
+    let mut seed = 0x2545f491u32;
+    (0..len)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        })
+        .collect()
+}
+
+#[test]
+fn testroundtrip() {
+    let mut samples: Vec<Vec<u8>> = vec![
+        Vec::new(),
+        b"a".to_vec(),
+        b"abcdabcdabcdabcd".to_vec(),
+        vec![7u8; 100_000],
+        noise(5000),
+    ];
+    // 背包快照这类内容: 重复的结构, 不同的数值
+    let bag: Vec<u8> = (0..2000u32)
+        .flat_map(|i| {
+            [
+                0x08,
+                (i % 7) as u8,
+                0x10,
+                0x01,
+                0x1a,
+                0x04,
+                b'i',
+                b't',
+                b'e',
+                b'm',
+            ]
+        })
+        .collect();
+    samples.push(bag);
+    let mut mixed = noise(300);
+    mixed.extend_from_slice(&vec![0u8; 70_000]);
+    mixed.extend_from_slice(&noise(300));
+    samples.push(mixed);
+
+    for src in samples {
+        let encoded = compress::encode(&src);
+        assert_eq!(compress::decode(&encoded, src.len()).unwrap(), src);
+    }
+}
+
+#[test]
+fn testthreshold() {
+    let compression = Compression::new(64);
+    assert!(compression.compress(&[1u8; 32]).is_none());
+    let compressed = compression.compress(&[1u8; 1000]).unwrap();
+    assert!(compressed.len() < 100);
+    // 压缩后没有变小就不压缩
+    assert!(compression.compress(&noise(1000)).is_none());
+
+    let stats = compression.stats();
+    assert_eq!(
+        stats,
+        CompressStats {
+            compressed: 1,
+            skipped: 1,
+            raw_bytes: 1000,
+            compressed_bytes: compressed.len() as u64,
+        }
+    );
+    assert!(stats.ratio() < 0.1);
+}
+
+#[test]
+fn testcorrupt() {
+    let src = vec![3u8; 1000];
+    let encoded = compress::encode(&src);
+    // 原始长度超过限制
+    let err = compress::decode(&encoded, 999).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "[compress.decode]: exceed max_message_len,1000"
+    );
+    // 内容被截断
+    assert!(compress::decode(&encoded[..encoded.len() - 1], 1000).is_err());
+    assert!(compress::decode(&encoded[..2], 1000).is_err());
+    // 偏移超出已经解压的内容
+    let bad = [4, 0, 0, 0, 0x00, 0x05, 0x00];
+    assert!(compress::decode(&bad, 1000).is_err());
+    // 很小的包声称很大的原始长度, 不分配内存直接拒绝
+    let forged = [0, 0, 0, 1, 0x10, 0x00];
+    let err = compress::decode(&forged, 1 << 24).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "[compress.decode]: invalid raw length,16777216"
+    );
+}
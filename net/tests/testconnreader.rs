@@ -0,0 +1,507 @@
+use conf::conf::BucketConf;
+use net::tcp::compress::Compression;
+use net::tcp::outbound::{self, OutboundPolicy, Policy, Priority};
+use net::tcp::rate_limit::{LimitAction, RateLimitConfig};
+use net::{
+    ConnOptions, ConnReader, ConnWriter, DisconnectReason, NetEvent, ProtoType, ServiceType,
+};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio::time::Duration;
+
+// 协议头(协议id + 协议包长度)和协议包
+fn frame(pto: ProtoType) -> Vec<u8> {
+    let id = pto.inner_info().0;
+    let body = net::allptos::serialize(pto).unwrap();
+    let mut buf = id.to_le_bytes().to_vec();
+    buf.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&body);
+    buf
+}
+
+// 发送 frames 后, 返回服务端读取到的协议id, 或者读取出错的原因
+async fn read_frames(serv_type: ServiceType, frames: Vec<Vec<u8>>) -> Vec<Result<u32, String>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+    let (read_stream, _write_stream) = stream.into_split();
+
+    let (proto_tx, _proto_rx) = mpsc::channel(10);
+    let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+    let mut reader = ConnReader::new(
+        1,
+        read_stream,
+        proto_tx,
+        Arc::new(Semaphore::new(1)),
+        shutdown_tx,
+    );
+    reader.set_service_type(serv_type);
+
+    let count = frames.len();
+    for buf in frames {
+        client.write_all(&buf).await.unwrap();
+    }
+    let mut res = Vec::new();
+    for _ in 0..count {
+        match reader.read_frame("testconnreader.log").await {
+            Ok(Some((_, proto_id, _))) => res.push(Ok(proto_id)),
+            Ok(None) => break,
+            Err(err) => {
+                // 出错后连接就会断开
+                res.push(Err(err.to_string()));
+                break;
+            }
+        }
+    }
+    res
+}
+
+#[test]
+fn testdirection() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let s_login = || ProtoType::s_login(Default::default());
+        let c_login = ProtoType::c_login(Default::default());
+        let db_save_req = || ProtoType::db_save_req(Default::default());
+        let s_login_id = proto::s_login::s_login::id();
+        let c_login_id = proto::c_login::c_login::id();
+        let db_save_req_id = proto::db_save_req::db_save_req::id();
+
+        // 玩家连接只接收 s_xxx
+        let res = read_frames(ServiceType::Tcp, vec![frame(s_login()), frame(c_login)]).await;
+        assert_eq!(
+            res,
+            vec![
+                Ok(s_login_id),
+                Err(format!(
+                    "[parse_frame]: Tcp connection does not accept proto_id={}",
+                    c_login_id
+                )),
+            ]
+        );
+        let res = read_frames(ServiceType::Tcp, vec![frame(db_save_req())]).await;
+        assert!(res[0].is_err());
+
+        // rpc 连接不接收客户端的协议
+        let res = read_frames(
+            ServiceType::Rpc,
+            vec![frame(db_save_req()), frame(s_login())],
+        )
+        .await;
+        assert_eq!(
+            res,
+            vec![
+                Ok(db_save_req_id),
+                Err(format!(
+                    "[parse_frame]: Rpc connection does not accept proto_id={}",
+                    s_login_id
+                )),
+            ]
+        );
+    });
+}
+
+#[test]
+fn testlifecycle() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read_stream, _write_stream) = stream.into_split();
+
+        let (event_tx, mut event_rx) = mpsc::channel(10);
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let (_notify_tx, notify_rx) = broadcast::channel(1);
+        let mut reader = ConnReader::new(
+            7,
+            read_stream,
+            event_tx,
+            Arc::new(Semaphore::new(1)),
+            shutdown_tx,
+        );
+        reader.set_service_type(ServiceType::Tcp);
+
+        // 玩家连接收到 c_xxx 会断开
+        client
+            .write_all(&frame(ProtoType::s_login(Default::default())))
+            .await
+            .unwrap();
+        client
+            .write_all(&frame(ProtoType::c_login(Default::default())))
+            .await
+            .unwrap();
+        assert!(reader.run("testconnreader.log", notify_rx).await.is_err());
+
+        match event_rx.recv().await {
+            Some(NetEvent::Connected { vfd, peer_addr }) => {
+                assert_eq!(vfd, 7);
+                assert_eq!(peer_addr, client.local_addr().unwrap());
+            }
+            other => panic!("expect Connected, got {:?}", other),
+        }
+        match event_rx.recv().await {
+            Some(NetEvent::Proto((vfd, proto_id, _))) => {
+                assert_eq!(vfd, 7);
+                assert_eq!(proto_id, proto::s_login::s_login::id());
+            }
+            other => panic!("expect Proto, got {:?}", other),
+        }
+        match event_rx.recv().await {
+            Some(NetEvent::Disconnected { vfd, reason }) => {
+                assert_eq!(vfd, 7);
+                assert_eq!(
+                    reason,
+                    DisconnectReason::Error(format!(
+                        "[parse_frame]: Tcp connection does not accept proto_id={}",
+                        proto::c_login::c_login::id()
+                    ))
+                );
+            }
+            other => panic!("expect Disconnected, got {:?}", other),
+        }
+        assert!(event_rx.try_recv().is_err());
+    });
+}
+
+#[test]
+fn testheartbeat() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read_stream, _write_stream) = stream.into_split();
+
+        let (event_tx, mut event_rx) = mpsc::channel(10);
+        let (feedback_tx, mut feedback_rx) = outbound::channel(Arc::new(OutboundPolicy::new(10)));
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let (_notify_tx, notify_rx) = broadcast::channel(1);
+        let mut reader = ConnReader::new(
+            1,
+            read_stream,
+            event_tx,
+            Arc::new(Semaphore::new(1)),
+            shutdown_tx,
+        );
+        reader.set_service_type(ServiceType::Tcp);
+        reader.set_feedback(feedback_tx);
+        reader.set_idle_timeout(Duration::from_millis(100));
+
+        // 心跳由 ConnReader 直接回复, 之后没有再收到协议就超时断开
+        let s_ping = proto::s_ping::s_ping { seq: 9 };
+        client
+            .write_all(&frame(ProtoType::s_ping(s_ping)))
+            .await
+            .unwrap();
+        reader.run("testconnreader.log", notify_rx).await.unwrap();
+
+        match feedback_rx.try_recv() {
+            Some((1, proto_id, ProtoType::c_pong(c_pong))) => {
+                assert_eq!(proto_id, proto::c_pong::c_pong::id());
+                assert_eq!(c_pong.seq, 9);
+            }
+            other => panic!("expect c_pong, got {:?}", other),
+        }
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(NetEvent::Connected { vfd: 1, .. })
+        ));
+        match event_rx.recv().await {
+            Some(NetEvent::Disconnected { vfd, reason }) => {
+                assert_eq!(vfd, 1);
+                assert_eq!(reason, DisconnectReason::IdleTimeout);
+            }
+            other => panic!("expect Disconnected, got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn testratelimited() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read_stream, _write_stream) = stream.into_split();
+
+        let (event_tx, mut event_rx) = mpsc::channel(10);
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let (_notify_tx, notify_rx) = broadcast::channel(1);
+        let mut reader = ConnReader::new(
+            1,
+            read_stream,
+            event_tx,
+            Arc::new(Semaphore::new(1)),
+            shutdown_tx,
+        );
+        let rate_limit = RateLimitConfig::new(LimitAction::Disconnect(1))
+            .global(BucketConf { rate: 1, burst: 1 })
+            .unwrap();
+        reader.set_options(&ConnOptions {
+            rate_limit: Some(Arc::new(rate_limit)),
+            ..Default::default()
+        });
+
+        // 第二个协议包超出限制, 直接断开
+        for _ in 0..2 {
+            client
+                .write_all(&frame(ProtoType::s_login(Default::default())))
+                .await
+                .unwrap();
+        }
+        reader.run("testconnreader.log", notify_rx).await.unwrap();
+
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(NetEvent::Connected { .. })
+        ));
+        assert!(matches!(event_rx.recv().await, Some(NetEvent::Proto(_))));
+        match event_rx.recv().await {
+            Some(NetEvent::Disconnected { reason, .. }) => {
+                assert_eq!(reason, DisconnectReason::RateLimited)
+            }
+            other => panic!("expect Disconnected, got {:?}", other),
+        }
+    });
+}
+
+// 暂停读取期间也能关闭连接
+#[test]
+fn testratelimitdelay() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read_stream, _write_stream) = stream.into_split();
+
+        let (event_tx, mut event_rx) = mpsc::channel(10);
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let (notify_tx, notify_rx) = broadcast::channel(1);
+        let mut reader = ConnReader::new(
+            1,
+            read_stream,
+            event_tx,
+            Arc::new(Semaphore::new(1)),
+            shutdown_tx,
+        );
+        let rate_limit = RateLimitConfig::new(LimitAction::Delay)
+            .global(BucketConf { rate: 1, burst: 1 })
+            .unwrap();
+        reader.set_options(&ConnOptions {
+            rate_limit: Some(Arc::new(rate_limit)),
+            ..Default::default()
+        });
+
+        // 第二个协议包要等 1 秒
+        for _ in 0..2 {
+            client
+                .write_all(&frame(ProtoType::s_login(Default::default())))
+                .await
+                .unwrap();
+        }
+        let handle = tokio::spawn(async move { reader.run("testconnreader.log", notify_rx).await });
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(NetEvent::Connected { .. })
+        ));
+        assert!(matches!(event_rx.recv().await, Some(NetEvent::Proto(_))));
+        notify_tx.send(()).unwrap();
+        let res = tokio::time::timeout(Duration::from_millis(500), handle).await;
+        res.expect("delay ignores shutdown").unwrap().unwrap();
+        match event_rx.recv().await {
+            Some(NetEvent::Disconnected { reason, .. }) => {
+                assert_eq!(reason, DisconnectReason::Shutdown)
+            }
+            other => panic!("expect Disconnected, got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn testslowconsumer() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read_stream, _write_stream) = stream.into_split();
+
+        let (event_tx, mut event_rx) = mpsc::channel(10);
+        let policy = OutboundPolicy::new(1).policy(Priority::Normal, Policy::Disconnect);
+        let (feedback_tx, _feedback_rx) = outbound::channel(Arc::new(policy));
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let (_notify_tx, notify_rx) = broadcast::channel(1);
+        let mut reader = ConnReader::new(
+            1,
+            read_stream,
+            event_tx,
+            Arc::new(Semaphore::new(1)),
+            shutdown_tx,
+        );
+        reader.set_feedback(feedback_tx.clone());
+
+        // 没有人从发送队列取协议, 第二个协议放不下, 连接断开
+        let s_login = || (1, 0, ProtoType::s_login(Default::default()));
+        let task = tokio::spawn(async move {
+            reader.run("testconnreader.log", notify_rx).await.unwrap();
+        });
+        assert!(feedback_tx.try_send(s_login()).is_ok());
+        assert!(feedback_tx.try_send(s_login()).is_err());
+        task.await.unwrap();
+
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(NetEvent::Connected { .. })
+        ));
+        match event_rx.recv().await {
+            Some(NetEvent::Disconnected { reason, .. }) => {
+                assert_eq!(reason, DisconnectReason::SlowConsumer)
+            }
+            other => panic!("expect Disconnected, got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn testchunk() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read_stream, _write_stream) = stream.into_split();
+        let (_client_read, client_write) = client.into_split();
+
+        let (event_tx, _event_rx) = mpsc::channel(10);
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let mut reader = ConnReader::new(
+            1,
+            read_stream,
+            event_tx,
+            Arc::new(Semaphore::new(1)),
+            shutdown_tx,
+        );
+        reader.set_service_type(ServiceType::Rpc);
+        reader.set_max_message_len(300 * 1024);
+        let (_proto_tx, proto_rx) = outbound::channel(Arc::new(OutboundPolicy::new(1)));
+        let mut writer = ConnWriter::new(1, client_write, proto_rx);
+
+        // 一个超过 64k 的协议包拆成 4 个分片, 前后各有一个普通的协议包
+        let value: Vec<u8> = (0..200 * 1024).map(|i| i as u8).collect();
+        let db_load_resp = proto::db_load_resp::db_load_resp {
+            key: "player".to_string(),
+            value: value.clone(),
+            ..Default::default()
+        };
+        let db_save_req = || ProtoType::db_save_req(Default::default());
+        for pto in [
+            db_save_req(),
+            ProtoType::db_load_resp(db_load_resp),
+            db_save_req(),
+        ] {
+            let (proto_id, _) = pto.inner_info();
+            let buf = net::allptos::serialize(pto).unwrap();
+            writer.write_frame(proto_id, &buf).await.unwrap();
+        }
+
+        let mut protos = Vec::new();
+        for _ in 0..3 {
+            match reader.read_frame("testconnreader.log").await.unwrap() {
+                Some((_, _, pto)) => protos.push(pto),
+                None => panic!("connection closed"),
+            }
+        }
+        assert!(matches!(protos[0], ProtoType::db_save_req(_)));
+        match &protos[1] {
+            ProtoType::db_load_resp(resp) => {
+                assert_eq!(resp.key, "player");
+                assert_eq!(resp.value, value);
+            }
+            other => panic!("expect db_load_resp, got {:?}", other),
+        }
+        assert!(matches!(protos[2], ProtoType::db_save_req(_)));
+
+        // 超过限制就断开
+        reader.set_max_message_len(100 * 1024);
+        let db_load_resp = proto::db_load_resp::db_load_resp {
+            value,
+            ..Default::default()
+        };
+        let pto = ProtoType::db_load_resp(db_load_resp);
+        let (proto_id, _) = pto.inner_info();
+        let buf = net::allptos::serialize(pto).unwrap();
+        writer.write_frame(proto_id, &buf).await.unwrap();
+        let err = reader.read_frame("testconnreader.log").await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("[parse_frame]: exceed max_message_len"));
+    });
+}
+
+#[test]
+fn testcompressed() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read_stream, _write_stream) = stream.into_split();
+        let (_client_read, client_write) = client.into_split();
+
+        let (event_tx, _event_rx) = mpsc::channel(10);
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let mut reader = ConnReader::new(
+            1,
+            read_stream,
+            event_tx,
+            Arc::new(Semaphore::new(1)),
+            shutdown_tx,
+        );
+        reader.set_max_message_len(1024 * 1024);
+        let (_proto_tx, proto_rx) = outbound::channel(Arc::new(OutboundPolicy::new(1)));
+        let mut writer = ConnWriter::new(1, client_write, proto_rx);
+        let compression = Arc::new(Compression::new(100));
+        writer.set_compression(compression.clone());
+
+        // 小的协议包不压缩; 重复的内容压缩; 压缩后仍然超过 64k 的分片发送
+        let db_load_resp = |value: Vec<u8>| {
+            ProtoType::db_load_resp(proto::db_load_resp::db_load_resp {
+                value,
+                ..Default::default()
+            })
+        };
+        let noise: Vec<u8> = (0..150_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let values = vec![vec![1, 2, 3], vec![9u8; 500_000], noise];
+        for value in &values {
+            let pto = db_load_resp(value.clone());
+            let (proto_id, _) = pto.inner_info();
+            let buf = net::allptos::serialize(pto).unwrap();
+            writer.write_frame(proto_id, &buf).await.unwrap();
+        }
+        for value in &values {
+            match reader.read_frame("testconnreader.log").await.unwrap() {
+                Some((_, _, ProtoType::db_load_resp(resp))) => assert_eq!(&resp.value, value),
+                other => panic!("expect db_load_resp, got {:?}", other),
+            }
+        }
+        let stats = compression.stats();
+        assert_eq!(stats.compressed + stats.skipped, 2);
+        assert!(stats.compressed >= 1);
+        assert!(stats.ratio() < 0.5);
+    });
+}
@@ -0,0 +1,180 @@
+use net::tcp::crypto::{self, Role, Session, MAX_RECORD_LEN};
+use net::tcp::outbound::{self, OutboundPolicy};
+use net::{ConnReader, ConnWriter, ProtoType, ServiceType};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+
+// 在内存里完成一次握手, 返回服务端和客户端的会话
+async fn sessions() -> (Session, Session) {
+    let (mut server, mut client) = io::duplex(1024);
+    let (server, client) = tokio::join!(
+        crypto::handshake(&mut server, Role::Server),
+        crypto::handshake(&mut client, Role::Client)
+    );
+    (server.unwrap(), client.unwrap())
+}
+
+// 用 session 加密 plain, 返回写到 socket 上的内容
+async fn seal(session: Session, plain: &[&[u8]]) -> Vec<u8> {
+    let (_, mut writer) = session.split(io::empty(), Vec::new());
+    for buf in plain {
+        writer.write_all(buf).await.unwrap();
+        writer.flush().await.unwrap();
+    }
+    writer.get_ref().clone()
+}
+
+#[test]
+fn testroundtrip() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let (server, client) = sessions().await;
+        let (server_stream, client_stream) = io::duplex(64 * 1024);
+        let (server_read, server_write) = io::split(server_stream);
+        let (client_read, client_write) = io::split(client_stream);
+        let (mut server_reader, mut server_writer) = server.split(server_read, server_write);
+        let (mut client_reader, mut client_writer) = client.split(client_read, client_write);
+
+        // 超过 MAX_RECORD_LEN 的内容拆成多条记录
+        let big: Vec<u8> = (0..MAX_RECORD_LEN * 3 + 5).map(|i| i as u8).collect();
+        let expect = big.clone();
+        let writer = tokio::spawn(async move {
+            client_writer.write_all(b"hello").await.unwrap();
+            client_writer.flush().await.unwrap();
+            client_writer.write_all(&big).await.unwrap();
+            client_writer.shutdown().await.unwrap();
+        });
+        let mut hello = [0u8; 5];
+        server_reader.read_exact(&mut hello).await.unwrap();
+        assert_eq!(&hello, b"hello");
+        let mut rest = Vec::new();
+        server_reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, expect);
+        writer.await.unwrap();
+
+        // 另一个方向用的是另一个密钥
+        server_writer.write_all(b"world").await.unwrap();
+        server_writer.flush().await.unwrap();
+        let mut world = [0u8; 5];
+        client_reader.read_exact(&mut world).await.unwrap();
+        assert_eq!(&world, b"world");
+    });
+}
+
+#[test]
+fn testtamper() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let plain = b"player password 123456".as_slice();
+
+        // 密文里看不到明文
+        let (server, client) = sessions().await;
+        let sealed = seal(client, &[plain]).await;
+        assert!(!sealed.windows(plain.len()).any(|w| w == plain));
+        let (mut reader, _) = server.split(sealed.as_slice(), io::sink());
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, plain);
+
+        // 改动任意一个字节
+        let (server, client) = sessions().await;
+        let mut sealed = seal(client, &[plain]).await;
+        sealed[10] ^= 1;
+        let (mut reader, _) = server.split(sealed.as_slice(), io::sink());
+        let err = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // 重放第一条记录
+        let (server, client) = sessions().await;
+        let sealed = seal(client, &[plain, plain]).await;
+        let first = &sealed[..sealed.len() / 2];
+        let replayed = [first, first].concat();
+        let (mut reader, _) = server.split(replayed.as_slice(), io::sink());
+        let mut buf = vec![0u8; plain.len()];
+        reader.read_exact(&mut buf).await.unwrap();
+        let err = reader.read_exact(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // 记录不完整时对端关闭
+        let (server, client) = sessions().await;
+        let sealed = seal(client, &[plain]).await;
+        let (mut reader, _) = server.split(&sealed[..sealed.len() - 1], io::sink());
+        let err = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    });
+}
+
+#[test]
+fn testbadhandshake() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        // 没有握手的客户端直接发协议
+        let (mut server, mut client) = io::duplex(1024);
+        client.write_all(&[0u8; 64]).await.unwrap();
+        let err = crypto::handshake(&mut server, Role::Server)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "[crypto.handshake]: bad handshake magic");
+    });
+}
+
+#[test]
+fn testencryptedconn() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let (server, client_session) = tokio::join!(
+            crypto::handshake(&mut stream, Role::Server),
+            crypto::handshake(&mut client, Role::Client)
+        );
+        let peer_addr = stream.peer_addr().ok();
+        let (read_half, write_half) = stream.into_split();
+        let (read_stream, _write_stream) = server.unwrap().split(read_half, write_half);
+        let (read_half, write_half) = client.into_split();
+        let (_client_read, client_write) = client_session.unwrap().split(read_half, write_half);
+
+        let (event_tx, _event_rx) = mpsc::channel(10);
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let mut reader = ConnReader::with_stream(
+            1,
+            Box::new(read_stream),
+            peer_addr,
+            event_tx,
+            Arc::new(Semaphore::new(1)),
+            shutdown_tx,
+        );
+        reader.set_service_type(ServiceType::Tcp);
+        let (_proto_tx, proto_rx) = outbound::channel(Arc::new(OutboundPolicy::new(1)));
+        let mut writer = ConnWriter::with_stream(1, Box::new(client_write), proto_rx);
+
+        // 协议头和协议包的格式不变
+        let s_login = proto::s_login::s_login {
+            acc: "player".to_string(),
+            ..Default::default()
+        };
+        let frames = [
+            ProtoType::s_login(s_login),
+            ProtoType::s_ping(proto::s_ping::s_ping { seq: 7 }),
+        ];
+        for pto in frames {
+            let (proto_id, _) = pto.inner_info();
+            let buf = net::allptos::serialize(pto).unwrap();
+            writer.write_frame(proto_id, &buf).await.unwrap();
+        }
+        match reader.read_frame("testcrypto.log").await.unwrap() {
+            Some((_, _, ProtoType::s_login(pto))) => assert_eq!(pto.acc, "player"),
+            other => panic!("expect s_login, got {:?}", other),
+        }
+        match reader.read_frame("testcrypto.log").await.unwrap() {
+            Some((_, _, ProtoType::s_ping(pto))) => assert_eq!(pto.seq, 7),
+            other => panic!("expect s_ping, got {:?}", other),
+        }
+    });
+}
@@ -2,7 +2,6 @@ use conf::conf::Conf;
 use tokio::signal;
 use tokio::sync::mpsc;
 extern crate net;
-use llog;
 use net::http::http_service;
 use net::http::{ChanHttpProtoReceiverOp, HttpProtoType};
 use tokio::time::{self, Duration};
@@ -10,7 +9,7 @@ use tokio::time::{self, Duration};
 #[test]
 fn test_http_service() {
     let rt = tokio::runtime::Runtime::new().unwrap();
-    let _ = rt.block_on(async move {
+    rt.block_on(async move {
         let (chan_out_tx, chan_out_rx) = mpsc::channel(1);
         let (shutdown_complete_tx1, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
         let shutdown_complete_tx2 = shutdown_complete_tx1.clone();
@@ -21,7 +20,7 @@ fn test_http_service() {
             let conf = Conf::new();
             let addr = conf.get_http_serv_addr();
             let addr = addr.parse().unwrap();
-            http_service::start_service(addr, signal::ctrl_c(), chan_out_tx.clone()).await;
+            http_service::start_service(addr, None, signal::ctrl_c(), chan_out_tx.clone()).await;
             drop(shutdown_complete_tx1);
             let _ = shutdown_notify_tx.send(()).await;
         });
@@ -0,0 +1,177 @@
+use conf::conf::BackpressureConf;
+use net::tcp::outbound::{self, OutboundPolicy, OutboundStats, Policy, Priority};
+use net::{ProtoMsgType, ProtoType};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+
+fn c_login() -> u32 {
+    proto::c_login::c_login::id()
+}
+
+fn c_item_bag() -> u32 {
+    proto::c_item_bag::c_item_bag::id()
+}
+
+// 用 vfd 标记协议的发送顺序
+fn msg(seq: u64, proto_id: u32) -> ProtoMsgType {
+    (seq, proto_id, ProtoType::c_login(Default::default()))
+}
+
+fn policy(normal: Policy, critical: Policy) -> Arc<OutboundPolicy> {
+    let policy = OutboundPolicy::new(2)
+        .policy(Priority::Normal, normal)
+        .policy(Priority::Critical, critical)
+        .critical(c_login());
+    Arc::new(policy)
+}
+
+fn drain(rx: &mut outbound::ProtoReceiver) -> Vec<u64> {
+    std::iter::from_fn(|| rx.try_recv().map(|m| m.0)).collect()
+}
+
+#[test]
+fn testdropnewest() {
+    let policy = policy(Policy::DropNewest, Policy::DropNewest);
+    let (tx, mut rx) = outbound::channel(policy.clone());
+    assert!(tx.try_send(msg(1, c_item_bag())).is_ok());
+    assert!(tx.try_send(msg(2, c_item_bag())).is_ok());
+    assert!(matches!(
+        tx.try_send(msg(3, c_item_bag())),
+        Err(TrySendError::Full((3, _, _)))
+    ));
+    assert_eq!(drain(&mut rx), vec![1, 2]);
+    assert_eq!(policy.stats().dropped_newest, 1);
+
+    // 所有 sender 都被 drop 后 recv 返回 None
+    drop(tx);
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    assert!(rt.block_on(rx.recv()).is_none());
+}
+
+#[test]
+fn testdropoldest() {
+    let policy = policy(Policy::DropOldest, Policy::DropNewest);
+    let (tx, mut rx) = outbound::channel(policy.clone());
+    for seq in 1..=4 {
+        assert!(tx.try_send(msg(seq, c_item_bag())).is_ok());
+    }
+    assert_eq!(drain(&mut rx), vec![3, 4]);
+
+    // 普通协议挤不掉 critical 的协议
+    assert!(tx.try_send(msg(5, c_login())).is_ok());
+    assert!(tx.try_send(msg(6, c_login())).is_ok());
+    assert!(tx.try_send(msg(7, c_item_bag())).is_err());
+    assert_eq!(drain(&mut rx), vec![5, 6]);
+    let stats = policy.stats();
+    assert_eq!((stats.dropped_oldest, stats.dropped_newest), (2, 1));
+}
+
+#[test]
+fn testcritical() {
+    let policy = policy(Policy::DropNewest, Policy::Disconnect);
+    let (tx, mut rx) = outbound::channel(policy.clone());
+    assert!(tx.try_send(msg(1, c_item_bag())).is_ok());
+    assert!(tx.try_send(msg(2, c_item_bag())).is_ok());
+    // 先挤掉最旧的普通协议
+    assert!(tx.try_send(msg(3, c_login())).is_ok());
+    assert!(tx.try_send(msg(4, c_login())).is_ok());
+    assert_eq!(drain(&mut rx), vec![3, 4]);
+
+    assert!(tx.try_send(msg(5, c_login())).is_ok());
+    assert!(tx.try_send(msg(6, c_login())).is_ok());
+    // 队列里都是 critical 的协议, 断开连接
+    assert!(matches!(
+        tx.try_send(msg(7, c_login())),
+        Err(TrySendError::Closed(_))
+    ));
+    assert!(tx.is_closed());
+    assert!(rx.try_recv().is_none());
+    assert_eq!(
+        policy.stats(),
+        OutboundStats {
+            dropped_newest: 0,
+            dropped_oldest: 2,
+            timed_out: 0,
+            disconnected: 1,
+        }
+    );
+}
+
+#[test]
+fn testblock() {
+    let policy = OutboundPolicy::new(1)
+        .policy(Priority::Normal, Policy::Block)
+        .block_timeout(Duration::from_millis(200));
+    let policy = Arc::new(policy);
+    let (tx, mut rx) = outbound::channel(policy.clone());
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        assert!(tx.try_send(msg(1, c_item_bag())).is_ok());
+        // try_send 不会等待
+        assert!(matches!(
+            tx.try_send(msg(2, c_item_bag())),
+            Err(TrySendError::Full((2, _, _)))
+        ));
+        // 没有人取走协议, 超时后丢弃
+        assert!(matches!(
+            tx.send_timeout(msg(3, c_item_bag())).await,
+            Err(TrySendError::Full((3, _, _)))
+        ));
+
+        // ConnWriter 取走协议后等到空位
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let first = rx.recv().await.map(|m| m.0);
+            (first, rx)
+        });
+        assert!(tx.send_timeout(msg(4, c_item_bag())).await.is_ok());
+        let (first, mut rx) = writer.await.unwrap();
+        assert_eq!(first, Some(1));
+        assert_eq!(drain(&mut rx), vec![4]);
+
+        // 等待期间连接关闭, 不用等到超时
+        assert!(tx.try_send(msg(5, c_item_bag())).is_ok());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(rx);
+        });
+        assert!(matches!(
+            tx.send_timeout(msg(6, c_item_bag())).await,
+            Err(TrySendError::Closed(_))
+        ));
+    });
+    let stats = policy.stats();
+    assert_eq!((stats.dropped_newest, stats.timed_out), (1, 1));
+}
+
+#[test]
+fn testfromconf() {
+    let mut conf = BackpressureConf {
+        capacity: 1,
+        normal: "drop_oldest".to_string(),
+        critical: "disconnect".to_string(),
+        critical_protos: vec!["c_login".to_string()],
+        block_timeout_ms: 0,
+    };
+    let policy = OutboundPolicy::from_conf("tcp_backpressure", &conf).unwrap();
+    assert_eq!(policy.capacity(), 1);
+    assert_eq!(policy.priority(c_login()), Priority::Critical);
+    assert_eq!(policy.priority(c_item_bag()), Priority::Normal);
+
+    conf.normal = "kick".to_string();
+    let err = OutboundPolicy::from_conf("tcp_backpressure", &conf).unwrap_err();
+    assert_eq!(err.to_string(), "[tcp_backpressure]: unknown policy 'kick'");
+    conf.normal = "block".to_string();
+    let policy = OutboundPolicy::from_conf("rpc_backpressure", &conf).unwrap();
+    assert!(policy.is_blocking(c_item_bag()));
+    assert!(!policy.is_blocking(c_login()));
+
+    conf.normal = "disconnect".to_string();
+    conf.critical_protos.push("c_nothing".to_string());
+    let err = OutboundPolicy::from_conf("rpc_backpressure", &conf).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "[rpc_backpressure]: no such protocol 'c_nothing'"
+    );
+}
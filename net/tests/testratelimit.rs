@@ -0,0 +1,125 @@
+use conf::conf::{BucketConf, RateLimitConf};
+use net::tcp::rate_limit::{LimitAction, RateLimitConfig, RateLimitStats, RateLimiter, Verdict};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+
+fn s_login() -> u32 {
+    proto::s_login::s_login::id()
+}
+
+fn s_item_bag() -> u32 {
+    proto::s_item_bag::s_item_bag::id()
+}
+
+fn bucket(rate: u32, burst: u32) -> BucketConf {
+    BucketConf { rate, burst }
+}
+
+// 全局每秒 10 个, 突发 3 个; s_login 每秒 1 个, 突发 1 个
+fn limiter(action: LimitAction) -> (Arc<RateLimitConfig>, RateLimiter) {
+    let config = RateLimitConfig::new(action)
+        .global(bucket(10, 3))
+        .unwrap()
+        .proto(s_login(), bucket(1, 1))
+        .unwrap();
+    let config = Arc::new(config);
+    (config.clone(), RateLimiter::new(config))
+}
+
+#[test]
+fn testdrop() {
+    let (config, mut limiter) = limiter(LimitAction::Drop);
+    let now = Instant::now();
+    for _ in 0..3 {
+        assert_eq!(limiter.check_at(s_item_bag(), now), Verdict::Pass);
+    }
+    assert_eq!(limiter.check_at(s_item_bag(), now), Verdict::Drop);
+
+    // 100ms 补充一个全局令牌, 但 s_login 自己的桶只有 1 个
+    let now = now + Duration::from_millis(100);
+    assert_eq!(limiter.check_at(s_login(), now), Verdict::Pass);
+    let now = now + Duration::from_millis(100);
+    assert_eq!(limiter.check_at(s_login(), now), Verdict::Drop);
+    // 被 s_login 的桶拒绝时不消耗全局令牌
+    assert_eq!(limiter.check_at(s_item_bag(), now), Verdict::Pass);
+
+    assert_eq!(limiter.violations(), 2);
+    assert_eq!(
+        config.stats(),
+        RateLimitStats {
+            passed: 5,
+            dropped: 2,
+            delayed: 0,
+            disconnected: 0,
+        }
+    );
+}
+
+#[test]
+fn testdelay() {
+    let (config, mut limiter) = limiter(LimitAction::Delay);
+    let now = Instant::now();
+    assert_eq!(limiter.check_at(s_login(), now), Verdict::Pass);
+    // 令牌预支后按顺序排队
+    assert_eq!(
+        limiter.check_at(s_login(), now),
+        Verdict::Delay(Duration::from_secs(1))
+    );
+    assert_eq!(
+        limiter.check_at(s_login(), now),
+        Verdict::Delay(Duration::from_secs(2))
+    );
+    assert_eq!(
+        limiter.check_at(s_login(), now + Duration::from_secs(3)),
+        Verdict::Pass
+    );
+    assert_eq!(config.stats().delayed, 2);
+}
+
+#[test]
+fn testdisconnect() {
+    let (config, mut limiter) = limiter(LimitAction::Disconnect(2));
+    let now = Instant::now();
+    assert_eq!(limiter.check_at(s_login(), now), Verdict::Pass);
+    assert_eq!(limiter.check_at(s_login(), now), Verdict::Drop);
+    assert_eq!(limiter.check_at(s_login(), now), Verdict::Disconnect);
+    let stats = config.stats();
+    assert_eq!((stats.dropped, stats.disconnected), (1, 1));
+}
+
+#[test]
+fn testfromconf() {
+    let mut protos = HashMap::new();
+    protos.insert("s_login".to_string(), bucket(1, 1));
+    let mut conf = RateLimitConf {
+        action: "disconnect".to_string(),
+        max_violations: 1,
+        global: None,
+        protos,
+    };
+    let mut limiter = RateLimiter::new(Arc::new(RateLimitConfig::from_conf(&conf).unwrap()));
+    let now = Instant::now();
+    assert_eq!(limiter.check_at(s_login(), now), Verdict::Pass);
+    assert_eq!(limiter.check_at(s_login(), now), Verdict::Disconnect);
+    // 没有全局限制
+    for _ in 0..100 {
+        assert_eq!(limiter.check_at(s_item_bag(), now), Verdict::Pass);
+    }
+
+    conf.action = "kick".to_string();
+    let err = RateLimitConfig::from_conf(&conf).unwrap_err();
+    assert_eq!(err.to_string(), "[tcp_rate_limit]: unknown action 'kick'");
+
+    conf.action = "drop".to_string();
+    conf.protos.insert("s_nothing".to_string(), bucket(1, 1));
+    let err = RateLimitConfig::from_conf(&conf).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "[tcp_rate_limit]: no such protocol 's_nothing'"
+    );
+
+    conf.protos.clear();
+    conf.global = Some(bucket(0, 1));
+    assert!(RateLimitConfig::from_conf(&conf).is_err());
+}
@@ -8,18 +8,19 @@ use rand::Rng;
 #[test]
 fn test_rpc_client() {
     let rt = tokio::runtime::Runtime::new().unwrap();
-    let _ = rt.block_on(async move {
+    rt.block_on(async move {
         let sysconf = Conf::new();
         let mut rpc_sender = rpc_sender::RpcSender::new(sysconf);
         let mut m = std::collections::HashMap::new();
         let mut rng = rand::thread_rng();
         for _ in 0..10000 {
-            let proto_id = 101;
-            let s_login = proto::s_login::s_login::default_with_random_value();
+            // rpc 连接只接收服务器之间的协议
+            let proto_id = proto::db_save_req::db_save_req::id();
+            let db_save_req = proto::db_save_req::db_save_req::default_with_random_value();
             let hostid = rng.gen_range(1001..1010);
-            let _ = rpc_sender.send2host(hostid, proto_id, ProtoType::s_login(s_login));
-            if !m.contains_key(&hostid) {
-                m.insert(hostid, true);
+            rpc_sender.send2host(hostid, proto_id, ProtoType::db_save_req(db_save_req));
+            if let std::collections::hash_map::Entry::Vacant(e) = m.entry(hostid) {
+                e.insert(true);
                 println!("tick ");
                 thread::sleep(time::Duration::from_millis(500));
             }
@@ -5,9 +5,8 @@ use tokio::{
     time::{self, Duration},
 };
 extern crate net;
-use llog;
 
-use net::{tcp::client, Communicate, ProtoMsgType, ProtoSender};
+use net::{tcp::client, Communicate, ProtoSender};
 use proto::allptos::ProtoType;
 
 #[test]
@@ -16,7 +15,7 @@ fn testclient() {
         pub service_sender: Option<ProtoSender>,
     }
 
-    impl net::Communicate<ProtoMsgType> for TmpEntity {
+    impl net::Communicate for TmpEntity {
         fn register(&mut self, _vfd: u64, sender: ProtoSender) {
             assert!(self.service_sender.is_none());
             self.service_sender = Some(sender);
@@ -30,7 +29,7 @@ fn testclient() {
     }
 
     let rt = tokio::runtime::Runtime::new().unwrap();
-    let _ = rt.block_on(async move {
+    rt.block_on(async move {
         let entity = TmpEntity {
             service_sender: None,
         };
@@ -49,7 +48,7 @@ fn testclient() {
             let addr = conf.get_tcp_serv_addr().to_owned();
             let _ = client::run(addr, signal::ctrl_c(),identity,chan_out_tx.clone(),out_sender).await;
             drop(shutdown_complete_tx1);
-            let _ = shutdown_notify_tx.send(());
+            let _ = shutdown_notify_tx.send(()).await;
         });
 
         // service
@@ -74,12 +73,12 @@ fn testclient() {
                         }
                     },
                     res = mailbox.recv() => {
-                        if let Some((vfd,proto_id,pto)) = res {
+                        if let Some(net::NetEvent::Proto((vfd,proto_id,pto))) = res {
                             //println!("service get proto: vfd={},proto_id={}",vfd,proto_id);
                             // 处理协议,并返回结果(协议).这里测试我们直接返回接收到的协议
                             match entity.get(vfd) {
                                 Some(ch) => {
-                                    if let Err(err) = ch.send((vfd,proto_id,pto)).await {
+                                    if let Err(err) = ch.try_send((vfd,proto_id,pto)) {
                                         llog::error!(log_name,"service send proto to connection failed: vfd={},proto_id={},err={:?}",vfd,proto_id,err);
                                     }
                                 },
@@ -98,7 +97,7 @@ fn testclient() {
                         if !stopsend {
                             match entity.get(vfd) {
                                 Some(ch) => {
-                                    if let Err(err) = ch.send((vfd,proto_id,ProtoType::s_login(s_login))).await {
+                                    if let Err(err) = ch.try_send((vfd,proto_id,ProtoType::s_login(s_login))) {
                                         llog::error!(log_name,"tick send proto to connection failed: vfd={},proto_id={},err={:?}",vfd,proto_id,err);
                                         entity.unregister(vfd);
                                     }
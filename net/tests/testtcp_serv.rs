@@ -3,9 +3,8 @@ use std::collections::HashMap;
 use tokio::signal;
 use tokio::sync::mpsc;
 extern crate net;
-use llog;
 
-use net::{tcp::tcp_service, Communicate, ProtoMsgType, ProtoSender};
+use net::{tcp::tcp_service, Communicate, ProtoSender};
 
 #[test]
 fn testservice() {
@@ -14,7 +13,7 @@ fn testservice() {
         pub conn_map: HashMap<u64, ProtoSender>,
     }
 
-    impl Communicate<ProtoMsgType> for TmpEntity {
+    impl Communicate for TmpEntity {
         fn register(&mut self, vfd: u64, sender: ProtoSender) {
             self.conn_map.insert(vfd, sender);
         }
@@ -27,7 +26,7 @@ fn testservice() {
     }
 
     let rt = tokio::runtime::Runtime::new().unwrap();
-    let _ = rt.block_on(async move {
+    rt.block_on(async move {
         let entity = TmpEntity::default();
         let shared_state = net::ServiceState::new(entity, 10);
 
@@ -45,6 +44,7 @@ fn testservice() {
             let addr = conf.get_tcp_serv_addr();
             tcp_service::start_service(
                 net::ServiceType::Tcp,
+                net::ConnOptions::default(),
                 log_name,
                 addr,
                 signal::ctrl_c(),
@@ -53,7 +53,7 @@ fn testservice() {
             )
             .await;
             drop(shutdown_complete_tx1);
-            let _ = shutdown_notify_tx.send(());
+            let _ = shutdown_notify_tx.send(()).await;
         });
 
         // service
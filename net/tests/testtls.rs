@@ -0,0 +1,260 @@
+use conf::conf::TlsConf;
+use net::http::http_service;
+use net::tcp::outbound::{self, OutboundPolicy};
+use net::tls::{TlsAcceptor, TlsConnector};
+use net::{ConnReader, ConnWriter, ProtoType, ServiceType};
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509Name, X509};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+
+fn new_key() -> PKey<Private> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+}
+
+// issuer 为 None 时是自签名的 CA 证书
+fn new_cert(cn: &str, key: &PKey<Private>, issuer: Option<(&X509, &PKey<Private>)>) -> X509 {
+    let mut name = X509Name::builder().unwrap();
+    name.append_entry_by_text("CN", cn).unwrap();
+    let name = name.build();
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(rand::random::<u32>() >> 1).unwrap();
+    builder
+        .set_serial_number(&serial.to_asn1_integer().unwrap())
+        .unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_pubkey(key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let (issuer_name, issuer_key) = match issuer {
+        Some((cert, key)) => (cert.subject_name(), key),
+        None => {
+            let ca = BasicConstraints::new().critical().ca().build().unwrap();
+            builder.append_extension(ca).unwrap();
+            (name.as_ref(), key)
+        }
+    };
+    builder.set_issuer_name(issuer_name).unwrap();
+    if issuer.is_some() {
+        let san = SubjectAlternativeName::new()
+            .ip("127.0.0.1")
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+    }
+    builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+    builder.build()
+}
+
+// 生成的证书和私钥写到临时目录, 和 conf.toml 一样按路径配置
+struct Certs {
+    dir: PathBuf,
+}
+
+impl Certs {
+    fn new(name: &str) -> Certs {
+        let dir = std::env::temp_dir().join(format!("testtls_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let certs = Certs { dir };
+        let ca_key = new_key();
+        let ca = new_cert("cluster ca", &ca_key, None);
+        certs.write("ca", &ca, &ca_key);
+        for cn in ["server", "node"] {
+            let key = new_key();
+            let cert = new_cert(cn, &key, Some((&ca, &ca_key)));
+            certs.write(cn, &cert, &key);
+        }
+        // 不是集群的 CA 签发的证书
+        let rogue_key = new_key();
+        let rogue = new_cert("rogue", &rogue_key, None);
+        certs.write("rogue", &rogue, &rogue_key);
+        certs
+    }
+
+    fn write(&self, name: &str, cert: &X509, key: &PKey<Private>) {
+        std::fs::write(self.path(name, "pem"), cert.to_pem().unwrap()).unwrap();
+        let key = key.private_key_to_pem_pkcs8().unwrap();
+        std::fs::write(self.path(name, "key"), key).unwrap();
+    }
+
+    fn path(&self, name: &str, ext: &str) -> String {
+        let path = self.dir.join(format!("{}.{}", name, ext));
+        path.to_str().unwrap().to_string()
+    }
+
+    // name 的证书和私钥, 用 ca 校验对端
+    fn conf(&self, name: &str, ca: &str, verify_peer: bool) -> TlsConf {
+        TlsConf {
+            cert: self.path(name, "pem"),
+            key: self.path(name, "key"),
+            ca: Some(self.path(ca, "pem")),
+            verify_peer,
+            server_name: None,
+        }
+    }
+}
+
+impl Drop for Certs {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// 返回服务端和客户端各自握手的结果
+async fn handshake(
+    acceptor: &TlsAcceptor,
+    connector: &TlsConnector,
+) -> (
+    std::io::Result<net::tls::TlsStream<TcpStream>>,
+    std::io::Result<net::tls::TlsStream<TcpStream>>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let client = TcpStream::connect(&addr).await.unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+    tokio::join!(acceptor.accept(stream), connector.connect(&addr, client))
+}
+
+#[test]
+fn testtcptls() {
+    let certs = Certs::new("tcp");
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let acceptor =
+            TlsAcceptor::from_conf("tcp_tls", &certs.conf("server", "ca", false)).unwrap();
+        let connector =
+            TlsConnector::from_conf("rpc_tls", &certs.conf("node", "ca", false)).unwrap();
+        let (server, client) = handshake(&acceptor, &connector).await;
+        let (server, client) = (server.unwrap(), client.unwrap());
+
+        let peer_addr = server.get_ref().0.peer_addr().ok();
+        let (event_tx, _event_rx) = mpsc::channel(10);
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let mut reader = ConnReader::with_stream(
+            1,
+            Box::new(io::split(server).0),
+            peer_addr,
+            event_tx,
+            Arc::new(Semaphore::new(1)),
+            shutdown_tx,
+        );
+        reader.set_service_type(ServiceType::Tcp);
+        let (_proto_tx, proto_rx) = outbound::channel(Arc::new(OutboundPolicy::new(1)));
+        let mut writer = ConnWriter::with_stream(1, Box::new(io::split(client).1), proto_rx);
+
+        let s_login = proto::s_login::s_login {
+            acc: "player".to_string(),
+            ..Default::default()
+        };
+        let pto = ProtoType::s_login(s_login);
+        let (proto_id, _) = pto.inner_info();
+        let buf = net::allptos::serialize(pto).unwrap();
+        writer.write_frame(proto_id, &buf).await.unwrap();
+        match reader.read_frame("testtls.log").await.unwrap() {
+            Some((_, _, ProtoType::s_login(pto))) => assert_eq!(pto.acc, "player"),
+            other => panic!("expect s_login, got {:?}", other),
+        }
+
+        // 服务端的证书不是 ca 签发的
+        let connector =
+            TlsConnector::from_conf("rpc_tls", &certs.conf("node", "rogue", false)).unwrap();
+        let (_, client) = handshake(&acceptor, &connector).await;
+        assert!(client.is_err());
+
+        // 证书里的名字不一致
+        let mut conf = certs.conf("node", "ca", false);
+        conf.server_name = Some("other.host".to_string());
+        let connector = TlsConnector::from_conf("rpc_tls", &conf).unwrap();
+        let (_, client) = handshake(&acceptor, &connector).await;
+        assert!(client.is_err());
+    });
+}
+
+#[test]
+fn testmutual() {
+    let certs = Certs::new("mutual");
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let acceptor =
+            TlsAcceptor::from_conf("rpc_tls", &certs.conf("server", "ca", true)).unwrap();
+
+        // 集群里的服务器
+        let connector =
+            TlsConnector::from_conf("rpc_tls", &certs.conf("node", "ca", true)).unwrap();
+        let (server, client) = handshake(&acceptor, &connector).await;
+        assert!(client.is_ok());
+        let server = server.unwrap();
+        let peer = &server.get_ref().1.peer_certificates().unwrap()[0];
+        let peer = X509::from_der(peer).unwrap();
+        let cn = peer.subject_name().entries().next().unwrap();
+        assert_eq!(cn.data().as_slice(), b"node");
+
+        // 不是集群的 CA 签发的证书
+        let connector =
+            TlsConnector::from_conf("rpc_tls", &certs.conf("rogue", "ca", true)).unwrap();
+        let (server, _) = handshake(&acceptor, &connector).await;
+        assert!(server.is_err());
+
+        // 双向认证必须配置 ca
+        let mut conf = certs.conf("server", "ca", true);
+        conf.ca = None;
+        let err = TlsAcceptor::from_conf("rpc_tls", &conf).unwrap_err();
+        assert_eq!(err.to_string(), "[rpc_tls]: verify_peer requires ca");
+    });
+}
+
+#[test]
+fn testhttps() {
+    let certs = Certs::new("https");
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let acceptor =
+            TlsAcceptor::from_conf("http_tls", &certs.conf("server", "ca", false)).unwrap();
+        // 先占一个空闲端口
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (chan_out_tx, _chan_out_rx) = mpsc::channel(1);
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(http_service::start_service(
+            addr,
+            Some(Arc::new(acceptor)),
+            shutdown_rx,
+            chan_out_tx,
+        ));
+
+        let ca = std::fs::read(certs.path("ca", "pem")).unwrap();
+        let body = tokio::task::spawn_blocking(move || {
+            let ca = reqwest::Certificate::from_pem(&ca).unwrap();
+            let client = reqwest::blocking::Client::builder()
+                .add_root_certificate(ca)
+                .build()
+                .unwrap();
+            let url = format!("https://{}/req/server/all", addr);
+            client.get(url).send().unwrap().text().unwrap()
+        })
+        .await
+        .unwrap();
+        assert_eq!(body, "name: s1, host: s1.xxx.com:8081");
+
+        let _ = shutdown_tx.send(());
+        let _ = server.await;
+    });
+}
@@ -0,0 +1,195 @@
+use futures_util::{SinkExt, StreamExt};
+use net::tcp::outbound::{self, OutboundPolicy};
+use net::tcp::tcp_service;
+use net::ws::{ws_service, WsStream};
+use net::{ConnOptions, ConnReader, ConnWriter, ProtoType, ServiceType};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use warp::Filter;
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// 起一个 websocket 服务, 返回服务端升级后的连接和客户端
+async fn connect() -> (WsStream, Client) {
+    let (tx, mut rx) = mpsc::channel(1);
+    let route = warp::path("ws")
+        .and(warp::ws())
+        .map(move |ws: warp::ws::Ws| {
+            let tx = tx.clone();
+            ws.on_upgrade(move |socket| async move {
+                let _ = tx.send(WsStream::new(socket)).await;
+            })
+        });
+    let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let url = format!("ws://{}/ws", addr);
+    let (client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    (rx.recv().await.unwrap(), client)
+}
+
+fn new_reader(stream: WsStream) -> ConnReader {
+    let (event_tx, _event_rx) = mpsc::channel(10);
+    let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+    let addr = "127.0.0.1:1".parse().ok();
+    let mut reader = ConnReader::with_stream(
+        1,
+        Box::new(stream),
+        addr,
+        event_tx,
+        Arc::new(Semaphore::new(1)),
+        shutdown_tx,
+    );
+    reader.set_service_type(ServiceType::Tcp);
+    reader
+}
+
+// 协议头 + 协议包, 和 tcp 上的帧一样
+fn frame(pto: ProtoType) -> Vec<u8> {
+    let (proto_id, _) = pto.inner_info();
+    let body = net::allptos::serialize(pto).unwrap();
+    let header = proto_id as u64 | (body.len() as u64) << 32;
+    [header.to_le_bytes().as_slice(), &body].concat()
+}
+
+fn s_login(acc: &str) -> ProtoType {
+    ProtoType::s_login(proto::s_login::s_login {
+        acc: acc.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn testwsread() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let (stream, mut client) = connect().await;
+        let mut reader = new_reader(stream);
+
+        // 一条消息里有两个帧, 第三个帧分在两条消息里
+        let ping = frame(ProtoType::s_ping(proto::s_ping::s_ping { seq: 7 }));
+        let both = [frame(s_login("player")), ping.clone()].concat();
+        client.send(Message::binary(both)).await.unwrap();
+        let (head, tail) = ping.split_at(5);
+        client.send(Message::binary(head)).await.unwrap();
+        client.send(Message::binary(tail)).await.unwrap();
+
+        match reader.read_frame("testws.log").await.unwrap() {
+            Some((_, _, ProtoType::s_login(pto))) => assert_eq!(pto.acc, "player"),
+            other => panic!("expect s_login, got {:?}", other),
+        }
+        for _ in 0..2 {
+            match reader.read_frame("testws.log").await.unwrap() {
+                Some((_, _, ProtoType::s_ping(pto))) => assert_eq!(pto.seq, 7),
+                other => panic!("expect s_ping, got {:?}", other),
+            }
+        }
+
+        // 客户端关闭
+        client.close(None).await.unwrap();
+        assert!(reader.read_frame("testws.log").await.unwrap().is_none());
+
+        // 不接受文本消息
+        let (stream, mut client) = connect().await;
+        let mut reader = new_reader(stream);
+        client.send(Message::text("hello")).await.unwrap();
+        assert!(reader.read_frame("testws.log").await.is_err());
+    });
+}
+
+#[test]
+fn testwswrite() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let (stream, mut client) = connect().await;
+        let (_proto_tx, proto_rx) = outbound::channel(Arc::new(OutboundPolicy::new(1)));
+        let (_, write_half) = io::split(stream);
+        let mut writer = ConnWriter::with_stream(1, Box::new(write_half), proto_rx);
+
+        // 每个协议包是一条二进制消息
+        for acc in ["a", "b"] {
+            let pto = s_login(acc);
+            let expect = frame(s_login(acc));
+            let (proto_id, _) = pto.inner_info();
+            let buf = net::allptos::serialize(pto).unwrap();
+            writer.write_frame(proto_id, &buf).await.unwrap();
+            match client.next().await.unwrap().unwrap() {
+                Message::Binary(data) => assert_eq!(data, expect),
+                other => panic!("expect binary message, got {:?}", other),
+            }
+        }
+    });
+}
+
+// 空闲的本地端口
+async fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+// tcp 和 websocket 服务传同一个 limit_connections 时, 两种传输的连接合计不超过上限
+#[test]
+fn testwssharedlimit() {
+    // llog 按当前目录读取 conf/conf.toml, 所以在临时目录里运行
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    let dir = std::env::temp_dir().join(format!("testws_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("conf")).unwrap();
+    std::fs::copy(root.join("conf/conf.toml"), dir.join("conf/conf.toml")).unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let limit = Arc::new(Semaphore::new(2));
+        let opts = ConnOptions {
+            limit_connections: Some(limit.clone()),
+            ..Default::default()
+        };
+        let (chan_out_tx, mut chan_out_rx) = mpsc::channel(10);
+        let (out_sender, _out_rx) = mpsc::channel(10);
+        let (tcp_addr, ws_addr) = (free_addr().await, free_addr().await);
+        let (addr, tcp_opts) = (tcp_addr.clone(), opts.clone());
+        let (tx, sender) = (chan_out_tx.clone(), out_sender.clone());
+        tokio::spawn(async move {
+            let shutdown = std::future::pending::<()>();
+            tcp_service::start_service(
+                ServiceType::Tcp,
+                tcp_opts,
+                "testws.log",
+                &addr,
+                shutdown,
+                tx,
+                sender,
+            )
+            .await;
+        });
+        let addr = ws_addr.clone();
+        tokio::spawn(async move {
+            let shutdown = std::future::pending::<()>();
+            ws_service::start_service(opts, "testws.log", &addr, shutdown, chan_out_tx, out_sender)
+                .await;
+        });
+        time::sleep(Duration::from_millis(100)).await;
+
+        let _tcp_client = TcpStream::connect(&tcp_addr).await.unwrap();
+        let url = format!("ws://{}/ws", ws_addr);
+        let (_ws_client, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        for _ in 0..2 {
+            assert!(chan_out_rx.recv().await.is_some());
+        }
+        assert_eq!(limit.available_permits(), 0);
+
+        // 已经达到上限, 新的 websocket 连接等不到升级
+        let res = time::timeout(
+            Duration::from_millis(300),
+            tokio_tungstenite::connect_async(&url),
+        )
+        .await;
+        assert!(res.is_err());
+    });
+}
@@ -0,0 +1,93 @@
+// allptos::serialize 的性能测试: cargo bench -p proto --bench serialize
+// 没有引入 criterion, 每个用例先预热, 再取几轮里最快的一轮的平均耗时.
+use proto::allptos::{self, ProtoType};
+use proto::{c_equip_bag, c_item_bag, equip_info, item_info, this_is_test};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 5;
+const ROUND_TIME: Duration = Duration::from_millis(300);
+
+fn test_info(i: usize) -> this_is_test::this_is_test {
+    this_is_test::this_is_test {
+        uid: 1_000_000_007 * i as u64,
+        is_equip: i.is_multiple_of(2),
+        slv: (0..8).map(|v| v * 100).collect(),
+        equiped: vec![true, false, true],
+        attr1: 1.5,
+        attr3: vec![0.25; 4],
+        attr4: 3.75,
+        attr5: vec![2.5; 4],
+        name: format!("test_{}", i),
+        tags: vec!["tag_a".to_owned(), "tag_b".to_owned()],
+        this_is_test: "nested".to_owned(),
+    }
+}
+
+fn equip(i: usize) -> equip_info::equip_info {
+    equip_info::equip_info {
+        uid: 9_000_000_000 + i as u64,
+        is_equip: true,
+        slv: (0..16).collect(),
+        equiped: vec![true; 4],
+        attr1: 10.5,
+        attr3: vec![1.0; 8],
+        attr4: 99.5,
+        attr5: vec![2.0; 8],
+        name: format!("equip_{}", i),
+        tags: vec!["sword".to_owned(), "epic".to_owned()],
+        this_is_test_m: (0..4).map(test_info).collect(),
+        this_is_test_s: test_info(i),
+    }
+}
+
+// 物品背包: 200 个小的 item_info
+fn item_bag() -> ProtoType {
+    ProtoType::c_item_bag(c_item_bag::c_item_bag {
+        bagtype: 2,
+        uid: 10001,
+        baginfo: (0..200)
+            .map(|i| item_info::item_info {
+                uid: 1_000_000 + i,
+                id: 3000 + i as u32,
+                stack: (i % 999) as i32,
+            })
+            .collect(),
+    })
+}
+
+// 装备背包: 每件装备有 5 个嵌套的 this_is_test, 嵌套 3 层
+fn equip_bag() -> ProtoType {
+    ProtoType::c_equip_bag(c_equip_bag::c_equip_bag {
+        bagtype: 1,
+        uid: 10001,
+        equiped: (0..5).map(equip).collect(),
+        baginfo: (0..100).map(equip).collect(),
+    })
+}
+
+fn bench(name: &str, pto: ProtoType) {
+    let len = allptos::serialize(&pto).unwrap().len();
+    let mut best = f64::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let mut iters = 0u32;
+        while start.elapsed() < ROUND_TIME {
+            black_box(allptos::serialize(black_box(&pto)).unwrap());
+            iters += 1;
+        }
+        best = best.min(start.elapsed().as_nanos() as f64 / iters as f64);
+    }
+    println!(
+        "{:<12} {:>8} bytes {:>12.0} ns/iter {:>8.1} MB/s",
+        name,
+        len,
+        best,
+        len as f64 * 1000.0 / best
+    );
+}
+
+fn main() {
+    bench("c_item_bag", item_bag());
+    bench("c_equip_bag", equip_bag());
+}
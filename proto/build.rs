@@ -0,0 +1,34 @@
+use conf::conf::Conf;
+use std::env;
+use std::path::PathBuf;
+
+// 编译时根据 ptosrc 生成协议代码到 OUT_DIR, 协议配置读取工作区的 conf/conf.toml.
+// 这里不会修改协议id清单, 新协议的 id 需要用 cargo run -p protogen 写入清单后提交.
+fn main() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let root = manifest_dir.parent().unwrap();
+    let conf_file = root.join("conf/conf.toml");
+    let sysconf = Conf::from_file(&conf_file);
+    let mut config = protogen::Config::from_conf(&sysconf, root);
+    config.out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    config.serde = env::var_os("CARGO_FEATURE_SERDE").is_some();
+    config.protobuf = env::var_os("CARGO_FEATURE_PROTOBUF").is_some();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", conf_file.display());
+    println!("cargo:rerun-if-changed={}", config.src_dir.display());
+    println!("cargo:rerun-if-changed={}", config.id_manifest.display());
+
+    let report = match protogen::generate(&config) {
+        Ok(report) => report,
+        Err(err) => panic!("generate protocols failed:\n{}", err),
+    };
+    for (name, id) in &report.appended {
+        println!(
+            "cargo:warning=protocol {} = {} is not in {}, run `cargo run -p protogen` to update it",
+            name,
+            id,
+            config.id_manifest.display()
+        );
+    }
+}
@@ -0,0 +1,28 @@
+// 生成客户端代码(protogen ts/csharp)的测试向量:
+// cargo run -p proto --example vectors > vectors.txt
+// 每行 "协议名 = 消息头和协议包的十六进制", 每个协议有一个默认值和一个 util::sample_proto 的向量.
+use proto::allptos;
+use std::process;
+
+fn frame_hex(id: u32, body: &[u8]) -> String {
+    let mut frame = Vec::with_capacity(8 + body.len());
+    frame.extend_from_slice(&id.to_le_bytes());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(body);
+    frame.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn main() {
+    if cfg!(feature = "protobuf") {
+        eprintln!("client codecs use the default encoding, build without the protobuf feature.");
+        process::exit(1);
+    }
+    for desc in allptos::DESCRIPTORS.iter().filter(|d| d.id != 0) {
+        let default = allptos::new_by_id(desc.id).unwrap();
+        let sample = proto::util::sample_proto(desc.id, 2).unwrap();
+        for pto in [default, sample] {
+            let body = allptos::serialize(pto).expect("serialize failed");
+            println!("{} = {}", desc.name, frame_hex(desc.id, &body));
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! 协议结构的运行时描述(反射)
+//! protogen 给每个 message 生成一个静态的 DESCRIPTOR, 并实现 Reflect, 可以按字段名读写字段.
+//! GM 控制台, 抓包工具, 机器人等可以用它按名字查看和构造协议, 不需要给每个协议手写代码.
+use crate::errors::{Error, Result};
+use std::convert::TryFrom;
+
+/// 协议的发送方向, 由协议名前缀决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// s_xxx: 客户端发给服务器
+    ToServer,
+    /// c_xxx: 服务器发给客户端
+    ToClient,
+    /// 其他协议(比如 db_xxx): 服务器之间的 rpc
+    Rpc,
+    /// datatype, 不能单独发送
+    None,
+}
+
+/// 协议使用的连接, 由协议名前缀决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// s_xxx, c_xxx: 客户端与网关之间的连接
+    Client,
+    /// db_xxx: 与 db 服务之间的 rpc 连接
+    Db,
+    /// 其他服务器之间的 rpc 连接
+    Rpc,
+    /// datatype, 不能单独发送
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    Bool,
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+    String,
+    /// 嵌套的 datatype
+    Message(&'static MessageDescriptor),
+}
+
+/// 协议源文件里声明的字段约束, 读取协议时检查. 数值范围统一用 f64 表示
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// string 的最大字节数
+    pub max_len: Option<usize>,
+    /// repeated 字段的最大元素个数
+    pub max_count: Option<usize>,
+}
+
+impl Limits {
+    pub const NONE: Limits = Limits {
+        min: None,
+        max: None,
+        max_len: None,
+        max_count: None,
+    };
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FieldDescriptor {
+    pub name: &'static str,
+    pub tag: u32,
+    pub ty: FieldType,
+    pub repeated: bool,
+    pub limits: Limits,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MessageDescriptor {
+    pub name: &'static str,
+    /// 协议id, datatype 为 0
+    pub id: u32,
+    pub direction: Direction,
+    pub channel: Channel,
+    pub fields: &'static [FieldDescriptor],
+}
+
+impl MessageDescriptor {
+    pub fn field(&self, name: &str) -> Option<&'static FieldDescriptor> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    pub fn field_by_tag(&self, tag: u32) -> Option<&'static FieldDescriptor> {
+        self.fields.iter().find(|f| f.tag == tag)
+    }
+}
+
+/// 字段的动态值
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    String(String),
+    /// repeated 字段
+    List(Vec<Value>),
+    /// 嵌套的 datatype, 按字段定义的顺序排列
+    Message(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "bool",
+            Value::I8(_) => "i8",
+            Value::U8(_) => "u8",
+            Value::I16(_) => "i16",
+            Value::U16(_) => "u16",
+            Value::I32(_) => "i32",
+            Value::U32(_) => "u32",
+            Value::I64(_) => "i64",
+            Value::U64(_) => "u64",
+            Value::F32(_) => "f32",
+            Value::F64(_) => "f64",
+            Value::String(_) => "String",
+            Value::List(_) => "list",
+            Value::Message(_) => "message",
+        }
+    }
+
+    // 所有整数类型都可以互相转换, 只要值在范围内
+    fn as_i128(&self) -> Option<i128> {
+        match *self {
+            Value::I8(v) => Some(v as i128),
+            Value::U8(v) => Some(v as i128),
+            Value::I16(v) => Some(v as i128),
+            Value::U16(v) => Some(v as i128),
+            Value::I32(v) => Some(v as i128),
+            Value::U32(v) => Some(v as i128),
+            Value::I64(v) => Some(v as i128),
+            Value::U64(v) => Some(v as i128),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::F32(v) => Some(v as f64),
+            Value::F64(v) => Some(v),
+            _ => self.as_i128().map(|v| v as f64),
+        }
+    }
+}
+
+fn type_error(expect: &str, value: &Value) -> Error {
+    Error::Reflect(format!(
+        "expect {}, found {}: {:?}",
+        expect,
+        value.type_name(),
+        value
+    ))
+}
+
+/// 可以与 Value 互相转换的字段类型
+pub trait FieldValue: Sized {
+    fn to_value(&self) -> Value;
+    fn from_value(value: Value) -> Result<Self>;
+}
+
+macro_rules! impl_int_field_value {
+    ($($t:ty => $v:ident),*) => {
+        $(
+            impl FieldValue for $t {
+                fn to_value(&self) -> Value {
+                    Value::$v(*self)
+                }
+                fn from_value(value: Value) -> Result<Self> {
+                    value
+                        .as_i128()
+                        .and_then(|v| <$t>::try_from(v).ok())
+                        .ok_or_else(|| type_error(stringify!($t), &value))
+                }
+            }
+        )*
+    };
+}
+
+impl_int_field_value!(i8 => I8, u8 => U8, i16 => I16, u16 => U16, i32 => I32, u32 => U32, i64 => I64, u64 => U64);
+
+impl FieldValue for f32 {
+    fn to_value(&self) -> Value {
+        Value::F32(*self)
+    }
+    fn from_value(value: Value) -> Result<Self> {
+        value
+            .as_f64()
+            .map(|v| v as f32)
+            .ok_or_else(|| type_error("f32", &value))
+    }
+}
+
+impl FieldValue for f64 {
+    fn to_value(&self) -> Value {
+        Value::F64(*self)
+    }
+    fn from_value(value: Value) -> Result<Self> {
+        value.as_f64().ok_or_else(|| type_error("f64", &value))
+    }
+}
+
+impl FieldValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Bool(*self)
+    }
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Bool(v) => Ok(v),
+            v => Err(type_error("bool", &v)),
+        }
+    }
+}
+
+impl FieldValue for String {
+    fn to_value(&self) -> Value {
+        Value::String(self.clone())
+    }
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::String(v) => Ok(v),
+            v => Err(type_error("String", &v)),
+        }
+    }
+}
+
+impl<T: FieldValue> FieldValue for Vec<T> {
+    fn to_value(&self) -> Value {
+        Value::List(self.iter().map(|v| v.to_value()).collect())
+    }
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::List(vs) => vs.into_iter().map(T::from_value).collect(),
+            v => Err(type_error("list", &v)),
+        }
+    }
+}
+
+/// 按字段名读写 message, 由 protogen 生成实现
+pub trait Reflect {
+    fn descriptor(&self) -> &'static MessageDescriptor;
+    fn get_field(&self, name: &str) -> Option<Value>;
+    fn set_field(&mut self, name: &str, value: Value) -> Result<()>;
+}
+
+/// 把 message 转换成 Value::Message, 生成的 FieldValue 实现使用
+pub fn message_to_value<T: Reflect>(msg: &T) -> Value {
+    let fields = msg
+        .descriptor()
+        .fields
+        .iter()
+        .filter_map(|f| Some((f.name.to_owned(), msg.get_field(f.name)?)))
+        .collect();
+    Value::Message(fields)
+}
+
+/// 用 Value::Message 构造 message, 没有给出的字段使用默认值
+pub fn message_from_value<T: Reflect + Default>(value: Value) -> Result<T> {
+    let mut msg = T::default();
+    match value {
+        Value::Message(fields) => {
+            for (name, v) in fields {
+                msg.set_field(&name, v)?;
+            }
+            Ok(msg)
+        }
+        v => Err(type_error(msg.descriptor().name, &v)),
+    }
+}
+
+/// set_field 找不到字段时的错误
+pub fn no_such_field(msg: &str, name: &str) -> Error {
+    Error::Reflect(format!("{} has no field '{}'", msg, name))
+}
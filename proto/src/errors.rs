@@ -19,17 +19,23 @@ pub enum Error {
     UnexpectedEndOfBuffer,
     /// The supplied output buffer is not large enough to serialize the message
     OutputBufferTooSmall(usize, usize, usize),
+    /// Field access by name failed (no such field or wrong value type)
+    Reflect(String),
+    /// Json encoding or decoding failed (`serde` feature)
+    Json(String),
+    /// A field violates a constraint declared in the protocol source (max_len, min, max, max_count)
+    Validation(String),
 }
 
 /// A wrapper for `Result<T, Error>`
 pub type Result<T> = ::core::result::Result<T, Error>;
 
-impl Into<std::io::Error> for Error {
-    fn into(self) -> ::std::io::Error {
-        match self {
+impl From<Error> for std::io::Error {
+    fn from(val: Error) -> Self {
+        match val {
             Error::Io(x) => x,
             Error::Utf8(x) => std::io::Error::new(std::io::ErrorKind::InvalidData, x),
-            x => std::io::Error::new(std::io::ErrorKind::Other, x),
+            x => std::io::Error::other(x),
         }
     }
 }
@@ -73,6 +79,9 @@ impl core::fmt::Display for Error {
                 "Output buffer too small: cursor: {}, add: {}, cap: {}",
                 cursor, add, cap
             ),
+            Error::Reflect(msg) => write!(f, "Reflect error: {}", msg),
+            Error::Json(msg) => write!(f, "Json error: {}", msg),
+            Error::Validation(msg) => write!(f, "Validation error: {}", msg),
         }
     }
 }
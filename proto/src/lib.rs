@@ -1,10 +1,25 @@
+pub mod descriptor;
 pub mod errors;
-pub mod ptoout;
 pub mod reader;
 pub mod sizeofs;
 pub mod util;
 pub mod writer;
 
+// 由 build.rs 根据 ptosrc 生成
+#[allow(
+    non_snake_case,
+    non_upper_case_globals,
+    non_camel_case_types,
+    unused_imports,
+    clippy::all
+)]
+pub mod ptoout {
+    include!(concat!(env!("OUT_DIR"), "/ptoout.rs"));
+}
+
+pub use crate::descriptor::{
+    Channel, Direction, FieldType, FieldValue, Limits, MessageDescriptor, Reflect, Value,
+};
 pub use crate::errors::{Error, Result};
 pub use crate::ptoout::*;
 pub use crate::reader::{BytesReader, MsgRead};
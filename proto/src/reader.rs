@@ -237,8 +237,47 @@ impl BytesReader {
         Ok(str.to_owned())
     }
 
-    pub fn read_unknow(&mut self, _bytes: &[u8], tag: u64) -> Result<()> {
-        Err(Error::UnknownWireType((tag & 0x7) as u8))
+    // 默认编码: 字段按 field number 升序写, 不认识的字段和它后面的字段都是新版本加的, 跳过消息剩下的部分.
+    // repeated 字段前面是元素个数, 不能按 wire type 跳过单个字段
+    pub fn read_unknow(&mut self, _bytes: &[u8], _tag: u64) -> Result<()> {
+        self.start = self.end;
+        Ok(())
+    }
+
+    // protobuf 兼容模式: 按 wire type 跳过不认识的字段
+    pub fn skip_field(&mut self, bytes: &[u8], tag: u64) -> Result<()> {
+        let size = match tag & 0x7 {
+            0 => {
+                self.read_u64(bytes)?;
+                0
+            }
+            1 => 8,
+            2 => self.get_len(bytes)?,
+            3 | 4 => return Err(Error::Deprecated("group")),
+            5 => 4,
+            t => return Err(Error::UnknownWireType(t as u8)),
+        };
+        if self.start + size > self.end {
+            return Err(Error::UnexpectedEndOfBuffer);
+        }
+        self.start += size;
+        Ok(())
+    }
+
+    // protobuf 兼容模式: packed repeated 字段, 长度后面是连续的元素
+    pub fn read_packed<T, F>(&mut self, bytes: &[u8], out: &mut Vec<T>, mut read: F) -> Result<()>
+    where
+        F: FnMut(&mut Self, &[u8]) -> Result<T>,
+    {
+        let len = self.get_len(bytes)?;
+        let end = self.start + len;
+        if end > self.end {
+            return Err(Error::UnexpectedEndOfBuffer);
+        }
+        while self.start < end {
+            out.push(read(self, bytes)?);
+        }
+        Ok(())
     }
 }
 
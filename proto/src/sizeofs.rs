@@ -5,6 +5,7 @@
 /// Computes the binary size of the varint encoded u64
 ///
 /// https://developers.google.com/protocol-buffers/docs/encoding
+#[inline]
 pub fn sizeof_varint(v: u64) -> usize {
     match v {
         0x0..=0x7F => 1,
@@ -24,71 +25,85 @@ pub fn sizeof_varint(v: u64) -> usize {
 ///
 /// The total size is the varint encoded length size plus the length itself
 /// https://developers.google.com/protocol-buffers/docs/encoding
+#[inline]
 pub fn sizeof_len(len: usize) -> usize {
     //:TODO: this sizeof_len is just focus the "length" value itself.
     sizeof_varint(len as u64)
 }
 
+#[inline]
 pub fn sizeof_tag(tag: u64) -> usize {
     sizeof_varint(tag)
 }
 
 /// Computes the binary size of the varint encoded u8
+#[inline]
 pub fn sizeof_u8(_: u8) -> usize {
     1
 }
 
 /// Computes the binary size of the varint encoded i8
+#[inline]
 pub fn sizeof_i8(_: i8) -> usize {
     1
 }
 
 /// Computes the binary size of the varint encoded u16
+#[inline]
 pub fn sizeof_u16(v: u16) -> usize {
     sizeof_varint(v as u64)
 }
 
 /// Computes the binary size of the varint encoded i16
+#[inline]
 pub fn sizeof_i16(v: i16) -> usize {
     sizeof_varint(v as u16 as u64)
 }
 
 /// Computes the binary size of the varint encoded u32
+#[inline]
 pub fn sizeof_u32(v: u32) -> usize {
     sizeof_varint(v as u64)
 }
 
 /// Computes the binary size of the varint encoded i32
+#[inline]
 pub fn sizeof_i32(v: i32) -> usize {
     sizeof_varint(v as u32 as u64)
 }
 
 /// Computes the binary size of the varint encoded u64
+#[inline]
 pub fn sizeof_u64(v: u64) -> usize {
     sizeof_varint(v)
 }
 
 /// Computes the binary size of the varint encoded i64
+#[inline]
 pub fn sizeof_i64(v: i64) -> usize {
     sizeof_varint(v as u64)
 }
 
 /// Computes the binary size of the varint encoded bool (always = 1)
+#[inline]
 pub fn sizeof_bool(_: bool) -> usize {
     1
 }
 
 /// Computes the binary size of the varint encoded f32
+#[inline]
 pub fn sizeof_f32(_v: f32) -> usize {
     4
 }
 
 /// Computes the binary size of the varint encoded f64
+#[inline]
 pub fn sizeof_f64(_v: f64) -> usize {
     8
 }
 
 /// Computes the binary size of the varint encoded string
+#[inline]
 pub fn sizeof_string(v: &str) -> usize {
     let len = v.len();
     len + sizeof_varint(len as u64)
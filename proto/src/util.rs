@@ -1,6 +1,11 @@
 extern crate rand;
 
+use crate::allptos::{self, ProtoType};
+use crate::descriptor::{FieldDescriptor, FieldType, Limits, Value};
+use crate::errors::{Error, Result};
 use rand::Rng;
+use std::cmp::Ordering;
+use std::fmt::Display;
 
 pub fn default_random_value(literal: &str) -> String {
     let mut rng = rand::thread_rng();
@@ -46,3 +51,146 @@ pub fn default_random_value(literal: &str) -> String {
     };
     str
 }
+
+// 随机数组长度
+pub fn random_len() -> usize {
+    rand::thread_rng().gen_range(10..100)
+}
+
+// 把 s 截断到不超过 max_len 字节, 不会截断在多字节字符中间
+pub fn truncate_str(s: &mut String, max_len: usize) {
+    let mut end = max_len.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+}
+
+// 字段约束的检查, 由生成的代码调用. field 是 "结构体名.字段名"
+pub fn check_len(field: &str, len: usize, max_len: usize) -> Result<()> {
+    if len > max_len {
+        return Err(Error::Validation(format!(
+            "{}: length {} exceeds max_len {}",
+            field, len, max_len
+        )));
+    }
+    Ok(())
+}
+
+pub fn check_count(field: &str, count: usize, max_count: usize) -> Result<()> {
+    if count > max_count {
+        return Err(Error::Validation(format!(
+            "{}: count {} exceeds max_count {}",
+            field, count, max_count
+        )));
+    }
+    Ok(())
+}
+
+// NaN 不满足任何范围
+pub fn check_min<T: PartialOrd + Display>(field: &str, v: T, min: T) -> Result<()> {
+    match v.partial_cmp(&min) {
+        Some(Ordering::Less) | None => Err(Error::Validation(format!(
+            "{}: value {} is less than min {}",
+            field, v, min
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub fn check_max<T: PartialOrd + Display>(field: &str, v: T, max: T) -> Result<()> {
+    match v.partial_cmp(&max) {
+        Some(Ordering::Greater) | None => Err(Error::Validation(format!(
+            "{}: value {} exceeds max {}",
+            field, v, max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// 确定的测试值, 覆盖各类型的边界(负数, 多字节 varint, 多字节字符), 用于生成客户端的测试向量
+// 数组取 2 个元素, 嵌套的 datatype 最多 depth 层
+pub fn sample_value(ty: &FieldType, repeated: bool, depth: usize) -> Value {
+    if repeated {
+        let v = sample_value(ty, false, depth);
+        return Value::List(vec![v.clone(), v]);
+    }
+    match *ty {
+        FieldType::Bool => Value::Bool(true),
+        FieldType::I8 => Value::I8(-100),
+        FieldType::U8 => Value::U8(200),
+        FieldType::I16 => Value::I16(-30000),
+        FieldType::U16 => Value::U16(60000),
+        FieldType::I32 => Value::I32(-2_000_000_000),
+        FieldType::U32 => Value::U32(4_000_000_000),
+        FieldType::I64 => Value::I64(i64::MIN + 1),
+        FieldType::U64 => Value::U64(u64::MAX - 1),
+        FieldType::F32 => Value::F32(-1.25),
+        FieldType::F64 => Value::F64(3.5e100),
+        FieldType::String => Value::String("协议 ptos ✓".to_owned()),
+        FieldType::Message(desc) => {
+            let fields = if depth == 0 {
+                Vec::new()
+            } else {
+                desc.fields
+                    .iter()
+                    .map(|f| (f.name.to_owned(), sample_field(f, depth - 1)))
+                    .collect()
+            };
+            Value::Message(fields)
+        }
+    }
+}
+
+// 满足字段约束的 sample_value
+pub fn sample_field(f: &FieldDescriptor, depth: usize) -> Value {
+    fit_limits(sample_value(&f.ty, f.repeated, depth), &f.limits)
+}
+
+// 把值调整到约束范围内: 数值取最近的边界, string 和数组截断
+fn fit_limits(v: Value, limits: &Limits) -> Value {
+    let clamp = |x: f64| {
+        let x = limits.min.map_or(x, |min| x.max(min));
+        limits.max.map_or(x, |max| x.min(max))
+    };
+    match v {
+        Value::List(mut list) => {
+            if let Some(max_count) = limits.max_count {
+                list.truncate(max_count);
+            }
+            Value::List(list.into_iter().map(|v| fit_limits(v, limits)).collect())
+        }
+        Value::String(mut s) => {
+            if let Some(max_len) = limits.max_len {
+                truncate_str(&mut s, max_len);
+            }
+            Value::String(s)
+        }
+        Value::I8(x) => Value::I8(clamp(x as f64) as i8),
+        Value::U8(x) => Value::U8(clamp(x as f64) as u8),
+        Value::I16(x) => Value::I16(clamp(x as f64) as i16),
+        Value::U16(x) => Value::U16(clamp(x as f64) as u16),
+        Value::I32(x) => Value::I32(clamp(x as f64) as i32),
+        Value::U32(x) => Value::U32(clamp(x as f64) as u32),
+        Value::I64(x) if limits.min.is_some() || limits.max.is_some() => {
+            Value::I64(clamp(x as f64) as i64)
+        }
+        Value::U64(x) if limits.min.is_some() || limits.max.is_some() => {
+            Value::U64(clamp(x as f64) as u64)
+        }
+        Value::F32(x) => Value::F32(clamp(x as f64) as f32),
+        Value::F64(x) => Value::F64(clamp(x)),
+        v => v,
+    }
+}
+
+// 所有字段都是 sample_field 的协议
+pub fn sample_proto(proto_id: u32, depth: usize) -> Option<ProtoType> {
+    let desc = allptos::descriptor_by_id(proto_id)?;
+    let mut pto = allptos::new_by_id(proto_id)?;
+    for f in desc.fields {
+        pto.set_field(f.name, sample_field(f, depth))
+            .expect("sample value mismatch");
+    }
+    Some(pto)
+}
@@ -2,6 +2,7 @@
 //byte order is LittleEndian by default.
 
 use crate::errors::{Error, Result};
+use crate::sizeofs;
 
 #[derive(Debug)]
 pub struct BytesWriter<'a> {
@@ -18,37 +19,62 @@ impl<'a> BytesWriter<'a> {
         self.cursor
     }
 
-    pub fn write_u8(&mut self, val: u8) -> Result<()> {
-        let total = self.cursor + 1;
-        if total > self.buf.capacity() {
+    // 写入前检查容量, 缓冲区的容量就是 size() 计算出的大小
+    #[inline]
+    fn reserve(&self, len: usize) -> Result<()> {
+        if self.cursor + len > self.buf.capacity() {
             return Err(Error::OutputBufferTooSmall(
                 self.cursor,
-                1,
+                len,
                 self.buf.capacity(),
             ));
         }
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.reserve(bytes.len())?;
+        self.buf.extend_from_slice(bytes);
+        self.cursor += bytes.len();
+        Ok(())
+    }
+
+    #[inline]
+    pub fn write_u8(&mut self, val: u8) -> Result<()> {
+        self.reserve(1)?;
         self.buf.push(val);
-        self.cursor = total;
+        self.cursor += 1;
         Ok(())
     }
 
+    // 先算出字节数检查容量, 再逐字节写入, 避免小块的 memcpy
+    #[inline]
     pub fn write_vint(&mut self, val: u64, maxbytes: i32) -> Result<()> {
+        // tag 和小的整数只有 1 个字节
+        if val <= 0x7f {
+            return self.write_u8(val as u8);
+        }
+        let len = sizeofs::sizeof_varint(val);
+        assert!(len as i32 <= maxbytes + 1);
+        self.reserve(len)?;
         let mut val = val;
-        let mut maxbytes = maxbytes;
         while val > 0x7f {
-            self.write_u8(((val as u8) & 0x7f) | 0x80)?;
+            self.buf.push(((val as u8) & 0x7f) | 0x80);
             val >>= 7;
-            maxbytes -= 1;
         }
-        assert!(maxbytes >= 0);
-        self.write_u8(val as u8)
+        self.buf.push(val as u8);
+        self.cursor += len;
+        Ok(())
     }
 
     // (field_number << 3) | wire_type
+    #[inline]
     pub fn write_tag(&mut self, tag: u64) -> Result<()> {
         self.write_u64(tag)
     }
 
+    #[inline]
     pub fn write_len(&mut self, val: usize) -> Result<()> {
         if val >= u32::MAX as usize {
             return Err(Error::Message("len beyond max".to_owned()));
@@ -56,150 +82,163 @@ impl<'a> BytesWriter<'a> {
         self.write_vint(val as u32 as u64, 4)
     }
 
+    #[inline]
     pub fn write_i8(&mut self, val: i8) -> Result<()> {
         self.write_u8(val as u8)
     }
 
+    #[inline]
     pub fn write_u16(&mut self, val: u16) -> Result<()> {
         self.write_vint(val as u64, 2)
     }
 
+    #[inline]
     pub fn write_i16(&mut self, val: i16) -> Result<()> {
         self.write_vint(val as u16 as u64, 2) //不能直接转换成u64,因为是负数时,as 转换会把符号位转换成高位的1
     }
 
+    #[inline]
     pub fn write_u32(&mut self, val: u32) -> Result<()> {
         self.write_vint(val as u64, 4)
     }
 
+    #[inline]
     pub fn write_i32(&mut self, val: i32) -> Result<()> {
         self.write_vint(val as u32 as u64, 4)
     }
 
+    #[inline]
     pub fn write_u64(&mut self, val: u64) -> Result<()> {
         self.write_vint(val, 9)
     }
 
+    #[inline]
     pub fn write_i64(&mut self, val: i64) -> Result<()> {
         self.write_vint(val as u64, 9)
     }
 
+    #[inline]
     pub fn write_bool(&mut self, val: bool) -> Result<()> {
         let val = if val { 1 } else { 0 };
         self.write_u8(val)
     }
 
     //固定 4 bytes
+    #[inline]
     pub fn write_f32(&mut self, val: f32) -> Result<()> {
-        let lebytes = f32::to_le_bytes(val);
-        let len = lebytes.len();
-        assert!(len == 4);
-        let total = self.cursor + len;
-        if total > self.buf.capacity() {
-            return Err(Error::OutputBufferTooSmall(
-                self.cursor,
-                len,
-                self.buf.capacity(),
-            ));
-        }
-        //self.buf[self.cursor..total].copy_from_slice(&lebytes);
-        for v in lebytes {
-            self.buf.push(v);
-        }
-        self.cursor = total;
-        Ok(())
+        self.write_bytes(&val.to_le_bytes())
     }
 
     //固定 8 bytes
+    #[inline]
     pub fn write_f64(&mut self, val: f64) -> Result<()> {
-        let lebytes = f64::to_le_bytes(val);
-        let len = lebytes.len();
-        assert!(len == 8);
-        let total = self.cursor + len;
-        if total > self.buf.capacity() {
-            return Err(Error::OutputBufferTooSmall(
-                self.cursor,
-                len,
-                self.buf.capacity(),
-            ));
-        }
-        //self.buf[self.cursor..total].copy_from_slice(&lebytes);
-        for v in lebytes {
-            self.buf.push(v);
-        }
-        self.cursor = total;
-        Ok(())
+        self.write_bytes(&val.to_le_bytes())
     }
 
+    #[inline]
     pub fn write_string(&mut self, val: &str) -> Result<()> {
-        let len = val.len();
-        self.write_len(len)?;
-        let total = self.cursor + len;
-        if total > self.buf.capacity() {
-            return Err(Error::OutputBufferTooSmall(
-                self.cursor,
-                len,
-                self.buf.capacity(),
-            ));
+        self.write_len(val.len())?;
+        self.write_bytes(val.as_bytes())
+    }
+
+    // 嵌套的 datatype: 先写入内容再回填长度, 不需要为了长度前缀再调用一次 size().
+    // 长度先占 1 个字节, 内容超过 127 字节时把内容往后移, 给长度的 varint 腾出位置.
+    #[inline]
+    pub fn write_message<M: MsgWrite + ?Sized>(&mut self, msg: &M) -> Result<()> {
+        let start = self.buf.len();
+        self.write_u8(0)?;
+        msg.write(self)?;
+        let len = self.buf.len() - start - 1;
+        if len >= u32::MAX as usize {
+            return Err(Error::Message("len beyond max".to_owned()));
         }
-        //self.buf[self.cursor..total].copy_from_slice(&lebytes);
-        for v in val.as_bytes() {
-            self.buf.push(*v);
+        let mut bytes = [0u8; 10];
+        let n = encode_vint(len as u64, &mut bytes);
+        if n > 1 {
+            self.reserve(n - 1)?;
+            self.buf
+                .splice(start..start + 1, bytes[..n].iter().copied());
+            self.cursor += n - 1;
+        } else {
+            self.buf[start] = bytes[0];
         }
-        self.cursor = total;
         Ok(())
     }
 
+    #[inline]
     pub fn write_u8_with_tag(&mut self, tag: u64, val: u8) -> Result<()> {
         self.write_tag(tag)?;
         self.write_u8(val)
     }
+    #[inline]
     pub fn write_i8_with_tag(&mut self, tag: u64, val: i8) -> Result<()> {
         self.write_tag(tag)?;
         self.write_i8(val)
     }
+    #[inline]
     pub fn write_u16_with_tag(&mut self, tag: u64, val: u16) -> Result<()> {
         self.write_tag(tag)?;
         self.write_u16(val)
     }
+    #[inline]
     pub fn write_i16_with_tag(&mut self, tag: u64, val: i16) -> Result<()> {
         self.write_tag(tag)?;
         self.write_i16(val)
     }
+    #[inline]
     pub fn write_u32_with_tag(&mut self, tag: u64, val: u32) -> Result<()> {
         self.write_tag(tag)?;
         self.write_u32(val)
     }
+    #[inline]
     pub fn write_i32_with_tag(&mut self, tag: u64, val: i32) -> Result<()> {
         self.write_tag(tag)?;
         self.write_i32(val)
     }
+    #[inline]
     pub fn write_u64_with_tag(&mut self, tag: u64, val: u64) -> Result<()> {
         self.write_tag(tag)?;
         self.write_u64(val)
     }
+    #[inline]
     pub fn write_i64_with_tag(&mut self, tag: u64, val: i64) -> Result<()> {
         self.write_tag(tag)?;
         self.write_i64(val)
     }
+    #[inline]
     pub fn write_bool_with_tag(&mut self, tag: u64, val: bool) -> Result<()> {
         self.write_tag(tag)?;
         self.write_bool(val)
     }
+    #[inline]
     pub fn write_f32_with_tag(&mut self, tag: u64, val: f32) -> Result<()> {
         self.write_tag(tag)?;
         self.write_f32(val)
     }
+    #[inline]
     pub fn write_f64_with_tag(&mut self, tag: u64, val: f64) -> Result<()> {
         self.write_tag(tag)?;
         self.write_f64(val)
     }
+    #[inline]
     pub fn write_string_with_tag(&mut self, tag: u64, val: &str) -> Result<()> {
         self.write_tag(tag)?;
         self.write_string(val)
     }
 }
 
+// varint 编码到 bytes, 返回字节数
+fn encode_vint(mut val: u64, bytes: &mut [u8; 10]) -> usize {
+    let mut len = 0;
+    while val > 0x7f {
+        bytes[len] = ((val as u8) & 0x7f) | 0x80;
+        val >>= 7;
+        len += 1;
+    }
+    bytes[len] = val as u8;
+    len + 1
+}
+
 pub trait MsgWrite {
     fn size(&self) -> usize;
     fn write(&self, w: &mut BytesWriter) -> Result<()>;
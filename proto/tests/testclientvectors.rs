@@ -0,0 +1,76 @@
+// 用 tsc 编译 protogen ts 生成的代码, 在 node 里用 checkVectors 检查服务器的测试向量(和 examples/vectors 相同).
+// 没有安装 tsc 或 node 时跳过
+#![cfg(not(feature = "protobuf"))]
+
+use conf::conf::Conf;
+use proto::allptos;
+use std::path::Path;
+use std::process::Command;
+
+fn frame_hex(id: u32, body: &[u8]) -> String {
+    let mut frame = Vec::with_capacity(8 + body.len());
+    frame.extend_from_slice(&id.to_le_bytes());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(body);
+    frame.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn vectors() -> String {
+    let mut text = String::new();
+    for desc in allptos::DESCRIPTORS.iter().filter(|d| d.id != 0) {
+        let default = allptos::new_by_id(desc.id).unwrap();
+        let sample = proto::util::sample_proto(desc.id, 2).unwrap();
+        for pto in [default, sample] {
+            let body = allptos::serialize(pto).unwrap();
+            text.push_str(&format!("{} = {}\n", desc.name, frame_hex(desc.id, &body)));
+        }
+    }
+    text
+}
+
+fn installed(cmd: &str) -> bool {
+    Command::new(cmd).arg("--version").output().is_ok()
+}
+
+#[test]
+fn testtsvectors() {
+    if !installed("tsc") || !installed("node") {
+        eprintln!("tsc or node not found, skip testtsvectors");
+        return;
+    }
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    let config = protogen::Config::from_conf(&Conf::from_file(root.join("conf/conf.toml")), root);
+    let dir = std::env::temp_dir().join(format!("testtsvectors_{}", std::process::id()));
+    protogen::export_ts(&config, &dir).unwrap();
+    // 向量直接写在入口文件里, 不需要 node 的类型声明
+    let run = format!(
+        "import {{ checkVectors }} from \"./ptos_test\";\nconst VECTORS = {:?};\nconsole.log(JSON.stringify(checkVectors(VECTORS)));\n",
+        vectors()
+    );
+    std::fs::write(dir.join("run.ts"), run).unwrap();
+
+    let out = dir.join("out");
+    let tsc = Command::new("tsc")
+        .args(["--target", "es2020", "--module", "commonjs", "--outDir"])
+        .arg(&out)
+        .args(["ptos.ts", "ptos_test.ts", "run.ts"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(
+        tsc.status.success(),
+        "tsc failed:\n{}",
+        String::from_utf8_lossy(&tsc.stdout)
+    );
+    let node = Command::new("node")
+        .arg(out.join("run.js"))
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(
+        node.status.success(),
+        "{}",
+        String::from_utf8_lossy(&node.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&node.stdout).trim(), "[]");
+}
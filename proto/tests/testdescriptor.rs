@@ -0,0 +1,75 @@
+use proto::allptos::{self, ProtoType};
+use proto::{Channel, Direction, FieldType, FieldValue, Reflect, Value};
+
+#[test]
+fn testdescriptor() {
+    let desc = allptos::descriptor_by_name("c_item_bag").unwrap();
+    assert_eq!(desc.id, proto::c_item_bag::c_item_bag::id());
+    assert_eq!(desc.direction, Direction::ToClient);
+    let names: Vec<&str> = desc.fields.iter().map(|f| f.name).collect();
+    assert_eq!(names, vec!["bagtype", "uid", "baginfo"]);
+    let baginfo = desc.field("baginfo").unwrap();
+    assert_eq!(baginfo.tag, 3);
+    assert!(baginfo.repeated);
+    assert_eq!(
+        baginfo.ty,
+        FieldType::Message(&proto::item_info::DESCRIPTOR)
+    );
+
+    assert_eq!(
+        allptos::descriptor_by_name("s_login").unwrap().direction,
+        Direction::ToServer
+    );
+    assert_eq!(
+        allptos::descriptor_by_name("db_load_req")
+            .unwrap()
+            .direction,
+        Direction::Rpc
+    );
+    assert_eq!(proto::item_info::DESCRIPTOR.direction, Direction::None);
+    assert_eq!(desc.channel, Channel::Client);
+    assert_eq!(
+        allptos::descriptor_by_name("db_save_req").unwrap().channel,
+        Channel::Db
+    );
+    assert_eq!(proto::item_info::DESCRIPTOR.channel, Channel::None);
+    assert!(allptos::descriptor_by_id(desc.id).unwrap() == desc);
+    assert!(allptos::descriptor_by_name("no_such_pto").is_none());
+}
+
+#[test]
+fn testdynamicfield() {
+    let mut pto = allptos::new_by_name("c_item_bag").unwrap();
+    assert_eq!(pto.descriptor().name, "c_item_bag");
+    pto.set_field("bagtype", Value::I64(2)).unwrap();
+    let item = Value::Message(vec![
+        ("uid".to_owned(), Value::U64(1001)),
+        ("stack".to_owned(), Value::I32(5)),
+    ]);
+    pto.set_field("baginfo", Value::List(vec![item])).unwrap();
+    assert_eq!(pto.get_field("bagtype"), Some(Value::U8(2)));
+
+    let obj = match &pto {
+        ProtoType::c_item_bag(obj) => obj,
+        _ => unreachable!(),
+    };
+    assert_eq!(obj.baginfo[0].uid, 1001);
+    assert_eq!(obj.baginfo[0].stack, 5);
+    assert_eq!(
+        obj.baginfo[0].to_value(),
+        Value::Message(vec![
+            ("uid".to_owned(), Value::U64(1001)),
+            ("id".to_owned(), Value::U32(0)),
+            ("stack".to_owned(), Value::I32(5)),
+        ])
+    );
+
+    // 值超出范围, 类型不对, 或字段不存在
+    assert!(pto.set_field("bagtype", Value::I32(256)).is_err());
+    assert!(pto.set_field("uid", Value::String("1".to_owned())).is_err());
+    assert!(pto.set_field("no_such_field", Value::Bool(true)).is_err());
+    assert!(pto.get_field("no_such_field").is_none());
+
+    let mut brief = proto::s_player_brief::s_player_brief::default();
+    assert!(brief.set_field("x", Value::Bool(true)).is_err());
+}
@@ -0,0 +1,2 @@
+// 每个结构体的读写测试, 由 build.rs 生成
+include!(concat!(env!("OUT_DIR"), "/ptotests.rs"));
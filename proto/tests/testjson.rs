@@ -0,0 +1,41 @@
+// 需要开启 serde feature: cargo test -p proto --features serde
+#![cfg(feature = "serde")]
+use proto::allptos::{self, ProtoType};
+
+#[test]
+fn testjson() {
+    let pto = allptos::from_json("s_login", r#"{"vers":"1.0","acc":"test01"}"#).unwrap();
+    let obj = match &pto {
+        ProtoType::s_login(obj) => obj,
+        _ => unreachable!(),
+    };
+    assert_eq!(obj.vers, "1.0");
+    assert_eq!(obj.acc, "test01");
+    assert_eq!(
+        allptos::to_json(&pto).unwrap(),
+        r#"{"vers":"1.0","acc":"test01"}"#
+    );
+
+    // 没有给出的字段使用默认值, 嵌套的 datatype 和数组
+    let json = r#"{"bagtype":2,"baginfo":[{"uid":1001,"stack":5}]}"#;
+    let pto = allptos::from_json("c_item_bag", json).unwrap();
+    let obj = match &pto {
+        ProtoType::c_item_bag(obj) => obj,
+        _ => unreachable!(),
+    };
+    assert_eq!(obj.bagtype, 2);
+    assert_eq!(obj.uid, 0);
+    assert_eq!(obj.baginfo[0].uid, 1001);
+    assert_eq!(obj.baginfo[0].id, 0);
+
+    // ProtoType 按协议名打标签
+    let tagged = serde_json::to_string(&pto).unwrap();
+    assert!(tagged.starts_with(r#"{"c_item_bag":{"#));
+    match serde_json::from_str::<ProtoType>(&tagged).unwrap() {
+        ProtoType::c_item_bag(obj) => assert_eq!(obj.baginfo[0].stack, 5),
+        _ => unreachable!(),
+    }
+
+    assert!(allptos::from_json("no_such_pto", "{}").is_err());
+    assert!(allptos::from_json("s_login", r#"{"vers":1}"#).is_err());
+}
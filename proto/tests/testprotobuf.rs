@@ -0,0 +1,122 @@
+// 需要开启 protobuf feature: cargo test -p proto --features protobuf
+#![cfg(feature = "protobuf")]
+use proto::{BytesReader, BytesWriter, MsgRead, MsgWrite};
+use std::collections::HashMap;
+
+fn fixtures() -> HashMap<String, Vec<u8>> {
+    let src = include_str!("fixtures/protobuf.txt");
+    let mut fixtures = HashMap::new();
+    for line in src.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let (name, hex) = line.split_once('=').unwrap();
+        let hex = hex.trim();
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        fixtures.insert(name.trim().to_owned(), bytes);
+    }
+    fixtures
+}
+
+fn encode<T: MsgWrite>(msg: &T) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(msg.size());
+    msg.write(&mut BytesWriter::new(&mut buf)).unwrap();
+    assert_eq!(buf.len(), msg.size());
+    buf
+}
+
+fn decode<T: MsgRead>(buf: &[u8]) -> T {
+    T::read(&mut BytesReader::new(0, buf.len()), buf).unwrap()
+}
+
+// 编码结果与参考编码一致, 并且能解码回相同的值
+fn roundtrip<T: MsgRead + MsgWrite + std::fmt::Debug>(name: &str, msg: &T) {
+    let expect = &fixtures()[name];
+    assert_eq!(&encode(msg), expect, "{}", name);
+    let msg2: T = decode(expect);
+    assert_eq!(format!("{:?}", msg2), format!("{:?}", msg), "{}", name);
+}
+
+#[test]
+fn testscalar() {
+    let msg = proto::c_login::c_login {
+        ret: -1,
+        magic: 300,
+        param: "ok".to_owned(),
+        ..Default::default()
+    };
+    roundtrip("c_login", &msg);
+    roundtrip("c_errors", &proto::c_errors::c_errors::default());
+    roundtrip("s_player_brief", &proto::s_player_brief::s_player_brief {});
+
+    let msg = proto::db_save_req::db_save_req {
+        value: vec![0, 127, 128, 255],
+        counter: 1,
+        ..Default::default()
+    };
+    roundtrip("db_save_req", &msg);
+}
+
+#[test]
+fn testnested() {
+    let msg = proto::c_item_bag::c_item_bag {
+        bagtype: 3,
+        uid: 0,
+        baginfo: vec![proto::item_info::item_info {
+            uid: 1,
+            id: 2,
+            stack: 3,
+        }],
+    };
+    roundtrip("c_item_bag", &msg);
+
+    use proto::this_is_test::this_is_test;
+    let equip = proto::equip_info::equip_info {
+        uid: 1001,
+        is_equip: true,
+        slv: vec![1, -2, 300],
+        equiped: vec![true, false],
+        attr1: 1.5,
+        attr3: vec![0.25, -1.0],
+        attr4: -2.5,
+        attr5: vec![1e10],
+        name: "剑".to_owned(),
+        tags: vec!["a".to_owned(), "".to_owned()],
+        this_is_test_m: vec![
+            this_is_test {
+                uid: 7,
+                ..Default::default()
+            },
+            this_is_test::default(),
+        ],
+        this_is_test_s: this_is_test {
+            attr4: -0.5,
+            name: "s".to_owned(),
+            ..Default::default()
+        },
+    };
+    let msg = proto::c_equip_bag::c_equip_bag {
+        bagtype: -5,
+        uid: u64::MAX,
+        equiped: vec![equip],
+        baginfo: vec![],
+    };
+    roundtrip("c_equip_bag", &msg);
+}
+
+#[test]
+fn testdecodeonly() {
+    let fixtures = fixtures();
+    // 跳过不认识的字段
+    let msg: proto::c_login::c_login = decode(&fixtures["c_login_unknown"]);
+    assert_eq!(msg.ret, 2);
+    assert_eq!(msg.param, "p");
+
+    // 非 packed 的 repeated 字段
+    let msg: proto::equip_info::equip_info = decode(&fixtures["equip_info_unpacked"]);
+    assert_eq!(msg.slv, vec![-1, 2]);
+    assert_eq!(msg.attr5, vec![0.5, 2.0]);
+}
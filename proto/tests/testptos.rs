@@ -9,8 +9,7 @@ fn testallptos() {
 
 #[test]
 fn testrwpto() {
-    let mut s_equip_bag = proto::s_item_bag::s_item_bag::default();
-    s_equip_bag.bagtype = 255;
+    let s_equip_bag = proto::s_item_bag::s_item_bag { bagtype: 255 };
     println!("{:?}", s_equip_bag);
     let msglen = s_equip_bag.size();
     println!("s_equip_bag.size: {}", s_equip_bag.size());
@@ -23,3 +22,64 @@ fn testrwpto() {
     let s2 = proto::s_item_bag::s_item_bag::read(&mut r, &buf).unwrap();
     println!("s_equip_bag from buf: {:?}", s2);
 }
+
+// 嵌套的 datatype 超过 127 字节时, 长度前缀需要多个字节
+#[test]
+fn testnestedlen() {
+    use proto::{allptos, c_equip_bag, equip_info, this_is_test};
+    let nested = this_is_test::this_is_test {
+        name: "n".repeat(200),
+        ..Default::default()
+    };
+    let equip = equip_info::equip_info {
+        uid: 1,
+        this_is_test_m: vec![Default::default(), nested],
+        ..Default::default()
+    };
+    let pto = allptos::ProtoType::c_equip_bag(c_equip_bag::c_equip_bag {
+        baginfo: vec![equip, Default::default()],
+        ..Default::default()
+    });
+    let size = match &pto {
+        allptos::ProtoType::c_equip_bag(obj) => obj.size(),
+        _ => unreachable!(),
+    };
+    let buf = allptos::serialize(&pto).unwrap();
+    assert_eq!(buf.len(), size);
+    let id = c_equip_bag::c_equip_bag::id();
+    let pto2 = allptos::parse_proto(id, &buf, 0, buf.len()).unwrap();
+    assert_eq!(allptos::serialize(pto2).unwrap(), buf);
+
+    // 缓冲区不够时返回错误
+    let mut small = Vec::with_capacity(size - 1);
+    let mut w = proto::BytesWriter::new(&mut small);
+    match &pto {
+        allptos::ProtoType::c_equip_bag(obj) => assert!(w.write_message(obj).is_err()),
+        _ => unreachable!(),
+    }
+}
+
+// 新版本加在最后的字段, 旧版本读的时候跳过. protobuf 编码见 testprotobuf.rs
+#[cfg(not(feature = "protobuf"))]
+#[test]
+fn testunknownfield() {
+    let login = proto::s_login::s_login {
+        vers: "1.0".to_owned(),
+        acc: "player".to_owned(),
+    };
+    let mut buf = Vec::with_capacity(login.size() + 32);
+    let mut w = proto::BytesWriter::new(&mut buf);
+    login.write(&mut w).unwrap();
+    // repeated int32 = 3: tag, 元素个数, 元素
+    w.write_tag(3 << 3).unwrap();
+    w.write_len(2).unwrap();
+    w.write_i32(-1).unwrap();
+    w.write_i32(300).unwrap();
+    w.write_string_with_tag(4 << 3 | 2, "extra").unwrap();
+
+    let mut r = proto::BytesReader::new(0, buf.len());
+    let read = proto::s_login::s_login::read(&mut r, &buf).unwrap();
+    assert!(r.is_complete());
+    assert_eq!(read.vers, login.vers);
+    assert_eq!(read.acc, login.acc);
+}
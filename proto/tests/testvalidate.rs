@@ -0,0 +1,121 @@
+use proto::allptos::{self, ProtoType};
+use proto::{Error, Limits};
+
+fn validation_error(res: proto::Result<ProtoType>) -> String {
+    match res {
+        Err(Error::Validation(msg)) => msg,
+        res => panic!("expected validation error, got {:?}", res),
+    }
+}
+
+// 序列化不检查约束, 读取时检查
+fn reparse(pto: ProtoType) -> proto::Result<ProtoType> {
+    let id = pto.inner_info().0;
+    let buf = allptos::serialize(pto).unwrap();
+    allptos::parse_proto(id, &buf, 0, buf.len())
+}
+
+#[test]
+fn testmaxlen() {
+    let login = |len| proto::s_login::s_login {
+        acc: "a".repeat(len),
+        ..Default::default()
+    };
+    assert!(reparse(ProtoType::s_login(login(32))).is_ok());
+    assert!(login(40).validate().is_err());
+    assert_eq!(
+        validation_error(reparse(ProtoType::s_login(login(40)))),
+        "s_login.acc: length 40 exceeds max_len 32"
+    );
+}
+
+#[test]
+fn testrange() {
+    let item = |stack| proto::item_info::item_info {
+        uid: 1,
+        id: 2,
+        stack,
+    };
+    let bag = |stack| {
+        ProtoType::c_item_bag(proto::c_item_bag::c_item_bag {
+            bagtype: 2,
+            uid: 1,
+            baginfo: vec![item(0), item(stack)],
+        })
+    };
+    assert!(reparse(bag(999)).is_ok());
+    // 嵌套的 datatype 也会检查
+    assert!(bag(-1).validate().is_err());
+    assert_eq!(
+        validation_error(reparse(bag(-1))),
+        "item_info.stack: value -1 is less than min 0"
+    );
+    assert_eq!(
+        validation_error(reparse(bag(1000))),
+        "item_info.stack: value 1000 exceeds max 999"
+    );
+}
+
+#[test]
+fn testmaxcount() {
+    let mut bag = proto::c_item_bag::c_item_bag::default();
+    bag.baginfo.resize_with(201, Default::default);
+    assert_eq!(
+        validation_error(reparse(ProtoType::c_item_bag(bag))),
+        "c_item_bag.baginfo: count 201 exceeds max_count 200"
+    );
+}
+
+#[test]
+fn testdefault() {
+    assert_eq!(proto::s_item_bag::s_item_bag::default().bagtype, 2);
+    // 没有写入的字段: 默认编码使用 default, protobuf 编码就是零值
+    let pto = allptos::parse_proto(proto::s_item_bag::s_item_bag::id(), &[], 0, 0).unwrap();
+    let expect = if cfg!(feature = "protobuf") { 0 } else { 2 };
+    match pto {
+        ProtoType::s_item_bag(obj) => assert_eq!(obj.bagtype, expect),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn testlimits() {
+    let desc = allptos::descriptor_by_name("item_info").unwrap();
+    assert_eq!(
+        desc.field("stack").unwrap().limits,
+        Limits {
+            min: Some(0.0),
+            max: Some(999.0),
+            ..Limits::NONE
+        }
+    );
+    assert_eq!(desc.field("uid").unwrap().limits, Limits::NONE);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn testjson() {
+    let json = r#"{"baginfo":[{"stack":-5}]}"#;
+    assert_eq!(
+        validation_error(allptos::from_json("c_item_bag", json)),
+        "item_info.stack: value -5 is less than min 0"
+    );
+}
+
+// 截断 string 时不会截断在多字节字符中间
+#[test]
+fn testtruncatestr() {
+    let mut s = "协议 ptos".to_owned();
+    proto::util::truncate_str(&mut s, 5);
+    assert_eq!(s, "协");
+    proto::util::truncate_str(&mut s, 3);
+    assert_eq!(s, "协");
+    proto::util::truncate_str(&mut s, 0);
+    assert_eq!(s, "");
+
+    // 满足约束的随机值
+    for _ in 0..10 {
+        let login = proto::s_login::s_login::default_with_random_value();
+        assert!(login.validate().is_ok());
+    }
+}
@@ -0,0 +1,17 @@
+use proto::allptos;
+use proto::util::sample_proto;
+
+// 客户端测试向量使用的协议可以正确编解码
+#[test]
+fn testsamplevectors() {
+    for desc in allptos::DESCRIPTORS.iter().filter(|d| d.id != 0) {
+        let pto = sample_proto(desc.id, 2).unwrap();
+        let values: Vec<_> = desc.fields.iter().map(|f| pto.get_field(f.name)).collect();
+        let buf = allptos::serialize(pto).unwrap();
+        let pto2 = allptos::parse_proto(desc.id, &buf, 0, buf.len()).unwrap();
+        assert_eq!(pto2.inner_info(), (desc.id, desc.name));
+        for (f, v) in desc.fields.iter().zip(values) {
+            assert_eq!(pto2.get_field(f.name), v, "{}.{}", desc.name, f.name);
+        }
+    }
+}
@@ -0,0 +1,464 @@
+// 协议结构的兼容性检查
+// 比较当前的协议源文件与基线版本(另一个源文件目录或 git 版本), 区分会让已发布的客户端/服务器无法互通的改动和安全的改动.
+// 破坏兼容的改动: 删除或改名 message, 删除或改名字段, 修改字段类型, 复用字段编号, 修改协议id.
+// 安全的改动: 新增 message, 新增字段(使用新的字段编号).
+// 默认编码的旧版本读到不认识的字段时跳过消息剩下的部分, 新增字段的编号必须大于已有的字段编号.
+
+use crate::errors::Error;
+use crate::parser::OptionValue;
+use crate::proto::{self, Config};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSchema {
+    pub name: String,
+    pub ty: String,
+    pub tag: i32,
+    pub repeated: bool,
+}
+
+impl FieldSchema {
+    fn type_str(&self) -> String {
+        if self.repeated {
+            format!("repeated {}", self.ty)
+        } else {
+            self.ty.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageSchema {
+    pub name: String,
+    pub is_protocol: bool,
+    pub id: Option<u32>, // 协议id, datatype 没有 id
+    pub fields: Vec<FieldSchema>,
+}
+
+impl MessageSchema {
+    fn kind(&self) -> &'static str {
+        if self.is_protocol {
+            "protocol"
+        } else {
+            "datatype"
+        }
+    }
+}
+
+// 源文件里声明的常量, ty 是源文件里的类型名
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstSchema {
+    pub name: String,
+    pub ty: String,
+    pub value: OptionValue,
+}
+
+// 一个版本的所有 datatype 和 protocol, 以及按名字排序的常量.
+// 常量不影响编码, 兼容性检查不比较常量
+#[derive(Debug, Default)]
+pub struct Schema {
+    pub messages: BTreeMap<String, MessageSchema>,
+    pub consts: Vec<ConstSchema>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub breaking: bool,
+    pub message: String,
+    pub detail: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.message, self.detail)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub changes: Vec<Change>,
+}
+
+impl CheckReport {
+    pub fn breaking(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(|c| c.breaking)
+    }
+
+    pub fn safe(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(|c| !c.breaking)
+    }
+
+    pub fn is_breaking(&self) -> bool {
+        self.changes.iter().any(|c| c.breaking)
+    }
+}
+
+// 比较 current 与 baseline 两份配置对应的协议结构
+pub fn check(current: &Config, baseline: &Config) -> Result<CheckReport, Error> {
+    let mut new = proto::load_schema(current, true)?;
+    // 基线版本只使用清单里记录的 id
+    let mut old = proto::load_schema(baseline, false)?;
+    // 只调整字段顺序不影响兼容性
+    for m in new.messages.values_mut().chain(old.messages.values_mut()) {
+        m.fields.sort_by_key(|f| f.tag);
+    }
+    Ok(CheckReport {
+        changes: compare(&old, &new, current.protobuf),
+    })
+}
+
+// protobuf 编码按 wire type 跳过不认识的字段, 新增字段可以使用任意未用过的编号
+pub fn compare(old: &Schema, new: &Schema, protobuf: bool) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut push = |breaking: bool, message: &str, detail: String| {
+        changes.push(Change {
+            breaking,
+            message: message.to_owned(),
+            detail,
+        })
+    };
+
+    let removed: Vec<&MessageSchema> = old
+        .messages
+        .values()
+        .filter(|m| !new.messages.contains_key(&m.name))
+        .collect();
+    let mut added: Vec<&MessageSchema> = new
+        .messages
+        .values()
+        .filter(|m| !old.messages.contains_key(&m.name))
+        .collect();
+
+    for m in removed {
+        // 字段完全一致的新 message 视为改名
+        let renamed = added
+            .iter()
+            .position(|a| a.is_protocol == m.is_protocol && a.fields == m.fields);
+        match renamed {
+            Some(idx) => {
+                let a = added.remove(idx);
+                push(
+                    true,
+                    &m.name,
+                    format!("{} renamed to '{}'", m.kind(), a.name),
+                );
+            }
+            None => push(true, &m.name, format!("{} removed", m.kind())),
+        }
+    }
+    for a in added {
+        let detail = match a.id {
+            Some(id) => format!("new {}, id = {}", a.kind(), id),
+            None => format!("new {}", a.kind()),
+        };
+        push(false, &a.name, detail);
+        // 新协议不能使用已删除协议的 id
+        if let Some(id) = a.id {
+            let reused = old
+                .messages
+                .values()
+                .find(|m| m.id == Some(id) && m.name != a.name);
+            if let Some(m) = reused {
+                push(
+                    true,
+                    &a.name,
+                    format!("id {} was used by protocol '{}'", id, m.name),
+                );
+            }
+        }
+    }
+
+    for (name, m) in &old.messages {
+        let n = match new.messages.get(name) {
+            Some(n) => n,
+            None => continue,
+        };
+        if m.is_protocol != n.is_protocol {
+            push(
+                true,
+                name,
+                format!("changed from {} to {}", m.kind(), n.kind()),
+            );
+            continue;
+        }
+        if let (Some(old_id), Some(new_id)) = (m.id, n.id) {
+            if old_id != new_id {
+                push(
+                    true,
+                    name,
+                    format!("protocol id changed: {} -> {}", old_id, new_id),
+                );
+            }
+        }
+        compare_fields(m, n, protobuf, &mut push);
+    }
+    changes
+}
+
+fn compare_fields<F>(m: &MessageSchema, n: &MessageSchema, protobuf: bool, push: &mut F)
+where
+    F: FnMut(bool, &str, String),
+{
+    let name = &m.name;
+    for f in &m.fields {
+        match n.fields.iter().find(|nf| nf.tag == f.tag) {
+            Some(nf) if nf.name != f.name && nf.ty == f.ty && nf.repeated == f.repeated => push(
+                true,
+                name,
+                format!("field '{}' = {} renamed to '{}'", f.name, f.tag, nf.name),
+            ),
+            Some(nf) if nf.name != f.name => push(
+                true,
+                name,
+                format!(
+                    "field number {} reused: '{}' {} -> '{}' {}",
+                    f.tag,
+                    f.name,
+                    f.type_str(),
+                    nf.name,
+                    nf.type_str()
+                ),
+            ),
+            Some(nf) if nf.ty != f.ty || nf.repeated != f.repeated => push(
+                true,
+                name,
+                format!(
+                    "field '{}' type changed: {} -> {}",
+                    f.name,
+                    f.type_str(),
+                    nf.type_str()
+                ),
+            ),
+            Some(_) => {}
+            None => match n.fields.iter().find(|nf| nf.name == f.name) {
+                Some(nf) => push(
+                    true,
+                    name,
+                    format!("field '{}' number changed: {} -> {}", f.name, f.tag, nf.tag),
+                ),
+                None => push(
+                    true,
+                    name,
+                    format!("field '{}' = {} removed", f.name, f.tag),
+                ),
+            },
+        }
+    }
+    let max_tag = m.fields.iter().map(|f| f.tag).max().unwrap_or(0);
+    for nf in &n.fields {
+        let exist = m
+            .fields
+            .iter()
+            .any(|f| f.tag == nf.tag || f.name == nf.name);
+        if !exist && (protobuf || nf.tag > max_tag) {
+            push(
+                false,
+                name,
+                format!("new field '{}' {} = {}", nf.name, nf.type_str(), nf.tag),
+            );
+        } else if !exist {
+            push(
+                true,
+                name,
+                format!(
+                    "new field '{}' {} = {} is not after field number {}",
+                    nf.name,
+                    nf.type_str(),
+                    nf.tag,
+                    max_tag
+                ),
+            );
+        }
+    }
+}
+
+fn git(args: &[&str]) -> Result<Vec<u8>, Error> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        return Err(Error::Git(format!(
+            "git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+// 把 git 版本 rev 里的源文件目录和协议id清单导出到 into 目录, 返回指向导出内容的配置.
+// config 里的路径需要是相对于当前工作目录的路径.
+pub fn export_git_rev(config: &Config, rev: &str, into: &Path) -> Result<Config, Error> {
+    let src_dir = config.src_dir.display().to_string();
+    let tree = format!("{}:./{}", rev, src_dir.trim_start_matches("./"));
+    let files = git(&["ls-tree", "-r", "--name-only", &tree])?;
+    let files = String::from_utf8_lossy(&files);
+    let mut baseline = config.clone();
+    baseline.src_dir = into.join("ptosrc");
+    baseline.id_manifest = into.join("ptoids.toml");
+    baseline.update_manifest = false;
+    for fname in files.lines() {
+        let content = git(&["show", &format!("{}/{}", tree, fname)])?;
+        let path = baseline.src_dir.join(fname);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, content)?;
+    }
+    for dir in ["primitive", "datatype", "protocol"] {
+        fs::create_dir_all(baseline.src_dir.join(dir))?;
+    }
+    // 基线版本可能还没有协议id清单
+    let manifest = config.id_manifest.display().to_string();
+    let manifest = format!("{}:./{}", rev, manifest.trim_start_matches("./"));
+    if let Ok(content) = git(&["show", &manifest]) {
+        fs::write(&baseline.id_manifest, content)?;
+    }
+    Ok(baseline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, ty: &str, tag: i32) -> FieldSchema {
+        FieldSchema {
+            name: name.to_owned(),
+            ty: ty.to_owned(),
+            tag,
+            repeated: false,
+        }
+    }
+
+    fn schema(msgs: Vec<(&str, Option<u32>, Vec<FieldSchema>)>) -> Schema {
+        let mut schema = Schema::default();
+        for (name, id, fields) in msgs {
+            let m = MessageSchema {
+                name: name.to_owned(),
+                is_protocol: id.is_some(),
+                id,
+                fields,
+            };
+            schema.messages.insert(name.to_owned(), m);
+        }
+        schema
+    }
+
+    fn details(changes: &[Change], breaking: bool) -> Vec<String> {
+        changes
+            .iter()
+            .filter(|c| c.breaking == breaking)
+            .map(|c| c.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn field_changes() {
+        let old = schema(vec![(
+            "s_x",
+            Some(201),
+            vec![
+                field("a", "int32", 1),
+                field("b", "string", 2),
+                field("c", "int32", 3),
+                field("d", "int32", 4),
+            ],
+        )]);
+        let new = schema(vec![(
+            "s_x",
+            Some(202),
+            vec![
+                field("a", "int32", 1),
+                field("b", "int64", 2),
+                field("e", "int32", 3),
+                field("f", "bool", 5),
+            ],
+        )]);
+        let changes = compare(&old, &new, false);
+        assert_eq!(
+            details(&changes, true),
+            vec![
+                "s_x: protocol id changed: 201 -> 202",
+                "s_x: field 'b' type changed: string -> int64",
+                "s_x: field 'c' = 3 renamed to 'e'",
+                "s_x: field 'd' = 4 removed",
+            ]
+        );
+        assert_eq!(
+            details(&changes, false),
+            vec!["s_x: new field 'f' bool = 5"]
+        );
+    }
+
+    #[test]
+    fn message_changes() {
+        let old = schema(vec![
+            ("info", None, vec![field("a", "int32", 1)]),
+            ("s_old", Some(201), vec![field("x", "info", 1)]),
+            ("s_gone", Some(202), vec![]),
+        ]);
+        let new = schema(vec![
+            ("info", None, vec![field("a", "int32", 1)]),
+            ("s_new", Some(203), vec![field("x", "info", 1)]),
+            ("s_add", Some(202), vec![field("y", "int32", 1)]),
+        ]);
+        let changes = compare(&old, &new, false);
+        assert_eq!(
+            details(&changes, true),
+            vec![
+                "s_gone: protocol removed",
+                "s_old: protocol renamed to 's_new'",
+                "s_add: id 202 was used by protocol 's_gone'",
+            ]
+        );
+        assert_eq!(
+            details(&changes, false),
+            vec!["s_add: new protocol, id = 202"]
+        );
+    }
+
+    #[test]
+    fn no_changes() {
+        let old = schema(vec![("s_x", Some(201), vec![field("a", "int32", 1)])]);
+        let new = schema(vec![("s_x", Some(201), vec![field("a", "int32", 1)])]);
+        assert!(compare(&old, &new, false).is_empty());
+    }
+
+    #[test]
+    fn field_numbers() {
+        let old = schema(vec![(
+            "s_x",
+            Some(201),
+            vec![field("a", "int32", 1), field("c", "int32", 3)],
+        )]);
+        let new = schema(vec![(
+            "s_x",
+            Some(201),
+            vec![
+                field("a", "int32", 1),
+                field("b", "int32", 2),
+                field("d", "string", 3),
+            ],
+        )]);
+        // 默认编码的旧版本读到字段 2 就跳过了后面的字段 3
+        let changes = compare(&old, &new, false);
+        assert_eq!(
+            details(&changes, true),
+            vec![
+                "s_x: field number 3 reused: 'c' int32 -> 'd' string",
+                "s_x: new field 'b' int32 = 2 is not after field number 3",
+            ]
+        );
+        assert!(details(&changes, false).is_empty());
+
+        let changes = compare(&old, &new, true);
+        assert_eq!(
+            details(&changes, true),
+            vec!["s_x: field number 3 reused: 'c' int32 -> 'd' string"]
+        );
+        assert_eq!(
+            details(&changes, false),
+            vec!["s_x: new field 'b' int32 = 2"]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+// 客户端代码生成共用的部分, 见 ts.rs 和 csharp.rs
+// 客户端代码使用默认的编码, 与 proto crate 的 writer.rs, reader.rs 一致:
+// 字段按字段编号升序全部写入, tag = (字段编号 << 3) | wire type, 数组先写元素个数再写每个元素,
+// int8/uint8/bool 固定 1 字节, 其他整数是 varint(负数按同宽度的无符号数编码), string 和 datatype 先写长度.
+// 读到不认识的字段时, 它和后面的字段都是新版本加的, 跳过协议包剩下的部分.
+// 开启 protobuf 编码时, 客户端应该使用 protogen proto3 导出的文件.
+
+use crate::check::{ConstSchema, FieldSchema, MessageSchema};
+use crate::errors::Error;
+use crate::proto::{self, Config};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    Bool,
+    F32,
+    F64,
+    String,
+    Message,
+}
+
+impl Kind {
+    pub fn of(ty: &str) -> Kind {
+        match ty {
+            "int8" => Kind::I8,
+            "uint8" => Kind::U8,
+            "int16" => Kind::I16,
+            "uint16" => Kind::U16,
+            "int" | "int32" => Kind::I32,
+            "uint32" => Kind::U32,
+            "int64" => Kind::I64,
+            "uint64" => Kind::U64,
+            "bool" => Kind::Bool,
+            "float" => Kind::F32,
+            "double" => Kind::F64,
+            "string" => Kind::String,
+            _ => Kind::Message,
+        }
+    }
+
+    // 读写函数名的后缀
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Kind::I8 => "I8",
+            Kind::U8 => "U8",
+            Kind::I16 => "I16",
+            Kind::U16 => "U16",
+            Kind::I32 => "I32",
+            Kind::U32 => "U32",
+            Kind::I64 => "I64",
+            Kind::U64 => "U64",
+            Kind::Bool => "Bool",
+            Kind::F32 => "F32",
+            Kind::F64 => "F64",
+            Kind::String => "String",
+            Kind::Message => "Message",
+        }
+    }
+
+    fn wire_type(self) -> u32 {
+        match self {
+            Kind::F64 => 1,
+            Kind::String | Kind::Message => 2,
+            Kind::F32 => 5,
+            _ => 0,
+        }
+    }
+}
+
+// repeated 字段的 tag 使用元素的 wire type
+pub(crate) fn tag(field: &FieldSchema) -> u32 {
+    ((field.tag as u32) << 3) | Kind::of(&field.ty).wire_type()
+}
+
+// 写入字段的顺序
+pub(crate) fn write_order(m: &MessageSchema) -> Vec<&FieldSchema> {
+    let mut fields: Vec<&FieldSchema> = m.fields.iter().collect();
+    fields.sort_by_key(|f| f.tag);
+    fields
+}
+
+// 生成客户端代码需要的协议结构
+pub(crate) struct ClientSchema {
+    // 按名字排序
+    pub datatypes: Vec<MessageSchema>,
+    // 按协议id排序
+    pub protocols: Vec<MessageSchema>,
+    // 按名字排序
+    pub consts: Vec<ConstSchema>,
+    pub version: String,
+}
+
+impl ClientSchema {
+    pub fn load(config: &Config) -> Result<ClientSchema, Error> {
+        let schema = proto::load_schema(config, true)?;
+        // 客户端代码总是使用默认编码
+        let version = proto::pto_version(&schema, false);
+        let (mut protocols, datatypes): (Vec<MessageSchema>, Vec<MessageSchema>) =
+            schema.messages.into_values().partition(|m| m.is_protocol);
+        protocols.sort_by_key(|m| m.id);
+        Ok(ClientSchema {
+            datatypes,
+            protocols,
+            consts: schema.consts,
+            version,
+        })
+    }
+
+    pub fn messages(&self) -> impl Iterator<Item = &MessageSchema> {
+        self.datatypes.iter().chain(self.protocols.iter())
+    }
+}
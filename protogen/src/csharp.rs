@@ -0,0 +1,906 @@
+// 生成 C# 客户端代码: Ptos.cs 包含编解码, 所有结构体的类, 协议id表和消息头的读写;
+// PtosTests.cs 用服务器生成的测试向量检查编解码.
+
+use crate::check::{ConstSchema, FieldSchema, MessageSchema};
+use crate::client::{self, ClientSchema, Kind};
+use crate::errors::Error;
+use crate::parser::OptionValue;
+use crate::proto::{ident, Config};
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+type Result<T> = std::result::Result<T, fmt::Error>;
+
+// C# 关键字
+#[rustfmt::skip]
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "base", "bool", "break", "byte", "case", "catch", "char", "checked", "class",
+    "const", "continue", "decimal", "default", "delegate", "do", "double", "else", "enum", "event",
+    "explicit", "extern", "false", "finally", "fixed", "float", "for", "foreach", "goto", "if",
+    "implicit", "in", "int", "interface", "internal", "is", "lock", "long", "namespace", "new",
+    "null", "object", "operator", "out", "override", "params", "private", "protected", "public",
+    "readonly", "ref", "return", "sbyte", "sealed", "short", "sizeof", "stackalloc", "static",
+    "string", "struct", "switch", "this", "throw", "true", "try", "typeof", "uint", "ulong",
+    "unchecked", "unsafe", "ushort", "using", "virtual", "void", "volatile", "while",
+];
+
+const RUNTIME: &str = r#"
+    public class ProtoException : Exception
+    {
+        public ProtoException(string message) : base(message) { }
+    }
+
+    public interface IMessage
+    {
+        void Write(BytesWriter w);
+    }
+
+    public interface IProto : IMessage
+    {
+        uint ProtoId { get; }
+    }
+
+    public class BytesWriter
+    {
+        private byte[] buf = new byte[64];
+        private int pos = 0;
+
+        private void Reserve(int n)
+        {
+            if (pos + n <= buf.Length)
+            {
+                return;
+            }
+            int cap = buf.Length * 2;
+            while (cap < pos + n)
+            {
+                cap *= 2;
+            }
+            Array.Resize(ref buf, cap);
+        }
+
+        public byte[] Finish()
+        {
+            var bytes = new byte[pos];
+            Buffer.BlockCopy(buf, 0, bytes, 0, pos);
+            return bytes;
+        }
+
+        public void WriteByte(byte v)
+        {
+            Reserve(1);
+            buf[pos++] = v;
+        }
+
+        public void WriteBytes(byte[] v)
+        {
+            Reserve(v.Length);
+            Buffer.BlockCopy(v, 0, buf, pos, v.Length);
+            pos += v.Length;
+        }
+
+        public void WriteVarint(ulong v)
+        {
+            while (v > 0x7f)
+            {
+                WriteByte((byte)((v & 0x7f) | 0x80));
+                v >>= 7;
+            }
+            WriteByte((byte)v);
+        }
+
+        public void WriteI8(sbyte v) { WriteByte(unchecked((byte)v)); }
+        public void WriteU8(byte v) { WriteByte(v); }
+        public void WriteI16(short v) { WriteVarint(unchecked((ushort)v)); }
+        public void WriteU16(ushort v) { WriteVarint(v); }
+        public void WriteI32(int v) { WriteVarint(unchecked((uint)v)); }
+        public void WriteU32(uint v) { WriteVarint(v); }
+        public void WriteI64(long v) { WriteVarint(unchecked((ulong)v)); }
+        public void WriteU64(ulong v) { WriteVarint(v); }
+        public void WriteBool(bool v) { WriteByte((byte)(v ? 1 : 0)); }
+
+        public void WriteF32(float v)
+        {
+            var bytes = BitConverter.GetBytes(v);
+            if (!BitConverter.IsLittleEndian)
+            {
+                Array.Reverse(bytes);
+            }
+            WriteBytes(bytes);
+        }
+
+        public void WriteF64(double v)
+        {
+            var bytes = BitConverter.GetBytes(v);
+            if (!BitConverter.IsLittleEndian)
+            {
+                Array.Reverse(bytes);
+            }
+            WriteBytes(bytes);
+        }
+
+        public void WriteString(string v)
+        {
+            var bytes = Encoding.UTF8.GetBytes(v);
+            WriteVarint((ulong)bytes.Length);
+            WriteBytes(bytes);
+        }
+
+        // datatype: 长度 + 内容
+        public void WriteMessage(IMessage v)
+        {
+            var w = new BytesWriter();
+            v.Write(w);
+            var bytes = w.Finish();
+            WriteVarint((ulong)bytes.Length);
+            WriteBytes(bytes);
+        }
+    }
+
+    public class BytesReader
+    {
+        private readonly byte[] buf;
+        public int Pos;
+
+        public BytesReader(byte[] buf, int pos)
+        {
+            this.buf = buf;
+            Pos = pos;
+        }
+
+        private void Check(int n)
+        {
+            if (n < 0 || Pos + n > buf.Length)
+            {
+                throw new ProtoException("unexpected end of buffer");
+            }
+        }
+
+        public byte ReadByte()
+        {
+            Check(1);
+            return buf[Pos++];
+        }
+
+        // 与服务器一致: u16 最多 3 个字节, u32 最多 5 个字节, u64 最多 10 个字节
+        public ulong ReadVarint(int maxBytes)
+        {
+            ulong r = 0;
+            for (int i = 0; i < maxBytes; i++)
+            {
+                byte b = ReadByte();
+                r |= (ulong)(b & 0x7f) << (7 * i);
+                if ((b & 0x80) == 0)
+                {
+                    return r;
+                }
+            }
+            throw new ProtoException("invalid varint");
+        }
+
+        public sbyte ReadI8() { return unchecked((sbyte)ReadByte()); }
+        public byte ReadU8() { return ReadByte(); }
+        public short ReadI16() { return unchecked((short)ReadVarint(3)); }
+        public ushort ReadU16() { return unchecked((ushort)ReadVarint(3)); }
+        public int ReadI32() { return unchecked((int)ReadVarint(5)); }
+        public uint ReadU32() { return unchecked((uint)ReadVarint(5)); }
+        public long ReadI64() { return unchecked((long)ReadVarint(10)); }
+        public ulong ReadU64() { return ReadVarint(10); }
+        public bool ReadBool() { return ReadByte() != 0; }
+
+        public float ReadF32()
+        {
+            Check(4);
+            var bytes = new byte[4];
+            Buffer.BlockCopy(buf, Pos, bytes, 0, 4);
+            if (!BitConverter.IsLittleEndian)
+            {
+                Array.Reverse(bytes);
+            }
+            Pos += 4;
+            return BitConverter.ToSingle(bytes, 0);
+        }
+
+        public double ReadF64()
+        {
+            Check(8);
+            var bytes = new byte[8];
+            Buffer.BlockCopy(buf, Pos, bytes, 0, 8);
+            if (!BitConverter.IsLittleEndian)
+            {
+                Array.Reverse(bytes);
+            }
+            Pos += 8;
+            return BitConverter.ToDouble(bytes, 0);
+        }
+
+        public string ReadString()
+        {
+            int len = (int)ReadU32();
+            Check(len);
+            var v = Encoding.UTF8.GetString(buf, Pos, len);
+            Pos += len;
+            return v;
+        }
+
+        // datatype: 长度 + 内容, read 读到 end 为止
+        public T ReadMessage<T>(Func<BytesReader, int, T> read)
+        {
+            int len = (int)ReadU32();
+            Check(len);
+            int end = Pos + len;
+            var v = read(this, end);
+            Pos = end;
+            return v;
+        }
+    }
+"#;
+
+const FRAME: &str = r#"
+    // 消息头: 协议id(u32) + 协议包长度(u32), 小端
+    public static class Frame
+    {
+        public const int HeaderLen = 8;
+        // 协议包长度的上限, 与服务器一致
+        public const int BodyMaxLen = 64 * 1024 - HeaderLen;
+        // 协议包长度的最高位: 后面还有同一个协议的分片. 更长的协议包拆成多个分片
+        public const uint ChunkFlag = 0x80000000;
+        // 协议包长度的次高位: 协议包是压缩的内容(原始长度(u32) + lz4 block)
+        public const uint CompressFlag = 0x40000000;
+
+        public static byte[] EncodeHeader(uint protoId, int len, bool more = false)
+        {
+            if (len >= BodyMaxLen)
+            {
+                throw new ProtoException("[Frame.EncodeHeader]: exceed BodyMaxLen, " + len);
+            }
+            uint size = more ? (uint)len | ChunkFlag : (uint)len;
+            var header = new byte[HeaderLen];
+            for (int i = 0; i < 4; i++)
+            {
+                header[i] = (byte)(protoId >> (8 * i));
+                header[4 + i] = (byte)(size >> (8 * i));
+            }
+            return header;
+        }
+
+        // 数据不足一个消息头时返回 false, more 表示后面还有分片, compressed 表示协议包是压缩的内容
+        public static bool TryDecodeHeader(byte[] buf, int offset, out uint protoId, out int len, out bool more, out bool compressed)
+        {
+            protoId = 0;
+            len = 0;
+            more = false;
+            compressed = false;
+            if (buf.Length - offset < HeaderLen)
+            {
+                return false;
+            }
+            uint size = 0;
+            for (int i = 0; i < 4; i++)
+            {
+                protoId |= (uint)buf[offset + i] << (8 * i);
+                size |= (uint)buf[offset + 4 + i] << (8 * i);
+            }
+            more = (size & ChunkFlag) != 0;
+            compressed = (size & CompressFlag) != 0;
+            size &= ~(ChunkFlag | CompressFlag);
+            if (size >= BodyMaxLen)
+            {
+                throw new ProtoException("[Frame.TryDecodeHeader]: exceed BodyMaxLen, " + size);
+            }
+            len = (int)size;
+            return true;
+        }
+
+        // 消息头 + 协议包, 协议包超过 BodyMaxLen 时拆成多个分片
+        public static byte[] Encode(IProto pto)
+        {
+            var body = AllPtos.Serialize(pto);
+            int chunkLen = BodyMaxLen - 1;
+            int count = body.Length < BodyMaxLen ? 1 : (body.Length + chunkLen - 1) / chunkLen;
+            var frame = new byte[HeaderLen * count + body.Length];
+            int pos = 0;
+            for (int i = 0; i < count; i++)
+            {
+                int start = i * chunkLen;
+                int len = Math.Min(chunkLen, body.Length - start);
+                Buffer.BlockCopy(EncodeHeader(pto.ProtoId, len, i + 1 < count), 0, frame, pos, HeaderLen);
+                Buffer.BlockCopy(body, start, frame, pos + HeaderLen, len);
+                pos += HeaderLen + len;
+            }
+            return frame;
+        }
+
+        // 解析 buf 从 offset 开始的一个完整协议包(包括所有分片), 数据不完整时返回 false, size 是消息头和协议包的总长度
+        public static bool TryDecode(byte[] buf, int offset, out IProto pto, out int size)
+        {
+            pto = null;
+            size = 0;
+            var chunks = new List<ArraySegment<byte>>();
+            int pos = offset;
+            uint first = 0;
+            bool compressed = false;
+            while (true)
+            {
+                uint protoId;
+                int len;
+                bool more;
+                if (!TryDecodeHeader(buf, pos, out protoId, out len, out more, out compressed))
+                {
+                    return false;
+                }
+                if (chunks.Count == 0)
+                {
+                    first = protoId;
+                }
+                else if (protoId != first)
+                {
+                    throw new ProtoException("[Frame.TryDecode]: chunk of proto_id=" + first + " interleaved with proto_id=" + protoId);
+                }
+                int start = pos + HeaderLen;
+                if (buf.Length < start + len)
+                {
+                    return false;
+                }
+                chunks.Add(new ArraySegment<byte>(buf, start, len));
+                pos = start + len;
+                if (!more)
+                {
+                    break;
+                }
+            }
+            size = pos - offset;
+            if (chunks.Count == 1 && !compressed)
+            {
+                pto = AllPtos.Parse(first, buf, offset + HeaderLen, pos);
+                return true;
+            }
+            int total = 0;
+            foreach (var chunk in chunks)
+            {
+                total += chunk.Count;
+            }
+            var body = new byte[total];
+            int at = 0;
+            foreach (var chunk in chunks)
+            {
+                Buffer.BlockCopy(chunk.Array, chunk.Offset, body, at, chunk.Count);
+                at += chunk.Count;
+            }
+            if (compressed)
+            {
+                body = Decompress(body);
+                total = body.Length;
+            }
+            pto = AllPtos.Parse(first, body, 0, total);
+            return true;
+        }
+
+        // 解压服务器发来的压缩内容: 原始长度(u32, 小端) + lz4 block
+        public static byte[] Decompress(byte[] buf)
+        {
+            if (buf.Length < 4)
+            {
+                throw new ProtoException("[Frame.Decompress]: missing raw length");
+            }
+            int rawLen = (int)((uint)buf[0] | (uint)buf[1] << 8 | (uint)buf[2] << 16 | (uint)buf[3] << 24);
+            var output = new byte[rawLen];
+            int pos = 4;
+            int at = 0;
+            while (true)
+            {
+                if (pos >= buf.Length)
+                {
+                    throw new ProtoException("[Frame.Decompress]: truncated token");
+                }
+                int token = buf[pos++];
+                int litLen = token >> 4;
+                if (litLen == 15)
+                {
+                    litLen = ReadLen(buf, ref pos, litLen);
+                }
+                if (pos + litLen > buf.Length || at + litLen > rawLen)
+                {
+                    throw new ProtoException("[Frame.Decompress]: truncated literals");
+                }
+                Buffer.BlockCopy(buf, pos, output, at, litLen);
+                pos += litLen;
+                at += litLen;
+                // 最后一个序列只有字面量
+                if (pos == buf.Length)
+                {
+                    break;
+                }
+                if (pos + 2 > buf.Length)
+                {
+                    throw new ProtoException("[Frame.Decompress]: truncated offset");
+                }
+                int offset = buf[pos] | buf[pos + 1] << 8;
+                pos += 2;
+                if (offset == 0 || offset > at)
+                {
+                    throw new ProtoException("[Frame.Decompress]: invalid offset " + offset);
+                }
+                int matchLen = token & 0x0f;
+                if (matchLen == 15)
+                {
+                    matchLen = ReadLen(buf, ref pos, matchLen);
+                }
+                matchLen += 4;
+                if (at + matchLen > rawLen)
+                {
+                    throw new ProtoException("[Frame.Decompress]: output overflow");
+                }
+                // 匹配可以和自己重叠, 逐个字节复制
+                for (int i = 0; i < matchLen; i++, at++)
+                {
+                    output[at] = output[at - offset];
+                }
+            }
+            if (at != rawLen)
+            {
+                throw new ProtoException("[Frame.Decompress]: raw length mismatch, expect " + rawLen + ", got " + at);
+            }
+            return output;
+        }
+
+        private static int ReadLen(byte[] buf, ref int pos, int len)
+        {
+            while (true)
+            {
+                if (pos >= buf.Length)
+                {
+                    throw new ProtoException("[Frame.Decompress]: truncated length");
+                }
+                int b = buf[pos++];
+                len += b;
+                if (b != 255)
+                {
+                    return len;
+                }
+            }
+        }
+    }
+}
+"#;
+
+const TESTS: &str = r##"// this file is automatically generated by protogen. please do not edit.
+// 用服务器生成的测试向量检查编解码: cargo run -p proto --example vectors > vectors.txt
+// 每行 "协议名 = 消息头和协议包的十六进制", 解码后重新编码必须得到相同的字节.
+// 协议包末尾加上不认识的字段后, 解码重新编码也必须得到原来的字节.
+using System;
+using System.Collections.Generic;
+using System.Text;
+
+namespace Ptos
+{
+    public static class PtosTests
+    {
+        private static byte[] FromHex(string hex)
+        {
+            var bytes = new byte[hex.Length / 2];
+            for (int i = 0; i < bytes.Length; i++)
+            {
+                bytes[i] = Convert.ToByte(hex.Substring(i * 2, 2), 16);
+            }
+            return bytes;
+        }
+
+        private static string ToHex(byte[] bytes)
+        {
+            var sb = new StringBuilder(bytes.Length * 2);
+            foreach (var b in bytes)
+            {
+                sb.Append(b.ToString("x2"));
+            }
+            return sb.ToString();
+        }
+
+        // 字段编号 2^29-1 的 varint 字段, 值是 1
+        private static readonly byte[] UnknownField = FromHex("f8ffffff0f01");
+
+        // 在协议包末尾加上 UnknownField, 分片或压缩的帧返回 null
+        private static byte[] WithUnknownField(byte[] frame)
+        {
+            int len = frame.Length - Frame.HeaderLen;
+            if ((uint)(frame[4] | frame[5] << 8 | frame[6] << 16 | frame[7] << 24) != (uint)len)
+            {
+                return null;
+            }
+            var extended = new byte[frame.Length + UnknownField.Length];
+            Buffer.BlockCopy(frame, 0, extended, 0, frame.Length);
+            Buffer.BlockCopy(UnknownField, 0, extended, frame.Length, UnknownField.Length);
+            len += UnknownField.Length;
+            for (int i = 0; i < 4; i++)
+            {
+                extended[4 + i] = (byte)(len >> (8 * i));
+            }
+            return extended;
+        }
+
+        // 返回所有失败的测试向量
+        public static List<string> CheckVectors(string text)
+        {
+            var failures = new List<string>();
+            foreach (var line in text.Split('\n'))
+            {
+                var content = line.Trim();
+                if (content.Length == 0 || content.StartsWith("#"))
+                {
+                    continue;
+                }
+                int idx = content.IndexOf('=');
+                var name = content.Substring(0, idx).Trim();
+                var hex = content.Substring(idx + 1).Trim();
+                try
+                {
+                    IProto pto;
+                    int size;
+                    if (!Frame.TryDecode(FromHex(hex), 0, out pto, out size) || size * 2 != hex.Length)
+                    {
+                        failures.Add(name + ": incomplete frame");
+                    }
+                    else if (AllPtos.NameOf(pto.ProtoId) != name)
+                    {
+                        failures.Add(name + ": decoded as " + AllPtos.NameOf(pto.ProtoId));
+                    }
+                    else if (ToHex(Frame.Encode(pto)) != hex)
+                    {
+                        failures.Add(name + ": re-encoded frame differs");
+                    }
+                    else
+                    {
+                        var extended = WithUnknownField(FromHex(hex));
+                        if (extended != null && (!Frame.TryDecode(extended, 0, out pto, out size) || ToHex(Frame.Encode(pto)) != hex))
+                        {
+                            failures.Add(name + ": unknown field is not skipped");
+                        }
+                    }
+                }
+                catch (Exception e)
+                {
+                    failures.Add(name + ": " + e.Message);
+                }
+            }
+            return failures;
+        }
+    }
+}
+"##;
+
+// 字段名是关键字时加 @, 与类同名时加 _
+fn cs_field(m: &MessageSchema, f: &FieldSchema) -> String {
+    if f.name == ident(&m.name) {
+        format!("{}_", f.name)
+    } else if KEYWORDS.contains(&f.name.as_str()) {
+        format!("@{}", f.name)
+    } else {
+        f.name.clone()
+    }
+}
+
+fn cs_elem_type(ty: &str) -> &str {
+    match Kind::of(ty) {
+        Kind::I8 => "sbyte",
+        Kind::U8 => "byte",
+        Kind::I16 => "short",
+        Kind::U16 => "ushort",
+        Kind::I32 => "int",
+        Kind::U32 => "uint",
+        Kind::I64 => "long",
+        Kind::U64 => "ulong",
+        Kind::Bool => "bool",
+        Kind::F32 => "float",
+        Kind::F64 => "double",
+        Kind::String => "string",
+        Kind::Message => ty,
+    }
+}
+
+// 带包名的 datatype 的类名是展开成标识符的全名
+fn cs_type(field: &FieldSchema) -> String {
+    let class = ident(&field.ty);
+    let ty = cs_elem_type(&class);
+    if field.repeated {
+        format!("List<{}>", ty)
+    } else {
+        ty.to_owned()
+    }
+}
+
+fn cs_default(field: &FieldSchema) -> Option<String> {
+    if field.repeated {
+        return Some(format!("new {}()", cs_type(field)));
+    }
+    match Kind::of(&field.ty) {
+        Kind::String => Some("\"\"".to_owned()),
+        Kind::Message => Some(format!("new {}()", ident(&field.ty))),
+        _ => None,
+    }
+}
+
+// float 常量需要 f 后缀, 整数可以直接赋给任何数值类型
+fn cs_const(c: &ConstSchema) -> String {
+    match (&c.value, Kind::of(&c.ty)) {
+        (OptionValue::Float(v), Kind::F32) => format!("{:?}f", v),
+        (value, _) => value.to_string(),
+    }
+}
+
+fn read_value(kind: Kind, ty: &str) -> String {
+    match kind {
+        Kind::Message => format!("r.ReadMessage({}.Read)", ty),
+        _ => format!("r.Read{}()", kind.suffix()),
+    }
+}
+
+fn write_class(out: &mut String, m: &MessageSchema) -> Result<()> {
+    let iface = if m.id.is_some() { "IProto" } else { "IMessage" };
+    let class = ident(&m.name);
+    writeln!(out)?;
+    writeln!(out, "    public class {} : {}", class, iface)?;
+    writeln!(out, "    {{")?;
+    if let Some(id) = m.id {
+        writeln!(out, "        public const uint ID = {};", id)?;
+        writeln!(out, "        public const string NAME = \"{}\";", m.name)?;
+        writeln!(
+            out,
+            "        public uint ProtoId {{ get {{ return ID; }} }}"
+        )?;
+    }
+    for f in &m.fields {
+        match cs_default(f) {
+            Some(v) => writeln!(
+                out,
+                "        public {} {} = {};",
+                cs_type(f),
+                cs_field(m, f),
+                v
+            )?,
+            None => writeln!(out, "        public {} {};", cs_type(f), cs_field(m, f))?,
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "        public void Write(BytesWriter w)")?;
+    writeln!(out, "        {{")?;
+    for f in client::write_order(m) {
+        let kind = Kind::of(&f.ty);
+        let name = cs_field(m, f);
+        writeln!(out, "            w.WriteVarint({});", client::tag(f))?;
+        if f.repeated {
+            writeln!(
+                out,
+                "            w.WriteVarint((ulong)this.{}.Count);",
+                name
+            )?;
+            writeln!(
+                out,
+                "            foreach (var v in this.{})\n            {{\n                w.Write{}(v);\n            }}",
+                name,
+                kind.suffix()
+            )?;
+        } else {
+            writeln!(out, "            w.Write{}(this.{});", kind.suffix(), name)?;
+        }
+    }
+    writeln!(out, "        }}")?;
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "        public static {} Read(BytesReader r, int end)",
+        class
+    )?;
+    writeln!(out, "        {{")?;
+    writeln!(out, "            var msg = new {}();", class)?;
+    writeln!(out, "            while (r.Pos < end)")?;
+    writeln!(out, "            {{")?;
+    writeln!(out, "                uint tag = r.ReadU32();")?;
+    writeln!(out, "                switch (tag)")?;
+    writeln!(out, "                {{")?;
+    for f in &m.fields {
+        let read = read_value(Kind::of(&f.ty), &ident(&f.ty));
+        let name = cs_field(m, f);
+        writeln!(out, "                    case {}:", client::tag(f))?;
+        if f.repeated {
+            writeln!(
+                out,
+                "                        {{\n                            uint n = r.ReadU32();\n                            for (uint i = 0; i < n; i++)\n                            {{\n                                msg.{}.Add({});\n                            }}\n                            break;\n                        }}",
+                name, read
+            )?;
+        } else {
+            writeln!(
+                out,
+                "                        msg.{} = {};\n                        break;",
+                name, read
+            )?;
+        }
+    }
+    writeln!(
+        out,
+        "                    default:\n                        // 新版本加的字段\n                        r.Pos = end;\n                        break;"
+    )?;
+    writeln!(
+        out,
+        "                }}\n            }}\n            return msg;\n        }}\n    }}"
+    )
+}
+
+fn ptos_source(schema: &ClientSchema) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// this file is automatically generated by protogen. please do not edit."
+    )?;
+    writeln!(out, "// 编解码与服务器 proto crate 的默认编码一致.")?;
+    writeln!(out, "using System;")?;
+    writeln!(out, "using System.Collections.Generic;")?;
+    writeln!(out, "using System.Text;")?;
+    writeln!(out)?;
+    writeln!(out, "namespace Ptos")?;
+    writeln!(out, "{{")?;
+    out.push_str(RUNTIME);
+    for m in schema.messages() {
+        write_class(&mut out, m)?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "    // 协议源文件里声明的常量")?;
+    writeln!(out, "    public static class Consts")?;
+    writeln!(out, "    {{")?;
+    for c in &schema.consts {
+        writeln!(
+            out,
+            "        public const {} {} = {};",
+            cs_elem_type(&c.ty),
+            c.name,
+            cs_const(c)
+        )?;
+    }
+    writeln!(out, "    }}")?;
+
+    writeln!(out)?;
+    writeln!(out, "    public static class AllPtos")?;
+    writeln!(out, "    {{")?;
+    writeln!(
+        out,
+        "        public const string Version = \"{}\";",
+        schema.version
+    )?;
+    writeln!(out)?;
+    writeln!(out, "        // 协议id -> 协议名, 未知的协议返回 null")?;
+    writeln!(out, "        public static string NameOf(uint protoId)")?;
+    writeln!(out, "        {{")?;
+    writeln!(out, "            switch (protoId)")?;
+    writeln!(out, "            {{")?;
+    for m in &schema.protocols {
+        writeln!(
+            out,
+            "                case {}: return \"{}\";",
+            m.id.unwrap(),
+            m.name
+        )?;
+    }
+    writeln!(out, "                default: return null;")?;
+    writeln!(out, "            }}\n        }}")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "        public static IProto Parse(uint protoId, byte[] buf, int start, int end)"
+    )?;
+    writeln!(out, "        {{")?;
+    writeln!(out, "            var r = new BytesReader(buf, start);")?;
+    writeln!(out, "            switch (protoId)")?;
+    writeln!(out, "            {{")?;
+    for m in &schema.protocols {
+        writeln!(
+            out,
+            "                case {}: return {}.Read(r, end);",
+            m.id.unwrap(),
+            ident(&m.name)
+        )?;
+    }
+    writeln!(
+        out,
+        "                default: throw new ProtoException(\"[AllPtos.Parse]: failed, proto_id=\" + protoId);"
+    )?;
+    writeln!(out, "            }}\n        }}")?;
+    writeln!(out)?;
+    writeln!(out, "        public static byte[] Serialize(IProto pto)")?;
+    writeln!(out, "        {{")?;
+    writeln!(out, "            var w = new BytesWriter();")?;
+    writeln!(out, "            pto.Write(w);")?;
+    writeln!(out, "            return w.Finish();")?;
+    writeln!(out, "        }}\n    }}")?;
+    out.push_str(FRAME);
+    Ok(out)
+}
+
+// 生成 into/Ptos.cs 和 into/PtosTests.cs
+pub fn export_csharp(config: &Config, into: &Path) -> std::result::Result<Vec<PathBuf>, Error> {
+    let schema = ClientSchema::load(config)?;
+    let ptos = ptos_source(&schema).expect("write c# failed");
+    fs::create_dir_all(into)?;
+    let mut files = Vec::new();
+    for (fname, content) in [("Ptos.cs", ptos.as_str()), ("PtosTests.cs", TESTS)] {
+        let path = into.join(fname);
+        fs::write(&path, content)?;
+        files.push(path);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> ClientSchema {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../proto");
+        let config = Config::new(dir.join("ptosrc"), dir.join("src"));
+        ClientSchema::load(&config).unwrap()
+    }
+
+    #[test]
+    fn client_sources() {
+        let schema = schema();
+        let ts = crate::ts::ptos_source(&schema).unwrap();
+        let cs = ptos_source(&schema).unwrap();
+        for m in &schema.protocols {
+            let id = m.id.unwrap();
+            assert!(ts.contains(&format!("export class {} {{", m.name)));
+            assert!(ts.contains(&format!(
+                "    case {}:\n      return {}.read(r, end);",
+                id, m.name
+            )));
+            assert!(cs.contains(&format!("    public class {} : IProto", m.name)));
+            assert!(cs.contains(&format!("case {}: return {}.Read(r, end);", id, m.name)));
+        }
+        assert!(ts.contains(&format!("PTO_VERSION = \"{}\"", schema.version)));
+        assert!(cs.contains(&format!("Version = \"{}\"", schema.version)));
+        // 常量
+        assert!(ts.contains("export const BAG_ITEMS_CAPACITY = 1000;\n"));
+        assert!(cs.contains("        public const uint BAG_ITEMS_CAPACITY = 1000;\n"));
+        assert!(cs.contains("        public const int LOGIN_SUCCESS = 2;\n"));
+    }
+
+    // 按字段编号升序写字段, 读到不认识的字段时跳过协议包剩下的部分
+    #[test]
+    fn unknown_fields() {
+        let m = MessageSchema {
+            name: "s_x".to_owned(),
+            is_protocol: true,
+            id: Some(201),
+            fields: vec![
+                FieldSchema {
+                    name: "b".to_owned(),
+                    ty: "string".to_owned(),
+                    tag: 2,
+                    repeated: false,
+                },
+                FieldSchema {
+                    name: "a".to_owned(),
+                    ty: "int32".to_owned(),
+                    tag: 1,
+                    repeated: true,
+                },
+            ],
+        };
+        let mut ts = String::new();
+        crate::ts::write_class(&mut ts, &m).unwrap();
+        let a = ts.find("    w.writeVarint32(8);").unwrap();
+        let b = ts.find("    w.writeVarint32(18);").unwrap();
+        assert!(a < b);
+        assert!(ts.contains("        default:\n          // 新版本加的字段\n          r.pos = end;\n          break;\n"));
+        assert!(!ts.contains("throw"));
+
+        let mut cs = String::new();
+        write_class(&mut cs, &m).unwrap();
+        let a = cs.find("            w.WriteVarint(8);").unwrap();
+        let b = cs.find("            w.WriteVarint(18);").unwrap();
+        assert!(a < b);
+        assert!(cs.contains("                    default:\n                        // 新版本加的字段\n                        r.Pos = end;\n                        break;\n"));
+        assert!(!cs.contains("throw"));
+    }
+}
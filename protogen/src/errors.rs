@@ -0,0 +1,44 @@
+use crate::lexer::ParseError;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    // 源文件或协议id清单的错误, 一次返回所有错误
+    Parse(Vec<ParseError>),
+    // 协议id分配失败
+    Manifest(String),
+    // 读取 git 版本失败
+    Git(String),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(errors) => {
+                for err in errors {
+                    writeln!(f, "error: {}", err)?;
+                }
+                write!(f, "{} error(s).", errors.len())
+            }
+            Error::Manifest(msg) => write!(f, "error: {}", msg),
+            Error::Git(msg) => write!(f, "error: {}", msg),
+            Error::Io(e) => write!(f, "error: {}", e),
+        }
+    }
+}
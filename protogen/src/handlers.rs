@@ -0,0 +1,403 @@
+// 为 service 里还没有处理函数的请求协议生成 handler 的框架代码(rengine/src/proto_handlers):
+// 每个请求生成一个 xxx_handler.rs, 并在 mod.rs 里加上 pub mod 和 Handler trait 的方法.
+// 已经存在的 handler 文件不会被覆盖, mod.rs 只插入缺少的部分.
+
+use crate::errors::Error;
+use crate::parser::RpcDef;
+use crate::proto::{self, ident, local_name, mod_path, Config};
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// 生成 handler 的结果
+#[derive(Debug, Default)]
+pub struct HandlerReport {
+    pub created: Vec<PathBuf>,   // 新建的 handler 文件
+    pub registered: Vec<String>, // 新注册到 mod.rs 的请求协议
+    pub skipped: Vec<String>,    // 没有处理的请求协议及原因
+}
+
+// rustfmt 的默认行宽, 超过时函数参数分行写
+const MAX_WIDTH: usize = 100;
+
+pub fn export_handlers(config: &Config, dir: &Path) -> Result<HandlerReport, Error> {
+    let services = proto::load_services(config)?;
+    let mod_file = dir.join("mod.rs");
+    let mut modrs = fs::read_to_string(&mod_file)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", mod_file.display(), err)))?;
+    let mut report = HandlerReport::default();
+    for service in &services {
+        let entity = match impl_block(&modrs, &service.name) {
+            Some((_, _, entity)) => entity,
+            None => {
+                for rpc in &service.rpcs {
+                    report.skipped.push(format!(
+                        "{}: no 'impl {}::Handler for ...' in {}",
+                        rpc.request,
+                        service.name,
+                        mod_file.display()
+                    ));
+                }
+                continue;
+            }
+        };
+        for rpc in &service.rpcs {
+            let method = ident(&rpc.request);
+            let (start, end, _) = impl_block(&modrs, &service.name).unwrap();
+            if modrs[start..end].contains(&format!("fn {}(", method)) {
+                continue;
+            }
+            let module = module_name(&rpc.request);
+            let path = dir.join(format!("{}.rs", module));
+            if path.exists() {
+                let content = fs::read_to_string(&path)?;
+                if !content.contains(&format!("pub fn {}(", method)) {
+                    report.skipped.push(format!(
+                        "{}: {} already exists, add 'pub fn {}' to it by hand",
+                        rpc.request,
+                        path.display(),
+                        method
+                    ));
+                    continue;
+                }
+            } else {
+                let content = handler_source(rpc, &entity).expect("write handler failed");
+                fs::write(&path, content)?;
+                report.created.push(path);
+            }
+            modrs = register(&modrs, &service.name, rpc, &module).expect("write mod.rs failed");
+            report.registered.push(rpc.request.clone());
+        }
+    }
+    if !report.registered.is_empty() {
+        fs::write(&mod_file, modrs)?;
+    }
+    Ok(report)
+}
+
+// s_shop_buy 的 handler 模块是 shop_buy_handler, 带包名时包名也是模块名的一部分
+fn module_name(request: &str) -> String {
+    let local = local_name(request);
+    let package = &request[..request.len() - local.len()];
+    let short = local.strip_prefix("s_").unwrap_or(local);
+    format!("{}{}_handler", ident(package), short)
+}
+
+// GameSharedEntity 的参数名是 game_entity
+fn entity_var(entity: &str) -> String {
+    let mut var = String::new();
+    for (idx, ch) in entity.trim_end_matches("SharedEntity").chars().enumerate() {
+        if ch.is_ascii_uppercase() && idx > 0 {
+            var.push('_');
+        }
+        var.push(ch.to_ascii_lowercase());
+    }
+    if var.is_empty() {
+        "entity".to_owned()
+    } else {
+        var + "_entity"
+    }
+}
+
+// 函数签名, 超过行宽时每个参数一行
+fn signature(indent: &str, head: &str, params: &[String], tail: &str) -> String {
+    let line = format!("{}{}({}){}", indent, head, params.join(", "), tail);
+    if line.len() <= MAX_WIDTH {
+        return line;
+    }
+    let mut out = format!("{}{}(\n", indent, head);
+    for param in params {
+        out.push_str(&format!("{}    {},\n", indent, param));
+    }
+    out.push_str(&format!("{}){}", indent, tail));
+    out
+}
+
+// service 在 mod.rs 里的 impl 块: (块的开始, 结束的 '}' 所在位置, 实现 Handler 的类型)
+fn impl_block(modrs: &str, service: &str) -> Option<(usize, usize, String)> {
+    let head = format!("impl {}::Handler for ", service);
+    let start = modrs.find(&head)?;
+    let rest = &modrs[start + head.len()..];
+    let entity: String = rest
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+    let open = start + modrs[start..].find('{')?;
+    let mut depth = 0;
+    for (idx, ch) in modrs[open..].char_indices() {
+        match ch {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((start, open + idx, entity));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// 在 mod.rs 里加上 pub mod 和 Handler 的方法
+fn register(modrs: &str, service: &str, rpc: &RpcDef, module: &str) -> Result<String, fmt::Error> {
+    let method = ident(&rpc.request);
+    let mut out = String::new();
+    let (_, end, _) = impl_block(modrs, service).unwrap();
+    out.push_str(&modrs[..end]);
+    writeln!(out)?;
+    let params = [
+        "&mut self".to_owned(),
+        "vfd: u64".to_owned(),
+        format!(
+            "pto: {}::{}",
+            mod_path(&rpc.request),
+            local_name(&rpc.request)
+        ),
+    ];
+    writeln!(
+        out,
+        "{}",
+        signature(
+            "    ",
+            &format!("fn {}", method),
+            &params,
+            " -> Result<()> {"
+        )
+    )?;
+    writeln!(out, "        {}::{}(self, vfd, pto)", module, method)?;
+    writeln!(out, "    }}")?;
+    out.push_str(&modrs[end..]);
+
+    // pub mod 按名字排序插入, 没有 pub mod 时插在最后一个 use 之后
+    let line = format!("pub mod {};", module);
+    let lines: Vec<&str> = out.lines().collect();
+    let mods: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i].starts_with("pub mod "))
+        .collect();
+    let pos = match mods.iter().find(|&&i| lines[i] > line.as_str()) {
+        Some(&i) => i,
+        None => match mods.last() {
+            Some(&i) => i + 1,
+            None => {
+                let last_use = (0..lines.len())
+                    .rev()
+                    .find(|&i| lines[i].starts_with("use "));
+                last_use.map(|i| i + 1).unwrap_or(0)
+            }
+        },
+    };
+    let mut result: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+    if mods.is_empty() {
+        result.insert(pos, String::new());
+        result.insert(pos + 1, line);
+    } else {
+        result.insert(pos, line);
+    }
+    Ok(result.join("\n") + "\n")
+}
+
+// 新的 handler 文件. 客户端的请求先找到玩家, 有返回协议时发送默认值的返回协议
+fn handler_source(rpc: &RpcDef, entity: &str) -> Result<String, fmt::Error> {
+    let method = ident(&rpc.request);
+    let request_ty = format!("{}::{}", mod_path(&rpc.request), local_name(&rpc.request));
+    let by_player = entity == "GameSharedEntity" && local_name(&rpc.request).starts_with("s_");
+    let var = entity_var(entity);
+    let mut out = String::new();
+    if by_player {
+        writeln!(out, "use crate::game_modules::player::Tplayer;")?;
+    }
+    writeln!(out, "use crate::shared_states::{};", entity)?;
+    writeln!(out, "use crate::Result;")?;
+    if by_player && rpc.response.is_some() {
+        writeln!(out, "use net::ProtoType;")?;
+    }
+    writeln!(out, "use proto::ptoout::*;")?;
+    writeln!(out)?;
+    match &rpc.response {
+        Some(resp) => writeln!(out, "// {} -> {}", rpc.request, resp)?,
+        None => writeln!(out, "// {}", rpc.request)?,
+    }
+    let entity_param = if by_player {
+        format!("{}: &mut {}", var, entity)
+    } else {
+        format!("_{}: &mut {}", var, entity)
+    };
+    let vfd_param = if by_player { "vfd: u64" } else { "_vfd: u64" };
+    let params = [
+        entity_param,
+        vfd_param.to_owned(),
+        format!("_ptoobj: {}", request_ty),
+    ];
+    writeln!(
+        out,
+        "{}",
+        signature(
+            "",
+            &format!("pub fn {}", method),
+            &params,
+            " -> Result<()> {"
+        )
+    )?;
+    if by_player {
+        let player = if rpc.response.is_some() {
+            "player"
+        } else {
+            "_player"
+        };
+        writeln!(
+            out,
+            "    let {} = match {}.get_player_by_vfd(vfd) {{",
+            player, var
+        )?;
+        writeln!(out, "        Some(player) => player,")?;
+        writeln!(out, "        None => return Ok(()),")?;
+        writeln!(out, "    }};")?;
+    }
+    match &rpc.response {
+        Some(resp) if by_player => {
+            let resp_ty = format!("{}::{}", mod_path(resp), local_name(resp));
+            writeln!(out, "    // TODO: 处理请求, 填写返回协议的字段")?;
+            writeln!(out, "    let sendptoid = {}::id();", resp_ty)?;
+            writeln!(out, "    let sendpto = {}::default();", resp_ty)?;
+            writeln!(
+                out,
+                "    let sendpto = ProtoType::{}(sendpto);",
+                ident(resp)
+            )?;
+            writeln!(out, "    player.send(sendptoid, sendpto);")?;
+        }
+        Some(resp) => writeln!(out, "    // TODO: 处理请求, 回复 {}", resp)?,
+        None => writeln!(out, "    // TODO: 处理请求")?,
+    }
+    writeln!(out, "    Ok(())")?;
+    writeln!(out, "}}")?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOD_RS: &str = r#"use crate::errors::Error;
+use crate::shared_states::GameSharedEntity;
+use crate::Result;
+use proto::ptoout::*;
+use proto::services::game;
+
+pub mod login_handler;
+
+impl game::Handler for GameSharedEntity {
+    type Error = Error;
+
+    fn s_login(&mut self, vfd: u64, pto: s_login::s_login) -> Result<()> {
+        login_handler::s_login(self, vfd, pto)
+    }
+}
+"#;
+
+    #[test]
+    fn generate_handlers() {
+        let root = std::env::temp_dir().join(format!("protogen_handlers_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let src = root.join("ptosrc");
+        let handlers = root.join("handlers");
+        let files = [
+            (
+                "protocol/login.proto",
+                "message s_login {}\nmessage c_login {}",
+            ),
+            (
+                "protocol/shop.proto",
+                "message s_shop_buy_with_a_long_name {}\nmessage c_shop_buy_with_a_long_name {}\nmessage s_ping {}\nmessage s_mail {}",
+            ),
+            ("protocol/db.proto", "message db_clear_req {}"),
+            (
+                "service/game.proto",
+                "service game { s_login -> c_login; s_shop_buy_with_a_long_name -> c_shop_buy_with_a_long_name; s_ping; s_mail; }\nservice db { db_clear_req; }",
+            ),
+        ];
+        for dir in ["primitive", "datatype"] {
+            fs::create_dir_all(src.join(dir)).unwrap();
+        }
+        for (fname, content) in files {
+            let path = src.join(fname);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        fs::create_dir_all(&handlers).unwrap();
+        fs::write(handlers.join("mod.rs"), MOD_RS).unwrap();
+        // 已有的文件不会被覆盖
+        fs::write(handlers.join("mail_handler.rs"), "// mail\n").unwrap();
+
+        let config = Config::new(&src, &root);
+        let report = export_handlers(&config, &handlers).unwrap();
+        assert_eq!(
+            report.registered,
+            vec!["s_shop_buy_with_a_long_name", "s_ping"]
+        );
+        assert_eq!(report.created.len(), 2);
+        assert_eq!(report.skipped.len(), 2);
+        assert!(report.skipped[0].starts_with("db_clear_req: no 'impl db::Handler for ...'"));
+        assert!(report.skipped[1].starts_with("s_mail: "));
+        assert_eq!(
+            fs::read_to_string(handlers.join("mail_handler.rs")).unwrap(),
+            "// mail\n"
+        );
+
+        let modrs = fs::read_to_string(handlers.join("mod.rs")).unwrap();
+        assert!(modrs.contains(
+            "pub mod login_handler;\npub mod ping_handler;\npub mod shop_buy_with_a_long_name_handler;\n"
+        ));
+        assert!(modrs.contains(
+            r#"        login_handler::s_login(self, vfd, pto)
+    }
+
+    fn s_shop_buy_with_a_long_name(
+        &mut self,
+        vfd: u64,
+        pto: s_shop_buy_with_a_long_name::s_shop_buy_with_a_long_name,
+    ) -> Result<()> {
+        shop_buy_with_a_long_name_handler::s_shop_buy_with_a_long_name(self, vfd, pto)
+    }
+
+    fn s_ping(&mut self, vfd: u64, pto: s_ping::s_ping) -> Result<()> {
+        ping_handler::s_ping(self, vfd, pto)
+    }
+}
+"#
+        ));
+        let ping = fs::read_to_string(handlers.join("ping_handler.rs")).unwrap();
+        assert_eq!(
+            ping,
+            r#"use crate::game_modules::player::Tplayer;
+use crate::shared_states::GameSharedEntity;
+use crate::Result;
+use proto::ptoout::*;
+
+// s_ping
+pub fn s_ping(game_entity: &mut GameSharedEntity, vfd: u64, _ptoobj: s_ping::s_ping) -> Result<()> {
+    let _player = match game_entity.get_player_by_vfd(vfd) {
+        Some(player) => player,
+        None => return Ok(()),
+    };
+    // TODO: 处理请求
+    Ok(())
+}
+"#
+        );
+        let shop =
+            fs::read_to_string(handlers.join("shop_buy_with_a_long_name_handler.rs")).unwrap();
+        assert!(shop.contains("use net::ProtoType;\n"));
+        assert!(shop.contains(
+            "    let sendpto = ProtoType::c_shop_buy_with_a_long_name(sendpto);\n    player.send(sendptoid, sendpto);\n"
+        ));
+
+        // 再次生成时没有变化
+        let report = export_handlers(&config, &handlers).unwrap();
+        assert!(report.registered.is_empty() && report.created.is_empty());
+        assert_eq!(fs::read_to_string(handlers.join("mod.rs")).unwrap(), modrs);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
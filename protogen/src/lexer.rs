@@ -0,0 +1,295 @@
+// 协议源文件(.proto)的词法分析
+// 支持行注释 "//" 和段块注释 "/*...*/", 每个 token 都记录了所在的行号和列号(从1开始).
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Ident(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Symbol(char),
+    Eof,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Ident(s) => write!(f, "'{}'", s),
+            TokenKind::Int(v) => write!(f, "'{}'", v),
+            TokenKind::Float(v) => write!(f, "'{}'", v),
+            TokenKind::Str(s) => write!(f, "\"{}\"", s),
+            TokenKind::Symbol(c) => write!(f, "'{}'", c),
+            TokenKind::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+    pub col: usize,
+}
+
+// 解析出错的位置及原因
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+impl ParseError {
+    pub fn new(file: &str, line: usize, col: usize, msg: String) -> ParseError {
+        ParseError {
+            file: file.to_owned(),
+            line,
+            col,
+            msg,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.col, self.msg)
+    }
+}
+
+const SYMBOLS: &str = "{}[]();=,-<>.";
+
+pub struct Lexer<'a> {
+    file: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(file: &'a str, src: &str) -> Lexer<'a> {
+        Lexer {
+            file,
+            chars: src.chars().collect(),
+            pos: 0,
+            line: 1,
+            col: 1,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, line: usize, col: usize, msg: String) -> ParseError {
+        ParseError::new(self.file, line, col, msg)
+    }
+
+    // 跳过空白和注释, 未闭合的段块注释会返回错误
+    fn skip_trivia(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => {
+                    while let Some(c) = self.peek(0) {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.bump();
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let (line, col) = (self.line, self.col);
+                    self.bump();
+                    self.bump();
+                    loop {
+                        match (self.peek(0), self.peek(1)) {
+                            (Some('*'), Some('/')) => {
+                                self.bump();
+                                self.bump();
+                                break;
+                            }
+                            (Some(_), _) => {
+                                self.bump();
+                            }
+                            (None, _) => {
+                                return Err(self.error(
+                                    line,
+                                    col,
+                                    "unterminated block comment".to_owned(),
+                                ));
+                            }
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_trivia()?;
+        let (line, col) = (self.line, self.col);
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => {
+                return Ok(Token {
+                    kind: TokenKind::Eof,
+                    line,
+                    col,
+                })
+            }
+        };
+        let kind = if c.is_ascii_alphabetic() || c == '_' {
+            let mut s = String::new();
+            while let Some(c) = self.peek(0) {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                s.push(c);
+                self.bump();
+            }
+            TokenKind::Ident(s)
+        } else if c.is_ascii_digit() {
+            let mut s = String::new();
+            while let Some(c) = self.peek(0) {
+                if !(c.is_ascii_alphanumeric() || c == '.' || c == '_') {
+                    break;
+                }
+                s.push(c);
+                self.bump();
+            }
+            parse_number(&s)
+                .ok_or_else(|| self.error(line, col, format!("invalid number '{}'", s)))?
+        } else if c == '"' {
+            self.bump();
+            let mut s = String::new();
+            loop {
+                match self.bump() {
+                    Some('"') => break,
+                    Some('\\') => match self.bump() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some(c) => s.push(c),
+                        None => break,
+                    },
+                    Some('\n') | None => {
+                        return Err(self.error(line, col, "unterminated string".to_owned()));
+                    }
+                    Some(c) => s.push(c),
+                }
+            }
+            TokenKind::Str(s)
+        } else if SYMBOLS.contains(c) {
+            self.bump();
+            TokenKind::Symbol(c)
+        } else {
+            self.bump();
+            return Err(self.error(line, col, format!("unexpected character '{}'", c)));
+        };
+        Ok(Token { kind, line, col })
+    }
+
+    // 整个文件切分成 token, 出错的字符会被跳过并记录, 最后总是以 Eof 结尾
+    pub fn tokenize(mut self) -> (Vec<Token>, Vec<ParseError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            match self.next_token() {
+                Ok(tok) => {
+                    let eof = tok.kind == TokenKind::Eof;
+                    tokens.push(tok);
+                    if eof {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    let fatal = self.peek(0).is_none();
+                    errors.push(err);
+                    if fatal {
+                        tokens.push(Token {
+                            kind: TokenKind::Eof,
+                            line: self.line,
+                            col: self.col,
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+        (tokens, errors)
+    }
+}
+
+fn parse_number(s: &str) -> Option<TokenKind> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return i64::from_str_radix(hex, 16).ok().map(TokenKind::Int);
+    }
+    if let Ok(v) = s.parse::<i64>() {
+        return Some(TokenKind::Int(v));
+    }
+    if s.contains('.') || s.contains('e') || s.contains('E') {
+        return s.parse::<f64>().ok().map(TokenKind::Float);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        let (tokens, errors) = Lexer::new("t.proto", src).tokenize();
+        assert!(errors.is_empty(), "{:?}", errors);
+        tokens.into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn skip_comments() {
+        let ks = kinds("message /* a\n b */ x { // c\n }");
+        assert_eq!(
+            ks,
+            vec![
+                TokenKind::Ident("message".to_owned()),
+                TokenKind::Ident("x".to_owned()),
+                TokenKind::Symbol('{'),
+                TokenKind::Symbol('}'),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn token_position() {
+        let (tokens, _) = Lexer::new("t.proto", "/* x */\n  int a = 1;").tokenize();
+        assert_eq!((tokens[0].line, tokens[0].col), (2, 3));
+        assert_eq!((tokens[3].line, tokens[3].col), (2, 11));
+    }
+
+    #[test]
+    fn unterminated_comment() {
+        let (_, errors) = Lexer::new("t.proto", "message x {}\n /* ...").tokenize();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "t.proto:2:2: unterminated block comment"
+        );
+    }
+}
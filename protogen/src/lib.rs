@@ -0,0 +1,22 @@
+// 协议代码生成
+// 在 build.rs 里调用 generate 把代码生成到 OUT_DIR, 也可以用 cargo run -p protogen 生成并更新协议id清单.
+pub mod check;
+mod client;
+mod csharp;
+mod errors;
+mod handlers;
+pub mod lexer;
+pub mod manifest;
+pub mod parser;
+mod proto;
+mod proto3;
+mod ts;
+
+pub use crate::check::{check, export_git_rev, CheckReport};
+pub use crate::csharp::export_csharp;
+pub use crate::errors::Error;
+pub use crate::handlers::{export_handlers, HandlerReport};
+pub use crate::lexer::ParseError;
+pub use crate::proto::{generate, Config, Report};
+pub use crate::proto3::export_proto3;
+pub use crate::ts::export_ts;
@@ -1,5 +1,162 @@
-mod proto;
+use conf::conf;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+const USAGE: &str = r#"usage:
+    protogen [--serde] [--protobuf]             生成代码到 out_dir, 并把新协议的id追加到协议id清单
+                                                --serde: 生成 serde derive 和 allptos 的 json 接口
+                                                --protobuf: 生成与 protobuf 完全一致的编码
+    protogen proto3 [dir]                       导出标准的 proto3 文件到 dir(默认 out_dir/proto3)
+    protogen ts [dir]                           生成 TypeScript 客户端代码到 dir(默认 out_dir/ts)
+    protogen csharp [dir]                       生成 C# 客户端代码到 dir(默认 out_dir/csharp)
+    protogen handlers [dir]                     为没有处理函数的请求协议生成 handler 框架代码到 dir(默认 handler_dir),
+                                                不会覆盖已有的文件
+    protogen check [--protobuf] [rev]           与 git 版本 rev(默认 HEAD) 比较协议兼容性
+    protogen check [--protobuf] --dir <ptosrc> [--manifest <ptoids.toml>]
+                                                与另一个源文件目录(及协议id清单)比较协议兼容性
+                                                --protobuf: 按 protobuf 编码检查, 新增字段可以使用任意未用过的编号"#;
 
 fn main() {
-    proto::parse_proto();
+    let sysconf = conf::Conf::new();
+    let config = protogen::Config::from_conf(&sysconf, ".");
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|s| s.as_str()) {
+        Some("check") => check(config, &args[1..]),
+        Some("proto3") if args.len() <= 2 => proto3(config, args.get(1)),
+        Some(lang @ ("ts" | "csharp")) if args.len() <= 2 => client(config, lang, args.get(1)),
+        Some("handlers") if args.len() <= 2 => {
+            let dir = args
+                .get(1)
+                .map(|s| s.as_str())
+                .or(sysconf.get_handler_dir());
+            handlers(config, dir.unwrap_or_else(|| usage()))
+        }
+        _ => generate(config, &args),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn exit_with(err: protogen::Error) -> ! {
+    eprintln!("{}", err);
+    eprintln!("protogen failed.");
+    process::exit(1);
+}
+
+fn generate(mut config: protogen::Config, args: &[String]) {
+    for arg in args {
+        match arg.as_str() {
+            "--serde" => config.serde = true,
+            "--protobuf" => config.protobuf = true,
+            _ => usage(),
+        }
+    }
+    config.update_manifest = true;
+    fs::create_dir_all(&config.out_dir).expect("create out_dir failed");
+    let report = protogen::generate(&config).unwrap_or_else(|err| exit_with(err));
+    for (name, id) in &report.retired {
+        println!(
+            "[id_manifest]: protocol removed, id {} is retired: {}",
+            id, name
+        );
+    }
+    for (name, id) in &report.appended {
+        println!("[id_manifest]: new protocol: {} = {}", name, id);
+    }
+    for fname in &report.files {
+        println!("{}", fname.display());
+    }
+    println!("protogen is ready.");
+}
+
+fn proto3(config: protogen::Config, dir: Option<&String>) {
+    let dir = dir
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config.out_dir.join("proto3"));
+    let path = protogen::export_proto3(&config, &dir).unwrap_or_else(|err| exit_with(err));
+    println!("{}", path.display());
+}
+
+fn client(config: protogen::Config, lang: &str, dir: Option<&String>) {
+    let dir = dir
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config.out_dir.join(lang));
+    let res = match lang {
+        "ts" => protogen::export_ts(&config, &dir),
+        _ => protogen::export_csharp(&config, &dir),
+    };
+    for path in res.unwrap_or_else(|err| exit_with(err)) {
+        println!("{}", path.display());
+    }
+}
+
+fn handlers(config: protogen::Config, dir: &str) {
+    let report =
+        protogen::export_handlers(&config, Path::new(dir)).unwrap_or_else(|err| exit_with(err));
+    for path in &report.created {
+        println!("[handlers]: new file: {}", path.display());
+    }
+    for name in &report.registered {
+        println!("[handlers]: registered: {}", name);
+    }
+    for msg in &report.skipped {
+        println!("[handlers]: skipped: {}", msg);
+    }
+    if report.registered.is_empty() && report.skipped.is_empty() {
+        println!("[handlers]: all requests are handled.");
+    }
+}
+
+// 有破坏兼容的改动时返回非 0
+fn check(mut config: protogen::Config, args: &[String]) {
+    let mut rev = None;
+    let mut dir = None;
+    let mut manifest = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--protobuf" => config.protobuf = true,
+            "--dir" => dir = iter.next().map(PathBuf::from),
+            "--manifest" => manifest = iter.next().map(PathBuf::from),
+            s if !s.starts_with('-') && rev.is_none() => rev = Some(s.to_owned()),
+            _ => usage(),
+        }
+    }
+
+    let tmpdir = env::temp_dir().join(format!("protogen_check_{}", process::id()));
+    let baseline = if dir.is_some() || manifest.is_some() {
+        let mut baseline = config.clone();
+        if let Some(dir) = dir {
+            baseline = protogen::Config::new(dir, &config.out_dir);
+            baseline.init_protos = config.init_protos.clone();
+        }
+        if let Some(manifest) = manifest {
+            baseline.id_manifest = manifest;
+        }
+        baseline
+    } else {
+        let rev = rev.unwrap_or_else(|| "HEAD".to_owned());
+        println!("[check]: baseline is git revision {}", rev);
+        let _ = fs::remove_dir_all(&tmpdir);
+        protogen::export_git_rev(&config, &rev, &tmpdir).unwrap_or_else(|err| exit_with(err))
+    };
+    let res = protogen::check(&config, &baseline);
+    let _ = fs::remove_dir_all(&tmpdir);
+    let report = res.unwrap_or_else(|err| exit_with(err));
+
+    for change in report.safe() {
+        println!("safe: {}", change);
+    }
+    for change in report.breaking() {
+        println!("breaking: {}", change);
+    }
+    let breaking = report.breaking().count();
+    if breaking > 0 {
+        println!("[check]: {} breaking change(s).", breaking);
+        process::exit(1);
+    }
+    println!("[check]: compatible.");
 }
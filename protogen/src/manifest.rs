@@ -0,0 +1,315 @@
+// 协议id清单
+// 清单文件记录了每个协议已分配的id, 每行一条 "协议名 = id", "#" 开头的是注释(与 toml 格式兼容).
+// 已在清单里的协议保持原来的 id 不变, 新协议分配新的 id 后追加到清单末尾.
+// 已删除的协议仍保留在清单里, 它的 id 不会再分配给其他协议.
+
+use crate::lexer::ParseError;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+// 前 100 是保留用
+pub const RESERVED_MAX_ID: u32 = 100;
+// 使用保留 id 的内置协议, 由网关(net 的 tcp 服务)直接处理: 心跳请求和回复
+pub const RESERVED_PROTOS: [(&str, u32); 2] = [("s_ping", 1), ("c_pong", 2)];
+// init_protos 的 id 范围: 101 到 200
+pub const INIT_MAX_ID: u32 = 200;
+pub const MAX_ID: u32 = 65535; // u16
+
+const MANIFEST_HEADER: &str = r#"# 协议id清单, 由 protogen 维护: 已有协议的 id 保持不变, 新协议的 id 追加到末尾.
+# 不要修改或删除已发布的条目, 否则客户端与服务器的协议id会不一致.
+"#;
+
+#[derive(Debug, Default)]
+pub struct IdManifest {
+    entries: Vec<(String, u32)>,
+    appended: Vec<(String, u32)>,
+}
+
+impl IdManifest {
+    // 文件不存在时返回空清单
+    pub fn load(path: &Path) -> Result<IdManifest, Vec<ParseError>> {
+        let fname = path.display().to_string();
+        let src = match fs::read_to_string(path) {
+            Ok(src) => src,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(vec![ParseError::new(&fname, 0, 0, err.to_string())]),
+        };
+        IdManifest::parse(&fname, &src)
+    }
+
+    pub fn parse(fname: &str, src: &str) -> Result<IdManifest, Vec<ParseError>> {
+        let mut manifest = IdManifest::default();
+        let mut errors = Vec::new();
+        let mut names = HashMap::new();
+        let mut ids = HashMap::new();
+        for (idx, line) in src.lines().enumerate() {
+            let lineno = idx + 1;
+            let content = line.split('#').next().unwrap().trim();
+            if content.is_empty() {
+                continue;
+            }
+            let col = line.find(content).unwrap() + 1;
+            let parsed = content
+                .split_once('=')
+                .and_then(|(name, id)| Some((name.trim(), id.trim().parse::<u32>().ok()?)));
+            let (name, id) = match parsed {
+                Some((name, id)) if !name.is_empty() => (name, id),
+                _ => {
+                    errors.push(ParseError::new(
+                        fname,
+                        lineno,
+                        col,
+                        format!("expected 'name = id', found '{}'", content),
+                    ));
+                    continue;
+                }
+            };
+            if let Some(reserved) = reserved_id(name) {
+                if id != reserved {
+                    errors.push(ParseError::new(
+                        fname,
+                        lineno,
+                        col,
+                        format!(
+                            "'{}' is a reserved protocol, its id must be {}",
+                            name, reserved
+                        ),
+                    ));
+                    continue;
+                }
+            } else if id <= RESERVED_MAX_ID || id > MAX_ID {
+                errors.push(ParseError::new(
+                    fname,
+                    lineno,
+                    col,
+                    format!(
+                        "id {} of '{}' out of range {}..={}",
+                        id,
+                        name,
+                        RESERVED_MAX_ID + 1,
+                        MAX_ID
+                    ),
+                ));
+                continue;
+            }
+            if let Some(exist) = names.insert(name.to_owned(), lineno) {
+                errors.push(ParseError::new(
+                    fname,
+                    lineno,
+                    col,
+                    format!("duplicate name '{}', first defined at line {}", name, exist),
+                ));
+                continue;
+            }
+            if let Some(exist) = ids.insert(id, lineno) {
+                errors.push(ParseError::new(
+                    fname,
+                    lineno,
+                    col,
+                    format!("duplicate id {}, first defined at line {}", id, exist),
+                ));
+                continue;
+            }
+            manifest.entries.push((name.to_owned(), id));
+        }
+        if errors.is_empty() {
+            Ok(manifest)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.entries
+            .iter()
+            .chain(self.appended.iter())
+            .find(|(n, _)| n == name)
+            .map(|(_, id)| *id)
+    }
+
+    fn is_used(&self, id: u32) -> bool {
+        self.entries
+            .iter()
+            .chain(self.appended.iter())
+            .any(|(_, i)| *i == id)
+    }
+
+    // 清单里已有但源文件里不存在的协议
+    pub fn retired<'a>(&'a self, names: &'a [String]) -> impl Iterator<Item = &'a (String, u32)> {
+        self.entries.iter().filter(move |(n, _)| !names.contains(n))
+    }
+
+    // 给不在清单里的协议分配 id.
+    // 内置协议使用 RESERVED_PROTOS 里固定的 id, init_protos 使用 101 到 200 里最小的空闲 id, 其他协议的 id 从当前最大 id(至少是 200)之后递增.
+    // 其他协议默认按名字排序分配; 指定 shuffle_seed 时, 用这个种子打乱分配顺序.
+    pub fn assign(
+        &mut self,
+        initprotos: &[String],
+        names: &[String],
+        shuffle_seed: Option<u64>,
+    ) -> Result<(), String> {
+        for (name, id) in RESERVED_PROTOS {
+            if names.iter().any(|n| n == name) && self.get(name).is_none() {
+                self.appended.push((name.to_owned(), id));
+            }
+        }
+        for name in initprotos {
+            if !names.contains(name) {
+                return Err(format!("no such init_protos: {}", name));
+            }
+            if self.get(name).is_some() {
+                continue;
+            }
+            let id = (RESERVED_MAX_ID + 1..=INIT_MAX_ID)
+                .find(|id| !self.is_used(*id))
+                .ok_or_else(|| format!("no free id in 101..=200 for init_protos: {}", name))?;
+            self.appended.push((name.clone(), id));
+        }
+
+        let mut others: Vec<&String> = names.iter().filter(|n| self.get(n).is_none()).collect();
+        others.sort();
+        if let Some(seed) = shuffle_seed {
+            others.shuffle(&mut StdRng::seed_from_u64(seed));
+        }
+        let mut next = self
+            .entries
+            .iter()
+            .chain(self.appended.iter())
+            .map(|(_, id)| *id)
+            .max()
+            .unwrap_or(0)
+            .max(INIT_MAX_ID);
+        for name in others {
+            next += 1;
+            if next > MAX_ID {
+                return Err(format!("protocol id exceeds {}: {}", MAX_ID, name));
+            }
+            self.appended.push((name.clone(), next));
+        }
+        Ok(())
+    }
+
+    // 本次新分配的协议
+    pub fn appended(&self) -> &[(String, u32)] {
+        &self.appended
+    }
+
+    // 把新分配的协议追加到清单文件末尾, 已有内容保持不变
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if self.appended.is_empty() {
+            return Ok(());
+        }
+        let is_new = !path.exists();
+        // 手工编辑过的清单最后一行可能没有换行, 先补上, 否则新的一行会接在它后面
+        let unterminated = !is_new && fs::read(path)?.last().is_some_and(|c| *c != b'\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if is_new {
+            file.write_all(MANIFEST_HEADER.as_bytes())?;
+        }
+        if unterminated {
+            file.write_all(b"\n")?;
+        }
+        for (name, id) in &self.appended {
+            writeln!(file, "{} = {}", name, id)?;
+        }
+        Ok(())
+    }
+}
+
+fn reserved_id(name: &str) -> Option<u32> {
+    RESERVED_PROTOS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, id)| *id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn keep_existing_ids() {
+        let mut m =
+            IdManifest::parse("ids", "s_login = 101\nc_x = 205 # 注释\nold = 230\n").unwrap();
+        let all = names(&["c_login", "c_x", "s_login", "b", "a"]);
+        m.assign(&names(&["s_login", "c_login"]), &all, None)
+            .unwrap();
+        assert_eq!(m.get("s_login"), Some(101));
+        assert_eq!(m.get("c_login"), Some(102));
+        assert_eq!(m.get("c_x"), Some(205));
+        // 新协议排在已有最大 id 之后, 已删除协议的 id 不会被复用
+        assert_eq!(m.get("a"), Some(231));
+        assert_eq!(m.get("b"), Some(232));
+        let retired: Vec<_> = m.retired(&all).collect();
+        assert_eq!(retired, vec![&("old".to_string(), 230)]);
+    }
+
+    #[test]
+    fn seeded_shuffle() {
+        let all = names(&["a", "b", "c", "d", "e", "f", "g", "h"]);
+        let assign = |seed| {
+            let mut m = IdManifest::default();
+            m.assign(&[], &all, seed).unwrap();
+            m.appended().to_vec()
+        };
+        assert_eq!(assign(None)[0], ("a".to_string(), 201));
+        assert_eq!(assign(Some(7)), assign(Some(7)));
+        assert_ne!(assign(Some(7)), assign(None));
+    }
+
+    #[test]
+    fn reserved_protos() {
+        let mut m = IdManifest::parse("ids", "s_login = 101\n").unwrap();
+        let all = names(&["c_pong", "s_login", "s_ping", "a"]);
+        m.assign(&names(&["s_login"]), &all, None).unwrap();
+        assert_eq!(m.get("s_ping"), Some(1));
+        assert_eq!(m.get("c_pong"), Some(2));
+        assert_eq!(m.get("a"), Some(201));
+
+        let errors = IdManifest::parse("ids", "s_ping = 1\nc_pong = 300\n").unwrap_err();
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["ids:2:1: 'c_pong' is a reserved protocol, its id must be 2"]
+        );
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        let errors = IdManifest::parse("ids", "a = 101\nb = 101\nc = 99\nd 300\n").unwrap_err();
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "ids:2:1: duplicate id 101, first defined at line 1",
+                "ids:3:1: id 99 of 'c' out of range 101..=65535",
+                "ids:4:1: expected 'name = id', found 'd 300'",
+            ]
+        );
+    }
+
+    #[test]
+    fn save_unterminated() {
+        let dir = std::env::temp_dir().join(format!("manifest_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ids.toml");
+        fs::write(&path, "s_login = 101").unwrap();
+        let mut m = IdManifest::load(&path).unwrap();
+        let all = names(&["s_login", "c_login"]);
+        m.assign(&names(&["s_login", "c_login"]), &all, None)
+            .unwrap();
+        m.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(saved, "s_login = 101\nc_login = 102\n");
+    }
+}
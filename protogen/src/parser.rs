@@ -0,0 +1,688 @@
+// 协议源文件(.proto)的语法分析
+// 语法规则:
+//   file    := [package] (message | service | const)*
+//   package := "package" name ("." name)* ";"
+//   message := "message" name "{" (field | message | ";")* "}" [";"]
+//   field   := ["repeated"] type name "=" tag ["[" option ("," option)* "]"] ";"
+//   type    := name ("." name)*
+//   option  := name "=" ["-"] value
+//   service := "service" name "{" (rpc | ";")* "}" [";"]
+//   rpc     := request ["->" response] ";"
+//   const   := "const" [type] name "=" ["-"] value ";"
+// 一个文件可以有多个 message, message 里也可以嵌套 message.
+// service 声明由哪个处理者处理哪些请求协议, 以及请求对应的返回协议.
+// package 声明文件里的 message 所在的包, 其他包的 message 用 "包名.名字" 引用.
+// 字段选项声明字段的约束和默认值, 如 [max_len=32], [min=0, max=999], [default=1], [max_count=100].
+// const 声明服务器和客户端共用的常量, 如 const uint32 BAG_ITEMS_CAPACITY = 1000;
+// 出错后会跳过当前字段(或 message)继续分析, 以便一次把所有错误都找出来.
+
+use crate::lexer::{Lexer, ParseError, Token, TokenKind};
+use std::fmt;
+
+// 字段选项的值
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionValue {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionValue::Int(v) => write!(f, "{}", v),
+            OptionValue::Float(v) => write!(f, "{:?}", v),
+            OptionValue::Str(s) => write!(f, "{:?}", s),
+            OptionValue::Bool(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldOption {
+    pub name: String,
+    pub value: OptionValue,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldDef {
+    pub name: String,
+    pub ty: String,
+    pub tag: i64,
+    pub repeated: bool,
+    pub options: Vec<FieldOption>,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct MessageDef {
+    pub name: String,
+    pub fields: Vec<FieldDef>,
+    pub nested: Vec<MessageDef>,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcDef {
+    pub request: String,
+    pub response: Option<String>,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceDef {
+    pub name: String,
+    pub rpcs: Vec<RpcDef>,
+    pub line: usize,
+    pub col: usize,
+}
+
+// 常量, 没有写类型时由值决定
+#[derive(Debug, Clone)]
+pub struct ConstDef {
+    pub name: String,
+    pub ty: Option<String>,
+    pub value: OptionValue,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageDef {
+    pub name: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+// 一个源文件里的所有顶层定义
+#[derive(Debug, Default)]
+pub struct SourceDef {
+    pub package: Option<PackageDef>,
+    pub messages: Vec<MessageDef>,
+    pub services: Vec<ServiceDef>,
+    pub consts: Vec<ConstDef>,
+}
+
+pub struct Parser<'a> {
+    file: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    errors: Vec<ParseError>,
+}
+
+// 分析一个源文件的内容, 返回所有顶层定义及所有错误
+pub fn parse_source(file: &str, src: &str) -> (SourceDef, Vec<ParseError>) {
+    let (tokens, errors) = Lexer::new(file, src).tokenize();
+    let mut parser = Parser {
+        file,
+        tokens,
+        pos: 0,
+        errors,
+    };
+    let source = parser.parse_file();
+    (source, parser.errors)
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn bump(&mut self) -> Token {
+        let tok = self.tokens[self.pos].clone();
+        if tok.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn is_symbol(&self, c: char) -> bool {
+        self.peek().kind == TokenKind::Symbol(c)
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Ident(s) if s == kw)
+    }
+
+    fn error_at(&self, tok: &Token, msg: String) -> ParseError {
+        ParseError::new(self.file, tok.line, tok.col, msg)
+    }
+
+    fn expect_symbol(&mut self, c: char) -> Result<Token, ParseError> {
+        if self.is_symbol(c) {
+            return Ok(self.bump());
+        }
+        let tok = self.peek().clone();
+        Err(self.error_at(&tok, format!("expected '{}', found {}", c, tok.kind)))
+    }
+
+    fn expect_ident(&mut self, what: &str) -> Result<(String, Token), ParseError> {
+        let tok = self.peek().clone();
+        match &tok.kind {
+            TokenKind::Ident(s) => {
+                let s = s.clone();
+                self.bump();
+                Ok((s, tok))
+            }
+            _ => Err(self.error_at(&tok, format!("expected {}, found {}", what, tok.kind))),
+        }
+    }
+
+    // 用 "." 连接的名字, 如 a.b.info
+    fn expect_dotted(&mut self, what: &str) -> Result<(String, Token), ParseError> {
+        let (mut name, start) = self.expect_ident(what)?;
+        while self.is_symbol('.') {
+            self.bump();
+            name.push('.');
+            name.push_str(&self.expect_ident(what)?.0);
+        }
+        Ok((name, start))
+    }
+
+    // 出错后跳到下一个 ";" 之后, 或停在 "}" 之前. 跳过的内容里有 "{ ... }"(比如出错的嵌套 message)时整个跳过,
+    // 不会把里面的 "}" 当成外层 message 的结束
+    fn recover_field(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            match self.peek().kind {
+                TokenKind::Eof => return,
+                TokenKind::Symbol('}') if depth == 0 => return,
+                TokenKind::Symbol('}') => {
+                    self.bump();
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                TokenKind::Symbol('{') => {
+                    self.bump();
+                    depth += 1;
+                }
+                TokenKind::Symbol(';') if depth == 0 => {
+                    self.bump();
+                    return;
+                }
+                _ => {
+                    self.bump();
+                }
+            }
+        }
+    }
+
+    // 出错后跳到下一个顶层 "message", "service" 或 "const"
+    fn recover_top(&mut self) {
+        while !(self.peek().kind == TokenKind::Eof
+            || self.is_keyword("message")
+            || self.is_keyword("service")
+            || self.is_keyword("const")
+            || self.is_keyword("package"))
+        {
+            self.bump();
+        }
+    }
+
+    fn parse_file(&mut self) -> SourceDef {
+        let mut source = SourceDef::default();
+        loop {
+            let tok = self.peek().clone();
+            match &tok.kind {
+                TokenKind::Eof => break,
+                TokenKind::Symbol(';') => {
+                    self.bump();
+                }
+                TokenKind::Ident(s) if s == "message" => match self.parse_message() {
+                    Ok(msg) => source.messages.push(msg),
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.recover_top();
+                    }
+                },
+                TokenKind::Ident(s) if s == "service" => match self.parse_service() {
+                    Ok(service) => source.services.push(service),
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.recover_top();
+                    }
+                },
+                TokenKind::Ident(s) if s == "package" => match self.parse_package() {
+                    Ok(def) => {
+                        let first =
+                            source.messages.len() + source.services.len() + source.consts.len()
+                                == 0;
+                        if let Some(exist) = &source.package {
+                            let msg = format!(
+                                "duplicate package declaration, first declared at line {}",
+                                exist.line
+                            );
+                            self.errors.push(self.error_at(&tok, msg));
+                        } else if !first {
+                            let msg = "package must be declared before any definition".to_owned();
+                            self.errors.push(self.error_at(&tok, msg));
+                        } else {
+                            source.package = Some(def);
+                        }
+                    }
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.recover_top();
+                    }
+                },
+                TokenKind::Ident(s) if s == "const" => match self.parse_const() {
+                    Ok(def) => source.consts.push(def),
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.recover_top();
+                    }
+                },
+                _ => {
+                    let err = self.error_at(
+                        &tok,
+                        format!(
+                            "expected 'message', 'service', 'const' or 'package', found {}",
+                            tok.kind
+                        ),
+                    );
+                    self.errors.push(err);
+                    self.bump();
+                    self.recover_top();
+                }
+            }
+        }
+        source
+    }
+
+    fn parse_message(&mut self) -> Result<MessageDef, ParseError> {
+        let kw = self.bump(); // "message"
+        let (name, _) = self.expect_ident("message name")?;
+        self.expect_symbol('{')?;
+        let mut msg = MessageDef {
+            name,
+            fields: Vec::new(),
+            nested: Vec::new(),
+            line: kw.line,
+            col: kw.col,
+        };
+        loop {
+            let tok = self.peek().clone();
+            match &tok.kind {
+                TokenKind::Symbol('}') => {
+                    self.bump();
+                    break;
+                }
+                TokenKind::Symbol(';') => {
+                    self.bump();
+                }
+                TokenKind::Eof => {
+                    return Err(self.error_at(
+                        &tok,
+                        format!("message '{}' is not closed, expected '}}'", msg.name),
+                    ));
+                }
+                TokenKind::Ident(s) if s == "message" => match self.parse_message() {
+                    Ok(nested) => msg.nested.push(nested),
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.recover_field();
+                    }
+                },
+                _ => match self.parse_field() {
+                    Ok(field) => msg.fields.push(field),
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.recover_field();
+                    }
+                },
+            }
+        }
+        Ok(msg)
+    }
+
+    fn parse_service(&mut self) -> Result<ServiceDef, ParseError> {
+        let kw = self.bump(); // "service"
+        let (name, _) = self.expect_ident("service name")?;
+        self.expect_symbol('{')?;
+        let mut service = ServiceDef {
+            name,
+            rpcs: Vec::new(),
+            line: kw.line,
+            col: kw.col,
+        };
+        loop {
+            let tok = self.peek().clone();
+            match &tok.kind {
+                TokenKind::Symbol('}') => {
+                    self.bump();
+                    break;
+                }
+                TokenKind::Symbol(';') => {
+                    self.bump();
+                }
+                TokenKind::Eof => {
+                    return Err(self.error_at(
+                        &tok,
+                        format!("service '{}' is not closed, expected '}}'", service.name),
+                    ));
+                }
+                _ => match self.parse_rpc() {
+                    Ok(rpc) => service.rpcs.push(rpc),
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.recover_field();
+                    }
+                },
+            }
+        }
+        Ok(service)
+    }
+
+    fn parse_rpc(&mut self) -> Result<RpcDef, ParseError> {
+        let (request, start) = self.expect_dotted("request protocol")?;
+        let response = if self.is_symbol('-') {
+            self.bump();
+            self.expect_symbol('>')?;
+            Some(self.expect_dotted("response protocol")?.0)
+        } else {
+            None
+        };
+        self.expect_symbol(';')?;
+        Ok(RpcDef {
+            request,
+            response,
+            line: start.line,
+            col: start.col,
+        })
+    }
+
+    fn parse_package(&mut self) -> Result<PackageDef, ParseError> {
+        let kw = self.bump(); // "package"
+        let (name, _) = self.expect_dotted("package name")?;
+        self.expect_symbol(';')?;
+        Ok(PackageDef {
+            name,
+            line: kw.line,
+            col: kw.col,
+        })
+    }
+
+    fn parse_const(&mut self) -> Result<ConstDef, ParseError> {
+        let kw = self.bump(); // "const"
+        let (first, _) = self.expect_ident("const name")?;
+        let (ty, name) = if self.is_symbol('=') {
+            (None, first)
+        } else {
+            (Some(first), self.expect_ident("const name")?.0)
+        };
+        self.expect_symbol('=')?;
+        let value = self.parse_value("const value")?;
+        self.expect_symbol(';')?;
+        Ok(ConstDef {
+            name,
+            ty,
+            value,
+            line: kw.line,
+            col: kw.col,
+        })
+    }
+
+    fn parse_field(&mut self) -> Result<FieldDef, ParseError> {
+        let start = self.peek().clone();
+        let repeated = if self.is_keyword("repeated") {
+            self.bump();
+            true
+        } else {
+            false
+        };
+        let (ty, _) = self.expect_dotted("field type")?;
+        let (name, _) = self.expect_ident("field name")?;
+        self.expect_symbol('=')?;
+        let tok = self.peek().clone();
+        let tag = match tok.kind {
+            TokenKind::Int(v) => v,
+            _ => {
+                return Err(
+                    self.error_at(&tok, format!("expected field number, found {}", tok.kind))
+                )
+            }
+        };
+        self.bump();
+        let options = if self.is_symbol('[') {
+            self.parse_options()?
+        } else {
+            Vec::new()
+        };
+        self.expect_symbol(';')?;
+        Ok(FieldDef {
+            name,
+            ty,
+            tag,
+            repeated,
+            options,
+            line: start.line,
+            col: start.col,
+        })
+    }
+
+    fn parse_options(&mut self) -> Result<Vec<FieldOption>, ParseError> {
+        self.bump(); // "["
+        let mut options = Vec::new();
+        loop {
+            let (name, start) = self.expect_ident("option name")?;
+            self.expect_symbol('=')?;
+            let value = self.parse_value("option value")?;
+            options.push(FieldOption {
+                name,
+                value,
+                line: start.line,
+                col: start.col,
+            });
+            if self.is_symbol(',') {
+                self.bump();
+                continue;
+            }
+            self.expect_symbol(']')?;
+            return Ok(options);
+        }
+    }
+
+    fn parse_value(&mut self, what: &str) -> Result<OptionValue, ParseError> {
+        let negative = self.is_symbol('-');
+        if negative {
+            self.bump();
+        }
+        let tok = self.peek().clone();
+        let value = match &tok.kind {
+            TokenKind::Int(v) if negative => OptionValue::Int(-v),
+            TokenKind::Int(v) => OptionValue::Int(*v),
+            TokenKind::Float(v) if negative => OptionValue::Float(-v),
+            TokenKind::Float(v) => OptionValue::Float(*v),
+            TokenKind::Str(s) if !negative => OptionValue::Str(s.clone()),
+            TokenKind::Ident(s) if !negative && s == "true" => OptionValue::Bool(true),
+            TokenKind::Ident(s) if !negative && s == "false" => OptionValue::Bool(false),
+            _ => return Err(self.error_at(&tok, format!("expected {}, found {}", what, tok.kind))),
+        };
+        self.bump();
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiple_and_nested() {
+        let src = r#"
+/* 多个 message */
+message a {
+    repeated int32 x = 1; message b { string s = 1; }
+    b y = 2;
+}
+message c {}
+"#;
+        let (source, errors) = parse_source("t.proto", src);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let msgs = source.messages;
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].name, "a");
+        assert_eq!(msgs[0].fields.len(), 2);
+        assert!(msgs[0].fields[0].repeated);
+        assert_eq!(msgs[0].nested[0].name, "b");
+        assert_eq!(msgs[0].nested[0].fields[0].ty, "string");
+        assert_eq!((msgs[1].line, msgs[1].col), (7, 1));
+    }
+
+    #[test]
+    fn collect_all_errors() {
+        let src = "message a {\n  int32 x = ;\n  int32 = 2;\n  int32 z = 3;\n}\nmesage b {}\n";
+        let (source, errors) = parse_source("t.proto", src);
+        let msgs = source.messages;
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "t.proto:2:13: expected field number, found ';'",
+                "t.proto:3:9: expected field name, found '='",
+                "t.proto:6:1: expected 'message', 'service', 'const' or 'package', found 'mesage'",
+            ]
+        );
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].fields.len(), 1);
+    }
+
+    #[test]
+    fn recover_nested() {
+        let src = "message a {\n  message {\n    int32 x = 1;\n  }\n  int32 y = 2;\n}\nmessage b { int32 z = 1; }\n";
+        let (source, errors) = parse_source("t.proto", src);
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["t.proto:2:11: expected message name, found '{'"]
+        );
+        let msgs = source.messages;
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].fields.len(), 1);
+        assert_eq!(msgs[0].fields[0].name, "y");
+        assert_eq!(msgs[1].name, "b");
+    }
+
+    #[test]
+    fn services() {
+        let src = "service player {\n  s_brief -> c_brief;\n  s_ping;\n}\nservice bad { s_x -> ; s_y; }\n";
+        let (source, errors) = parse_source("t.proto", src);
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["t.proto:5:22: expected response protocol, found ';'"]
+        );
+        assert_eq!(source.services.len(), 2);
+        let rpcs = &source.services[0].rpcs;
+        assert_eq!(source.services[0].name, "player");
+        assert_eq!(rpcs[0].request, "s_brief");
+        assert_eq!(rpcs[0].response.as_deref(), Some("c_brief"));
+        assert_eq!((rpcs[1].request.as_str(), rpcs[1].line), ("s_ping", 3));
+        assert!(rpcs[1].response.is_none());
+        assert_eq!(source.services[1].rpcs.len(), 1);
+    }
+
+    #[test]
+    fn field_options() {
+        let src = r#"message a {
+    string acc = 1 [max_len=32];
+    int32 stack = 2 [min=0, max=999, default=-1.5];
+    string s = 3 [default="x", flag=true];
+    int32 bad = 4 [min=-"x"];
+    int32 ok = 5;
+}"#;
+        let (source, errors) = parse_source("t.proto", src);
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["t.proto:5:25: expected option value, found \"x\""]
+        );
+        let fields = &source.messages[0].fields;
+        assert_eq!(fields.len(), 4);
+        assert_eq!(fields[0].options[0].name, "max_len");
+        assert_eq!(fields[0].options[0].value, OptionValue::Int(32));
+        let values: Vec<&OptionValue> = fields[1].options.iter().map(|o| &o.value).collect();
+        assert_eq!(
+            values,
+            vec![
+                &OptionValue::Int(0),
+                &OptionValue::Int(999),
+                &OptionValue::Float(-1.5)
+            ]
+        );
+        assert_eq!(
+            (fields[1].options[1].line, fields[1].options[1].col),
+            (3, 29)
+        );
+        assert_eq!(fields[2].options[0].value, OptionValue::Str("x".to_owned()));
+        assert_eq!(fields[2].options[1].value, OptionValue::Bool(true));
+        assert!(fields[3].options.is_empty());
+    }
+
+    #[test]
+    fn consts() {
+        let src = "const MAX = 10;\nconst uint32 CAP = 1000;\nconst bad = ;\nconst string NAME = \"x\";\nconst double RATE = -0.5;\n";
+        let (source, errors) = parse_source("t.proto", src);
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["t.proto:3:13: expected const value, found ';'"]
+        );
+        let consts = &source.consts;
+        assert_eq!(consts.len(), 4);
+        assert_eq!(
+            (consts[0].name.as_str(), consts[0].ty.as_deref()),
+            ("MAX", None)
+        );
+        assert_eq!(consts[0].value, OptionValue::Int(10));
+        assert_eq!(consts[1].ty.as_deref(), Some("uint32"));
+        assert_eq!((consts[1].line, consts[1].col), (2, 1));
+        assert_eq!(consts[2].value, OptionValue::Str("x".to_owned()));
+        assert_eq!(consts[3].value, OptionValue::Float(-0.5));
+    }
+
+    #[test]
+    fn packages() {
+        let src = "package bag.item;\nmessage a { common.info x = 1; info y = 2; }\nservice s { bag.s_x -> c_x; }\npackage other;\n";
+        let (source, errors) = parse_source("t.proto", src);
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["t.proto:4:1: duplicate package declaration, first declared at line 1"]
+        );
+        let package = source.package.unwrap();
+        assert_eq!((package.name.as_str(), package.line), ("bag.item", 1));
+        let fields = &source.messages[0].fields;
+        assert_eq!(fields[0].ty, "common.info");
+        assert_eq!(fields[1].ty, "info");
+        assert_eq!(source.services[0].rpcs[0].request, "bag.s_x");
+
+        let (source, errors) = parse_source("t.proto", "message a {}\npackage p;\n");
+        assert_eq!(
+            errors[0].to_string(),
+            "t.proto:2:1: package must be declared before any definition"
+        );
+        assert!(source.package.is_none());
+        let (_, errors) = parse_source("t.proto", "package p.;\n");
+        assert_eq!(
+            errors[0].to_string(),
+            "t.proto:1:11: expected package name, found ';'"
+        );
+    }
+
+    #[test]
+    fn unclosed_message() {
+        let (_, errors) = parse_source("t.proto", "message a {\n int32 x = 1;\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+        assert!(errors[0].msg.contains("not closed"));
+    }
+}
@@ -1,17 +1,22 @@
+use crate::check::{ConstSchema, FieldSchema, MessageSchema, Schema};
+use crate::errors::Error;
+use crate::lexer::ParseError;
+use crate::manifest::IdManifest;
+use crate::parser::{
+    self, ConstDef, FieldDef, FieldOption, MessageDef, OptionValue, RpcDef, ServiceDef,
+};
+use crate::proto3::{self, WireField};
 use conf::conf;
-use core::panic;
-use rand::Rng;
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Result;
-use std::io::{BufRead, Write};
+use std::fmt::{self, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::mpsc::{channel, Sender};
 use std::{fs, io, thread};
 extern crate md5;
-extern crate rand;
+
+type Result<T> = std::result::Result<T, fmt::Error>;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IType {
@@ -52,6 +57,19 @@ pub struct LineInfo {
     id: i32,                         // 协议id
     repeated: bool,                  // 是否是数组
     embed: Option<Rc<RefCell<Pto>>>, // 在具体解析时,bool 和 vint 共享 Vint; string 和 datatype 共享 Repeated
+    line: usize,                     // 在源文件中的行号
+    col: usize,                      // 在源文件中的列号
+    opts: FieldOpts,                 // 字段选项
+}
+
+// 字段选项里的约束和默认值, 已经检查过与字段类型匹配
+#[derive(Debug, Default)]
+pub struct FieldOpts {
+    max_len: Option<usize>,
+    min: Option<OptionValue>,
+    max: Option<OptionValue>,
+    default: Option<OptionValue>,
+    max_count: Option<usize>,
 }
 
 impl LineInfo {
@@ -71,105 +89,432 @@ impl LineInfo {
             id,
             repeated,
             embed: None,
+            line: 0,
+            col: 0,
+            opts: FieldOpts::default(),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct Pto {
-    name: String,
+    name: String, // 带包名的全名, 如 bag.item_info; 没有包名时就是 message 名字
+    package: String,
     itype: IType,
     members: Vec<LineInfo>,
+    file: String, // 定义所在的源文件
+    line: usize,
+    col: usize,
 }
 
 impl Pto {
     pub fn new(name: String, itype: IType) -> Pto {
         Pto {
             name,
+            package: String::new(),
             itype,
             members: Vec::new(),
+            file: String::new(),
+            line: 0,
+            col: 0,
         }
     }
+
+    fn error(&self, line: usize, col: usize, msg: String) -> ParseError {
+        ParseError::new(&self.file, line, col, msg)
+    }
 }
 
 type Dtmap = HashMap<String, Rc<RefCell<Pto>>>;
 
-pub fn parse_proto() {
-    let sysconf = conf::Conf::new();
-    let ptosrc = sysconf.get_src_dir();
-    let ptoout = sysconf.get_out_dir();
-    // ptoout 应该作为独立的仓库目录,不应自动创建
-    //fs::create_dir_all(&ptoout).unwrap();
-    let initprotos = sysconf.get_init_protos();
-    let (tx, rx) = channel::<(IType, PathBuf)>();
+// proto crate 根模块下已有的名字, 包名和没有包名的 message 不能与它们相同
+const RESERVED_NAMES: [&str; 10] = [
+    "allptos",
+    "consts",
+    "descriptor",
+    "errors",
+    "ptoout",
+    "reader",
+    "services",
+    "sizeofs",
+    "util",
+    "writer",
+];
+
+// 全名 a.b.name 的最后一段, 即结构体和模块的名字
+pub(crate) fn local_name(full: &str) -> &str {
+    full.rsplit('.').next().unwrap()
+}
+
+// 全名在 ptoout 里的模块路径 a::b::name
+pub(crate) fn mod_path(full: &str) -> String {
+    full.replace('.', "::")
+}
+
+// 全名展开成一个标识符 a_b_name, 用作 ProtoType 的成员名, 测试函数名和客户端的类名
+pub(crate) fn ident(full: &str) -> String {
+    full.replace('.', "_")
+}
+
+// 引用结构体的完整路径
+fn struct_path(full: &str) -> String {
+    format!("crate::{}::{}", mod_path(full), local_name(full))
+}
+
+// datatype 最大嵌套层数
+const MAX_NESTED_DEPTH: usize = 10;
+// field number 的上限, 与 protobuf 一致: 2^29 - 1
+const MAX_FIELD_NUMBER: i64 = (1 << 29) - 1;
+
+// 代码生成的配置, 路径都是相对于当前工作目录的
+#[derive(Debug, Clone)]
+pub struct Config {
+    // 源文件目录, 包含 primitive, datatype, protocol 三个子目录
+    pub src_dir: PathBuf,
+    // 生成文件目录, 生成 ptoout.rs 和 ptotests.rs
+    pub out_dir: PathBuf,
+    // 协议id编号固定的协议名
+    pub init_protos: Vec<String>,
+    // 协议id清单文件
+    pub id_manifest: PathBuf,
+    // 打乱新协议id分配顺序的随机种子
+    pub shuffle_seed: Option<u64>,
+    // 是否把新分配的协议id追加到清单文件
+    pub update_manifest: bool,
+    // 是否给生成的结构体加上 serde 的 Serialize/Deserialize, 并生成 allptos 的 json 接口
+    pub serde: bool,
+    // 是否生成与 protobuf 完全一致的编码, 见 proto3.rs
+    pub protobuf: bool,
+    // 没有 package 声明的文件是否以所在的子目录作为包名, 如 protocol/bag/item.proto 的包名是 bag
+    pub dir_packages: bool,
+}
+
+impl Config {
+    // 协议id清单默认是源文件目录旁边的 ptoids.toml
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(src_dir: P, out_dir: Q) -> Config {
+        let src_dir = src_dir.as_ref().to_path_buf();
+        let id_manifest = src_dir.with_file_name("ptoids.toml");
+        Config {
+            src_dir,
+            out_dir: out_dir.as_ref().to_path_buf(),
+            init_protos: Vec::new(),
+            id_manifest,
+            shuffle_seed: None,
+            update_manifest: false,
+            serde: false,
+            protobuf: false,
+            dir_packages: false,
+        }
+    }
+
+    // 从 conf.toml 读取配置, root 是 conf.toml 里相对路径的起点(工作区根目录)
+    pub fn from_conf<P: AsRef<Path>>(sysconf: &conf::Conf, root: P) -> Config {
+        let root = root.as_ref();
+        Config {
+            src_dir: root.join(sysconf.get_src_dir()),
+            out_dir: root.join(sysconf.get_out_dir()),
+            init_protos: sysconf.get_init_protos().clone(),
+            id_manifest: root.join(sysconf.get_id_manifest()),
+            shuffle_seed: sysconf.get_pto_shuffle_seed(),
+            update_manifest: false,
+            serde: false,
+            protobuf: false,
+            dir_packages: sysconf.get_pto_dir_packages(),
+        }
+    }
+}
+
+// 代码生成的结果
+#[derive(Debug, Default)]
+pub struct Report {
+    pub appended: Vec<(String, u32)>, // 本次新分配id的协议
+    pub retired: Vec<(String, u32)>,  // 已删除的协议, id 不会再被复用
+    pub files: Vec<PathBuf>,          // 生成的文件
+}
+
+// 解析 config.src_dir 下的所有源文件, 生成代码到 config.out_dir:
+// ptoout.rs 包含所有结构体的模块和 allptos 模块, 用 include! 引入到 ptoout 模块里;
+// ptotests.rs 包含每个结构体的读写测试, 用 include! 引入到集成测试里.
+pub fn generate(config: &Config) -> std::result::Result<Report, Error> {
+    let ptos = parse_src(config)?;
+    let mut names: Vec<String> = ptos.protocol.keys().cloned().collect();
+    names.sort();
+    let manifest = assign_ids(config, &names)?;
+    if config.update_manifest {
+        manifest.save(&config.id_manifest)?;
+    }
+    let mut allptos: Vec<(u32, String)> = names
+        .iter()
+        .map(|name| (manifest.get(name).unwrap(), name.clone()))
+        .collect();
+    allptos.sort();
+
+    let version = pto_version(&build_schema(&ptos, &manifest), config.protobuf);
+    // 写入 String 不会失败
+    let (ptoout, ptotests) =
+        generate_code(config, &allptos, &ptos, &version).expect("generate code failed");
+    let mut report = Report {
+        appended: manifest.appended().to_vec(),
+        retired: manifest.retired(&names).cloned().collect(),
+        files: Vec::new(),
+    };
+    for (fname, content) in [("ptoout.rs", ptoout), ("ptotests.rs", ptotests)] {
+        let path = config.out_dir.join(fname);
+        write_if_changed(&path, &content)?;
+        report.files.push(path);
+    }
+    Ok(report)
+}
+
+// 解析 config.src_dir 下的所有源文件, 得到协议结构的描述, 用于比较两个版本的差异.
+// assign 为 true 时给不在清单里的协议分配 id(不修改清单), 否则这些协议没有 id.
+pub(crate) fn load_schema(config: &Config, assign: bool) -> std::result::Result<Schema, Error> {
+    let ptos = parse_src(config)?;
+    let mut names: Vec<String> = ptos.protocol.keys().cloned().collect();
+    names.sort();
+    let manifest = if assign {
+        assign_ids(config, &names)?
+    } else {
+        IdManifest::load(&config.id_manifest).map_err(Error::Parse)?
+    };
+    Ok(build_schema(&ptos, &manifest))
+}
+
+// 所有 service 的声明, 请求和返回协议都是全名, 用于生成 handler 的框架代码
+pub(crate) fn load_services(config: &Config) -> std::result::Result<Vec<ServiceDef>, Error> {
+    let ptos = parse_src(config)?;
+    Ok(ptos.services.into_iter().map(|s| s.def).collect())
+}
+
+// 所有 datatype 和协议的结构, 字段按定义的顺序排列
+fn build_schema(ptos: &Ptos, manifest: &IdManifest) -> Schema {
+    let mut schema = Schema::default();
+    for pto in ptos.datatype.values().chain(ptos.protocol.values()) {
+        let pto = pto.borrow();
+        let fields = pto
+            .members
+            .iter()
+            .map(|l| FieldSchema {
+                name: l.name.clone(),
+                ty: l.literal.clone(),
+                tag: l.id,
+                repeated: l.repeated,
+            })
+            .collect();
+        let message = MessageSchema {
+            name: pto.name.clone(),
+            is_protocol: pto.itype == IType::Protocol,
+            id: manifest.get(&pto.name),
+            fields,
+        };
+        schema.messages.insert(pto.name.clone(), message);
+    }
+    schema.consts = ptos
+        .consts
+        .iter()
+        .map(|c| ConstSchema {
+            name: c.def.name.clone(),
+            ty: c.ty.clone(),
+            value: c.def.value.clone(),
+        })
+        .collect();
+    schema
+}
+
+// 协议版本号只由协议id和字段定义决定, 重新生成代码不会改变版本号.
+// 两种编码不能互通, 协议版本号也要区分.
+pub(crate) fn pto_version(schema: &Schema, protobuf: bool) -> String {
+    let mut ids: Vec<(u32, &str)> = schema
+        .messages
+        .values()
+        .filter_map(|m| Some((m.id?, m.name.as_str())))
+        .collect();
+    ids.sort();
+    let ids: Vec<String> = ids
+        .iter()
+        .map(|(id, name)| format!("{}={}", name, id))
+        .collect();
+    let digest: Vec<String> = schema
+        .messages
+        .values()
+        .map(|m| {
+            let fields: Vec<String> = m
+                .fields
+                .iter()
+                .map(|f| format!("{}:{}:{}:{}", f.tag, f.ty, f.name, f.repeated))
+                .collect();
+            format!("{}{{{}}}", m.name, fields.join(";"))
+        })
+        .collect();
+    let mut md5str = format!("{}\n{}", ids.join(","), digest.join("\n"));
+    if protobuf {
+        md5str.push_str("\nprotobuf");
+    }
+    format!("{:x}", md5::compute(&md5str))
+}
+
+// 源文件里定义的所有 message, 按类型分开
+struct Ptos {
+    primitive: Dtmap,
+    datatype: Dtmap,
+    protocol: Dtmap,
+    // 按名字排序
+    services: Vec<Service>,
+    // 按名字排序
+    consts: Vec<Const>,
+}
+
+// service 目录下定义的 service, rpc 里的协议名已经解析成全名
+struct Service {
+    def: ServiceDef,
+    package: String,
+    file: String,
+}
+
+// 任意源文件里定义的常量, ty 是源文件里的类型名, 没有写类型时由值推断
+struct Const {
+    def: ConstDef,
+    ty: String,
+    file: String,
+}
+
+fn parse_src(config: &Config) -> std::result::Result<Ptos, Error> {
+    let ptosrc = config.src_dir.clone();
+    let (tx, rx) = channel::<io::Result<(Option<IType>, PathBuf, String)>>();
     thread::spawn(move || {
-        let primitivedir = format!("{}/primitive", ptosrc);
-        let datatypedir = format!("{}/datatype", ptosrc);
-        let protocoldir = format!("{}/protocol", ptosrc);
-        walk_dir(&primitivedir, &tx, IType::Primitive).expect("walk_dir primitivedir failed");
-        walk_dir(&datatypedir, &tx, IType::Datatype).expect("walk_dir datatypedir failed");
-        walk_dir(&protocoldir, &tx, IType::Protocol).expect("walk_dir protocoldir failed");
-    });
-    let mut map_primitive = Dtmap::new();
-    let mut map_datatype = Dtmap::new();
-    let mut map_pto = Dtmap::new();
-    while let Ok((itype, fname)) = rx.recv() {
-        match itype {
-            IType::Primitive => {
-                srcfile2structs(itype, &fname, &mut map_primitive);
+        let dirs = [
+            ("primitive", Some(IType::Primitive)),
+            ("datatype", Some(IType::Datatype)),
+            ("protocol", Some(IType::Protocol)),
+            ("service", None),
+        ];
+        for (dir, itype) in dirs {
+            let path = ptosrc.join(dir);
+            // service 目录是可选的
+            if itype.is_none() && !path.exists() {
+                continue;
             }
-            IType::Datatype => {
-                srcfile2structs(itype, &fname, &mut map_datatype);
+            if let Err(err) = walk_dir(&path, "", &tx, itype) {
+                let err =
+                    io::Error::new(err.kind(), format!("{}/{}: {}", ptosrc.display(), dir, err));
+                let _ = tx.send(Err(err));
+                return;
             }
-            IType::Protocol => {
-                srcfile2structs(itype, &fname, &mut map_pto);
+        }
+    });
+    let mut ptos = Ptos {
+        primitive: Dtmap::new(),
+        datatype: Dtmap::new(),
+        protocol: Dtmap::new(),
+        services: Vec::new(),
+        consts: Vec::new(),
+    };
+    let mut errors = Vec::new();
+    while let Ok(res) = rx.recv() {
+        let (itype, fname, dir) = res?;
+        // primitive 没有包名
+        let dir_package = match itype {
+            Some(IType::Primitive) => None,
+            _ if config.dir_packages && !dir.is_empty() => Some(dir),
+            _ => None,
+        };
+        let (defs, services, consts) = srcfile2structs(itype, &fname, dir_package, &mut errors);
+        ptos.services.extend(services);
+        ptos.consts.extend(consts);
+        for pto in defs {
+            // 带包名的全名必须全局唯一
+            let name = pto.name.clone();
+            let exist = ptos
+                .primitive
+                .get(&name)
+                .or_else(|| ptos.datatype.get(&name))
+                .or_else(|| ptos.protocol.get(&name));
+            if let Some(exist) = exist {
+                let exist = exist.borrow();
+                errors.push(pto.error(
+                    pto.line,
+                    pto.col,
+                    format!(
+                        "duplicate message '{}', first defined at {}:{}:{}",
+                        name, exist.file, exist.line, exist.col
+                    ),
+                ));
+                continue;
             }
+            let dm = match pto.itype {
+                IType::Primitive => &mut ptos.primitive,
+                IType::Datatype => &mut ptos.datatype,
+                IType::Protocol => &mut ptos.protocol,
+            };
+            dm.insert(name, Rc::new(RefCell::new(pto)));
+        }
+    }
+    analyze_names(&ptos, &mut errors);
+    analyze_structs(
+        &ptos.primitive,
+        &mut ptos.datatype,
+        &mut ptos.protocol,
+        &mut errors,
+    );
+    analyze_services(&mut ptos.services, &ptos.protocol, &mut errors);
+    analyze_consts(&mut ptos.consts, &mut errors);
+    if !errors.is_empty() {
+        errors.sort_by(|a, b| (&a.file, a.line, a.col).cmp(&(&b.file, b.line, b.col)));
+        return Err(Error::Parse(errors));
+    }
+    Ok(ptos)
+}
+
+// 按协议id清单给协议分配 id, names 是排好序的协议名
+fn assign_ids(config: &Config, names: &[String]) -> std::result::Result<IdManifest, Error> {
+    let mut manifest = IdManifest::load(&config.id_manifest).map_err(Error::Parse)?;
+    manifest
+        .assign(&config.init_protos, names, config.shuffle_seed)
+        .map_err(Error::Manifest)?;
+    Ok(manifest)
+}
+
+// 内容没变时不重写文件, 避免依赖它的 crate 重新编译
+fn write_if_changed(path: &Path, content: &str) -> io::Result<()> {
+    if let Ok(old) = fs::read_to_string(path) {
+        if old == content {
+            return Ok(());
         }
     }
-    analyze_structs(&map_primitive, &mut map_datatype, &mut map_pto);
-    generate(initprotos, &ptoout, &mut map_datatype, &mut map_pto);
-    println!("parse_proto is ready.");
+    fs::write(path, content)
 }
 
+// dir 是相对于 primitive, datatype 等目录的子目录, 用 "." 连接, 如 bag.item
 fn walk_dir(
     srcdir: &dyn AsRef<Path>,
-    tx: &Sender<(IType, PathBuf)>,
-    itype: IType,
+    dir: &str,
+    tx: &Sender<io::Result<(Option<IType>, PathBuf, String)>>,
+    itype: Option<IType>,
 ) -> io::Result<()> {
     for entry in fs::read_dir(srcdir)? {
         let entry = entry?;
         let path = entry.path();
         if path.is_dir() {
-            walk_dir(&path, tx, itype)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let subdir = if dir.is_empty() {
+                name
+            } else {
+                format!("{}.{}", dir, name)
+            };
+            walk_dir(&path, &subdir, tx, itype)?;
         } else if path.is_file() {
-            tx.send((itype, path.clone())).unwrap();
+            // 接收端出错提前返回时, 不再需要后续的文件
+            if tx.send(Ok((itype, path.clone(), dir.to_owned()))).is_err() {
+                return Ok(());
+            }
         }
     }
     Ok(())
 }
 
-//str should be a "=" line
-fn line_strip(str: &str) -> LineInfo {
-    let (str1, str2) = str.split_once("=").unwrap();
-    let str2 = str2.trim();
-    assert!(!str2.is_empty());
-    let str1 = str1.trim();
-    assert!(!str1.is_empty());
-    let id: i32 = str2.parse().unwrap();
-    assert!(id > 0);
-    let mut vs: Vec<&str> = str1.split_whitespace().collect();
-    if !(vs.len() == 2 || (vs.len() == 3 && vs[0] == "repeated")) {
-        panic!("one line block error: {}", str);
-    }
-    let repeated = if vs.len() == 2 {
-        false
-    } else {
-        vs.remove(0);
-        true
-    };
-    //把字符串字面量转换成 wiretype, 在解析时找对应的 wiretype 即可.
-    let literal = vs[0];
-    let (wiretype, wirename) = match literal {
+// 基本类型对应的 wiretype 和 rust 类型, 不是基本类型时返回 None
+fn primitive_type(literal: &str) -> Option<(WireType, &'static str)> {
+    let res = match literal {
         "int" => (WireType::Vint, "i32"),
         "int8" => (WireType::Vint, "i8"),
         "uint8" => (WireType::Vint, "u8"),
@@ -183,208 +528,1015 @@ fn line_strip(str: &str) -> LineInfo {
         "float" => (WireType::Float, "f32"),
         "double" => (WireType::Double, "f64"),
         "string" => (WireType::Repeated, "String"),
-        _ => (WireType::Repeated, literal),
+        _ => return None,
     };
-    let name = vs[1];
-    LineInfo::new(
-        name.to_owned(),
+    Some(res)
+}
+
+//把字段定义转换成 LineInfo, literal 是已经解析过作用域的类型名
+fn field2lineinfo(field: &FieldDef, literal: &str) -> LineInfo {
+    //把字符串字面量转换成 wiretype, 在解析时找对应的 wiretype 即可.
+    let (wiretype, wirename) = primitive_type(literal).unwrap_or((WireType::Repeated, literal));
+    let mut lineinfo = LineInfo::new(
+        field.name.clone(),
         wirename.to_owned(),
         literal.to_owned(),
         wiretype,
-        id,
-        repeated,
-    )
+        field.tag as i32,
+        field.repeated,
+    );
+    lineinfo.line = field.line;
+    lineinfo.col = field.col;
+    lineinfo
 }
 
-// :TODO: 仅支持行注释 "//", 不支持段块注释 "/*...*/"
-fn srcfile2structs(itype: IType, path: &Path, dm: &mut Dtmap) {
-    //println!("[srcfile2structs]: {}, {}",itype,path.display());
-    let fname = path.file_name().unwrap();
-    let fname = Path::new(fname).file_stem().unwrap().to_str().unwrap();
-    println!("{}", fname);
-
-    let file = File::open(path).unwrap();
-    let mut unique_id = HashMap::<i32, bool>::new();
-    let mut unique_name = HashMap::<String, bool>::new();
-    let mut vs = Vec::new(); // 过滤掉所有注释后的文本行
-    for line in io::BufReader::new(file).lines() {
-        match line {
-            Ok(str0) => {
-                let str = if str0.contains("//") {
-                    let (str1, _) = str0.split_once("//").unwrap();
-                    str1.to_owned()
-                } else {
-                    str0
-                };
-                if str.trim().is_empty() {
-                    continue;
-                }
-                vs.push(str);
+// 分析一个源文件, 返回文件里定义的所有 message, service 和常量.
+// 嵌套的 message 会被展开成名为 "外层名_内层名" 的 datatype,
+// 外层 message 的字段可以直接用内层的名字引用它.
+// itype 为 None 的是 service 目录下的文件, 只能定义 service. 常量可以定义在任何源文件里.
+// 文件的包名由 package 声明决定, 没有声明时是 dir_package(按目录分包时的子目录).
+fn srcfile2structs(
+    itype: Option<IType>,
+    path: &Path,
+    dir_package: Option<String>,
+    errors: &mut Vec<ParseError>,
+) -> (Vec<Pto>, Vec<Service>, Vec<Const>) {
+    let fname = path.display().to_string();
+
+    let src = match fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(err) => {
+            errors.push(ParseError::new(&fname, 0, 0, err.to_string()));
+            return (Vec::new(), Vec::new(), Vec::new());
+        }
+    };
+    let (source, errs) = parser::parse_source(&fname, &src);
+    errors.extend(errs);
+
+    if let (Some(IType::Primitive), Some(def)) = (itype, &source.package) {
+        errors.push(ParseError::new(
+            &fname,
+            def.line,
+            def.col,
+            "primitive types can not be declared in a package".to_owned(),
+        ));
+    }
+    let package = match (itype, source.package) {
+        (Some(IType::Primitive), _) => String::new(),
+        (_, Some(def)) => def.name,
+        (_, None) => dir_package.unwrap_or_default(),
+    };
+    // 按目录分包时, 目录名要能作为模块名
+    let is_ident = |s: &str| {
+        s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if let Some(seg) = package
+        .split('.')
+        .find(|s| !package.is_empty() && !is_ident(s))
+    {
+        errors.push(ParseError::new(
+            &fname,
+            0,
+            0,
+            format!("directory '{}' can not be used as a package name", seg),
+        ));
+    }
+    let prefix = if package.is_empty() {
+        String::new()
+    } else {
+        format!("{}.", package)
+    };
+
+    let mut ptos = Vec::new();
+    let mut services = Vec::new();
+    match itype {
+        Some(itype) => {
+            for msg in &source.messages {
+                flatten_message(
+                    itype,
+                    &fname,
+                    msg,
+                    &prefix,
+                    &mut Vec::new(),
+                    &mut ptos,
+                    errors,
+                );
+            }
+            for pto in ptos.iter_mut() {
+                pto.package = package.clone();
+            }
+            for def in &source.services {
+                errors.push(ParseError::new(
+                    &fname,
+                    def.line,
+                    def.col,
+                    format!(
+                        "service '{}' must be defined in the service directory",
+                        def.name
+                    ),
+                ));
             }
-            Err(err) => {
-                println!("{}", err);
+        }
+        None => {
+            for msg in &source.messages {
+                errors.push(ParseError::new(
+                    &fname,
+                    msg.line,
+                    msg.col,
+                    format!(
+                        "message '{}' must be defined in the primitive, datatype or protocol directory",
+                        msg.name
+                    ),
+                ));
+            }
+            for def in source.services {
+                let file = fname.clone();
+                let package = package.clone();
+                services.push(Service { def, package, file });
             }
         }
     }
-    //由于可能出现一行的 block, 把所有行用分隔符" "合并为一行字符串,再分解
-    let str = vs.join(" ");
-    let (head, body) = str.split_once("{").unwrap();
-    assert!(body.ends_with('}'));
-    let body = body.strip_suffix('}').unwrap();
+    let consts = source
+        .consts
+        .into_iter()
+        .map(|def| Const {
+            ty: const_type(&def),
+            def,
+            file: fname.clone(),
+        })
+        .collect();
+    (ptos, services, consts)
+}
 
-    // get the message name
-    let vs: Vec<&str> = head.trim().split_whitespace().collect();
-    assert_eq!(vs.len(), 2);
-    assert_eq!(vs[0], "message");
-    let name = vs[1];
-    assert!(name == fname); //文件名与 message 名字必须相同.
+// 常量的类型, 没有写类型时: 整数是 int32(超出范围时 int64), 小数是 double
+fn const_type(def: &ConstDef) -> String {
+    if let Some(ty) = &def.ty {
+        return ty.clone();
+    }
+    let ty = match def.value {
+        OptionValue::Int(v) if i32::try_from(v).is_ok() => "int32",
+        OptionValue::Int(_) => "int64",
+        OptionValue::Float(_) => "double",
+        OptionValue::Str(_) => "string",
+        OptionValue::Bool(_) => "bool",
+    };
+    ty.to_owned()
+}
 
-    let pto = Rc::new(RefCell::new(Pto::new(name.to_owned(), itype)));
-    dm.insert(name.to_owned(), pto.clone());
+// scopes: 外层 message 的 (展开后的名字, 内层 message 的名字列表), 用于查找字段类型
+fn flatten_message(
+    itype: IType,
+    fname: &str,
+    msg: &MessageDef,
+    prefix: &str,
+    scopes: &mut Vec<(String, Vec<String>)>,
+    ptos: &mut Vec<Pto>,
+    errors: &mut Vec<ParseError>,
+) {
+    let name = format!("{}{}", prefix, msg.name);
+    let mut pto = Pto::new(name.clone(), itype);
+    pto.file = fname.to_owned();
+    pto.line = msg.line;
+    pto.col = msg.col;
+
+    let nested: Vec<String> = msg.nested.iter().map(|m| m.name.clone()).collect();
+    scopes.push((name.clone(), nested));
+
+    let mut unique_id = HashMap::<i64, &FieldDef>::new();
+    let mut unique_name = HashMap::<&str, &FieldDef>::new();
+    for field in &msg.fields {
+        if field.tag <= 0 || field.tag > MAX_FIELD_NUMBER {
+            errors.push(pto.error(
+                field.line,
+                field.col,
+                format!(
+                    "field number {} of '{}' out of range 1..={}",
+                    field.tag, field.name, MAX_FIELD_NUMBER
+                ),
+            ));
+            continue;
+        }
+        if let Some(exist) = unique_id.insert(field.tag, field) {
+            errors.push(pto.error(
+                field.line,
+                field.col,
+                format!(
+                    "field number {} of '{}' is already used by '{}' at line {}",
+                    field.tag, field.name, exist.name, exist.line
+                ),
+            ));
+            continue;
+        }
+        if let Some(exist) = unique_name.insert(&field.name, field) {
+            errors.push(pto.error(
+                field.line,
+                field.col,
+                format!(
+                    "duplicate field name '{}', first defined at line {}",
+                    field.name, exist.line
+                ),
+            ));
+            continue;
+        }
+        // 由内到外查找嵌套定义的 message
+        let literal = scopes
+            .iter()
+            .rev()
+            .find(|(_, nested)| nested.contains(&field.ty))
+            .map(|(scope, _)| format!("{}_{}", scope, field.ty))
+            .unwrap_or_else(|| field.ty.clone());
+        let mut lineinfo = field2lineinfo(field, &literal);
+        lineinfo.opts = field_options(&pto, field, &lineinfo.wirename, errors);
+        pto.members.push(lineinfo);
+    }
+    ptos.push(pto);
+
+    let prefix = format!("{}_", name);
+    for nested in &msg.nested {
+        flatten_message(
+            IType::Datatype,
+            fname,
+            nested,
+            &prefix,
+            scopes,
+            ptos,
+            errors,
+        );
+    }
+    scopes.pop();
+}
+
+// 整数类型的取值范围
+fn int_range(wirename: &str) -> Option<(i128, i128)> {
+    let range = match wirename {
+        "i8" => (i8::MIN as i128, i8::MAX as i128),
+        "u8" => (0, u8::MAX as i128),
+        "i16" => (i16::MIN as i128, i16::MAX as i128),
+        "u16" => (0, u16::MAX as i128),
+        "i32" => (i32::MIN as i128, i32::MAX as i128),
+        "u32" => (0, u32::MAX as i128),
+        "i64" => (i64::MIN as i128, i64::MAX as i128),
+        "u64" => (0, u64::MAX as i128),
+        _ => return None,
+    };
+    Some(range)
+}
+
+fn is_numeric(wirename: &str) -> bool {
+    int_range(wirename).is_some() || wirename == "f32" || wirename == "f64"
+}
 
-    // if this line has more fields, every line should be ended with ";"
-    let vs: Vec<&str> = body.split(';').collect();
-    for str in vs {
-        let str = str.trim();
-        if str.is_empty() {
+// 选项的值能否赋给 wirename 类型的字段
+fn value_fits(value: &OptionValue, wirename: &str) -> bool {
+    match (value, wirename) {
+        (OptionValue::Int(v), _) if int_range(wirename).is_some() => {
+            let (min, max) = int_range(wirename).unwrap();
+            (min..=max).contains(&(*v as i128))
+        }
+        (OptionValue::Int(_) | OptionValue::Float(_), "f32" | "f64") => true,
+        (OptionValue::Bool(_), "bool") => true,
+        (OptionValue::Str(_), "String") => true,
+        _ => false,
+    }
+}
+
+// 比较两个数值选项的大小
+fn compare_values(a: &OptionValue, b: &OptionValue) -> Option<std::cmp::Ordering> {
+    let as_f64 = |v: &OptionValue| match *v {
+        OptionValue::Int(v) => Some(v as f64),
+        OptionValue::Float(v) => Some(v),
+        _ => None,
+    };
+    match (a, b) {
+        (OptionValue::Int(a), OptionValue::Int(b)) => Some(a.cmp(b)),
+        _ => as_f64(a)?.partial_cmp(&as_f64(b)?),
+    }
+}
+
+// 检查字段选项: 选项名, 选项是否适用于字段类型, 值的类型和范围, 默认值是否满足约束
+fn field_options(
+    pto: &Pto,
+    field: &FieldDef,
+    wirename: &str,
+    errors: &mut Vec<ParseError>,
+) -> FieldOpts {
+    let mut opts = FieldOpts::default();
+    let mut errs = Vec::new();
+    let mut seen = HashMap::<&str, &FieldOption>::new();
+    let ty = if field.repeated {
+        format!("repeated {}", field.ty)
+    } else {
+        field.ty.clone()
+    };
+    for opt in &field.options {
+        let at = (opt.line, opt.col);
+        if let Some(exist) = seen.insert(&opt.name, opt) {
+            errs.push((
+                at,
+                format!(
+                    "duplicate option '{}' of field '{}', first defined at line {}",
+                    opt.name, field.name, exist.line
+                ),
+            ));
             continue;
         }
-        let lineinfo = line_strip(str);
-        let id = lineinfo.id;
-        let name = lineinfo.name.clone();
-        assert_eq!(unique_id.insert(id, true), None);
-        assert_eq!(unique_name.insert(name, true), None);
-        pto.borrow_mut().members.push(lineinfo);
+        let applies = match opt.name.as_str() {
+            "max_len" => wirename == "String",
+            "min" | "max" => is_numeric(wirename),
+            "default" => {
+                !field.repeated
+                    && (is_numeric(wirename) || wirename == "bool" || wirename == "String")
+            }
+            "max_count" => field.repeated,
+            _ => {
+                errs.push((
+                    at,
+                    format!(
+                        "unknown option '{}' of field '{}', expected max_len, min, max, default or max_count",
+                        opt.name, field.name
+                    ),
+                ));
+                continue;
+            }
+        };
+        if !applies {
+            errs.push((
+                at,
+                format!(
+                    "option '{}' does not apply to field '{}' of type {}",
+                    opt.name, field.name, ty
+                ),
+            ));
+            continue;
+        }
+        match (opt.name.as_str(), &opt.value) {
+            ("max_len", OptionValue::Int(v)) if *v >= 0 => opts.max_len = Some(*v as usize),
+            ("max_count", OptionValue::Int(v)) if *v >= 0 => opts.max_count = Some(*v as usize),
+            ("max_len" | "max_count", value) => errs.push((
+                at,
+                format!(
+                    "option '{}' of field '{}' must be a non-negative integer, found {}",
+                    opt.name, field.name, value
+                ),
+            )),
+            (name, value) if value_fits(value, wirename) => {
+                let value = Some(value.clone());
+                match name {
+                    "min" => opts.min = value,
+                    "max" => opts.max = value,
+                    _ => opts.default = value,
+                }
+            }
+            (name, value) => errs.push((
+                at,
+                format!(
+                    "value {} of option '{}' does not fit field '{}' of type {}",
+                    value, name, field.name, ty
+                ),
+            )),
+        }
+    }
+
+    let at = (field.line, field.col);
+    if let (Some(min), Some(max)) = (&opts.min, &opts.max) {
+        if compare_values(min, max) == Some(std::cmp::Ordering::Greater) {
+            errs.push((
+                at,
+                format!(
+                    "min {} of field '{}' is greater than max {}",
+                    min, field.name, max
+                ),
+            ));
+        }
+    }
+    if let Some(default) = &opts.default {
+        if let Some(min) = &opts.min {
+            if compare_values(default, min) == Some(std::cmp::Ordering::Less) {
+                errs.push((
+                    at,
+                    format!(
+                        "default {} of field '{}' is less than min {}",
+                        default, field.name, min
+                    ),
+                ));
+            }
+        }
+        if let Some(max) = &opts.max {
+            if compare_values(default, max) == Some(std::cmp::Ordering::Greater) {
+                errs.push((
+                    at,
+                    format!(
+                        "default {} of field '{}' exceeds max {}",
+                        default, field.name, max
+                    ),
+                ));
+            }
+        }
+        if let (OptionValue::Str(s), Some(max_len)) = (default, opts.max_len) {
+            if s.len() > max_len {
+                errs.push((
+                    at,
+                    format!(
+                        "default of field '{}' is {} bytes long, exceeds max_len {}",
+                        field.name,
+                        s.len(),
+                        max_len
+                    ),
+                ));
+            }
+        }
+    }
+    for ((line, col), msg) in errs {
+        errors.push(pto.error(line, col, msg));
+    }
+    opts
+}
+
+// 查找 package 里引用的 message: 带 "." 的是全名, 否则先找同一个包, 再找没有包名的
+fn resolve<'a>(map: &'a Dtmap, package: &str, name: &str) -> Option<&'a Rc<RefCell<Pto>>> {
+    if !package.is_empty() && !name.contains('.') {
+        if let Some(pto) = map.get(&format!("{}.{}", package, name)) {
+            return Some(pto);
+        }
     }
+    map.get(name)
 }
 
-fn recursive_clone(
-    parentname: &str,
+fn link_members(
+    pto: &Rc<RefCell<Pto>>,
     map_primitive: &Dtmap,
-    lineinfo: &mut LineInfo,
-    mcopy: &Dtmap,
-    depth: i32,
+    map_datatype: &Dtmap,
+    errors: &mut Vec<ParseError>,
 ) {
-    assert!(depth <= 10);
-    if lineinfo.embed.is_some() {
-        return;
-    }
-    //println!("{}-{}-{}",depth,lname,lineinfo.literal);
-    if lineinfo.wiretype == WireType::Repeated && lineinfo.literal != "string" {
-        if let Some(res) = mcopy.get(&lineinfo.literal) {
-            //内嵌 datatype
-            for lineinfo in res.borrow_mut().members.iter_mut() {
-                // 如果存在环形引用, RefCell 的动态借用规则会报错.
-                recursive_clone(parentname, map_primitive, lineinfo, mcopy, depth + 1);
+    let mut pto = pto.borrow_mut();
+    let package = pto.package.clone();
+    let mut errs = Vec::new();
+    for lineinfo in pto.members.iter_mut() {
+        let res = if lineinfo.wiretype == WireType::Repeated && lineinfo.literal != "string" {
+            //内嵌 datatype, 引用改成全名
+            let res = resolve(map_datatype, &package, &lineinfo.literal);
+            if let Some(embed) = res {
+                lineinfo.literal = embed.borrow().name.clone();
+                lineinfo.wirename = struct_path(&lineinfo.literal);
             }
-            lineinfo.embed = Some(res.clone());
+            res
         } else {
-            panic!(
-                "no such literal: {}-{}-{}",
-                parentname, lineinfo.name, lineinfo.literal
-            );
+            //找 primitive, literal 就是关键字而不是字段名
+            map_primitive.get(&lineinfo.literal)
+        };
+        match res {
+            Some(res) => lineinfo.embed = Some(res.clone()),
+            None => errs.push((
+                lineinfo.line,
+                lineinfo.col,
+                format!(
+                    "unknown type '{}' of field '{}'",
+                    lineinfo.literal, lineinfo.name
+                ),
+            )),
+        }
+    }
+    for (line, col, msg) in errs {
+        errors.push(pto.error(line, col, msg));
+    }
+}
+
+// 返回 datatype 的嵌套层数, 遇到环形嵌套时返回环的路径
+fn nested_depth(
+    name: &str,
+    map_datatype: &Dtmap,
+    path: &mut Vec<String>,
+    depths: &mut HashMap<String, usize>,
+) -> std::result::Result<usize, Vec<String>> {
+    if let Some(depth) = depths.get(name) {
+        return Ok(*depth);
+    }
+    if let Some(idx) = path.iter().position(|n| n == name) {
+        let mut cycle = path[idx..].to_vec();
+        cycle.push(name.to_owned());
+        return Err(cycle);
+    }
+    path.push(name.to_owned());
+    let mut depth = 1;
+    let pto = map_datatype[name].borrow();
+    for lineinfo in &pto.members {
+        if let Some(embed) = &lineinfo.embed {
+            let embed = embed.borrow();
+            if embed.itype == IType::Datatype {
+                depth = depth.max(1 + nested_depth(&embed.name, map_datatype, path, depths)?);
+            }
+        }
+    }
+    path.pop();
+    depths.insert(name.to_owned(), depth);
+    Ok(depth)
+}
+
+// 包名和 message 在生成代码里的名字不能冲突:
+// 1. 展开成标识符后相同(如 a.b_c 和 a_b.c), ProtoType 的成员名和客户端的类名会冲突;
+// 2. message 与包同名(如 message a.b 和 package a.b), 生成的模块会冲突;
+// 3. 包名或没有包名的 message 与 proto crate 根模块下已有的名字相同.
+fn analyze_names(ptos: &Ptos, errors: &mut Vec<ParseError>) {
+    let mut all: Vec<&Rc<RefCell<Pto>>> = ptos
+        .datatype
+        .values()
+        .chain(ptos.protocol.values())
+        .collect();
+    all.sort_by_key(|p| p.borrow().name.clone());
+    let mut packages = HashMap::<String, &Rc<RefCell<Pto>>>::new();
+    for pto in &all {
+        let package = pto.borrow().package.clone();
+        let mut path = String::new();
+        for seg in package.split('.').filter(|s| !s.is_empty()) {
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(seg);
+            packages.entry(path.clone()).or_insert(pto);
+        }
+    }
+    let mut idents = HashMap::<String, &Rc<RefCell<Pto>>>::new();
+    for pto in &all {
+        let p = pto.borrow();
+        if let Some(exist) = idents.insert(ident(&p.name), pto) {
+            let exist = exist.borrow();
+            errors.push(p.error(
+                p.line,
+                p.col,
+                format!(
+                    "message '{}' conflicts with '{}' at {}:{}:{}, both are generated as '{}'",
+                    p.name,
+                    exist.name,
+                    exist.file,
+                    exist.line,
+                    exist.col,
+                    ident(&p.name)
+                ),
+            ));
+        }
+        if let Some(exist) = packages.get(&p.name) {
+            let exist = exist.borrow();
+            errors.push(p.error(
+                p.line,
+                p.col,
+                format!(
+                    "message '{}' conflicts with package '{}' declared in {}",
+                    p.name, p.name, exist.file
+                ),
+            ));
+        }
+        let root = p.name.split('.').next().unwrap();
+        if RESERVED_NAMES.contains(&root) {
+            let what = if p.package.is_empty() {
+                "message"
+            } else {
+                "package"
+            };
+            errors.push(p.error(
+                p.line,
+                p.col,
+                format!(
+                    "{} '{}' conflicts with the module proto::{}",
+                    what, root, root
+                ),
+            ));
         }
-    } else if let Some(res) = map_primitive.get(&lineinfo.literal) {
-        //找 primitive, literal 就是关键字而不是字段名
-        lineinfo.embed = Some(res.clone());
-    } else {
-        panic!(
-            "no such literal: {}-{}-{}",
-            parentname, lineinfo.name, lineinfo.literal
-        );
     }
 }
 
-fn analyze_structs(map_primitive: &Dtmap, map_datatype: &mut Dtmap, map_pto: &mut Dtmap) {
-    //println!("map_primitive: {:?}\n\n", map_primitive);
-    //println!("map_datatype: {:?}\n\n", map_datatype);
-    //println!("map_pto: {:?}\n\n", map_pto);
+fn analyze_structs(
+    map_primitive: &Dtmap,
+    map_datatype: &mut Dtmap,
+    map_pto: &mut Dtmap,
+    errors: &mut Vec<ParseError>,
+) {
+    // 每一行不是 primitive 就是 datatype
+    for v in map_datatype.values().chain(map_pto.values()) {
+        link_members(v, map_primitive, map_datatype, errors);
+    }
 
     // map_datatype 可以有多层嵌套,但我们得有个层数限制,并且防范环形引用
-    //println!("\nmap_datatype:");
-    for (_k, v) in map_datatype.iter() {
-        let parentname = v.borrow_mut().name.clone();
-        for lineinfo in v.borrow_mut().members.iter_mut() {
-            recursive_clone(&parentname, map_primitive, lineinfo, map_datatype, 1);
+    let mut depths = HashMap::new();
+    let mut names: Vec<&String> = map_datatype.keys().collect();
+    names.sort();
+    for name in names {
+        let pto = map_datatype[name].borrow();
+        match nested_depth(name, map_datatype, &mut Vec::new(), &mut depths) {
+            Ok(depth) if depth > MAX_NESTED_DEPTH => errors.push(pto.error(
+                pto.line,
+                pto.col,
+                format!(
+                    "datatype '{}' is nested {} levels deep, max is {}",
+                    name, depth, MAX_NESTED_DEPTH
+                ),
+            )),
+            Ok(_) => {}
+            Err(cycle) => {
+                // 同一个环只在名字最小的 datatype 上报告一次
+                if cycle[0] == *name && cycle.iter().all(|n| n >= name) {
+                    errors.push(pto.error(
+                        pto.line,
+                        pto.col,
+                        format!(
+                            "datatype '{}' is recursively nested: {}",
+                            name,
+                            cycle.join(" -> ")
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+// service 的请求和返回都必须是协议, 每个请求协议只能由一个 service 处理
+fn analyze_services(services: &mut [Service], map_pto: &Dtmap, errors: &mut Vec<ParseError>) {
+    services.sort_by(|a, b| {
+        (&a.def.name, &a.file, a.def.line).cmp(&(&b.def.name, &b.file, b.def.line))
+    });
+    // 协议名解析成全名, 找不到的保持原样, 在下面报错
+    for service in services.iter_mut() {
+        for rpc in service.def.rpcs.iter_mut() {
+            for name in std::iter::once(&mut rpc.request).chain(rpc.response.as_mut()) {
+                if let Some(pto) = resolve(map_pto, &service.package, name) {
+                    *name = pto.borrow().name.clone();
+                }
+            }
         }
-        //println!("\n\n");
     }
-    // map_pto 只有一层,每一行不是 primitive 就是 datatype
-    //println!("\nmap_pto:");
-    for (_k, v) in map_pto.iter() {
-        let parentname = v.borrow_mut().name.clone();
-        for lineinfo in v.borrow_mut().members.iter_mut() {
-            assert!(lineinfo.embed.is_none());
-            recursive_clone(&parentname, map_primitive, lineinfo, map_datatype, 1);
+    let mut names = HashMap::<&str, &Service>::new();
+    let mut handled = HashMap::<&str, (&Service, &RpcDef)>::new();
+    for service in services.iter() {
+        let def = &service.def;
+        if let Some(exist) = names.insert(&def.name, service) {
+            errors.push(ParseError::new(
+                &service.file,
+                def.line,
+                def.col,
+                format!(
+                    "duplicate service '{}', first defined at {}:{}:{}",
+                    def.name, exist.file, exist.def.line, exist.def.col
+                ),
+            ));
+            continue;
+        }
+        for rpc in &def.rpcs {
+            for name in std::iter::once(&rpc.request).chain(rpc.response.as_ref()) {
+                if !map_pto.contains_key(name) {
+                    errors.push(ParseError::new(
+                        &service.file,
+                        rpc.line,
+                        rpc.col,
+                        format!("unknown protocol '{}' in service '{}'", name, def.name),
+                    ));
+                }
+            }
+            if let Some((exist, exist_rpc)) = handled.insert(&rpc.request, (service, rpc)) {
+                errors.push(ParseError::new(
+                    &service.file,
+                    rpc.line,
+                    rpc.col,
+                    format!(
+                        "protocol '{}' is already handled by service '{}' at {}:{}:{}",
+                        rpc.request, exist.def.name, exist.file, exist_rpc.line, exist_rpc.col
+                    ),
+                ));
+            }
         }
-        //println!("\n\n");
     }
-    //println!("map_pto: {:?}\n\n", map_pto);
 }
 
-fn generate(initprotos: &[String], outdir: &str, map_datatype: &mut Dtmap, map_pto: &mut Dtmap) {
-    let mut struct_names = Vec::<String>::new();
+// 常量的名字必须全局唯一, 类型必须是基本类型, 值必须能赋给该类型
+fn analyze_consts(consts: &mut [Const], errors: &mut Vec<ParseError>) {
+    consts.sort_by(|a, b| {
+        (&a.def.name, &a.file, a.def.line).cmp(&(&b.def.name, &b.file, b.def.line))
+    });
+    let mut names = HashMap::<&str, &Const>::new();
+    for c in consts.iter() {
+        let def = &c.def;
+        let error = |msg| ParseError::new(&c.file, def.line, def.col, msg);
+        if let Some(exist) = names.insert(&def.name, c) {
+            errors.push(error(format!(
+                "duplicate const '{}', first defined at {}:{}:{}",
+                def.name, exist.file, exist.def.line, exist.def.col
+            )));
+            continue;
+        }
+        match primitive_type(&c.ty) {
+            None => errors.push(error(format!(
+                "const '{}' has unknown type '{}', expected a primitive type",
+                def.name, c.ty
+            ))),
+            Some((_, wirename)) if !value_fits(&def.value, wirename) => {
+                errors.push(error(format!(
+                    "value {} of const '{}' does not fit type {}",
+                    def.value, def.name, c.ty
+                )))
+            }
+            _ => {}
+        }
+    }
+}
+
+// 生成 ptoout.rs 和 ptotests.rs 的内容
+fn generate_code(
+    config: &Config,
+    allptos: &[(u32, String)],
+    ptos: &Ptos,
+    version: &str,
+) -> Result<(String, String)> {
+    let mut out = String::new();
+    let mut tests = String::new();
+    write_file_header(&mut out)?;
+    write_file_header(&mut tests)?;
+    tests.push_str("use proto::{MsgRead, MsgWrite};\n");
+
+    // 每个包的模块, 没有包名的在根模块
+    let mut packages = Package::default();
+
     //生成 datatype struct
-    for (_k, v) in map_datatype.iter() {
-        datatype2file(outdir, 0, v).unwrap();
-        let name = v.borrow_mut().name.clone();
-        struct_names.push(name);
-    }
-
-    //生成 protocol struct
-    let mut allptos = Vec::new();
-    let mut ptoid = 100u32; // 前 100 是保留用
-    for name in initprotos {
-        if let Some((_, pto)) = map_pto.remove_entry(name) {
-            ptoid += 1;
-            let name = pto.borrow_mut().name.clone();
-            struct_names.push(name.clone());
-            allptos.push((ptoid, name));
-            pto2file(outdir, ptoid, &pto).unwrap();
-        } else {
-            panic!("no such init_protos: {}", name);
-        }
-    }
-    let mut others: Vec<&Rc<RefCell<Pto>>> = map_pto.values().collect();
-    let mut inorder = Vec::new();
-    let size = others.len();
-    let mut rng = rand::thread_rng();
-    for _i in 0..size {
-        let idx = rng.gen_range(0..others.len());
-        inorder.push(others.remove(idx));
-    }
-    assert!(inorder.len() == size);
-    ptoid = 200; // ptoid为 101 到 200 的顺序是固定不变的, 201 之后的顺序是随机的
-    for pto in inorder {
-        ptoid += 1;
-        let name = pto.borrow_mut().name.clone();
-        struct_names.push(name.clone());
-        allptos.push((ptoid, name));
-        pto2file(outdir, ptoid, pto).unwrap();
-    }
-    assert!(ptoid <= 65535); // u16
-
-    generate_all_pto_mapping(&allptos, outdir, "allptos.rs").unwrap();
-
-    // generate mod.rs
-    let fname = format!("{}/mod.rs", outdir);
-    let mut file = File::create(fname).unwrap();
-    //file header
-    write_file_header(&mut file).unwrap();
-    for name in struct_names {
-        let linestr = format!("pub mod {};\n", name);
-        file.write_all(linestr.as_bytes()).unwrap();
-    }
-    let linestr = format!("pub mod {};\n", "allptos");
-    file.write_all(linestr.as_bytes()).unwrap();
-}
-
-fn datatype2file_empty(outdir: &str, pto: &Rc<RefCell<Pto>>) -> Result<()> {
-    let struct_name = pto.borrow_mut().name.clone();
-    let fname = format!("{}/{}.rs", outdir, struct_name);
-    let mut file = File::create(fname).unwrap();
-    //file header
-    write_file_header(&mut file)?;
+    let mut datatypes: Vec<&Rc<RefCell<Pto>>> = ptos.datatype.values().collect();
+    datatypes.sort_by_key(|v| v.borrow().name.clone());
+    let mut datatype_names = Vec::new();
+    for v in datatypes {
+        let code = packages.get(&v.borrow().package);
+        datatype2mod(code, &mut tests, 0, v, config)?;
+        datatype_names.push(v.borrow().name.clone());
+    }
+
+    //生成 protocol struct, id 由协议id清单分配
+    for (ptoid, name) in allptos {
+        let pto = &ptos.protocol[name];
+        let code = packages.get(&pto.borrow().package);
+        pto2mod(code, &mut tests, *ptoid, pto, config)?;
+    }
+    packages.write(&mut out)?;
+
+    write_mod(&mut out, "consts", |out| generate_consts(out, &ptos.consts))?;
+    write_mod(&mut out, "allptos", |out| {
+        generate_all_pto_mapping(out, allptos, &datatype_names, version, config.serde)
+    })?;
+    write_mod(&mut out, "services", |out| {
+        generate_services(out, allptos, &ptos.services)
+    })?;
+    Ok((out, tests))
+}
+
+// 包 a.b 的代码生成在 pub mod a { pub mod b { ... } } 里
+#[derive(Default)]
+struct Package {
+    code: String,
+    children: std::collections::BTreeMap<String, Package>,
+}
+
+impl Package {
+    fn get(&mut self, package: &str) -> &mut String {
+        let mut pkg = self;
+        for seg in package.split('.').filter(|s| !s.is_empty()) {
+            pkg = pkg.children.entry(seg.to_owned()).or_default();
+        }
+        &mut pkg.code
+    }
+
+    fn write(&self, out: &mut String) -> Result<()> {
+        out.push_str(&self.code);
+        for (name, child) in &self.children {
+            write_mod(out, name, |out| child.write(out))?;
+        }
+        Ok(())
+    }
+}
+
+// 常量生成为 pub const, string 是 &str
+fn generate_consts(file: &mut String, consts: &[Const]) -> Result<()> {
+    for c in consts {
+        let wirename = primitive_type(&c.ty).unwrap().1;
+        let (ty, value) = match &c.def.value {
+            OptionValue::Str(s) => ("&str", format!("{:?}", s)),
+            value => (wirename, rust_literal(value, wirename)),
+        };
+        writeln!(file, "pub const {}: {} = {};", c.def.name, ty, value)?;
+    }
+    Ok(())
+}
+
+// 结构体的 derive 属性. 开启 serde 时缺少的字段使用默认值, 与二进制编码的规则一致.
+// 有字段声明了 default 选项时, Default 由 write_default 手动实现
+fn derive_attrs(config: &Config, derive_default: bool) -> String {
+    let derive = if derive_default {
+        "Debug,Default"
+    } else {
+        "Debug"
+    };
+    if config.serde {
+        format!(
+            "#[derive({},serde::Serialize,serde::Deserialize)]\n#[serde(default)]",
+            derive
+        )
+    } else {
+        format!("#[derive({})]", derive)
+    }
+}
+
+// 选项的值在生成代码里的字面量, 数值带上类型后缀
+fn rust_literal(value: &OptionValue, wirename: &str) -> String {
+    match value {
+        OptionValue::Int(v) if wirename == "f32" || wirename == "f64" => {
+            format!("{:?}{}", *v as f64, wirename)
+        }
+        OptionValue::Int(v) => format!("{}{}", v, wirename),
+        OptionValue::Float(v) => format!("{:?}{}", v, wirename),
+        OptionValue::Str(s) => format!("{:?}.to_owned()", s),
+        OptionValue::Bool(v) => v.to_string(),
+    }
+}
+
+// 数值选项在 descriptor 里的 f64 字面量
+fn f64_literal(value: &OptionValue) -> String {
+    match *value {
+        OptionValue::Int(v) => format!("{:?}", v as f64),
+        OptionValue::Float(v) => format!("{:?}", v),
+        _ => unreachable!(),
+    }
+}
+
+// 字段约束的检查语句, 不包括嵌套的 datatype. repeated 字段检查元素个数和每个元素
+fn constraint_checks(struct_name: &str, lineinfo: &LineInfo) -> Vec<String> {
+    let opts = &lineinfo.opts;
+    let field = format!("\"{}.{}\"", struct_name, lineinfo.name);
+    let (value, len) = if lineinfo.repeated {
+        ("*v".to_owned(), "v.len()".to_owned())
+    } else {
+        let value = format!("self.{}", lineinfo.name);
+        let len = format!("{}.len()", value);
+        (value, len)
+    };
+    let mut checks = Vec::new();
+    if let Some(max_len) = opts.max_len {
+        checks.push(format!(
+            "util::check_len({}, {}, {})?;",
+            field, len, max_len
+        ));
+    }
+    if let Some(min) = &opts.min {
+        checks.push(format!(
+            "util::check_min({}, {}, {})?;",
+            field,
+            value,
+            rust_literal(min, &lineinfo.wirename)
+        ));
+    }
+    if let Some(max) = &opts.max {
+        checks.push(format!(
+            "util::check_max({}, {}, {})?;",
+            field,
+            value,
+            rust_literal(max, &lineinfo.wirename)
+        ));
+    }
+    if !lineinfo.repeated {
+        return checks;
+    }
+    let mut stmts = Vec::new();
+    if let Some(max_count) = opts.max_count {
+        stmts.push(format!(
+            "util::check_count({}, self.{}.len(), {})?;",
+            field, lineinfo.name, max_count
+        ));
+    }
+    if !checks.is_empty() {
+        stmts.push(format!(
+            "for v in &self.{} {{ {} }}",
+            lineinfo.name,
+            checks.join(" ")
+        ));
+    }
+    stmts
+}
+
+// 随机值调整到约束范围内
+fn random_fixups(lineinfo: &LineInfo) -> Vec<String> {
+    let opts = &lineinfo.opts;
+    let name = &lineinfo.name;
+    let ty = &lineinfo.wirename;
+    let clamp = match (&opts.min, &opts.max) {
+        (Some(min), Some(max)) => format!(
+            ".clamp({}, {})",
+            rust_literal(min, ty),
+            rust_literal(max, ty)
+        ),
+        (Some(min), None) => format!(".max({})", rust_literal(min, ty)),
+        (None, Some(max)) => format!(".min({})", rust_literal(max, ty)),
+        (None, None) => String::new(),
+    };
+    let mut fixups = Vec::new();
+    if lineinfo.repeated {
+        if let Some(max_count) = opts.max_count {
+            fixups.push(format!("msg.{}.truncate({});", name, max_count));
+        }
+        if let Some(max_len) = opts.max_len {
+            fixups.push(format!(
+                "for v in msg.{}.iter_mut() {{ util::truncate_str(v, {}); }}",
+                name, max_len
+            ));
+        }
+        if !clamp.is_empty() {
+            fixups.push(format!(
+                "for v in msg.{}.iter_mut() {{ *v = v{}; }}",
+                name, clamp
+            ));
+        }
+    } else {
+        if let Some(max_len) = opts.max_len {
+            fixups.push(format!(
+                "util::truncate_str(&mut msg.{}, {});",
+                name, max_len
+            ));
+        }
+        if !clamp.is_empty() {
+            fixups.push(format!("msg.{0} = msg.{0}{1};", name, clamp));
+        }
+    }
+    fixups
+        .into_iter()
+        .map(|s| format!("        {}", s))
+        .collect()
+}
+
+// check 只检查本结构体的字段, 读取协议时调用(嵌套的 datatype 在读取时已经检查过);
+// validate 还会检查嵌套的 datatype, 用于检查构造出来的协议
+fn write_validate(
+    file: &mut String,
+    struct_name: &str,
+    checks: &[String],
+    nested: &[String],
+) -> Result<()> {
+    let indent =
+        |stmts: &[String]| -> String { stmts.iter().map(|s| format!("        {}\n", s)).collect() };
+    write!(
+        file,
+        r#"
+impl {0} {{
+    // 检查字段是否满足协议源文件里的约束, 不包括嵌套的 datatype
+    fn check(&self) -> Result<()> {{
+{1}        Ok(())
+    }}
+
+    // 检查所有字段(包括嵌套的 datatype)是否满足协议源文件里的约束
+    pub fn validate(&self) -> Result<()> {{
+        self.check()?;
+{2}        Ok(())
+    }}
+}}
+"#,
+        struct_name,
+        indent(checks),
+        indent(nested),
+    )
+}
+
+// 有字段声明了 default 选项时手动实现 Default
+fn write_default(file: &mut String, struct_name: &str, members: &[LineInfo]) -> Result<()> {
+    let fields: Vec<String> = members
+        .iter()
+        .map(|l| {
+            let value = match &l.opts.default {
+                Some(value) => rust_literal(value, &l.wirename),
+                None => "Default::default()".to_owned(),
+            };
+            format!("            {}: {},", l.name, value)
+        })
+        .collect();
+    write!(
+        file,
+        r#"
+impl Default for {0} {{
+    fn default() -> Self {{
+        {0} {{
+{1}
+        }}
+    }}
+}}
+"#,
+        struct_name,
+        fields.join("\n"),
+    )
+}
+
+// 每个结构体生成为一个内联模块: pub mod name { ... }
+fn write_mod<F>(out: &mut String, name: &str, f: F) -> Result<()>
+where
+    F: FnOnce(&mut String) -> Result<()>,
+{
+    writeln!(out, "\npub mod {} {{", name)?;
+    f(out)?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn datatype2mod_empty(file: &mut String, pto: &Rc<RefCell<Pto>>, config: &Config) -> Result<()> {
+    let struct_name = local_name(&pto.borrow().name).to_owned();
     //a whole empty structure
     let wholestruct = format!(
         r#"
@@ -392,7 +1544,7 @@ use crate::{{MsgRead, MsgWrite, BytesReader, BytesWriter, Error, Result}};
 use crate::sizeofs;
 use crate::util;
 
-#[derive(Debug,Default)]
+{1}
 pub struct {0} {{
 
 }}
@@ -402,7 +1554,19 @@ impl {0} {{
         Self::default()
     }}
 }}
-
+"#,
+        struct_name,
+        derive_attrs(config, true),
+    );
+    write_line(file, &wholestruct)?;
+    write_validate(file, &struct_name, &[], &[])?;
+    if config.protobuf {
+        proto3::write_codec(file, &struct_name, &mut [])?;
+        write_line(file, "\n\n")?;
+        return Ok(());
+    }
+    let codec = format!(
+        r#"
 impl MsgRead for {0} {{
     fn read(_r: &mut BytesReader, _bytes: &[u8]) -> Result<Self> {{
         let msg = {0} {{}};
@@ -421,61 +1585,236 @@ impl MsgWrite for {0} {{
 "#,
         struct_name,
     );
-    write_line(&mut file, &wholestruct)?;
-    write_line(&mut file, "\n\n")?;
+    write_line(file, &codec)?;
+    write_line(file, "\n\n")?;
     Ok(())
 }
 
-fn datatype2file(outdir: &str, ptoid: u32, pto: &Rc<RefCell<Pto>>) -> Result<()> {
-    if pto.borrow_mut().members.is_empty() {
-        return datatype2file_empty(outdir, pto);
+fn datatype2mod(
+    out: &mut String,
+    tests: &mut String,
+    ptoid: u32,
+    pto: &Rc<RefCell<Pto>>,
+    config: &Config,
+) -> Result<()> {
+    let full_name = pto.borrow().name.clone();
+    write_mod(out, local_name(&full_name), |file| {
+        if pto.borrow().members.is_empty() {
+            datatype2mod_empty(file, pto, config)?;
+        } else {
+            datatype2mod_body(file, ptoid, pto, config)?;
+        }
+        write_descriptor(file, ptoid, pto)
+    })?;
+    generate_test_func(tests, pto)
+}
+
+// 协议的发送方向由协议名前缀决定: s_ 发给服务器, c_ 发给客户端, 其他的是服务器之间的 rpc
+fn direction(pto: &Pto) -> &'static str {
+    let name = local_name(&pto.name);
+    if pto.itype != IType::Protocol {
+        "Direction::None"
+    } else if name.starts_with("s_") {
+        "Direction::ToServer"
+    } else if name.starts_with("c_") {
+        "Direction::ToClient"
+    } else {
+        "Direction::Rpc"
     }
-    let struct_name = pto.borrow_mut().name.clone();
-    let fname = format!("{}/{}.rs", outdir, struct_name);
-    let mut file = File::create(fname).unwrap();
-    //file header
-    write_file_header(&mut file)?;
-    //imports
-    let mut embednames = HashMap::new();
-    for lineinfo in &pto.borrow_mut().members {
-        if let Some(embed) = &lineinfo.embed {
-            if embed.borrow_mut().itype == IType::Datatype {
-                let names = embed.borrow_mut().name.clone();
-                let str = format!("use crate::{}::{}", names, names);
-                embednames.insert(str, true);
-            }
+}
+
+// 协议使用的连接: s_ 和 c_ 是客户端连接, db_ 是 db 服务的 rpc 连接, 其他的是服务器之间的 rpc 连接
+fn pto_channel(pto: &Pto) -> &'static str {
+    let name = local_name(&pto.name);
+    if pto.itype != IType::Protocol {
+        "Channel::None"
+    } else if name.starts_with("s_") || name.starts_with("c_") {
+        "Channel::Client"
+    } else if name.starts_with("db_") {
+        "Channel::Db"
+    } else {
+        "Channel::Rpc"
+    }
+}
+
+fn field_type(lineinfo: &LineInfo) -> String {
+    if let Some(embed) = &lineinfo.embed {
+        if embed.borrow().itype == IType::Datatype {
+            return format!(
+                "FieldType::Message(&crate::{}::DESCRIPTOR)",
+                mod_path(&lineinfo.literal)
+            );
         }
     }
-    let line = if embednames.is_empty() {
-        r#"
-use crate::{MsgRead, MsgWrite, BytesReader, BytesWriter, Error, Result};
-use crate::sizeofs;
-use crate::util;
-"#
-        .to_owned()
+    let ty = match lineinfo.wirename.as_str() {
+        "bool" => "Bool",
+        "i8" => "I8",
+        "u8" => "U8",
+        "i16" => "I16",
+        "u16" => "U16",
+        "i32" => "I32",
+        "u32" => "U32",
+        "i64" => "I64",
+        "u64" => "U64",
+        "f32" => "F32",
+        "f64" => "F64",
+        _ => "String",
+    };
+    format!("FieldType::{}", ty)
+}
+
+fn field_limits(opts: &FieldOpts) -> String {
+    let mut limits = Vec::new();
+    if let Some(min) = &opts.min {
+        limits.push(format!("min: Some({})", f64_literal(min)));
+    }
+    if let Some(max) = &opts.max {
+        limits.push(format!("max: Some({})", f64_literal(max)));
+    }
+    if let Some(max_len) = opts.max_len {
+        limits.push(format!("max_len: Some({})", max_len));
+    }
+    if let Some(max_count) = opts.max_count {
+        limits.push(format!("max_count: Some({})", max_count));
+    }
+    if limits.is_empty() {
+        "Limits::NONE".to_owned()
     } else {
-        let keys: Vec<String> = embednames.into_keys().collect();
-        let str = keys.join(", ");
+        format!("Limits {{ {}, ..Limits::NONE }}", limits.join(", "))
+    }
+}
+
+// 静态的 DESCRIPTOR 和 Reflect, FieldValue 的实现
+fn write_descriptor(file: &mut String, ptoid: u32, pto: &Rc<RefCell<Pto>>) -> Result<()> {
+    let pto = pto.borrow();
+    let mut fields = Vec::new();
+    let mut get_body = Vec::new();
+    let mut set_body = Vec::new();
+    for lineinfo in &pto.members {
+        fields.push(format!(
+            "        FieldDescriptor {{ name: \"{}\", tag: {}, ty: {}, repeated: {}, limits: {} }},",
+            lineinfo.name,
+            lineinfo.id,
+            field_type(lineinfo),
+            lineinfo.repeated,
+            field_limits(&lineinfo.opts)
+        ));
+        get_body.push(format!(
+            "            \"{0}\" => Some(self.{0}.to_value()),",
+            lineinfo.name
+        ));
+        set_body.push(format!(
+            "            \"{0}\" => self.{0} = FieldValue::from_value(value)?,",
+            lineinfo.name
+        ));
+    }
+    let set_field = if set_body.is_empty() {
         format!(
-            r#"
-{};
-use crate::{{MsgRead, MsgWrite, BytesReader, BytesWriter, Error, Result}};
-use crate::sizeofs;
-use crate::util;
-"#,
-            str
+            r#"    fn set_field(&mut self, name: &str, _value: Value) -> Result<()> {{
+        Err(descriptor::no_such_field("{}", name))
+    }}"#,
+            pto.name
+        )
+    } else {
+        format!(
+            r#"    fn set_field(&mut self, name: &str, value: Value) -> Result<()> {{
+        match name {{
+{}
+            _ => return Err(descriptor::no_such_field("{}", name)),
+        }}
+        Ok(())
+    }}"#,
+            set_body.join("\n"),
+            pto.name
         )
     };
+    write!(
+        file,
+        r#"
+use crate::descriptor::{{self, Channel, Direction, FieldDescriptor, FieldType, FieldValue, Limits, MessageDescriptor, Reflect, Value}};
+
+pub static DESCRIPTOR: MessageDescriptor = MessageDescriptor {{
+    name: "{7}",
+    id: {1},
+    direction: {2},
+    channel: {6},
+    fields: &[
+{3}
+    ],
+}};
+
+impl Reflect for {0} {{
+    fn descriptor(&self) -> &'static MessageDescriptor {{
+        &DESCRIPTOR
+    }}
+
+    fn get_field(&self, name: &str) -> Option<Value> {{
+        match name {{
+{4}
+            _ => None,
+        }}
+    }}
+
+{5}
+}}
+
+impl FieldValue for {0} {{
+    fn to_value(&self) -> Value {{
+        descriptor::message_to_value(self)
+    }}
+
+    fn from_value(value: Value) -> Result<Self> {{
+        descriptor::message_from_value(value)
+    }}
+}}
+"#,
+        local_name(&pto.name),
+        ptoid,
+        direction(&pto),
+        fields.join("\n"),
+        get_body.join("\n"),
+        set_field,
+        pto_channel(&pto),
+        pto.name,
+    )
+}
 
-    write_line(&mut file, &line)?;
+fn datatype2mod_body(
+    file: &mut String,
+    ptoid: u32,
+    pto: &Rc<RefCell<Pto>>,
+    config: &Config,
+) -> Result<()> {
+    // 错误信息里用全名, 嵌套的 datatype 用完整路径引用, 不需要 use
+    let full_name = pto.borrow().name.clone();
+    let struct_name = local_name(&full_name).to_owned();
+    //imports
+    let line = r#"
+use crate::{MsgRead, MsgWrite, BytesReader, BytesWriter, Error, Result};
+use crate::sizeofs;
+use crate::util;
+"#;
+    write_line(file, line)?;
     let mut body = Vec::new();
     let mut rand_body = Vec::new();
     let mut impl_read_body = Vec::new();
-    let mut impl_write_body = Vec::new();
+    let mut write_fields = Vec::new();
     let mut impl_size_body = Vec::new();
+    let mut checks = Vec::new();
+    let mut nested_checks = Vec::new();
     let mut tap = "";
     let mut rtap = "";
     for lineinfo in &pto.borrow_mut().members {
+        let mut impl_write_body = Vec::new();
+        checks.extend(constraint_checks(&full_name, lineinfo));
+        // 元素个数在读取元素之前检查
+        let count_check = match lineinfo.opts.max_count {
+            Some(max_count) => format!(
+                " util::check_count(\"{}.{}\", len, {})?;",
+                full_name, lineinfo.name, max_count
+            ),
+            None => String::new(),
+        };
         let mut is_embed_datatype = false;
         let mut needand = "";
         if let Some(embed) = &lineinfo.embed {
@@ -492,7 +1831,6 @@ use crate::util;
         } else {
             wirename
         };
-        let literal = &lineinfo.literal;
 
         if !lineinfo.repeated {
             let str = format!("    pub {}: {},", linename, wirename);
@@ -527,10 +1865,12 @@ use crate::util;
                 );
                 impl_size_body.push(str);
             } else {
+                nested_checks.push(format!("self.{}.validate()?;", linename));
+
                 // random default
                 let str = format!(
                     "\t\tmsg.{} = {}::default_with_random_value();",
-                    linename, literal
+                    linename, wirename
                 );
                 rand_body.push(str);
 
@@ -544,10 +1884,7 @@ use crate::util;
                 //write
                 let str = format!("{}w.write_tag({})?;", tap, tag);
                 impl_write_body.push(str);
-                let str = format!(
-                    "{}let objsize = self.{}.size(); w.write_len(objsize)?; self.{}.write(w)?;",
-                    tap, linename, linename
-                );
+                let str = format!("{}w.write_message(&self.{})?;", tap, linename);
                 impl_write_body.push(str);
 
                 //size
@@ -563,14 +1900,13 @@ use crate::util;
 
             if !is_embed_datatype {
                 // random default
-                let randlen = rand::thread_rng().gen_range(10..100);
                 let str = format!(
-                    r#"        let len = {};
+                    r#"        let len = util::random_len();
         for _idx in 0..len {{
             let val = util::default_random_value("{}").parse().unwrap();
             msg.{}.push(val);
         }}"#,
-                    randlen, wirename, linename
+                    wirename, linename
                 );
                 rand_body.push(str);
 
@@ -580,8 +1916,8 @@ use crate::util;
                     wirename_func, linename
                 );
                 let str = format!(
-                    "{}Ok({}) => {{ let len = r.get_len(bytes)?; for _idx in 0..len {{ {} }} }}",
-                    rtap, tag, str
+                    "{}Ok({}) => {{ let len = r.get_len(bytes)?;{} for _idx in 0..len {{ {} }} }}",
+                    rtap, tag, count_check, str
                 );
                 impl_read_body.push(str);
 
@@ -608,26 +1944,27 @@ use crate::util;
                 let str = format!("{}{{ let mut total = 0; for idx in 0..self.{}.len() {{ total += sizeofs::sizeof_{}({}self.{}[idx]); }} total }}",tap,linename,wirename_func,needand,linename);
                 impl_size_body.push(str);
             } else {
+                nested_checks.push(format!("for v in &self.{} {{ v.validate()?; }}", linename));
+
                 // random default
-                let randlen = rand::thread_rng().gen_range(10..100);
                 let str = format!(
-                    r#"        let len = {};
+                    r#"        let len = util::random_len();
         for _idx in 0..len {{
             let val = {}::default_with_random_value();
             msg.{}.push(val);
         }}"#,
-                    randlen, wirename, linename
+                    wirename, linename
                 );
                 rand_body.push(str);
 
                 //read
                 let str = format!(
                     "let objsize = r.get_len(bytes)?; let mut nextr = BytesReader::new(r.get_read_start(),r.get_read_start()+objsize); let val = {}::read(&mut nextr,bytes)?; msg.{}.push(val); r.step(objsize);",
-                    literal, linename
+                    wirename, linename
                 );
                 let str = format!(
-                    "{}Ok({}) => {{ let len = r.get_len(bytes)?; for _idx in 0..len {{ {} }} }}",
-                    rtap, tag, str
+                    "{}Ok({}) => {{ let len = r.get_len(bytes)?;{} for _idx in 0..len {{ {} }} }}",
+                    rtap, tag, count_check, str
                 );
                 impl_read_body.push(str);
 
@@ -640,8 +1977,8 @@ use crate::util;
                 );
                 impl_write_body.push(str);
                 let str = format!(
-                    "{}for idx in 0..len {{ let objsize = self.{}[idx].size(); w.write_len(objsize)?; self.{}[idx].write(w)?; }}",
-                    tap, linename,linename
+                    "{}for v in &self.{} {{ w.write_message(v)?; }}",
+                    tap, linename
                 );
                 impl_write_body.push(str);
 
@@ -655,23 +1992,62 @@ use crate::util;
                 impl_size_body.push(str);
             }
         }
+        rand_body.extend(random_fixups(lineinfo));
+        write_fields.push((lineinfo.id, impl_write_body));
         tap = "        ";
         rtap = "                ";
     }
-    let str = format!("{}Ok(t) => {{ println!(\"[read]: {} get an unknow member: {{}}\",t); r.read_unknow(bytes,t)?; }}",rtap,struct_name);
+    let str = format!("{}Ok(t) => {{ r.read_unknow(bytes, t)?; }}", rtap);
     impl_read_body.push(str);
     let str = format!("{}Err(e) => {{ return Err(e); }}", rtap);
     impl_read_body.push(str);
 
     let body = body.join("\n");
     //struct body
-    write_struct(&mut file, &struct_name, &body)?;
-    write_line(&mut file, "\n")?;
+    let has_default = pto
+        .borrow()
+        .members
+        .iter()
+        .any(|l| l.opts.default.is_some());
+    write_struct(
+        file,
+        &struct_name,
+        &body,
+        &derive_attrs(config, !has_default),
+    )?;
+    write_line(file, "\n")?;
+    if has_default {
+        write_default(file, &struct_name, &pto.borrow().members)?;
+    }
+    write_validate(file, &struct_name, &checks, &nested_checks)?;
 
     // with random default
     let random_default_body = rand_body.join("\n");
-    write_imp_struct_with_random_default(&mut file, ptoid, &struct_name, &random_default_body)?;
-    write_line(&mut file, "\n\n")?;
+    write_imp_struct_with_random_default(file, ptoid, &struct_name, &random_default_body)?;
+    write_line(file, "\n\n")?;
+
+    if config.protobuf {
+        let pto = pto.borrow();
+        let mut fields: Vec<WireField> = pto
+            .members
+            .iter()
+            .map(|l| WireField {
+                name: &l.name,
+                literal: &l.literal,
+                rust_type: &l.wirename,
+                number: l.id,
+                repeated: l.repeated,
+                message: l
+                    .embed
+                    .as_ref()
+                    .is_some_and(|e| e.borrow().itype == IType::Datatype),
+                default: l.opts.default.is_some(),
+            })
+            .collect();
+        proto3::write_codec(file, &struct_name, &mut fields)?;
+        write_line(file, "\n")?;
+        return Ok(());
+    }
 
     // trait MsgRead
     let read_body = format!(
@@ -682,14 +2058,21 @@ use crate::util;
                 {}
             }}
         }}
+        msg.check()?;
         Ok(msg)
     }}"#,
         impl_read_body.join("\n"),
     );
-    write_imp_read_for_struct(&mut file, &struct_name, &read_body)?;
-    write_line(&mut file, "\n\n")?;
+    write_imp_read_for_struct(file, &struct_name, &read_body)?;
+    write_line(file, "\n\n")?;
 
     // trait MsgWrite
+    // 按 field number 升序写, 旧版本读到不认识的字段时后面都是新加的字段, 可以直接跳过
+    write_fields.sort_by_key(|(id, _)| *id);
+    let impl_write_body: Vec<&str> = write_fields
+        .iter()
+        .flat_map(|(_, lines)| lines.iter().map(|l| l.trim_start()))
+        .collect();
     let write_body = format!(
         r#"    fn size(&self) -> usize {{
         {}
@@ -699,29 +2082,33 @@ use crate::util;
         Ok(())
     }}"#,
         impl_size_body.join(" +\n"),
-        impl_write_body.join("\n"),
+        impl_write_body.join("\n        "),
     );
-    write_imp_write_for_struct(&mut file, &struct_name, &write_body)?;
-    write_line(&mut file, "\n\n")?;
-
-    generate_test_func(outdir, pto)?;
-
+    write_imp_write_for_struct(file, &struct_name, &write_body)?;
+    write_line(file, "\n")?;
     Ok(())
 }
 
-fn pto2file(outdir: &str, ptoid: u32, pto: &Rc<RefCell<Pto>>) -> Result<()> {
-    datatype2file(outdir, ptoid, pto)
+fn pto2mod(
+    out: &mut String,
+    tests: &mut String,
+    ptoid: u32,
+    pto: &Rc<RefCell<Pto>>,
+    config: &Config,
+) -> Result<()> {
+    datatype2mod(out, tests, ptoid, pto, config)
 }
 
-fn generate_all_pto_mapping(allptos: &[(u32, String)], outdir: &str, fname: &str) -> Result<()> {
-    let fname = format!("{}/{}", outdir, fname);
-    let mut file = File::create(fname).unwrap();
-
-    //file header
-    write_file_header(&mut file)?;
+fn generate_all_pto_mapping(
+    file: &mut String,
+    allptos: &[(u32, String)],
+    datatypes: &[String],
+    version: &str,
+    serde: bool,
+) -> Result<()> {
     //imports
     write_line(
-        &mut file,
+        file,
         r#"
 use std::collections::HashMap;
 use std::default::Default;
@@ -731,6 +2118,7 @@ use crate::{
     MsgRead,
     BytesWriter,
     MsgWrite,
+    descriptor::{MessageDescriptor, Reflect, Value},
 };
 
 "#,
@@ -740,23 +2128,26 @@ use crate::{
     let mut f2vs = Vec::new();
     let mut f3vs = Vec::new();
     let mut f4vs = Vec::new();
+    // ProtoType 的成员名是展开成标识符的全名, inner_info 返回全名
     for (id, name) in allptos {
-        let str = format!("    {0}({0}::{0}),", name);
+        let var = ident(name);
+        let path = struct_path(name);
+        let str = format!("    {}({}),", var, path);
         vs.push(str);
 
         // parse_proto
         f2vs.push(format!("        {} => {{", id));
         f2vs.push("            let mut r = BytesReader::new(start_pos,end_pos);".to_string());
         f2vs.push(format!(
-            "            let obj = {0}::{0}::read(&mut r, buf)?;",
-            name
+            "            let obj = {}::read(&mut r, buf)?;",
+            path
         ));
         f2vs.push("            if !r.is_complete() { return Err(crate::Error::Message(format!(\"[allptos.parse_proto]: partial parsed, proto_id={}\",proto_id))) }".to_string());
-        f2vs.push(format!("            Ok(ProtoType::{0}(obj))", name));
+        f2vs.push(format!("            Ok(ProtoType::{0}(obj))", var));
         f2vs.push("        },".to_string());
 
         // serialize
-        f3vs.push(format!("        ProtoType::{}(obj) => {{", name));
+        f3vs.push(format!("        ProtoType::{}(obj) => {{", var));
         f3vs.push("            let msglen = obj.size();".to_string());
         f3vs.push("            let mut buf = Vec::with_capacity(msglen);".to_string());
         f3vs.push("            let mut w = BytesWriter::new(&mut buf);".to_string());
@@ -766,21 +2157,18 @@ use crate::{
 
         // inner_info
         f4vs.push(format!(
-            "            ProtoType::{1}(_obj) => {{ ({0},\"{1}\") }},",
-            id, name
+            "            ProtoType::{1}(_obj) => {{ ({0},\"{2}\") }},",
+            id, var, name
         ));
     }
 
     let enumstr = vs.join("\n");
-    let salt = rand::thread_rng().gen_range(0..645752165);
-    let md5str = format!("{}{}", enumstr, salt);
-    let version = md5::compute(&md5str);
-    let version = format!("const PTO_VERSION: &str = \"{:x}\";", version);
-    write_line(&mut file, &version)?;
+    let version = format!("const PTO_VERSION: &str = \"{}\";", version);
+    write_line(file, &version)?;
 
     // function 1
     write_line(
-        &mut file,
+        file,
         r#"
 pub fn is_proto_version(vers: &str) -> bool {
     PTO_VERSION.eq(vers)
@@ -789,15 +2177,21 @@ pub fn is_proto_version(vers: &str) -> bool {
 "#,
     )?;
 
+    // 开启 serde 时, ProtoType 按协议名打标签: {"s_login": {...}}
+    let enum_derive = if serde {
+        "#[derive(Debug,serde::Serialize,serde::Deserialize)]"
+    } else {
+        "#[derive(Debug)]"
+    };
     let enumstr = format!(
-        r#"#[derive(Debug)]
+        r#"{}
 pub enum ProtoType {{
 {}
 }}
 "#,
-        enumstr
+        enum_derive, enumstr
     );
-    write_line(&mut file, &enumstr)?;
+    write_line(file, &enumstr)?;
 
     // function inner_info
     let fnstr = f4vs.join("\n");
@@ -813,7 +2207,7 @@ impl ProtoType {{
 "#,
         fnstr
     );
-    write_line(&mut file, &fnstr)?;
+    write_line(file, &fnstr)?;
 
     // function parse_proto
     let fnstr = f2vs.join("\n");
@@ -828,81 +2222,304 @@ pub fn parse_proto(proto_id: u32,buf: &[u8], start_pos: usize, end_pos: usize) -
 "#,
         fnstr
     );
-    write_line(&mut file, &f2)?;
+    write_line(file, &f2)?;
 
     // function serialize
     let fnstr = f3vs.join("\n");
     let f3 = format!(
         r#"
-pub fn serialize(pto: ProtoType) -> ::core::result::Result<Vec<u8>, crate::Error> {{
-    match pto {{
+// 参数可以是 ProtoType 或 &ProtoType, 编码同一个协议多次(广播, 压测)时不需要复制
+pub fn serialize<P: ::core::borrow::Borrow<ProtoType>>(pto: P) -> ::core::result::Result<Vec<u8>, crate::Error> {{
+    match pto.borrow() {{
 {}
     }}
 }}"#,
         fnstr
     );
-    write_line(&mut file, &f3)?;
+    write_line(file, &f3)?;
+
+    // 反射: 按名字或id查找 descriptor, 构造协议
+    let reflect_arms: Vec<String> = allptos
+        .iter()
+        .map(|(_, name)| format!("            ProtoType::{0}(obj) => obj,", ident(name)))
+        .collect();
+    let reflect_arms = reflect_arms.join("\n");
+    let descriptors: Vec<String> = datatypes
+        .iter()
+        .chain(allptos.iter().map(|(_, name)| name))
+        .map(|name| format!("    &{}::DESCRIPTOR,", mod_path(name)))
+        .collect();
+    let new_arms: Vec<String> = allptos
+        .iter()
+        .map(|(id, name)| {
+            format!(
+                "        {} => Some(ProtoType::{}(Default::default())),",
+                id,
+                ident(name)
+            )
+        })
+        .collect();
+    let validate_arms: Vec<String> = allptos
+        .iter()
+        .map(|(_, name)| {
+            format!(
+                "            ProtoType::{0}(obj) => obj.validate(),",
+                ident(name)
+            )
+        })
+        .collect();
+    let fnstr = format!(
+        r#"
+
+impl ProtoType {{
+    pub fn as_reflect(&self) -> &dyn Reflect {{
+        match self {{
+{0}
+        }}
+    }}
+
+    pub fn as_reflect_mut(&mut self) -> &mut dyn Reflect {{
+        match self {{
+{0}
+        }}
+    }}
+
+    pub fn descriptor(&self) -> &'static MessageDescriptor {{
+        self.as_reflect().descriptor()
+    }}
+
+    pub fn get_field(&self, name: &str) -> Option<Value> {{
+        self.as_reflect().get_field(name)
+    }}
+
+    pub fn set_field(&mut self, name: &str, value: Value) -> crate::Result<()> {{
+        self.as_reflect_mut().set_field(name, value)
+    }}
+
+    // 检查协议的所有字段是否满足协议源文件里的约束, 读取协议时已经检查过
+    pub fn validate(&self) -> crate::Result<()> {{
+        match self {{
+{3}
+        }}
+    }}
+}}
+
+// 所有 datatype 和协议的 descriptor
+pub static DESCRIPTORS: &[&MessageDescriptor] = &[
+{1}
+];
+
+pub fn descriptor_by_name(name: &str) -> Option<&'static MessageDescriptor> {{
+    DESCRIPTORS.iter().find(|d| d.name == name).copied()
+}}
+
+pub fn descriptor_by_id(proto_id: u32) -> Option<&'static MessageDescriptor> {{
+    DESCRIPTORS.iter().find(|d| d.id == proto_id && proto_id != 0).copied()
+}}
+
+// 构造字段都是默认值的协议
+pub fn new_by_id(proto_id: u32) -> Option<ProtoType> {{
+    match proto_id {{
+{2}
+        _ => None,
+    }}
+}}
+
+pub fn new_by_name(name: &str) -> Option<ProtoType> {{
+    new_by_id(descriptor_by_name(name)?.id)
+}}
+"#,
+        reflect_arms,
+        descriptors.join("\n"),
+        new_arms.join("\n"),
+        validate_arms.join("\n"),
+    );
+    write_line(file, &fnstr)?;
+
+    if serde {
+        generate_json_mapping(file, allptos)?;
+    }
 
     //tail
-    write_line(&mut file, "")?;
+    write_line(file, "")?;
     Ok(())
 }
 
-fn write_file_header(file: &mut File) -> Result<()> {
-    file.write_all(b"//this file is automatically generated by protos. please do not edit.\n")?;
-    let str = r#"#![allow(non_snake_case)]
-#![allow(non_upper_case_globals)]
-#![allow(non_camel_case_types)]
-#![allow(unused_imports)]"#
-        .to_string();
-    file.write_fmt(format_args!("{}\n\n", &str))?;
+// 每个 service 生成一个模块: 处理者的 trait, 每个请求协议一个方法; 以及按协议id分发的函数.
+// 新增协议时只需要在 service 里声明, 再实现 trait 里新增的方法.
+fn generate_services(
+    file: &mut String,
+    allptos: &[(u32, String)],
+    services: &[Service],
+) -> Result<()> {
+    let id_of = |name: &str| allptos.iter().find(|(_, n)| n == name).unwrap().0;
+    writeln!(file, "\nuse crate::{{allptos::ProtoType, ptoout::*}};")?;
+    for service in services {
+        let def = &service.def;
+        let mut methods = Vec::new();
+        let mut ids = Vec::new();
+        let mut responses = Vec::new();
+        let mut arms = Vec::new();
+        for rpc in &def.rpcs {
+            let id = id_of(&rpc.request);
+            let comment = match &rpc.response {
+                Some(resp) => {
+                    responses.push(format!("        {} => Some({}),", id, id_of(resp)));
+                    format!("{} -> {}", rpc.request, resp)
+                }
+                None => rpc.request.clone(),
+            };
+            // 方法名是展开成标识符的全名
+            methods.push(format!(
+                "    // {1}\n    fn {0}(&mut self, vfd: u64, pto: {2}) -> Result<(), Self::Error>;",
+                ident(&rpc.request),
+                comment,
+                struct_path(&rpc.request)
+            ));
+            ids.push(id.to_string());
+            arms.push(format!(
+                "        ProtoType::{0}(obj) => Some(handler.{0}(vfd, obj)),",
+                ident(&rpc.request)
+            ));
+        }
+        let handles = if ids.is_empty() {
+            "false".to_owned()
+        } else {
+            format!("matches!(proto_id, {})", ids.join(" | "))
+        };
+        let prefix = if def.rpcs.is_empty() { "_" } else { "" };
+        responses.push("        _ => None,".to_owned());
+        arms.push("        _ => None,".to_owned());
+        write_mod(file, &def.name, |file| {
+            writeln!(
+                file,
+                r#"
+use super::*;
+
+pub const NAME: &str = "{0}";
+
+// 处理 service {0} 的请求
+pub trait Handler {{
+    type Error;
+{1}
+}}
+
+// 协议是否由 service {0} 处理
+pub fn handles(proto_id: u32) -> bool {{
+    {2}
+}}
+
+// 请求协议对应的返回协议id
+pub fn response_id(proto_id: u32) -> Option<u32> {{
+    match proto_id {{
+{3}
+    }}
+}}
+
+// 把协议分发给 handler 对应的方法, 协议不由 service {0} 处理时返回 None
+pub fn dispatch<H: Handler + ?Sized>({4}handler: &mut H, {4}vfd: u64, pto: ProtoType) -> Option<Result<(), H::Error>> {{
+    match pto {{
+{5}
+    }}
+}}"#,
+                def.name,
+                methods.join("\n"),
+                handles,
+                responses.join("\n"),
+                prefix,
+                arms.join("\n"),
+            )
+        })?;
+    }
     Ok(())
 }
 
-fn write_struct(file: &mut File, struct_name: &str, body: &str) -> Result<()> {
-    file.write_fmt(format_args!(
+// json 接口: 协议名 + 协议结构体的 json, 供 GM 命令, 调试工具和网页后台使用
+fn generate_json_mapping(file: &mut String, allptos: &[(u32, String)]) -> Result<()> {
+    let from_arms: Vec<String> = allptos
+        .iter()
+        .map(|(_, name)| {
+            format!(
+                "        \"{}\" => serde_json::from_str(json).map(ProtoType::{}),",
+                name,
+                ident(name)
+            )
+        })
+        .collect();
+    let to_arms: Vec<String> = allptos
+        .iter()
+        .map(|(_, name)| {
+            format!(
+                "        ProtoType::{}(obj) => serde_json::to_string(obj),",
+                ident(name)
+            )
+        })
+        .collect();
+    let fnstr = format!(
+        r#"
+
+// 用协议名和 json 构造协议, json 里没有给出的字段使用默认值, 字段需要满足协议源文件里的约束
+pub fn from_json(name: &str, json: &str) -> crate::Result<ProtoType> {{
+    let res = match name {{
+{}
+        _ => return Err(crate::Error::Json(format!("[allptos.from_json]: unknown protocol: {{}}", name))),
+    }};
+    let pto = res.map_err(|e| crate::Error::Json(format!("[allptos.from_json]: {{}}: {{}}", name, e)))?;
+    pto.validate()?;
+    Ok(pto)
+}}
+
+// 协议结构体的 json, 协议名由 inner_info 得到
+pub fn to_json(pto: &ProtoType) -> crate::Result<String> {{
+    let res = match pto {{
+{}
+    }};
+    res.map_err(|e| crate::Error::Json(format!("[allptos.to_json]: {{}}: {{}}", pto.inner_info().1, e)))
+}}
+"#,
+        from_arms.join("\n"),
+        to_arms.join("\n"),
+    );
+    write_line(file, &fnstr)
+}
+
+fn write_file_header(file: &mut String) -> Result<()> {
+    writeln!(
+        file,
+        "//this file is automatically generated by protogen. please do not edit."
+    )
+}
+
+fn write_struct(file: &mut String, struct_name: &str, body: &str, derive: &str) -> Result<()> {
+    write!(
+        file,
         r#"
-#[derive(Debug,Default)]
+{}
 pub struct {} {{
 {}
 }}"#,
-        struct_name, body
-    ))?;
-    Ok(())
+        derive, struct_name, body
+    )
 }
 
-fn write_line(file: &mut File, line: &str) -> Result<()> {
-    file.write_all(line.as_bytes())?;
-    Ok(())
+fn write_line(file: &mut String, line: &str) -> Result<()> {
+    file.write_str(line)
 }
 
-fn write_imp_write_for_struct(file: &mut File, struct_name: &str, body: &str) -> Result<()> {
-    file.write_fmt(format_args!(
-        "impl MsgWrite for {} {{\n{}\n}}",
-        struct_name, body
-    ))?;
-    Ok(())
+fn write_imp_write_for_struct(file: &mut String, struct_name: &str, body: &str) -> Result<()> {
+    write!(file, "impl MsgWrite for {} {{\n{}\n}}", struct_name, body)
 }
 
-fn write_imp_read_for_struct(file: &mut File, struct_name: &str, body: &str) -> Result<()> {
-    file.write_fmt(format_args!(
-        "impl MsgRead for {} {{\n{}\n}}",
-        struct_name, body
-    ))?;
-    Ok(())
+fn write_imp_read_for_struct(file: &mut String, struct_name: &str, body: &str) -> Result<()> {
+    write!(file, "impl MsgRead for {} {{\n{}\n}}", struct_name, body)
 }
 
 fn write_imp_struct_with_random_default(
-    file: &mut File,
+    file: &mut String,
     ptoid: u32,
     struct_name: &str,
     body: &str,
 ) -> Result<()> {
-    // file.write_fmt(format_args!(
-    //     "impl {} {{\n    pub fn default_with_random_value() -> Self {{\n\t\tlet mut msg = Self::default();\n{}\n\t\tmsg\n\t}}\n}}\n",
-    //     struct_name, body
-    // ))?;
-
     let str = format!(
         r#"
 impl {1} {{
@@ -917,28 +2534,19 @@ impl {1} {{
         ptoid, struct_name, body
     );
 
-    file.write_all(str.as_bytes())?;
-    Ok(())
+    file.write_str(&str)
 }
 
 // every protocol should have its own test function.
-fn generate_test_func(outdir: &str, pto: &Rc<RefCell<Pto>>) -> Result<()> {
-    let entity_name = pto.borrow_mut().name.clone();
-    let fname = format!("{}/../../tests/test_{}.rs", outdir, entity_name);
-    let mut file = File::create(fname).unwrap();
-
-    // header
-    let header = r#"//this file is automatically generated by protos. please do not edit. 
-use proto::{MsgWrite, MsgRead};
-extern crate proto;
-"#;
-    file.write_fmt(format_args!("{}\n\n", header))?;
-
+fn generate_test_func(file: &mut String, pto: &Rc<RefCell<Pto>>) -> Result<()> {
+    let full_name = pto.borrow().name.clone();
+    let entity_name = ident(&full_name);
+    let path = format!("{}::{}", mod_path(&full_name), local_name(&full_name));
     let str = format!(
         r#"
 #[test]
 fn testfunc_{0}() {{
-    let {0} = proto::{0}::{0}::default_with_random_value();
+    let {0} = proto::{1}::default_with_random_value();
     //println!("{{:?}}", {0});
     let msglen = {0}.size();
     println!("{0}.size: {{}}", msglen);
@@ -951,7 +2559,7 @@ fn testfunc_{0}() {{
     println!("{0} into buf: successful, objsize: {{}}",msglen);
 
     let mut r = proto::BytesReader::new(0,buf.len());
-    let s2 = proto::{0}::{0}::read(&mut r, &buf).unwrap();
+    let s2 = proto::{1}::read(&mut r, &buf).unwrap();
     let msglen2 = s2.size();
     assert!(msglen == msglen2);
     let mut buf2 = Vec::with_capacity(msglen2);
@@ -960,10 +2568,382 @@ fn testfunc_{0}() {{
     assert_eq!(buf,buf2);
     println!("{0} from buf: successful");
 }}"#,
-        entity_name
+        entity_name, path
     );
 
-    file.write_fmt(format_args!("{}\n", str))?;
+    writeln!(file, "{}", str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(())
+    // 在临时目录下准备源文件
+    fn setup(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("protogen_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["primitive", "datatype", "protocol"] {
+            fs::create_dir_all(root.join("ptosrc").join(dir)).unwrap();
+        }
+        for (fname, src) in files {
+            let path = root.join("ptosrc").join(fname);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, src).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn generate_to_out_dir() {
+        let root = setup(
+            "ok",
+            &[
+                ("primitive/int32.proto", "message int32 {}"),
+                ("datatype/info.proto", "message info { int32 a = 1; }"),
+                ("protocol/s_x.proto", "message s_x { repeated info b = 1; }"),
+            ],
+        );
+        let config = Config::new(root.join("ptosrc"), &root);
+        let report = generate(&config).unwrap();
+        assert_eq!(report.appended, vec![("s_x".to_owned(), 201)]);
+        // 默认不修改协议id清单
+        assert!(!config.id_manifest.exists());
+        let out = fs::read_to_string(root.join("ptoout.rs")).unwrap();
+        assert!(out.contains("pub mod info {"));
+        assert!(out.contains("pub fn id() -> u32 { 201 }"));
+        let tests = fs::read_to_string(root.join("ptotests.rs")).unwrap();
+        assert!(tests.contains("fn testfunc_s_x()"));
+        assert!(!out.contains("serde"));
+
+        // 开启 serde 后生成 derive 和 json 接口
+        let config = Config {
+            serde: true,
+            ..config
+        };
+        generate(&config).unwrap();
+        let out = fs::read_to_string(root.join("ptoout.rs")).unwrap();
+        assert!(out.contains(
+            "serde::Serialize,serde::Deserialize)]\n#[serde(default)]\npub struct info {"
+        ));
+        assert!(out.contains("pub fn from_json(name: &str, json: &str)"));
+
+        // protobuf 编码: repeated datatype 每个元素单独写 tag, 跳过不认识的字段
+        let config = Config {
+            protobuf: true,
+            ..config
+        };
+        generate(&config).unwrap();
+        let out = fs::read_to_string(root.join("ptoout.rs")).unwrap();
+        assert!(out.contains("for v in &self.b { w.write_tag(10)?;"));
+        assert!(out.contains("Ok(t) => { r.skip_field(bytes, t)?; }"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    // 默认编码按 field number 升序写字段, 读到不认识的字段时跳过消息剩下的部分
+    #[test]
+    fn write_in_tag_order() {
+        let root = setup(
+            "order",
+            &[
+                ("primitive/int32.proto", "message int32 {}"),
+                (
+                    "protocol/s_x.proto",
+                    "message s_x { int32 b = 2; int32 a = 1; }",
+                ),
+            ],
+        );
+        let config = Config::new(root.join("ptosrc"), &root);
+        generate(&config).unwrap();
+        let out = fs::read_to_string(root.join("ptoout.rs")).unwrap();
+        let a = out.find("w.write_i32_with_tag(8,self.a)?;").unwrap();
+        let b = out.find("w.write_i32_with_tag(16,self.b)?;").unwrap();
+        assert!(a < b);
+        assert!(out.contains("Ok(t) => { r.read_unknow(bytes, t)?; }"));
+        assert!(!out.contains("println!"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn generate_services() {
+        let root = setup(
+            "service",
+            &[
+                ("protocol/s_a.proto", "message s_a {}\nmessage c_a {}"),
+                ("protocol/s_b.proto", "message s_b {}"),
+                ("service/game.proto", "service game { s_a -> c_a; s_b; }"),
+            ],
+        );
+        let config = Config::new(root.join("ptosrc"), &root);
+        generate(&config).unwrap();
+        let out = fs::read_to_string(root.join("ptoout.rs")).unwrap();
+        assert!(out.contains("pub mod services {"));
+        assert!(out.contains(
+            "    // s_a -> c_a\n    fn s_a(&mut self, vfd: u64, pto: crate::s_a::s_a) -> Result<(), Self::Error>;"
+        ));
+        assert!(out.contains("ProtoType::s_b(obj) => Some(handler.s_b(vfd, obj)),"));
+        fs::remove_dir_all(&root).unwrap();
+
+        let root = setup(
+            "service_err",
+            &[
+                ("protocol/s_a.proto", "message s_a {}\nservice x { s_a; }"),
+                ("datatype/info.proto", "message info {}"),
+                (
+                    "service/a.proto",
+                    "service a { s_a -> info; }\nmessage m {}",
+                ),
+                (
+                    "service/b.proto",
+                    "service b { s_a; s_none; }\nservice a {}",
+                ),
+            ],
+        );
+        let config = Config::new(root.join("ptosrc"), &root);
+        let errors = match generate(&config) {
+            Err(Error::Parse(errors)) => errors,
+            res => panic!("unexpected result: {:?}", res),
+        };
+        let errors: Vec<String> = errors.iter().map(|e| e.msg.clone()).collect();
+        let first = root.join("ptosrc/service/a.proto").display().to_string();
+        assert_eq!(
+            errors,
+            vec![
+                "service 'x' must be defined in the service directory".to_owned(),
+                "unknown protocol 'info' in service 'a'".to_owned(),
+                "message 'm' must be defined in the primitive, datatype or protocol directory"
+                    .to_owned(),
+                format!(
+                    "protocol 's_a' is already handled by service 'a' at {}:1:13",
+                    first
+                ),
+                "unknown protocol 's_none' in service 'b'".to_owned(),
+                format!("duplicate service 'a', first defined at {}:1:1", first),
+            ]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn field_options() {
+        let primitives = [
+            ("primitive/int32.proto", "message int32 {}"),
+            ("primitive/uint8.proto", "message uint8 {}"),
+            ("primitive/float.proto", "message float {}"),
+            ("primitive/string.proto", "message string {}"),
+        ];
+        let mut files = primitives.to_vec();
+        files.push((
+            "protocol/s_x.proto",
+            r#"message s_x {
+    string acc = 1 [max_len=32, default="guest"];
+    int32 n = 2 [min=-1, max=999, default=1];
+    float f = 3 [min=0.5];
+    repeated int32 ids = 4 [max_count=10, max=100];
+}"#,
+        ));
+        let root = setup("options", &files);
+        let config = Config::new(root.join("ptosrc"), &root);
+        generate(&config).unwrap();
+        let out = fs::read_to_string(root.join("ptoout.rs")).unwrap();
+        assert!(out.contains("impl Default for s_x {"));
+        assert!(out.contains("            acc: \"guest\".to_owned(),\n            n: 1i32,\n            f: Default::default(),"));
+        assert!(out.contains("util::check_len(\"s_x.acc\", self.acc.len(), 32)?;"));
+        assert!(out.contains("util::check_min(\"s_x.n\", self.n, -1i32)?;"));
+        assert!(out.contains("util::check_min(\"s_x.f\", self.f, 0.5f32)?;"));
+        assert!(out.contains(
+            "util::check_count(\"s_x.ids\", self.ids.len(), 10)?;\n        for v in &self.ids { util::check_max(\"s_x.ids\", *v, 100i32)?; }"
+        ));
+        // 元素个数在读取元素之前检查
+        assert!(
+            out.contains("let len = r.get_len(bytes)?; util::check_count(\"s_x.ids\", len, 10)?;")
+        );
+        assert!(
+            out.contains("limits: Limits { min: Some(-1.0), max: Some(999.0), ..Limits::NONE }")
+        );
+        fs::remove_dir_all(&root).unwrap();
+
+        let mut files = primitives.to_vec();
+        files.push((
+            "protocol/s_x.proto",
+            r#"message s_x {
+    int32 a = 1 [max_len=3];
+    uint8 b = 2 [min=-1, max=300];
+    int32 c = 3 [min=5, max=1];
+    string d = 4 [max_len=2, default="abc", max_len=4];
+    float e = 5 [default=true, unit=1];
+    int32 g = 6 [max_count=1, default=7, min=8];
+}"#,
+        ));
+        let root = setup("options_err", &files);
+        let config = Config::new(root.join("ptosrc"), &root);
+        let errors = match generate(&config) {
+            Err(Error::Parse(errors)) => errors,
+            res => panic!("unexpected result: {:?}", res),
+        };
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|e| format!("{}:{}: {}", e.line, e.col, e.msg))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "2:18: option 'max_len' does not apply to field 'a' of type int32",
+                "3:18: value -1 of option 'min' does not fit field 'b' of type uint8",
+                "3:26: value 300 of option 'max' does not fit field 'b' of type uint8",
+                "4:5: min 5 of field 'c' is greater than max 1",
+                "5:5: default of field 'd' is 3 bytes long, exceeds max_len 2",
+                "5:45: duplicate option 'max_len' of field 'd', first defined at line 5",
+                "6:18: value true of option 'default' does not fit field 'e' of type float",
+                "6:32: unknown option 'unit' of field 'e', expected max_len, min, max, default or max_count",
+                "7:5: default 7 of field 'g' is less than min 8",
+                "7:18: option 'max_count' does not apply to field 'g' of type int32",
+            ]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn generate_consts() {
+        let root = setup(
+            "consts",
+            &[
+                (
+                    "protocol/s_x.proto",
+                    "message s_x {}\nconst uint32 CAP = 1000;\nconst RET = -1;",
+                ),
+                (
+                    "datatype/limits.proto",
+                    "const BIG = 5000000000;\nconst float RATE = 1;\nconst NAME = \"a\\\"b\";",
+                ),
+            ],
+        );
+        let config = Config::new(root.join("ptosrc"), &root);
+        generate(&config).unwrap();
+        let out = fs::read_to_string(root.join("ptoout.rs")).unwrap();
+        assert!(out.contains(
+            "pub mod consts {\npub const BIG: i64 = 5000000000i64;\npub const CAP: u32 = 1000u32;\npub const NAME: &str = \"a\\\"b\";\npub const RATE: f32 = 1.0f32;\npub const RET: i32 = -1i32;\n}"
+        ));
+        fs::remove_dir_all(&root).unwrap();
+
+        let root = setup(
+            "consts_err",
+            &[
+                ("protocol/s_x.proto", "message s_x {}\nconst uint8 A = 300;"),
+                (
+                    "service/game.proto",
+                    "const A = 1;\nconst info B = 1;\nconst string C = 1;",
+                ),
+            ],
+        );
+        let config = Config::new(root.join("ptosrc"), &root);
+        let errors = match generate(&config) {
+            Err(Error::Parse(errors)) => errors,
+            res => panic!("unexpected result: {:?}", res),
+        };
+        let mut errors: Vec<String> = errors.iter().map(|e| e.msg.clone()).collect();
+        errors.sort();
+        let first = root.join("ptosrc/protocol/s_x.proto").display().to_string();
+        assert_eq!(
+            errors,
+            vec![
+                "const 'B' has unknown type 'info', expected a primitive type".to_owned(),
+                format!("duplicate const 'A', first defined at {}:2:1", first),
+                "value 1 of const 'C' does not fit type string".to_owned(),
+                "value 300 of const 'A' does not fit type uint8".to_owned(),
+            ]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn generate_packages() {
+        let root = setup(
+            "packages",
+            &[
+                ("primitive/int32.proto", "message int32 {}"),
+                ("datatype/info.proto", "message info { int32 a = 1; }"),
+                (
+                    "datatype/bag/item.proto",
+                    "message info { int32 id = 1; }\nmessage item { info a = 1; }",
+                ),
+                (
+                    "protocol/s_x.proto",
+                    "package game.bag;\nmessage s_x { repeated bag.item items = 1; info b = 2; }",
+                ),
+            ],
+        );
+        let config = Config {
+            dir_packages: true,
+            ..Config::new(root.join("ptosrc"), &root)
+        };
+        generate(&config).unwrap();
+        let out = fs::read_to_string(root.join("ptoout.rs")).unwrap();
+        // 目录和 package 语句都生成嵌套的模块, 同一个包里的 info 优先
+        assert!(out.contains("pub mod bag {\n\npub mod info {"));
+        assert!(out.contains("pub mod game {\n\npub mod bag {\n\npub mod s_x {"));
+        assert!(out.contains("    pub a: crate::bag::info::info,\n"));
+        assert!(out.contains(
+            "    pub items: Vec<crate::bag::item::item>,\n    pub b: crate::info::info,\n"
+        ));
+        assert!(out.contains("    name: \"game.bag.s_x\",\n"));
+        assert!(out.contains("    game_bag_s_x(crate::game::bag::s_x::s_x),"));
+        assert!(out.contains("ProtoType::game_bag_s_x(_obj) => { (201,\"game.bag.s_x\") },"));
+        fs::remove_dir_all(&root).unwrap();
+
+        let root = setup(
+            "packages_err",
+            &[
+                ("primitive/int32.proto", "package a;\nmessage int32 {}"),
+                ("datatype/a/b.proto", "message b {}"),
+                ("datatype/a_b.proto", "message a_b {}"),
+                ("datatype/a.proto", "message a {}"),
+                ("protocol/s_x.proto", "package consts;\nmessage s_x {}"),
+                ("datatype/my-dir/c.proto", "message c {}"),
+            ],
+        );
+        let config = Config {
+            dir_packages: true,
+            ..Config::new(root.join("ptosrc"), &root)
+        };
+        let errors = match generate(&config) {
+            Err(Error::Parse(errors)) => errors,
+            res => panic!("unexpected result: {:?}", res),
+        };
+        let mut errors: Vec<String> = errors.iter().map(|e| e.msg.clone()).collect();
+        errors.sort();
+        assert_eq!(
+            errors,
+            vec![
+                "directory 'my-dir' can not be used as a package name".to_owned(),
+                format!(
+                    "message 'a' conflicts with package 'a' declared in {}",
+                    root.join("ptosrc/datatype/a/b.proto").display()
+                ),
+                format!(
+                    "message 'a_b' conflicts with 'a.b' at {}:1:1, both are generated as 'a_b'",
+                    root.join("ptosrc/datatype/a/b.proto").display()
+                ),
+                "package 'consts' conflicts with the module proto::consts".to_owned(),
+                "primitive types can not be declared in a package".to_owned(),
+            ]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn generate_reports_all_errors() {
+        let root = setup(
+            "err",
+            &[
+                ("datatype/a.proto", "message a { b x = 1; }"),
+                ("protocol/s_x.proto", "message s_x { int32 y = 0; }"),
+            ],
+        );
+        let config = Config::new(root.join("ptosrc"), &root);
+        match generate(&config) {
+            Err(Error::Parse(errors)) => assert_eq!(errors.len(), 2),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
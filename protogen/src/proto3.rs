@@ -0,0 +1,465 @@
+// 与标准 protobuf 互通
+// 1. export_proto3 把协议源文件导出成标准的 proto3 文件, 客户端可以直接用 protobuf 的工具链生成代码.
+//    int8/int16 导出为 int32, uint8/uint16 导出为 uint32, 嵌套的 message 按展开后的名字导出.
+// 2. Config.protobuf 为 true 时, 生成的 MsgRead/MsgWrite 使用与 protobuf 完全一致的编码:
+//    字段按编号顺序写入, 值为默认值的标量字段不写入, 负数按 64 位符号扩展(10 字节),
+//    repeated 数值字段使用 packed 编码, repeated string/message 每个元素单独写 tag,
+//    读取时同时接受 packed 和非 packed 编码, 跳过不认识的字段.
+//    datatype 类型的字段总是写入(相当于 protobuf 里总是设置了这个字段).
+//    没有写入的字段就是零值, 所以声明了 default 选项的字段在读取前先置为零值.
+
+use crate::check::Schema;
+use crate::errors::Error;
+use crate::proto::{self, ident, Config};
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 协议源文件的类型名对应的 proto3 类型名
+pub fn proto3_type(literal: &str) -> &str {
+    match literal {
+        "int" | "int8" | "int16" => "int32",
+        "uint8" | "uint16" => "uint32",
+        _ => literal,
+    }
+}
+
+// 导出 into/ptos.proto: 所有 datatype, 按协议id排序的协议, 以及协议id的枚举
+pub fn export_proto3(config: &Config, into: &Path) -> Result<PathBuf, Error> {
+    let schema = proto::load_schema(config, true)?;
+    let content = proto3_source(&schema).expect("write proto3 failed");
+    fs::create_dir_all(into)?;
+    let path = into.join("ptos.proto");
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+fn proto3_source(schema: &Schema) -> Result<String, fmt::Error> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// this file is automatically generated by protogen. please do not edit."
+    )?;
+    writeln!(out, "syntax = \"proto3\";")?;
+
+    let mut protocols: Vec<_> = schema.messages.values().filter(|m| m.is_protocol).collect();
+    protocols.sort_by_key(|m| m.id);
+    let datatypes = schema.messages.values().filter(|m| !m.is_protocol);
+    for m in datatypes.chain(protocols.iter().copied()) {
+        writeln!(out)?;
+        if let Some(id) = m.id {
+            writeln!(out, "// id = {}", id)?;
+        }
+        // 带包名的 message 用展开成标识符的全名
+        writeln!(out, "message {} {{", ident(&m.name))?;
+        for f in &m.fields {
+            let ty = proto3_type(&f.ty);
+            let repeated = if f.repeated { "repeated " } else { "" };
+            write!(out, "  {}{} {} = {};", repeated, ident(ty), f.name, f.tag)?;
+            // 保留原来的类型, 客户端需要自己保证值的范围
+            if ty != f.ty && f.ty != "int" {
+                write!(out, " // {}", f.ty)?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out, "}}")?;
+    }
+
+    // 消息头里的 proto_id, proto3 的枚举第一个值必须是 0
+    writeln!(out)?;
+    writeln!(out, "enum PtoId {{")?;
+    writeln!(out, "  PTO_ID_NONE = 0;")?;
+    for m in &protocols {
+        writeln!(
+            out,
+            "  {} = {};",
+            ident(&m.name).to_uppercase(),
+            m.id.unwrap()
+        )?;
+    }
+    writeln!(out, "}}")?;
+    Ok(out)
+}
+
+// 生成编解码代码需要的字段信息
+pub(crate) struct WireField<'a> {
+    pub name: &'a str,
+    // 源文件里的类型名
+    pub literal: &'a str,
+    // rust 类型名
+    pub rust_type: &'a str,
+    pub number: i32,
+    pub repeated: bool,
+    // 字段类型是 datatype
+    pub message: bool,
+    // 字段声明了 default 选项
+    pub default: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Signed,
+    Unsigned,
+    Bool,
+    Float,
+    Double,
+    String,
+    Message,
+}
+
+impl Kind {
+    fn of(field: &WireField) -> Kind {
+        if field.message {
+            return Kind::Message;
+        }
+        match field.literal {
+            "int" | "int8" | "int16" | "int32" | "int64" => Kind::Signed,
+            "uint8" | "uint16" | "uint32" | "uint64" => Kind::Unsigned,
+            "bool" => Kind::Bool,
+            "float" => Kind::Float,
+            "double" => Kind::Double,
+            _ => Kind::String,
+        }
+    }
+
+    fn wire_type(self) -> i32 {
+        match self {
+            Kind::Signed | Kind::Unsigned | Kind::Bool => 0,
+            Kind::Double => 1,
+            Kind::String | Kind::Message => 2,
+            Kind::Float => 5,
+        }
+    }
+
+    fn packable(self) -> bool {
+        !matches!(self, Kind::String | Kind::Message)
+    }
+}
+
+// 整数都按 64 位的 varint 编码
+fn cast(v: &str, from: &str, to: &str) -> String {
+    if from == to {
+        v.to_owned()
+    } else {
+        format!("{} as {}", v, to)
+    }
+}
+
+// 写入一个值(不含 tag), v 是值(string 是引用)
+fn write_value(kind: Kind, ty: &str, v: &str) -> String {
+    match kind {
+        Kind::Signed => format!("w.write_i64({})?;", cast(v, ty, "i64")),
+        Kind::Unsigned => format!("w.write_u64({})?;", cast(v, ty, "u64")),
+        Kind::Bool => format!("w.write_bool({})?;", v),
+        Kind::Float => format!("w.write_f32({})?;", v),
+        Kind::Double => format!("w.write_f64({})?;", v),
+        Kind::String => format!("w.write_string({})?;", v),
+        Kind::Message => unreachable!(),
+    }
+}
+
+fn sizeof_value(kind: Kind, ty: &str, v: &str) -> String {
+    match kind {
+        Kind::Signed => format!("sizeofs::sizeof_i64({})", cast(v, ty, "i64")),
+        Kind::Unsigned => format!("sizeofs::sizeof_u64({})", cast(v, ty, "u64")),
+        Kind::Bool => "1".to_owned(),
+        Kind::Float => "4".to_owned(),
+        Kind::Double => "8".to_owned(),
+        Kind::String => format!("sizeofs::sizeof_string({})", v),
+        Kind::Message => unreachable!(),
+    }
+}
+
+fn read_value(kind: Kind, ty: &str) -> String {
+    match kind {
+        Kind::Signed => cast("r.read_i64(bytes)?", "i64", ty),
+        Kind::Unsigned => cast("r.read_u64(bytes)?", "u64", ty),
+        Kind::Bool => "r.read_u64(bytes)? != 0".to_owned(),
+        Kind::Float => "r.read_f32(bytes)?".to_owned(),
+        Kind::Double => "r.read_f64(bytes)?".to_owned(),
+        Kind::String => "r.read_string(bytes)?".to_owned(),
+        Kind::Message => unreachable!(),
+    }
+}
+
+// 不是默认值时才写入
+fn is_set(kind: Kind, v: &str) -> String {
+    match kind {
+        Kind::Signed | Kind::Unsigned => format!("{} != 0", v),
+        Kind::Bool => v.to_owned(),
+        // -0.0 也要写入
+        Kind::Float | Kind::Double => format!("{}.to_bits() != 0", v),
+        Kind::String => format!("!{}.is_empty()", v),
+        Kind::Message => unreachable!(),
+    }
+}
+
+fn read_message(ty: &str) -> String {
+    format!(
+        "{{ let objsize = r.get_len(bytes)?; let mut nextr = BytesReader::new(r.get_read_start(),r.get_read_start()+objsize); let val = {}::read(&mut nextr,bytes)?; r.step(objsize); val }}",
+        ty
+    )
+}
+
+// 生成 protobuf 编码的 MsgRead 和 MsgWrite 实现
+pub(crate) fn write_codec(
+    file: &mut String,
+    struct_name: &str,
+    fields: &mut [WireField],
+) -> fmt::Result {
+    fields.sort_by_key(|f| f.number);
+    let mut read_arms = Vec::new();
+    let mut write_stmts = Vec::new();
+    let mut size_terms = Vec::new();
+    for f in fields.iter() {
+        let kind = Kind::of(f);
+        let (name, ty) = (f.name, f.rust_type);
+        let tag = (f.number << 3) | kind.wire_type();
+        let field = format!("self.{}", name);
+        match (f.repeated, kind) {
+            (false, Kind::Message) => {
+                read_arms.push(format!(
+                    "Ok({}) => {{ msg.{} = {}; }}",
+                    tag,
+                    name,
+                    read_message(ty)
+                ));
+                write_stmts.push(format!(
+                    "w.write_tag({})?; w.write_message(&{})?;",
+                    tag, field
+                ));
+                size_terms.push(format!(
+                    "sizeofs::sizeof_tag({}) + {{ let objsize = {}.size(); sizeofs::sizeof_len(objsize) + objsize }}",
+                    tag, field
+                ));
+            }
+            (false, _) => {
+                let v = if kind == Kind::String {
+                    format!("&{}", field)
+                } else {
+                    field.clone()
+                };
+                read_arms.push(format!(
+                    "Ok({}) => {{ msg.{} = {}; }}",
+                    tag,
+                    name,
+                    read_value(kind, ty)
+                ));
+                write_stmts.push(format!(
+                    "if {} {{ w.write_tag({})?; {} }}",
+                    is_set(kind, &field),
+                    tag,
+                    write_value(kind, ty, &v)
+                ));
+                size_terms.push(format!(
+                    "if {} {{ sizeofs::sizeof_tag({}) + {} }} else {{ 0 }}",
+                    is_set(kind, &field),
+                    tag,
+                    sizeof_value(kind, ty, &v)
+                ));
+            }
+            (true, Kind::Message) => {
+                read_arms.push(format!(
+                    "Ok({}) => {{ msg.{}.push({}); }}",
+                    tag,
+                    name,
+                    read_message(ty)
+                ));
+                write_stmts.push(format!(
+                    "for v in &{} {{ w.write_tag({})?; w.write_message(v)?; }}",
+                    field, tag
+                ));
+                size_terms.push(format!(
+                    "{}.iter().map(|v| {{ let objsize = v.size(); sizeofs::sizeof_tag({}) + sizeofs::sizeof_len(objsize) + objsize }}).sum::<usize>()",
+                    field, tag
+                ));
+            }
+            (true, Kind::String) => {
+                read_arms.push(format!(
+                    "Ok({}) => {{ msg.{}.push({}); }}",
+                    tag,
+                    name,
+                    read_value(kind, ty)
+                ));
+                write_stmts.push(format!(
+                    "for v in &{} {{ w.write_tag({})?; {} }}",
+                    field,
+                    tag,
+                    write_value(kind, ty, "v")
+                ));
+                size_terms.push(format!(
+                    "{}.iter().map(|v| sizeofs::sizeof_tag({}) + {}).sum::<usize>()",
+                    field,
+                    tag,
+                    sizeof_value(kind, ty, "v")
+                ));
+            }
+            (true, _) => {
+                debug_assert!(kind.packable());
+                let packed_tag = (f.number << 3) | 2;
+                let len = match kind {
+                    Kind::Bool => format!("{}.len()", field),
+                    Kind::Float => format!("{}.len() * 4", field),
+                    Kind::Double => format!("{}.len() * 8", field),
+                    _ => format!(
+                        "{}.iter().map(|v| {}).sum::<usize>()",
+                        field,
+                        sizeof_value(kind, ty, "*v")
+                    ),
+                };
+                read_arms.push(format!(
+                    "Ok({}) => {{ r.read_packed(bytes, &mut msg.{}, |r, bytes| Ok({}))?; }}",
+                    packed_tag,
+                    name,
+                    read_value(kind, ty)
+                ));
+                read_arms.push(format!(
+                    "Ok({}) => {{ msg.{}.push({}); }}",
+                    tag,
+                    name,
+                    read_value(kind, ty)
+                ));
+                write_stmts.push(format!(
+                    "if !{0}.is_empty() {{ w.write_tag({1})?; w.write_len({2})?; for v in &{0} {{ {3} }} }}",
+                    field,
+                    packed_tag,
+                    len,
+                    write_value(kind, ty, "*v")
+                ));
+                size_terms.push(format!(
+                    "if {0}.is_empty() {{ 0 }} else {{ let len = {2}; sizeofs::sizeof_tag({1}) + sizeofs::sizeof_len(len) + len }}",
+                    field, packed_tag, len
+                ));
+            }
+        }
+    }
+    let resets: String = fields
+        .iter()
+        .filter(|f| f.default)
+        .map(|f| format!("        msg.{} = Default::default();\n", f.name))
+        .collect();
+    read_arms.push("Ok(t) => { r.skip_field(bytes, t)?; }".to_owned());
+    read_arms.push("Err(e) => { return Err(e); }".to_owned());
+    // 空 message 不读写任何字段
+    let (msg_mut, w) = if fields.is_empty() {
+        size_terms.push("0".to_owned());
+        ("", "_w")
+    } else {
+        ("mut ", "w")
+    };
+    // 多项相加时, if 表达式需要加括号
+    if size_terms.len() > 1 {
+        for term in size_terms.iter_mut() {
+            if term.starts_with("if ") {
+                *term = format!("({})", term);
+            }
+        }
+    }
+
+    let read_arms: Vec<String> = read_arms
+        .iter()
+        .map(|s| format!("                {}", s))
+        .collect();
+    let write_stmts: Vec<String> = write_stmts
+        .iter()
+        .map(|s| format!("        {}", s))
+        .collect();
+    write!(
+        file,
+        r#"
+impl MsgRead for {0} {{
+    fn read(r: &mut BytesReader, bytes: &[u8]) -> Result<Self> {{
+        let {4}msg = Self::default();
+{6}        while !r.is_eof() {{
+            match r.next_tag(bytes) {{
+{1}
+            }}
+        }}
+        msg.check()?;
+        Ok(msg)
+    }}
+}}
+
+impl MsgWrite for {0} {{
+    fn size(&self) -> usize {{
+        {2}
+    }}
+    fn write(&self, {5}: &mut BytesWriter) -> Result<()> {{
+{3}
+        Ok(())
+    }}
+}}
+"#,
+        struct_name,
+        read_arms.join("\n"),
+        size_terms.join(" +\n        "),
+        write_stmts.join("\n"),
+        msg_mut,
+        w,
+        resets,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::{FieldSchema, MessageSchema};
+
+    fn field(name: &str, ty: &str, tag: i32, repeated: bool) -> FieldSchema {
+        FieldSchema {
+            name: name.to_owned(),
+            ty: ty.to_owned(),
+            tag,
+            repeated,
+        }
+    }
+
+    #[test]
+    fn export_source() {
+        let mut schema = Schema::default();
+        let messages = vec![
+            ("info", None, vec![field("a", "int8", 1, false)]),
+            (
+                "s_x",
+                Some(202),
+                vec![field("b", "info", 1, true), field("c", "uint16", 2, false)],
+            ),
+            ("s_a", Some(201), vec![field("d", "int", 1, false)]),
+        ];
+        for (name, id, fields) in messages {
+            let m = MessageSchema {
+                name: name.to_owned(),
+                is_protocol: id.is_some(),
+                id,
+                fields,
+            };
+            schema.messages.insert(name.to_owned(), m);
+        }
+        let src = proto3_source(&schema).unwrap();
+        let expect = r#"// this file is automatically generated by protogen. please do not edit.
+syntax = "proto3";
+
+message info {
+  int32 a = 1; // int8
+}
+
+// id = 201
+message s_a {
+  int32 d = 1;
+}
+
+// id = 202
+message s_x {
+  repeated info b = 1;
+  uint32 c = 2; // uint16
+}
+
+enum PtoId {
+  PTO_ID_NONE = 0;
+  S_A = 201;
+  S_X = 202;
+}
+"#;
+        assert_eq!(src, expect);
+    }
+}
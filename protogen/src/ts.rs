@@ -0,0 +1,779 @@
+// 生成 TypeScript 客户端代码: ptos.ts 包含编解码, 所有结构体的类, 协议id表和消息头的读写;
+// ptos_test.ts 用服务器生成的测试向量检查编解码.
+// int64/uint64 使用 bigint, 其他数值使用 number, datatype 数组使用类的数组.
+
+use crate::check::{ConstSchema, FieldSchema, MessageSchema};
+use crate::client::{self, ClientSchema, Kind};
+use crate::errors::Error;
+use crate::parser::OptionValue;
+use crate::proto::{ident, Config};
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+type Result<T> = std::result::Result<T, fmt::Error>;
+
+const RUNTIME: &str = r#"
+// 消息头: 协议id(u32) + 协议包长度(u32), 小端
+export const FRAME_HEADER_LEN = 8;
+// 协议包长度的上限, 与服务器一致
+export const FRAME_BODY_MAX_LEN = 64 * 1024 - FRAME_HEADER_LEN;
+// 协议包长度的最高位: 后面还有同一个协议的分片. 更长的协议包拆成多个分片
+export const FRAME_CHUNK_FLAG = 0x80000000;
+// 协议包长度的次高位: 协议包是压缩的内容(原始长度(u32) + lz4 block)
+export const FRAME_COMPRESS_FLAG = 0x40000000;
+
+const textEncoder = new TextEncoder();
+const textDecoder = new TextDecoder("utf-8", { fatal: true });
+
+export class BytesWriter {
+  private buf: Uint8Array = new Uint8Array(64);
+  private pos: number = 0;
+
+  private reserve(n: number): void {
+    if (this.pos + n <= this.buf.length) {
+      return;
+    }
+    let cap = this.buf.length * 2;
+    while (cap < this.pos + n) {
+      cap *= 2;
+    }
+    const buf = new Uint8Array(cap);
+    buf.set(this.buf.subarray(0, this.pos));
+    this.buf = buf;
+  }
+
+  finish(): Uint8Array {
+    return this.buf.slice(0, this.pos);
+  }
+
+  writeByte(v: number): void {
+    this.reserve(1);
+    this.buf[this.pos++] = v & 0xff;
+  }
+
+  writeBytes(v: Uint8Array): void {
+    this.reserve(v.length);
+    this.buf.set(v, this.pos);
+    this.pos += v.length;
+  }
+
+  // v 按 32 位无符号数编码
+  writeVarint32(v: number): void {
+    v = v >>> 0;
+    while (v > 0x7f) {
+      this.writeByte((v & 0x7f) | 0x80);
+      v = v >>> 7;
+    }
+    this.writeByte(v);
+  }
+
+  // v 按 64 位无符号数编码
+  writeVarint64(v: bigint): void {
+    v = BigInt.asUintN(64, v);
+    while (v > 0x7fn) {
+      this.writeByte(Number(v & 0x7fn) | 0x80);
+      v >>= 7n;
+    }
+    this.writeByte(Number(v));
+  }
+
+  writeI8(v: number): void {
+    this.writeByte(v);
+  }
+
+  writeU8(v: number): void {
+    this.writeByte(v);
+  }
+
+  writeI16(v: number): void {
+    this.writeVarint32(v & 0xffff);
+  }
+
+  writeU16(v: number): void {
+    this.writeVarint32(v & 0xffff);
+  }
+
+  writeI32(v: number): void {
+    this.writeVarint32(v);
+  }
+
+  writeU32(v: number): void {
+    this.writeVarint32(v);
+  }
+
+  writeI64(v: bigint): void {
+    this.writeVarint64(v);
+  }
+
+  writeU64(v: bigint): void {
+    this.writeVarint64(v);
+  }
+
+  writeBool(v: boolean): void {
+    this.writeByte(v ? 1 : 0);
+  }
+
+  writeF32(v: number): void {
+    this.reserve(4);
+    new DataView(this.buf.buffer).setFloat32(this.pos, v, true);
+    this.pos += 4;
+  }
+
+  writeF64(v: number): void {
+    this.reserve(8);
+    new DataView(this.buf.buffer).setFloat64(this.pos, v, true);
+    this.pos += 8;
+  }
+
+  writeString(v: string): void {
+    const bytes = textEncoder.encode(v);
+    this.writeVarint32(bytes.length);
+    this.writeBytes(bytes);
+  }
+
+  // datatype: 长度 + 内容
+  writeMessage(v: { write(w: BytesWriter): void }): void {
+    const w = new BytesWriter();
+    v.write(w);
+    const bytes = w.finish();
+    this.writeVarint32(bytes.length);
+    this.writeBytes(bytes);
+  }
+}
+
+export class BytesReader {
+  pos: number;
+  private buf: Uint8Array;
+  private view: DataView;
+
+  constructor(buf: Uint8Array, pos: number) {
+    this.buf = buf;
+    this.pos = pos;
+    this.view = new DataView(buf.buffer, buf.byteOffset, buf.byteLength);
+  }
+
+  private check(n: number): void {
+    if (this.pos + n > this.buf.length) {
+      throw new Error("unexpected end of buffer");
+    }
+  }
+
+  readByte(): number {
+    this.check(1);
+    return this.buf[this.pos++];
+  }
+
+  // 与服务器一致: u16 最多 3 个字节, u32 最多 5 个字节
+  readVarint32(maxBytes: number = 5): number {
+    let r = 0;
+    for (let i = 0; i < maxBytes; i++) {
+      const b = this.readByte();
+      r |= (b & 0x7f) << (7 * i);
+      if ((b & 0x80) === 0) {
+        return r >>> 0;
+      }
+    }
+    throw new Error("invalid varint");
+  }
+
+  readVarint64(): bigint {
+    let r = 0n;
+    for (let i = 0; i < 10; i++) {
+      const b = this.readByte();
+      r |= BigInt(b & 0x7f) << BigInt(7 * i);
+      if ((b & 0x80) === 0) {
+        return BigInt.asUintN(64, r);
+      }
+    }
+    throw new Error("invalid varint");
+  }
+
+  readI8(): number {
+    return (this.readByte() << 24) >> 24;
+  }
+
+  readU8(): number {
+    return this.readByte();
+  }
+
+  readI16(): number {
+    return (this.readVarint32(3) << 16) >> 16;
+  }
+
+  readU16(): number {
+    return this.readVarint32(3) & 0xffff;
+  }
+
+  readI32(): number {
+    return this.readVarint32() | 0;
+  }
+
+  readU32(): number {
+    return this.readVarint32();
+  }
+
+  readI64(): bigint {
+    return BigInt.asIntN(64, this.readVarint64());
+  }
+
+  readU64(): bigint {
+    return this.readVarint64();
+  }
+
+  readBool(): boolean {
+    return this.readByte() !== 0;
+  }
+
+  readF32(): number {
+    this.check(4);
+    const v = this.view.getFloat32(this.pos, true);
+    this.pos += 4;
+    return v;
+  }
+
+  readF64(): number {
+    this.check(8);
+    const v = this.view.getFloat64(this.pos, true);
+    this.pos += 8;
+    return v;
+  }
+
+  readString(): string {
+    const len = this.readVarint32();
+    this.check(len);
+    const v = textDecoder.decode(this.buf.subarray(this.pos, this.pos + len));
+    this.pos += len;
+    return v;
+  }
+
+  // datatype: 长度 + 内容, read 读到 end 为止
+  readMessage<T>(read: (r: BytesReader, end: number) => T): T {
+    const len = this.readVarint32();
+    this.check(len);
+    const end = this.pos + len;
+    const v = read(this, end);
+    this.pos = end;
+    return v;
+  }
+}
+"#;
+
+const FRAME: &str = r#"
+export function serialize(pto: ProtoType): Uint8Array {
+  const w = new BytesWriter();
+  pto.write(w);
+  return w.finish();
+}
+
+export function encodeFrameHeader(protoId: number, len: number, more: boolean = false): Uint8Array {
+  if (len >= FRAME_BODY_MAX_LEN) {
+    throw new Error(`[encodeFrameHeader]: exceed FRAME_BODY_MAX_LEN, ${len}`);
+  }
+  const header = new Uint8Array(FRAME_HEADER_LEN);
+  const view = new DataView(header.buffer);
+  view.setUint32(0, protoId, true);
+  view.setUint32(4, more ? len + FRAME_CHUNK_FLAG : len, true);
+  return header;
+}
+
+// 数据不足一个消息头时返回 null, more 表示后面还有分片, compressed 表示协议包是压缩的内容
+export function decodeFrameHeader(
+  buf: Uint8Array,
+  offset: number = 0,
+): { protoId: number; len: number; more: boolean; compressed: boolean } | null {
+  if (buf.length - offset < FRAME_HEADER_LEN) {
+    return null;
+  }
+  const view = new DataView(buf.buffer, buf.byteOffset, buf.byteLength);
+  const protoId = view.getUint32(offset, true);
+  const raw = view.getUint32(offset + 4, true);
+  const more = (raw & FRAME_CHUNK_FLAG) !== 0;
+  const compressed = (raw & FRAME_COMPRESS_FLAG) !== 0;
+  const len = raw & (FRAME_COMPRESS_FLAG - 1);
+  if (len >= FRAME_BODY_MAX_LEN) {
+    throw new Error(`[decodeFrameHeader]: exceed FRAME_BODY_MAX_LEN, ${len}`);
+  }
+  return { protoId, len, more, compressed };
+}
+
+// 解压服务器发来的压缩内容: 原始长度(u32, 小端) + lz4 block
+export function decompress(buf: Uint8Array): Uint8Array {
+  if (buf.length < 4) {
+    throw new Error("[decompress]: missing raw length");
+  }
+  const rawLen = new DataView(buf.buffer, buf.byteOffset, buf.byteLength).getUint32(0, true);
+  const out = new Uint8Array(rawLen);
+  let pos = 4;
+  let at = 0;
+  const readLen = (len: number): number => {
+    for (;;) {
+      if (pos >= buf.length) {
+        throw new Error("[decompress]: truncated length");
+      }
+      const b = buf[pos++];
+      len += b;
+      if (b !== 255) {
+        return len;
+      }
+    }
+  };
+  for (;;) {
+    if (pos >= buf.length) {
+      throw new Error("[decompress]: truncated token");
+    }
+    const token = buf[pos++];
+    let litLen = token >> 4;
+    if (litLen === 15) {
+      litLen = readLen(litLen);
+    }
+    if (pos + litLen > buf.length || at + litLen > rawLen) {
+      throw new Error("[decompress]: truncated literals");
+    }
+    out.set(buf.subarray(pos, pos + litLen), at);
+    pos += litLen;
+    at += litLen;
+    // 最后一个序列只有字面量
+    if (pos === buf.length) {
+      break;
+    }
+    if (pos + 2 > buf.length) {
+      throw new Error("[decompress]: truncated offset");
+    }
+    const offset = buf[pos] | (buf[pos + 1] << 8);
+    pos += 2;
+    if (offset === 0 || offset > at) {
+      throw new Error(`[decompress]: invalid offset ${offset}`);
+    }
+    let matchLen = token & 0x0f;
+    if (matchLen === 15) {
+      matchLen = readLen(matchLen);
+    }
+    matchLen += 4;
+    if (at + matchLen > rawLen) {
+      throw new Error("[decompress]: output overflow");
+    }
+    // 匹配可以和自己重叠, 逐个字节复制
+    for (let i = 0; i < matchLen; i++, at++) {
+      out[at] = out[at - offset];
+    }
+  }
+  if (at !== rawLen) {
+    throw new Error(`[decompress]: raw length mismatch, expect ${rawLen}, got ${at}`);
+  }
+  return out;
+}
+
+// 消息头 + 协议包, 协议包超过 FRAME_BODY_MAX_LEN 时拆成多个分片
+export function encodeFrame(pto: ProtoType): Uint8Array {
+  const body = serialize(pto);
+  const chunkLen = FRAME_BODY_MAX_LEN - 1;
+  const count = body.length < FRAME_BODY_MAX_LEN ? 1 : Math.ceil(body.length / chunkLen);
+  const frame = new Uint8Array(FRAME_HEADER_LEN * count + body.length);
+  for (let i = 0, pos = 0; i < count; i++) {
+    const chunk = body.subarray(i * chunkLen, (i + 1) * chunkLen);
+    frame.set(encodeFrameHeader(pto.protoId(), chunk.length, i + 1 < count), pos);
+    frame.set(chunk, pos + FRAME_HEADER_LEN);
+    pos += FRAME_HEADER_LEN + chunk.length;
+  }
+  return frame;
+}
+
+// 解析 buf 从 offset 开始的一个完整协议包(包括所有分片), 数据不完整时返回 null, size 是消息头和协议包的总长度
+export function decodeFrame(buf: Uint8Array, offset: number = 0): { pto: ProtoType; size: number } | null {
+  const chunks: Uint8Array[] = [];
+  let pos = offset;
+  let protoId = -1;
+  let compressed = false;
+  for (;;) {
+    const header = decodeFrameHeader(buf, pos);
+    if (header === null) {
+      return null;
+    }
+    if (protoId !== -1 && header.protoId !== protoId) {
+      throw new Error(`[decodeFrame]: chunk of proto_id=${protoId} interleaved with proto_id=${header.protoId}`);
+    }
+    protoId = header.protoId;
+    const start = pos + FRAME_HEADER_LEN;
+    const end = start + header.len;
+    if (buf.length < end) {
+      return null;
+    }
+    chunks.push(buf.subarray(start, end));
+    pos = end;
+    compressed = header.compressed;
+    if (!header.more) {
+      break;
+    }
+  }
+  if (compressed) {
+    const body = decompress(concat(chunks));
+    return { pto: parseProto(protoId, body, 0, body.length), size: pos - offset };
+  }
+  if (chunks.length === 1) {
+    const start = offset + FRAME_HEADER_LEN;
+    return { pto: parseProto(protoId, buf, start, pos), size: pos - offset };
+  }
+  const body = concat(chunks);
+  return { pto: parseProto(protoId, body, 0, body.length), size: pos - offset };
+}
+
+function concat(chunks: Uint8Array[]): Uint8Array {
+  if (chunks.length === 1) {
+    return chunks[0];
+  }
+  const body = new Uint8Array(chunks.reduce((n, c) => n + c.length, 0));
+  chunks.reduce((at, c) => (body.set(c, at), at + c.length), 0);
+  return body;
+}
+"#;
+
+const TESTS: &str = r##"// this file is automatically generated by protogen. please do not edit.
+// 用服务器生成的测试向量检查编解码: cargo run -p proto --example vectors > vectors.txt
+// 每行 "协议名 = 消息头和协议包的十六进制", 解码后重新编码必须得到相同的字节.
+// 协议包末尾加上不认识的字段后, 解码重新编码也必须得到原来的字节.
+import { decodeFrame, encodeFrame, FRAME_HEADER_LEN, PTO_NAMES } from "./ptos";
+
+function fromHex(hex: string): Uint8Array {
+  const bytes = new Uint8Array(hex.length / 2);
+  for (let i = 0; i < bytes.length; i++) {
+    bytes[i] = parseInt(hex.substr(i * 2, 2), 16);
+  }
+  return bytes;
+}
+
+function toHex(bytes: Uint8Array): string {
+  return Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+// 字段编号 2^29-1 的 varint 字段, 值是 1
+const UNKNOWN_FIELD = fromHex("f8ffffff0f01");
+
+// 在协议包末尾加上 UNKNOWN_FIELD, 分片或压缩的帧返回 null
+function withUnknownField(frame: Uint8Array): Uint8Array | null {
+  const len = frame.length - FRAME_HEADER_LEN;
+  if (new DataView(frame.buffer, frame.byteOffset).getUint32(4, true) !== len) {
+    return null;
+  }
+  const out = new Uint8Array(frame.length + UNKNOWN_FIELD.length);
+  out.set(frame);
+  out.set(UNKNOWN_FIELD, frame.length);
+  new DataView(out.buffer).setUint32(4, len + UNKNOWN_FIELD.length, true);
+  return out;
+}
+
+// 返回所有失败的测试向量
+export function checkVectors(text: string): string[] {
+  const failures: string[] = [];
+  for (const line of text.split("\n")) {
+    const content = line.trim();
+    if (content === "" || content.startsWith("#")) {
+      continue;
+    }
+    const idx = content.indexOf("=");
+    const name = content.slice(0, idx).trim();
+    const hex = content.slice(idx + 1).trim();
+    try {
+      const res = decodeFrame(fromHex(hex));
+      if (res === null || res.size * 2 !== hex.length) {
+        failures.push(`${name}: incomplete frame`);
+      } else if (PTO_NAMES[res.pto.protoId()] !== name) {
+        failures.push(`${name}: decoded as ${PTO_NAMES[res.pto.protoId()]}`);
+      } else if (toHex(encodeFrame(res.pto)) !== hex) {
+        failures.push(`${name}: re-encoded frame differs`);
+      } else {
+        const extended = withUnknownField(fromHex(hex));
+        const res2 = extended === null ? null : decodeFrame(extended);
+        if (extended !== null && (res2 === null || toHex(encodeFrame(res2.pto)) !== hex)) {
+          failures.push(`${name}: unknown field is not skipped`);
+        }
+      }
+    } catch (e) {
+      failures.push(`${name}: ${e}`);
+    }
+  }
+  return failures;
+}
+"##;
+
+// 带包名的 datatype 的类名是展开成标识符的全名
+fn ts_type(field: &FieldSchema) -> String {
+    let class = ident(&field.ty);
+    let ty = match Kind::of(&field.ty) {
+        Kind::I64 | Kind::U64 => "bigint",
+        Kind::Bool => "boolean",
+        Kind::String => "string",
+        Kind::Message => &class,
+        _ => "number",
+    };
+    if field.repeated {
+        format!("{}[]", ty)
+    } else {
+        ty.to_owned()
+    }
+}
+
+fn ts_default(field: &FieldSchema) -> String {
+    if field.repeated {
+        return "[]".to_owned();
+    }
+    match Kind::of(&field.ty) {
+        Kind::I64 | Kind::U64 => "0n".to_owned(),
+        Kind::Bool => "false".to_owned(),
+        Kind::String => "\"\"".to_owned(),
+        Kind::Message => format!("new {}()", ident(&field.ty)),
+        _ => "0".to_owned(),
+    }
+}
+
+// 64 位整数是 bigint
+fn ts_const(c: &ConstSchema) -> String {
+    match (&c.value, Kind::of(&c.ty)) {
+        (OptionValue::Int(v), Kind::I64 | Kind::U64) => format!("{}n", v),
+        (value, _) => value.to_string(),
+    }
+}
+
+fn write_value(kind: Kind, v: &str) -> String {
+    format!("w.write{}({});", kind.suffix(), v)
+}
+
+fn read_value(kind: Kind, ty: &str) -> String {
+    match kind {
+        Kind::Message => format!("r.readMessage({}.read)", ty),
+        _ => format!("r.read{}()", kind.suffix()),
+    }
+}
+
+pub(crate) fn write_class(out: &mut String, m: &MessageSchema) -> Result<()> {
+    let class = ident(&m.name);
+    writeln!(out)?;
+    writeln!(out, "export class {} {{", class)?;
+    if let Some(id) = m.id {
+        writeln!(out, "  static readonly ID = {};", id)?;
+        writeln!(out, "  static readonly NAME = \"{}\";", m.name)?;
+    }
+    for f in &m.fields {
+        writeln!(out, "  {}: {} = {};", f.name, ts_type(f), ts_default(f))?;
+    }
+    if let Some(id) = m.id {
+        writeln!(out)?;
+        writeln!(out, "  protoId(): number {{\n    return {};\n  }}", id)?;
+    }
+
+    writeln!(out)?;
+    let w = if m.fields.is_empty() { "_w" } else { "w" };
+    writeln!(out, "  write({}: BytesWriter): void {{", w)?;
+    for f in client::write_order(m) {
+        let kind = Kind::of(&f.ty);
+        writeln!(out, "    w.writeVarint32({});", client::tag(f))?;
+        if f.repeated {
+            writeln!(out, "    w.writeVarint32(this.{}.length);", f.name)?;
+            writeln!(
+                out,
+                "    for (const v of this.{}) {{\n      {}\n    }}",
+                f.name,
+                write_value(kind, "v")
+            )?;
+        } else {
+            let v = format!("this.{}", f.name);
+            writeln!(out, "    {}", write_value(kind, &v))?;
+        }
+    }
+    writeln!(out, "  }}")?;
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "  static read(r: BytesReader, end: number): {} {{",
+        class
+    )?;
+    writeln!(out, "    const msg = new {}();", class)?;
+    writeln!(out, "    while (r.pos < end) {{")?;
+    writeln!(out, "      const tag = r.readVarint32();")?;
+    writeln!(out, "      switch (tag) {{")?;
+    for f in &m.fields {
+        let read = read_value(Kind::of(&f.ty), &ident(&f.ty));
+        if f.repeated {
+            writeln!(
+                out,
+                "        case {}: {{\n          const n = r.readVarint32();\n          for (let i = 0; i < n; i++) {{\n            msg.{}.push({});\n          }}\n          break;\n        }}",
+                client::tag(f),
+                f.name,
+                read
+            )?;
+        } else {
+            writeln!(
+                out,
+                "        case {}:\n          msg.{} = {};\n          break;",
+                client::tag(f),
+                f.name,
+                read
+            )?;
+        }
+    }
+    writeln!(
+        out,
+        "        default:\n          // 新版本加的字段\n          r.pos = end;\n          break;"
+    )?;
+    writeln!(out, "      }}\n    }}\n    return msg;\n  }}\n}}")
+}
+
+pub(crate) fn ptos_source(schema: &ClientSchema) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// this file is automatically generated by protogen. please do not edit."
+    )?;
+    writeln!(out, "// 编解码与服务器 proto crate 的默认编码一致.")?;
+    writeln!(out)?;
+    writeln!(out, "export const PTO_VERSION = \"{}\";", schema.version)?;
+    if !schema.consts.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "// 协议源文件里声明的常量")?;
+    }
+    for c in &schema.consts {
+        writeln!(out, "export const {} = {};", c.name, ts_const(c))?;
+    }
+    out.push_str(RUNTIME);
+    for m in schema.messages() {
+        write_class(&mut out, m)?;
+    }
+
+    writeln!(out)?;
+    let names: Vec<String> = schema.protocols.iter().map(|m| ident(&m.name)).collect();
+    writeln!(
+        out,
+        "export type ProtoType =\n  | {};",
+        names.join("\n  | ")
+    )?;
+    writeln!(out)?;
+    writeln!(out, "// 协议id -> 协议名")?;
+    writeln!(
+        out,
+        "export const PTO_NAMES: {{ [id: number]: string }} = {{"
+    )?;
+    for m in &schema.protocols {
+        writeln!(out, "  {}: \"{}\",", m.id.unwrap(), m.name)?;
+    }
+    writeln!(out, "}};")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "export function parseProto(protoId: number, buf: Uint8Array, start: number, end: number): ProtoType {{"
+    )?;
+    writeln!(out, "  const r = new BytesReader(buf, start);")?;
+    writeln!(out, "  switch (protoId) {{")?;
+    for m in &schema.protocols {
+        writeln!(
+            out,
+            "    case {}:\n      return {}.read(r, end);",
+            m.id.unwrap(),
+            ident(&m.name)
+        )?;
+    }
+    writeln!(
+        out,
+        "    default:\n      throw new Error(`[allptos.parseProto]: failed, proto_id=${{protoId}}`);"
+    )?;
+    writeln!(out, "  }}\n}}")?;
+    out.push_str(FRAME);
+    Ok(out)
+}
+
+// 生成 into/ptos.ts 和 into/ptos_test.ts
+pub fn export_ts(config: &Config, into: &Path) -> std::result::Result<Vec<PathBuf>, Error> {
+    let schema = ClientSchema::load(config)?;
+    let ptos = ptos_source(&schema).expect("write ts failed");
+    fs::create_dir_all(into)?;
+    let mut files = Vec::new();
+    for (fname, content) in [("ptos.ts", ptos.as_str()), ("ptos_test.ts", TESTS)] {
+        let path = into.join(fname);
+        fs::write(&path, content)?;
+        files.push(path);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, ty: &str, tag: i32, repeated: bool) -> FieldSchema {
+        FieldSchema {
+            name: name.to_owned(),
+            ty: ty.to_owned(),
+            tag,
+            repeated,
+        }
+    }
+
+    // 一个协议的完整编解码
+    #[test]
+    fn codec() {
+        let m = MessageSchema {
+            name: "s_x".to_owned(),
+            is_protocol: true,
+            id: Some(201),
+            fields: vec![
+                field("items", "item", 3, true),
+                field("uid", "uint64", 1, false),
+                field("name", "string", 2, false),
+            ],
+        };
+        let mut ts = String::new();
+        write_class(&mut ts, &m).unwrap();
+        assert!(ts.contains("  static readonly ID = 201;\n  static readonly NAME = \"s_x\";\n"));
+        assert!(ts.contains("  items: item[] = [];\n  uid: bigint = 0n;\n  name: string = \"\";\n"));
+        assert!(ts.contains(
+            "  write(w: BytesWriter): void {
+    w.writeVarint32(8);
+    w.writeU64(this.uid);
+    w.writeVarint32(18);
+    w.writeString(this.name);
+    w.writeVarint32(26);
+    w.writeVarint32(this.items.length);
+    for (const v of this.items) {
+      w.writeMessage(v);
+    }
+  }
+"
+        ));
+        assert!(ts.contains(
+            "        case 26: {
+          const n = r.readVarint32();
+          for (let i = 0; i < n; i++) {
+            msg.items.push(r.readMessage(item.read));
+          }
+          break;
+        }
+        case 8:
+          msg.uid = r.readU64();
+          break;
+        case 18:
+          msg.name = r.readString();
+          break;
+        default:
+"
+        ));
+    }
+
+    // ptos_test.ts 用到的函数和常量都由 ptos.ts 导出
+    #[test]
+    fn vectors_imports() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../proto");
+        let config = Config::new(dir.join("ptosrc"), dir.join("src"));
+        let ptos = ptos_source(&ClientSchema::load(&config).unwrap()).unwrap();
+        let line = TESTS.lines().find(|l| l.starts_with("import {")).unwrap();
+        let names = &line["import {".len()..line.find('}').unwrap()];
+        for name in names.split(',').map(str::trim) {
+            assert!(
+                ptos.contains(&format!("export function {}(", name))
+                    || ptos.contains(&format!("export const {} ", name))
+                    || ptos.contains(&format!("export const {}:", name)),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -1,5 +1,3 @@
-use rengine;
-
 fn main() {
     rengine::entry::start();
 }
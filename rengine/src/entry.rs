@@ -1,19 +1,30 @@
-use conf::conf::Conf;
+use conf::conf::{Conf, TlsConf};
 use tokio::signal;
 extern crate net;
 use crate::shared_states::{
     DbSharedEntity, GameSharedEntity, HttpSharedEntity, RpcSharedEntity, TcpSharedEntity,
 };
 use llog;
-use net::{http::http_service, rpc::rpc_service, tcp::tcp_service, Communicate};
+use net::{
+    http::http_service,
+    rpc::rpc_service,
+    tcp::{
+        compress::Compression, listener, outbound::OutboundPolicy, rate_limit::RateLimitConfig,
+        tcp_service,
+    },
+    tls::TlsAcceptor,
+    ws::ws_service,
+    Communicate, NetEvent,
+};
+use std::sync::Arc;
 use tokio::{
-    sync::mpsc,
+    sync::{mpsc, Semaphore},
     time::{self, Duration},
 };
 
 pub fn start() {
     let rt = tokio::runtime::Runtime::new().unwrap();
-    let _ = rt.block_on(async move {
+    rt.block_on(async move {
         let sysconf = Conf::new();
         // :TODO: select! 宏没有办法按配置选择不同的 async 块,考虑把重复的代码提取出来
         if sysconf.get_host_type() == "game" {
@@ -55,27 +66,88 @@ async fn game_server_entry(sysconf: Conf) {
 
     // rpc service, 本身就是一个 tpc service, 只不过监听服务端口不一样, 而且协议类型可能需要做区分
     let rpc_addr = sysconf.get_rpc_serv_addr().to_owned();
+    let rpc_opts = net::ConnOptions {
+        max_message_len: sysconf.get_rpc_max_message_len(),
+        tls: tls_acceptor("rpc_tls", sysconf.get_rpc_tls()),
+        ..Default::default()
+    };
     tokio::spawn(async move {
-        rpc_service::start_service(&rpc_addr, signal::ctrl_c(), r_chan_out_tx, r_out_sender).await;
+        rpc_service::start_service(
+            rpc_opts,
+            &rpc_addr,
+            signal::ctrl_c(),
+            r_chan_out_tx,
+            r_out_sender,
+        )
+        .await;
         drop(r_shutdown_tx);
-        let _ = r_shutdown_notify_tx.send(());
+        let _ = r_shutdown_notify_tx.send(()).await;
     });
 
     // http service
     let http_addr = sysconf.get_http_serv_addr().to_owned();
+    let http_tls = tls_acceptor("http_tls", sysconf.get_http_tls());
     tokio::spawn(async move {
         let addr = http_addr.parse().unwrap();
-        http_service::start_service(addr, signal::ctrl_c(), h_chan_out_tx.clone()).await;
+        http_service::start_service(addr, http_tls, signal::ctrl_c(), h_chan_out_tx.clone()).await;
         drop(h_shutdown_tx);
         let _ = h_shutdown_notify_tx.send(()).await;
     });
 
     // player tcp service
     let tcp_addr = sysconf.get_tcp_serv_addr().to_owned();
+    let rate_limit = sysconf.get_tcp_rate_limit().map(|conf| {
+        let config = RateLimitConfig::from_conf(conf).unwrap_or_else(|err| panic!("{}", err));
+        Arc::new(config)
+    });
+    let outbound = sysconf.get_tcp_backpressure().map(|conf| {
+        let policy = OutboundPolicy::from_conf("tcp_backpressure", conf)
+            .unwrap_or_else(|err| panic!("{}", err));
+        Arc::new(policy)
+    });
+    let compression = sysconf
+        .get_tcp_compress_threshold()
+        .map(|threshold| Arc::new(Compression::new(threshold)));
+    let conn_opts = net::ConnOptions {
+        idle_timeout: sysconf.get_tcp_idle_timeout().map(Duration::from_secs),
+        rate_limit: rate_limit.clone(),
+        outbound: outbound.clone(),
+        max_message_len: sysconf.get_tcp_max_message_len(),
+        compression: compression.clone(),
+        encrypt: sysconf.get_tcp_encrypt(),
+        tls: tls_acceptor("tcp_tls", sysconf.get_tcp_tls()),
+        // tcp 和 websocket 的玩家连接合计不超过上限
+        limit_connections: Some(Arc::new(Semaphore::new(listener::MAX_CONNECTIONS))),
+    };
+
+    // player websocket service, 和 tcp 共用连接注册和 mailbox
+    if let Some(ws_addr) = sysconf.get_ws_serv_addr() {
+        let ws_addr = ws_addr.to_owned();
+        let ws_opts = conn_opts.clone();
+        let (chan_out_tx, out_sender) = (p_chan_out_tx.clone(), p_out_sender.clone());
+        let ws_shutdown_tx = shutdown_complete_tx.clone();
+        let ws_shutdown_notify_tx = shutdown_notify_tx.clone();
+        tokio::spawn(async move {
+            let log_name = "palyer_ws_service.log";
+            ws_service::start_service(
+                ws_opts,
+                log_name,
+                &ws_addr,
+                signal::ctrl_c(),
+                chan_out_tx,
+                out_sender,
+            )
+            .await;
+            drop(ws_shutdown_tx);
+            let _ = ws_shutdown_notify_tx.send(()).await;
+        });
+    }
+
     tokio::spawn(async move {
         let log_name = "palyer_tcp_service.log";
         tcp_service::start_service(
             net::ServiceType::Tcp,
+            conn_opts,
             log_name,
             &tcp_addr,
             signal::ctrl_c(),
@@ -84,7 +156,7 @@ async fn game_server_entry(sysconf: Conf) {
         )
         .await;
         drop(p_shutdown_tx);
-        let _ = p_shutdown_notify_tx.send(());
+        let _ = p_shutdown_notify_tx.send(()).await;
     });
 
     // tcp, http, rpc 等服务获得消息输出都会一个 loop 里进行处理,
@@ -103,6 +175,7 @@ async fn game_server_entry(sysconf: Conf) {
         let mut game_entity = GameSharedEntity::new(sysconf, tcp_entity, rpc_entity, http_entity);
 
         let mut heart_beat = time::interval(Duration::from_millis(1000));
+        let mut ticks = 0u64;
         loop {
             tokio::select! {
                 // for player tcp service
@@ -115,15 +188,6 @@ async fn game_server_entry(sysconf: Conf) {
                         break;
                     }
                 },
-                res = p_mailbox.recv() => {
-                    if let Some((vfd,proto_id,pto)) = res {
-                        //println!("service get proto: vfd={},proto_id={}",vfd,proto_id);
-                        let _ = game_entity.dispatch_tcp_msg(vfd,proto_id,pto).await;
-                    } else {
-                        llog::error!(log_name,"[tcp]: server service receive close");
-                        break;
-                    }
-                }
                 // for rpc service
                 res = r_chan_out_rx.recv() => {
                     if let Some((vfd,sender)) = res {
@@ -134,13 +198,50 @@ async fn game_server_entry(sysconf: Conf) {
                         break;
                     }
                 },
+                res = p_mailbox.recv() => {
+                    // 先处理连接注册, 再处理这个连接的事件
+                    while let Ok((vfd,sender)) = p_chan_out_rx.try_recv() {
+                        game_entity.tcp_entity.register(vfd,sender);
+                        llog::info!(log_name,"[tcp]: new client connection channel: vfd={}",vfd);
+                    }
+                    match res {
+                        Some(NetEvent::Proto((vfd,proto_id,pto))) => {
+                            //println!("service get proto: vfd={},proto_id={}",vfd,proto_id);
+                            let _ = game_entity.dispatch_tcp_msg(vfd,proto_id,pto).await;
+                        }
+                        Some(NetEvent::Connected { vfd, peer_addr }) => {
+                            llog::info!(log_name,"[tcp]: connected: vfd={},peer_addr={}",vfd,peer_addr);
+                        }
+                        Some(NetEvent::Disconnected { vfd, reason }) => {
+                            game_entity.on_tcp_disconnected(vfd, &reason);
+                        }
+                        None => {
+                            llog::error!(log_name,"[tcp]: server service receive close");
+                            break;
+                        }
+                    }
+                }
                 res = r_mailbox.recv() => {
-                    if let Some((vfd,proto_id,pto)) = res {
-                        //println!("service get proto: vfd={},proto_id={}",vfd,proto_id);
-                        let _ = game_entity.dispatch_rpc_msg(vfd,proto_id,pto).await;
-                    } else {
-                        llog::error!(log_name,"[rpc]: server service receive close");
-                        break;
+                    while let Ok((vfd,sender)) = r_chan_out_rx.try_recv() {
+                        game_entity.rpc_entity.register(vfd,sender);
+                        llog::info!(log_name,"[rpc]: new client connection channel: vfd={}",vfd);
+                    }
+                    match res {
+                        Some(NetEvent::Proto((vfd,proto_id,pto))) => {
+                            //println!("service get proto: vfd={},proto_id={}",vfd,proto_id);
+                            let _ = game_entity.dispatch_rpc_msg(vfd,proto_id,pto).await;
+                        }
+                        Some(NetEvent::Connected { vfd, peer_addr }) => {
+                            llog::info!(log_name,"[rpc]: connected: vfd={},peer_addr={}",vfd,peer_addr);
+                        }
+                        Some(NetEvent::Disconnected { vfd, reason }) => {
+                            game_entity.rpc_entity.unregister(vfd);
+                            llog::info!(log_name,"[rpc]: disconnected: vfd={},reason={:?}",vfd,reason);
+                        }
+                        None => {
+                            llog::error!(log_name,"[rpc]: server service receive close");
+                            break;
+                        }
                     }
                 }
                 // for http service
@@ -154,12 +255,33 @@ async fn game_server_entry(sysconf: Conf) {
                 },
                 _ = heart_beat.tick() => {
                     println!("service heart_beat tick");
+                    game_entity.expire_offline_players();
+                    ticks += 1;
+                    // 每分钟记录一次流量限制, 发送队列背压和压缩的计数
+                    if ticks.is_multiple_of(60) {
+                        if let Some(rate_limit) = &rate_limit {
+                            llog::info!(log_name,"[tcp]: rate limit: {:?}",rate_limit.stats());
+                        }
+                        if let Some(outbound) = &outbound {
+                            llog::info!(log_name,"[tcp]: outbound: {:?}",outbound.stats());
+                        }
+                        if let Some(compression) = &compression {
+                            let stats = compression.stats();
+                            llog::info!(log_name,"[tcp]: compress: {:?},ratio={:.3}",stats,stats.ratio());
+                        }
+                        llog::info!(log_name,"[rpc]: outbound: {:?}",game_entity.rpc_entity.outbound_stats());
+                        if let Some(stats) = game_entity.rpc_entity.compress_stats() {
+                            llog::info!(log_name,"[rpc]: compress: {:?},ratio={:.3}",stats,stats.ratio());
+                        }
+                    }
                 }
                 _ = shutdown_notify_rx.recv() => {
                     llog::error!(log_name,"server service shutdown");
                     break;
                 }
             }
+            // 配置了 Block 策略的 rpc 协议在处理完事件后等待发送
+            game_entity.rpc_entity.flush().await;
         }
         drop(shutdown_complete_tx);
         llog::info!(log_name, "all service stop");
@@ -182,11 +304,22 @@ async fn db_server_entry(sysconf: Conf) {
 
     // rpc service, 本身就是一个 tpc service, 只不过监听服务端口不一样, 而且协议类型可能需要做区分
     let rpc_db_addr = sysconf.get_rpc_db_serv_addr().to_owned();
+    let rpc_opts = net::ConnOptions {
+        max_message_len: sysconf.get_rpc_max_message_len(),
+        tls: tls_acceptor("rpc_tls", sysconf.get_rpc_tls()),
+        ..Default::default()
+    };
     tokio::spawn(async move {
-        rpc_service::start_service(&rpc_db_addr, signal::ctrl_c(), r_chan_out_tx, r_out_sender)
-            .await;
+        rpc_service::start_service(
+            rpc_opts,
+            &rpc_db_addr,
+            signal::ctrl_c(),
+            r_chan_out_tx,
+            r_out_sender,
+        )
+        .await;
         drop(r_shutdown_tx);
-        let _ = r_shutdown_notify_tx.send(());
+        let _ = r_shutdown_notify_tx.send(()).await;
     });
 
     tokio::spawn(async move {
@@ -213,12 +346,27 @@ async fn db_server_entry(sysconf: Conf) {
                     }
                 },
                 res = r_mailbox.recv() => {
-                    if let Some((vfd,proto_id,pto)) = res {
-                        //println!("service get proto: vfd={},proto_id={}",vfd,proto_id);
-                        let _ = db_entity.dispatch_rpc_msg(vfd,proto_id,pto).await;
-                    } else {
-                        llog::error!(log_name,"[rpc]: server service receive close");
-                        break;
+                    // 先处理连接注册, 再处理这个连接的事件
+                    while let Ok((vfd,sender)) = r_chan_out_rx.try_recv() {
+                        db_entity.rpc_entity.register(vfd,sender);
+                        llog::info!(log_name,"[rpc]: new client connection channel: vfd={}",vfd);
+                    }
+                    match res {
+                        Some(NetEvent::Proto((vfd,proto_id,pto))) => {
+                            //println!("service get proto: vfd={},proto_id={}",vfd,proto_id);
+                            let _ = db_entity.dispatch_rpc_msg(vfd,proto_id,pto).await;
+                        }
+                        Some(NetEvent::Connected { vfd, peer_addr }) => {
+                            llog::info!(log_name,"[rpc]: connected: vfd={},peer_addr={}",vfd,peer_addr);
+                        }
+                        Some(NetEvent::Disconnected { vfd, reason }) => {
+                            db_entity.rpc_entity.unregister(vfd);
+                            llog::info!(log_name,"[rpc]: disconnected: vfd={},reason={:?}",vfd,reason);
+                        }
+                        None => {
+                            llog::error!(log_name,"[rpc]: server service receive close");
+                            break;
+                        }
                     }
                 }
                 _ = heart_beat.tick() => {
@@ -229,9 +377,20 @@ async fn db_server_entry(sysconf: Conf) {
                     break;
                 }
             }
+            // 配置了 Block 策略的 rpc 协议在处理完事件后等待发送
+            db_entity.rpc_entity.flush().await;
         }
         drop(shutdown_complete_tx);
         llog::info!(log_name, "all service stop");
     });
     let _ = shutdown_complete_rx.recv().await;
 }
+
+// 证书或私钥读取失败时不启动
+fn tls_acceptor(section: &str, conf: Option<&TlsConf>) -> Option<Arc<TlsAcceptor>> {
+    conf.map(|conf| {
+        let acceptor =
+            TlsAcceptor::from_conf(section, conf).unwrap_or_else(|err| panic!("{}", err));
+        Arc::new(acceptor)
+    })
+}
@@ -1,30 +1,32 @@
 use super::items::Item;
 use crate::errors::Error;
 use crate::Result;
+use proto::consts;
 use proto::item_info::item_info;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+// 取值与协议里的 bagtype 一致
 pub enum BagType {
-    Equiped = 1,
-    Items = 2,
-    Temp = 3,
+    Equiped = consts::BAG_EQUIPED as isize,
+    Items = consts::BAG_ITEMS as isize,
+    Temp = consts::BAG_TEMP as isize,
 }
 
 impl BagType {
     pub fn into_u8(&self) -> u8 {
         match self {
-            BagType::Equiped => 1,
-            BagType::Items => 2,
-            BagType::Temp => 3,
+            BagType::Equiped => consts::BAG_EQUIPED,
+            BagType::Items => consts::BAG_ITEMS,
+            BagType::Temp => consts::BAG_TEMP,
         }
     }
 
     pub fn from_u8(bag_type: u8) -> Self {
         match bag_type {
-            1 => BagType::Equiped,
-            2 => BagType::Items,
-            3 => BagType::Temp,
+            consts::BAG_EQUIPED => BagType::Equiped,
+            consts::BAG_ITEMS => BagType::Items,
+            consts::BAG_TEMP => BagType::Temp,
             _ => BagType::Temp,
         }
     }
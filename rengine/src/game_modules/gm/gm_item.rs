@@ -1,7 +1,5 @@
 use crate::game_modules::items::item_mgr::TitemMgr;
-use crate::{
-    errors::Error, game_modules::player::Tplayer, shared_states::GameSharedEntity, Result,
-};
+use crate::{errors::Error, shared_states::GameSharedEntity, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -21,9 +19,7 @@ impl TgmItem for GameSharedEntity {
         }
         match serde_json::from_value::<GmAddItemFormat>(value) {
             Ok(gmobj) => self.reward_item_to_player(gmobj.uid, gmobj.item_id, gmobj.stack),
-            Err(err) => {
-                return Err(Error::from(err.to_string()));
-            }
+            Err(err) => Err(Error::from(err.to_string())),
         }
     }
 }
@@ -13,11 +13,7 @@ impl Tgm for GameSharedEntity {
             Ok(value) => match value {
                 Value::Object(args) => match args.get("func") {
                     Some(func_name) => {
-                        let func_name = func_name.as_str();
-                        let func = if func_name.is_none() {
-                            return Err(Error::from("[handler_gm_cmd]: func name error"));
-                        } else {
-                            let func_name = func_name.unwrap();
+                        let func = if let Some(func_name) = func_name.as_str() {
                             let func = GMFuncMarker::from_str(func_name).into_func();
                             if func.is_none() {
                                 return Err(Error::from(format!(
@@ -26,6 +22,8 @@ impl Tgm for GameSharedEntity {
                                 )));
                             }
                             func.unwrap()
+                        } else {
+                            return Err(Error::from("[handler_gm_cmd]: func name error"));
                         };
                         func(self, Value::Object(args))
                     }
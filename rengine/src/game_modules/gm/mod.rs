@@ -24,6 +24,7 @@ impl GMFuncMarker {
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(func_name: &str) -> Self {
         match func_name {
             "add_item" => GMFuncMarker::add_item,
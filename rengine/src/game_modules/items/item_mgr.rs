@@ -3,13 +3,13 @@ use crate::{
     game_modules::{
         bag::{Bag, BagType},
         items::Item,
-        player::{self, Player, Tplayer},
+        player,
         uuid::Tuuid,
     },
-    shared_states::game_state,
     Result,
 };
-use proto::{c_item_bag::c_item_bag, item_info::item_info};
+use proto::consts;
+use proto::item_info::item_info;
 use serde::{Deserialize, Serialize};
 
 const LOG_NAME: &str = "item_mgr.log";
@@ -25,9 +25,9 @@ pub struct ItemMgr {
 
 impl ItemMgr {
     pub fn new(owner: u64) -> Self {
-        let bag_equiped = Bag::new(BagType::Equiped, 5);
-        let bag_items = Bag::new(BagType::Items, 1000);
-        let bag_temp = Bag::new(BagType::Temp, 500);
+        let bag_equiped = Bag::new(BagType::Equiped, consts::BAG_EQUIPED_CAPACITY as usize);
+        let bag_items = Bag::new(BagType::Items, consts::BAG_ITEMS_CAPACITY as usize);
+        let bag_temp = Bag::new(BagType::Temp, consts::BAG_TEMP_CAPACITY as usize);
 
         ItemMgr {
             owner,
@@ -44,18 +44,15 @@ impl ItemMgr {
             BagType::Temp => &mut self.bag_temp,
         };
         let item_uid = new_item.uid();
-        match bag.add_item(new_item) {
-            Err(err) => {
-                llog::info!(
-                    LOG_NAME,
-                    "[add_item_to_bag]: owner={},bag_type={},{}",
-                    self.owner,
-                    bag_type.into_u8(),
-                    err
-                );
-                return Err(err);
-            }
-            _ => {}
+        if let Err(err) = bag.add_item(new_item) {
+            llog::info!(
+                LOG_NAME,
+                "[add_item_to_bag]: owner={},bag_type={},{}",
+                self.owner,
+                bag_type.into_u8(),
+                err
+            );
+            return Err(err);
         }
         let item = bag.get_item(item_uid).unwrap();
         llog::info!(
@@ -82,14 +79,12 @@ impl ItemMgr {
             BagType::Equiped => self.bag_equiped.pack(),
             BagType::Items => self.bag_equiped.pack(),
             BagType::Temp => self.bag_temp.pack(),
-            _ => vec![],
         }
     }
 }
 
 pub fn create_new_item(item_uid: u64, id: u32, stack: i32) -> Item {
-    let new_item = Item::new(item_uid, id, stack);
-    new_item
+    Item::new(item_uid, id, stack)
 }
 
 pub trait TitemMgr {
@@ -1,4 +1,4 @@
-use super::db::DBObj;
+use super::db::{DBConf, DBObj};
 use super::items::item_mgr::ItemMgr;
 use crate::{
     errors::Error,
@@ -7,10 +7,12 @@ use crate::{
 };
 use net::{utils, Communicate, ProtoSender, ProtoType};
 use proto::ptoout::*;
-use proto::util::default_random_value;
+use rand::{rngs::OsRng, Rng};
 use serde::{Deserialize, Serialize};
 
 const LOG_NAME: &str = "player.log";
+// 断线重连的 token 连续不匹配这么多次后作废, 只能重新登录
+const MAX_TOKEN_FAILURES: u32 = 3;
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Player {
@@ -25,7 +27,9 @@ pub struct Player {
     #[serde(skip)]
     sender: Option<ProtoSender>,
     #[serde(skip)]
-    magic: i32,
+    token: Option<u64>,
+    #[serde(skip)]
+    token_failures: u32,
     item_mgr: ItemMgr,
     #[serde(skip)]
     inner: Option<DBObj>,
@@ -43,6 +47,11 @@ impl Player {
         }
     }
 
+    pub fn build_with_db(mut self, dbobj: DBObj) -> Self {
+        self.inner = Some(dbobj);
+        self
+    }
+
     pub fn get_vfd(&self) -> u64 {
         self.vfd
     }
@@ -76,11 +85,38 @@ impl Player {
     }
 
     pub fn get_sender(&mut self) -> &Option<ProtoSender> {
-        return &self.sender;
+        &self.sender
+    }
+
+    pub fn set_token(&mut self, token: u64) {
+        self.token = Some(token);
+        self.token_failures = 0;
+    }
+
+    // 不匹配的次数达到 MAX_TOKEN_FAILURES 时 token 作废
+    pub fn check_token(&mut self, token: u64) -> bool {
+        match self.token {
+            Some(t) if t == token => true,
+            Some(_) => {
+                self.token_failures += 1;
+                if self.token_failures >= MAX_TOKEN_FAILURES {
+                    self.token = None;
+                }
+                false
+            }
+            None => false,
+        }
+    }
+
+    // 断线重连后绑定新的连接
+    pub fn rebind(&mut self, vfd: u64, new_sender: ProtoSender) {
+        self.vfd = vfd;
+        self.sender = Some(new_sender);
     }
 
-    pub fn set_magic(&mut self, magic: i32) {
-        self.magic = magic;
+    // 断线后不再给旧的连接发消息
+    pub fn unbind(&mut self) {
+        self.sender = None;
     }
 
     pub fn get_item_mgr(&mut self) -> &mut ItemMgr {
@@ -126,7 +162,7 @@ impl Player {
         };
 
         // 初始化 player 对象
-        let mut player: Player = match serde_json::from_slice(&ptoobj.value) {
+        let player: Player = match serde_json::from_slice(&ptoobj.value) {
             Ok(player) => player,
             Err(err) => {
                 llog::error!(
@@ -139,15 +175,22 @@ impl Player {
                 return;
             }
         };
-        let magic = default_random_value("i32").parse().unwrap();
+        let host_id = game_entity.get_host_id();
+        let dbobj = DBObj::new(host_id, DBConf::Player(player.get_acc().to_string()));
+        let mut player = player.build_with_db(dbobj);
+        // 存盘里的 vfd 是上一次登录的连接
+        player.vfd = vfd;
+        let token = new_token();
         player.update_sender(ch.clone());
-        player.set_magic(magic);
+        player.set_token(token);
 
         //告诉客户端登录加载完毕
         let sendptoid = c_login::c_login::id();
-        let mut c_login = c_login::c_login::default();
-        c_login.ret = 1;
-        c_login.magic = magic;
+        let c_login = c_login::c_login {
+            ret: consts::LOGIN_SUCCESS,
+            token,
+            ..Default::default()
+        };
         let sendpto = ProtoType::c_login(c_login);
         player.send(sendptoid, sendpto);
 
@@ -164,6 +207,11 @@ impl Player {
     }
 }
 
+// 断线重连的凭证, 用操作系统的安全随机数生成
+pub fn new_token() -> u64 {
+    OsRng.gen()
+}
+
 pub fn player_do_mut<F, T>(game_entity: &mut GameSharedEntity, uid: u64, mut f: F) -> Result<T>
 where
     F: FnMut(&mut GameSharedEntity, &mut Player) -> Result<T>,
@@ -195,6 +243,8 @@ pub trait Tplayer {
     fn remove_player_by_vfd(&mut self, vfd: u64);
     fn remove_player_by_uid(&mut self, uid: u64);
     fn remove_player(&mut self, vfd: u64, uid: u64);
+    fn resume_player(&mut self, vfd: u64, uid: u64, token: u64, sender: ProtoSender)
+        -> Option<u64>;
 }
 
 impl Tplayer for GameSharedEntity {
@@ -204,7 +254,7 @@ impl Tplayer for GameSharedEntity {
 
     fn get_player_by_vfd(&mut self, vfd: u64) -> Option<&mut Player> {
         if let Some((uid, _acc)) = self.vfd2uidacc.get(&vfd) {
-            self.player_by_uid.as_mut().unwrap().get_mut(&uid)
+            self.player_by_uid.as_mut().unwrap().get_mut(uid)
         } else {
             None
         }
@@ -231,6 +281,7 @@ impl Tplayer for GameSharedEntity {
             .unwrap()
             .insert(player.get_uid(), player);
         self.vfd2uidacc.insert(vfd, (uid, acc));
+        self.offline_players.remove(&uid);
 
         llog::info!(LOG_NAME, "[add_player]: vfd={},uid={}", vfd, uid);
     }
@@ -247,4 +298,46 @@ impl Tplayer for GameSharedEntity {
         self.remove_player_by_vfd(vfd);
         self.remove_player_by_uid(uid);
     }
+
+    // 把断线的玩家绑定到新的连接 vfd 上, 成功时返回新的 token.
+    // 还在线的玩家不能被重连, 旧的连接断开(或读空闲超时)之后才可以
+    fn resume_player(
+        &mut self,
+        vfd: u64,
+        uid: u64,
+        token: u64,
+        sender: ProtoSender,
+    ) -> Option<u64> {
+        if self.is_vfd_validated(vfd) {
+            return None; // 这个连接已经登录过
+        }
+        if !self.offline_players.contains_key(&uid) {
+            return None;
+        }
+        let player = self.player_by_uid.as_mut().unwrap().get_mut(&uid)?;
+        if !player.check_token(token) {
+            llog::info!(
+                LOG_NAME,
+                "[resume_player]: token mismatch: vfd={},uid={}",
+                vfd,
+                uid
+            );
+            return None;
+        }
+        let old_vfd = player.get_vfd();
+        let acc = player.get_acc().to_string();
+        let new_token = new_token();
+        player.rebind(vfd, sender);
+        player.set_token(new_token);
+        self.vfd2uidacc.insert(vfd, (uid, acc));
+        self.offline_players.remove(&uid);
+        llog::info!(
+            LOG_NAME,
+            "[resume_player]: vfd={},old_vfd={},uid={}",
+            vfd,
+            old_vfd,
+            uid
+        );
+        Some(new_token)
+    }
 }
@@ -39,14 +39,12 @@ impl UUID {
 
     pub fn inc_player_uid(&mut self) -> u64 {
         self.for_player += 1;
-        let puid = self.for_player * BASE + self.host_id;
-        puid
+        self.for_player * BASE + self.host_id
     }
 
     pub fn inc_item_uid(&mut self) -> u64 {
         self.for_item += 1;
-        let iuid = self.for_item * BASE + self.host_id;
-        iuid
+        self.for_item * BASE + self.host_id
     }
 
     pub fn load_ret(game_entity: &mut GameSharedEntity, pto: ProtoType) {
@@ -61,7 +59,7 @@ impl UUID {
         };
 
         let host_id = game_entity.get_host_id();
-        if ptoobj.value.len() == 0 {
+        if ptoobj.value.is_empty() {
             llog::info!(LOG_NAME, "[uuid.load_ret]: new uuid");
             let dbobj = DBObj::new(host_id, DBConf::UUID);
             let mut uuid = UUID::new(host_id, 0, 0).build_with_db(dbobj);
@@ -1,25 +1,17 @@
-use crate::errors::Error;
 use crate::game_modules::bag::BagType;
-use crate::game_modules::player::{Player, Tplayer};
+use crate::game_modules::player::Tplayer;
 use crate::shared_states::GameSharedEntity;
 use crate::Result;
-use net::{allptos, utils, ProtoType};
-use proto::ptoout::*;
-use proto::{c_item_bag::c_item_bag, item_info::item_info};
+use net::ProtoType;
+use proto::c_item_bag::c_item_bag;
+use proto::s_item_bag::s_item_bag;
 
-const LOG_NAME: &str = "item_handler.log";
-
-pub fn s_item_bag(game_entity: &mut GameSharedEntity, vfd: u64, pto: ProtoType) -> Result<()> {
+pub fn s_item_bag(game_entity: &mut GameSharedEntity, vfd: u64, ptoobj: s_item_bag) -> Result<()> {
     let player = game_entity.get_player_by_vfd(vfd);
     if player.is_none() {
         return Ok(());
     }
     let player = player.unwrap();
-    let ptoobj = match pto {
-        ProtoType::s_item_bag(ptoobj) => ptoobj,
-        _ => return Ok(()),
-    };
-
     let bag_type = BagType::from_u8(ptoobj.bagtype);
     let item_mgr = player.get_item_mgr();
     let pack_info = item_mgr.pack_bag_info(bag_type);
@@ -1,9 +1,5 @@
 use crate::{
-    errors::Error,
-    game_modules::{
-        db::DBRetFuncMarker,
-        uuid::{self, UUID},
-    },
+    game_modules::db::DBRetFuncMarker,
     shared_states::{DbSharedEntity, GameSharedEntity},
     Result,
 };
@@ -13,12 +9,11 @@ use proto::ptoout::*;
 
 const LOG_NAME: &str = "leveldb_handler.log";
 
-pub fn db_load_req(db_entity: &mut DbSharedEntity, _vfd: u64, pto: ProtoType) -> Result<()> {
-    let ptoobj = match pto {
-        ProtoType::db_load_req(ptoobj) => ptoobj,
-        _ => return Err(Error::Feedback((111, String::from("")))),
-    };
-
+pub fn db_load_req(
+    db_entity: &mut DbSharedEntity,
+    _vfd: u64,
+    ptoobj: db_load_req::db_load_req,
+) -> Result<()> {
     let sendptoid = db_load_resp::db_load_resp::id();
 
     let from_host = ptoobj.from_host;
@@ -47,12 +42,11 @@ pub fn db_load_req(db_entity: &mut DbSharedEntity, _vfd: u64, pto: ProtoType) ->
     Ok(())
 }
 
-pub fn db_save_req(db_entity: &mut DbSharedEntity, _vfd: u64, pto: ProtoType) -> Result<()> {
-    let ptoobj = match pto {
-        ProtoType::db_save_req(ptoobj) => ptoobj,
-        _ => return Ok(()),
-    };
-
+pub fn db_save_req(
+    db_entity: &mut DbSharedEntity,
+    _vfd: u64,
+    ptoobj: db_save_req::db_save_req,
+) -> Result<()> {
     let ukey = format!("{}/{}", ptoobj.db_name, ptoobj.key);
     if let Some((counter, _)) = db_entity.get(&ukey) {
         if ptoobj.counter <= *counter {
@@ -71,17 +65,17 @@ pub fn db_save_req(db_entity: &mut DbSharedEntity, _vfd: u64, pto: ProtoType) ->
     Ok(())
 }
 
-pub fn db_load_resp(game_entity: &mut GameSharedEntity, _vfd: u64, pto: ProtoType) -> Result<()> {
-    let ptoobj = match &pto {
-        ProtoType::db_load_resp(ptoobj) => ptoobj,
-        _ => return Ok(()),
-    };
+pub fn db_load_resp(
+    game_entity: &mut GameSharedEntity,
+    _vfd: u64,
+    ptoobj: db_load_resp::db_load_resp,
+) -> Result<()> {
     let ret_func = DBRetFuncMarker::from_u64(ptoobj.ret_func).into_func();
     if ret_func.is_none() {
         llog::info!(LOG_NAME, "[db_load_resp]: db_funcs: {}", ptoobj.ret_func);
         return Ok(());
     }
     let ret_func = ret_func.unwrap();
-    ret_func(game_entity, pto);
+    ret_func(game_entity, ProtoType::db_load_resp(ptoobj));
     Ok(())
 }
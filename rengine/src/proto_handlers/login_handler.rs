@@ -1,5 +1,5 @@
 use crate::game_modules::db::{load, DBConf};
-use crate::game_modules::player::{Player, Tplayer};
+use crate::game_modules::player::Tplayer;
 use crate::shared_states::GameSharedEntity;
 use crate::Result;
 use net::Communicate;
@@ -8,20 +8,21 @@ use proto::ptoout::*;
 
 const LOG_NAME: &str = "login_handler.log";
 
-pub fn s_login(game_entity: &mut GameSharedEntity, vfd: u64, pto: ProtoType) -> Result<()> {
+pub fn s_login(
+    game_entity: &mut GameSharedEntity,
+    vfd: u64,
+    ptoobj: s_login::s_login,
+) -> Result<()> {
     let ch = match game_entity.tcp_entity.get(vfd) {
         Some(ch) => ch,
         None => return Ok(()),
     };
-    let ptoobj = match pto {
-        ProtoType::s_login(ptoobj) => ptoobj,
-        _ => return Ok(()),
-    };
 
     let sendptoid = c_login::c_login::id();
-    let mut c_login = c_login::c_login::default();
-    c_login.ret = 1;
-    c_login.magic = 0;
+    let c_login = c_login::c_login {
+        ret: consts::LOGIN_VERSION_MISMATCH,
+        ..Default::default()
+    };
 
     if !allptos::is_proto_version(&ptoobj.vers) {
         let sendpto = ProtoType::c_login(c_login);
@@ -47,3 +48,30 @@ pub fn s_login(game_entity: &mut GameSharedEntity, vfd: u64, pto: ProtoType) ->
     );
     Ok(())
 }
+
+pub fn s_reconnect(
+    game_entity: &mut GameSharedEntity,
+    vfd: u64,
+    ptoobj: s_reconnect::s_reconnect,
+) -> Result<()> {
+    let ch = match game_entity.tcp_entity.get(vfd) {
+        Some(ch) => ch.clone(),
+        None => return Ok(()),
+    };
+
+    // 重连失败时客户端需要重新登录
+    let c_reconnect = match game_entity.resume_player(vfd, ptoobj.uid, ptoobj.token, ch.clone()) {
+        Some(token) => c_reconnect::c_reconnect {
+            ret: consts::RECONNECT_SUCCESS,
+            token,
+        },
+        None => c_reconnect::c_reconnect {
+            ret: consts::RECONNECT_FAILED,
+            token: 0,
+        },
+    };
+    let sendptoid = c_reconnect::c_reconnect::id();
+    let sendpto = ProtoType::c_reconnect(c_reconnect);
+    utils::try_send(LOG_NAME, &ch, vfd, sendptoid, sendpto);
+    Ok(())
+}
@@ -1,9 +1,8 @@
+use crate::errors::Error;
 use crate::shared_states::{DbSharedEntity, GameSharedEntity};
 use crate::Result;
-use net::ProtoType;
-
-pub type ProtoHandlerFunc = fn(&mut GameSharedEntity, u64, ProtoType) -> Result<()>;
-pub type DbProtoHandlerFunc = fn(&mut DbSharedEntity, u64, ProtoType) -> Result<()>;
+use proto::ptoout::*;
+use proto::services::{db, game, game_rpc};
 
 pub mod item_handler;
 pub mod leveldb_handler;
@@ -12,90 +11,49 @@ pub mod mysql_handler;
 pub mod player_handler;
 pub mod rpc_handler;
 
-use item_handler::*;
-use leveldb_handler::*;
-use login_handler::*;
-use player_handler::*;
+// 协议与处理函数的映射由 ptosrc/service 里的 service 声明生成,
+// 新增请求协议时在 service 里声明, 再在这里实现生成的 trait 方法,
+// 或者执行 cargo run -p protogen -- handlers 生成处理函数的框架代码.
 
-// rpc 通信协议与协议处理函数的映射
-#[allow(non_camel_case_types)]
-#[derive(Debug)]
-pub enum RpcRetFuncMarker {
-    //与 db 进程相关的
-    db_load_resp,
-    // 其他进程通信相关的
-    unknow,
-}
+// tcp 通信协议: service game
+impl game::Handler for GameSharedEntity {
+    type Error = Error;
 
-impl RpcRetFuncMarker {
-    pub fn into_func(self) -> Option<ProtoHandlerFunc> {
-        match self {
-            RpcRetFuncMarker::db_load_resp => Some(db_load_resp),
-            _ => None,
-        }
+    fn s_login(&mut self, vfd: u64, pto: s_login::s_login) -> Result<()> {
+        login_handler::s_login(self, vfd, pto)
     }
 
-    pub fn from_str(proto_name: &str) -> Self {
-        match proto_name {
-            "db_load_resp" => RpcRetFuncMarker::db_load_resp,
-            _ => RpcRetFuncMarker::unknow,
-        }
+    fn s_reconnect(&mut self, vfd: u64, pto: s_reconnect::s_reconnect) -> Result<()> {
+        login_handler::s_reconnect(self, vfd, pto)
     }
-}
-
-// rpc 通信协议与协议处理函数的映射
-#[allow(non_camel_case_types)]
-#[derive(Debug)]
-pub enum RpcDbSendFuncMarker {
-    db_load_req,
-    db_save_req,
-    unknow,
-}
 
-impl RpcDbSendFuncMarker {
-    pub fn into_func(self) -> Option<DbProtoHandlerFunc> {
-        match self {
-            RpcDbSendFuncMarker::db_load_req => Some(db_load_req),
-            RpcDbSendFuncMarker::db_save_req => Some(db_save_req),
-            _ => None,
-        }
+    fn s_player_brief(&mut self, vfd: u64, pto: s_player_brief::s_player_brief) -> Result<()> {
+        player_handler::s_player_brief(self, vfd, pto)
     }
 
-    pub fn from_str(proto_name: &str) -> Self {
-        match proto_name {
-            "db_load_req" => RpcDbSendFuncMarker::db_load_req,
-            "db_save_req" => RpcDbSendFuncMarker::db_save_req,
-            _ => RpcDbSendFuncMarker::unknow,
-        }
+    fn s_item_bag(&mut self, vfd: u64, pto: s_item_bag::s_item_bag) -> Result<()> {
+        item_handler::s_item_bag(self, vfd, pto)
     }
 }
 
-// tcp 通信协议与协议处理函数的映射
-#[allow(non_camel_case_types)]
-#[derive(Debug)]
-pub enum ProtoRetFuncMarker {
-    s_login,
-    s_player_brief,
-    s_item_bag,
-    unknow,
+// rpc 通信协议: service game_rpc, 与 db 进程相关的返回
+impl game_rpc::Handler for GameSharedEntity {
+    type Error = Error;
+
+    fn db_load_resp(&mut self, vfd: u64, pto: db_load_resp::db_load_resp) -> Result<()> {
+        leveldb_handler::db_load_resp(self, vfd, pto)
+    }
 }
 
-impl ProtoRetFuncMarker {
-    pub fn into_func(self) -> Option<ProtoHandlerFunc> {
-        match self {
-            ProtoRetFuncMarker::s_login => Some(s_login),
-            ProtoRetFuncMarker::s_player_brief => Some(s_player_brief),
-            ProtoRetFuncMarker::s_item_bag => Some(s_item_bag),
-            _ => None,
-        }
+// rpc 通信协议: service db
+impl db::Handler for DbSharedEntity {
+    type Error = Error;
+
+    fn db_load_req(&mut self, vfd: u64, pto: db_load_req::db_load_req) -> Result<()> {
+        leveldb_handler::db_load_req(self, vfd, pto)
     }
 
-    pub fn from_str(proto_name: &str) -> Self {
-        match proto_name {
-            "s_login" => ProtoRetFuncMarker::s_login,
-            "s_player_brief" => ProtoRetFuncMarker::s_player_brief,
-            "s_item_bag" => ProtoRetFuncMarker::s_item_bag,
-            _ => ProtoRetFuncMarker::unknow,
-        }
+    fn db_save_req(&mut self, vfd: u64, pto: db_save_req::db_save_req) -> Result<()> {
+        leveldb_handler::db_save_req(self, vfd, pto)
     }
 }
@@ -1,14 +1,14 @@
-use crate::errors::Error;
-use crate::game_modules::player::{Player, Tplayer};
+use crate::game_modules::player::Tplayer;
 use crate::shared_states::GameSharedEntity;
 use crate::Result;
-use net::utils;
 use net::ProtoType;
 use proto::ptoout::*;
 
-const LOG_NAME: &str = "player_handler.log";
-
-pub fn s_player_brief(game_entity: &mut GameSharedEntity, vfd: u64, _pto: ProtoType) -> Result<()> {
+pub fn s_player_brief(
+    game_entity: &mut GameSharedEntity,
+    vfd: u64,
+    _ptoobj: s_player_brief::s_player_brief,
+) -> Result<()> {
     let player = game_entity.get_player_by_vfd(vfd);
     if player.is_none() {
         return Ok(());
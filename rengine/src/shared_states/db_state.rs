@@ -1,12 +1,14 @@
 use super::RpcSharedEntity;
-use crate::{proto_handlers::RpcDbSendFuncMarker, Result};
+use crate::Result;
 use conf::conf::Conf;
 use net::ProtoType;
+use proto::services::db;
 use std::collections::HashMap;
 
 const LOG_NAME: &str = "db_state.log";
 
 pub struct DbSharedEntity {
+    #[allow(dead_code)]
     sysconf: Conf,
     pub rpc_entity: RpcSharedEntity,
     datas: HashMap<String, (u64, Vec<u8>)>, // <addr,(counter,buf)>
@@ -14,12 +16,11 @@ pub struct DbSharedEntity {
 
 impl DbSharedEntity {
     pub fn new(sysconf: Conf, rpc_entity: RpcSharedEntity) -> Self {
-        let db_entity = DbSharedEntity {
+        DbSharedEntity {
             sysconf,
             rpc_entity,
             datas: HashMap::new(),
-        };
-        db_entity
+        }
     }
 
     // :TODO: 以 db 接口代替
@@ -45,20 +46,16 @@ impl DbSharedEntity {
         pto: ProtoType,
     ) -> Result<()> {
         let (pid, proto_name) = pto.inner_info();
-        let proto_func = RpcDbSendFuncMarker::from_str(proto_name).into_func();
-        if proto_func.is_none() {
-            llog::info!(
+        match db::dispatch(self, vfd, pto) {
+            Some(Err(err)) => llog::info!(LOG_NAME, "[tcp.dispatch_rpc_msg]: {}", err),
+            Some(Ok(())) => {}
+            None => llog::info!(
                 LOG_NAME,
                 "[tcp.dispatch_rpc_msg]: protocol id does not match: {},{},{}",
                 proto_id,
                 pid,
                 proto_name
-            );
-            return Ok(());
-        }
-        let proto_func = proto_func.unwrap();
-        if let Err(err) = proto_func(self, vfd, pto) {
-            llog::info!(LOG_NAME, "[tcp.dispatch_rpc_msg]: {}", err);
+            ),
         }
         Ok(())
     }
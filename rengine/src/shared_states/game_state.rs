@@ -5,15 +5,16 @@ use crate::{
         player::{Player, Tplayer},
         uuid::{Tuuid, UUID},
     },
-    proto_handlers::{ProtoRetFuncMarker, RpcRetFuncMarker},
     Result,
 };
 use conf::conf::Conf;
 use net::{
     http::{HttpProtoSenderOp, HttpProtoType},
-    utils, ProtoType,
+    utils, Communicate, DisconnectReason, ProtoType,
 };
+use proto::services::{game, game_rpc};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 const LOG_NAME: &str = "game_state.log";
 
@@ -25,6 +26,7 @@ pub struct GameSharedEntity {
     pub http_entity: HttpSharedEntity,
     pub player_by_uid: Option<HashMap<u64, Player>>,
     pub vfd2uidacc: HashMap<u64, (u64, String)>,
+    pub offline_players: HashMap<u64, Instant>, // <uid,断线时间>, 等待断线重连的玩家
     pub uuid: Option<UUID>,
     proto_need_not_vfd_validate: HashMap<String, bool>,
 }
@@ -44,6 +46,7 @@ impl GameSharedEntity {
             http_entity,
             player_by_uid: Some(HashMap::new()),
             vfd2uidacc: HashMap::new(),
+            offline_players: HashMap::new(),
             op_entity: Default::default(),
             uuid: None,
             proto_need_not_vfd_validate: HashMap::new(),
@@ -53,6 +56,9 @@ impl GameSharedEntity {
             .proto_need_not_vfd_validate
             .insert("s_login".to_string(), true);
         game_entity
+            .proto_need_not_vfd_validate
+            .insert("s_reconnect".to_string(), true);
+        game_entity
     }
 
     // get / set methods
@@ -64,6 +70,58 @@ impl GameSharedEntity {
         self.sysconf.get_host_id()
     }
 
+    // 玩家连接断开: 注销连接, 已登录的玩家先存盘.
+    // 配置了 reconnect_grace 时玩家保留在内存里等待断线重连, 否则直接移除
+    pub fn on_tcp_disconnected(&mut self, vfd: u64, reason: &DisconnectReason) {
+        self.tcp_entity.unregister(vfd);
+        let uid = match self.get_vfd_info(vfd) {
+            Some((uid, _acc)) => *uid,
+            None => 0, // 还没有登录
+        };
+        llog::info!(
+            LOG_NAME,
+            "[on_tcp_disconnected]: vfd={},uid={},reason={:?}",
+            vfd,
+            uid,
+            reason
+        );
+        self.remove_player_by_vfd(vfd);
+        let player = match self.player_by_uid.as_mut().unwrap().get_mut(&uid) {
+            Some(player) => player,
+            None => return,
+        };
+        // 同一个账号已经在新的连接上重新登录, 旧连接断开时不能影响新连接上的玩家
+        if player.get_vfd() != vfd {
+            return;
+        }
+        player.save(&mut self.rpc_entity);
+        player.unbind();
+        if self.sysconf.get_reconnect_grace().is_some() {
+            self.offline_players.insert(uid, Instant::now());
+        } else {
+            self.remove_player_by_uid(uid);
+        }
+    }
+
+    // 移除超过断线重连保留时间的玩家, 在服务循环的 heart_beat 里调用
+    pub fn expire_offline_players(&mut self) {
+        let grace = match self.sysconf.get_reconnect_grace() {
+            Some(grace) => Duration::from_secs(grace),
+            None => return,
+        };
+        let expired: Vec<u64> = self
+            .offline_players
+            .iter()
+            .filter(|(_, offline_at)| offline_at.elapsed() >= grace)
+            .map(|(uid, _)| *uid)
+            .collect();
+        for uid in expired {
+            self.offline_players.remove(&uid);
+            self.remove_player_by_uid(uid);
+            llog::info!(LOG_NAME, "[expire_offline_players]: uid={}", uid);
+        }
+    }
+
     pub async fn dispatch_tcp_msg(
         &mut self,
         vfd: u64,
@@ -79,8 +137,7 @@ impl GameSharedEntity {
         }
 
         let (pid, proto_name) = pto.inner_info();
-        let proto_func = ProtoRetFuncMarker::from_str(proto_name).into_func();
-        if proto_func.is_none() {
+        if !game::handles(pid) {
             llog::info!(
                 LOG_NAME,
                 "[tcp.dispatch_tcp_msg]: protocol id does not match: {},{},{}",
@@ -91,19 +148,17 @@ impl GameSharedEntity {
             return Ok(());
         }
 
-        if self.proto_need_not_vfd_validate.get(proto_name).is_none() {
-            if !self.is_vfd_validated(vfd) {
-                println!("[dispatch_tcp_msg]: vfd={} hasn't validated", vfd);
-                return Ok(());
-            }
+        if !self.proto_need_not_vfd_validate.contains_key(proto_name) && !self.is_vfd_validated(vfd)
+        {
+            println!("[dispatch_tcp_msg]: vfd={} hasn't validated", vfd);
+            return Ok(());
         }
-        let proto_func = proto_func.unwrap();
-        if let Err(Error::Feedback((id, err))) = proto_func(self, vfd, pto) {
-            self.get_player_by_vfd(vfd).map(|player| {
+        if let Some(Err(Error::Feedback((id, err)))) = game::dispatch(self, vfd, pto) {
+            if let Some(player) = self.get_player_by_vfd(vfd) {
                 if let Some(ch) = player.get_sender() {
                     utils::feekback(LOG_NAME, ch, vfd, id, err);
                 }
-            });
+            }
         }
         Ok(())
     }
@@ -115,20 +170,16 @@ impl GameSharedEntity {
         pto: ProtoType,
     ) -> Result<()> {
         let (pid, proto_name) = pto.inner_info();
-        let proto_func = RpcRetFuncMarker::from_str(proto_name).into_func();
-        if proto_func.is_none() {
-            llog::info!(
+        match game_rpc::dispatch(self, vfd, pto) {
+            Some(Err(err)) => llog::info!(LOG_NAME, "[tcp.dispatch_rpc_msg]: {}", err),
+            Some(Ok(())) => {}
+            None => llog::info!(
                 LOG_NAME,
                 "[tcp.dispatch_rpc_msg]: protocol id does not match: {},{},{}",
                 proto_id,
                 pid,
                 proto_name
-            );
-            return Ok(());
-        }
-        let proto_func = proto_func.unwrap();
-        if let Err(err) = proto_func(self, vfd, pto) {
-            llog::info!(LOG_NAME, "[tcp.dispatch_rpc_msg]: {}", err);
+            ),
         }
         Ok(())
     }
@@ -33,9 +33,9 @@ impl OperationEntity {
             Some(last_time) => {
                 let duration = Duration::from_secs(60); // 60秒就算超时
                 if last_time.elapsed() >= duration {
-                    return OperationStatus::Idle;
+                    OperationStatus::Idle
                 } else {
-                    return OperationStatus::Busy;
+                    OperationStatus::Busy
                 }
             }
         }
@@ -1,7 +1,8 @@
 use conf::conf::Conf;
 use net::{
     rpc::rpc_sender::{self, RpcSender},
-    Communicate, ProtoMsgType, ProtoSender, ProtoType,
+    tcp::{compress::CompressStats, outbound::OutboundStats},
+    Communicate, ProtoSender, ProtoType,
 };
 use std::collections::HashMap;
 
@@ -11,7 +12,7 @@ pub struct RpcSharedEntity {
     inner: rpc_sender::RpcSender,
 }
 
-impl Communicate<ProtoMsgType> for RpcSharedEntity {
+impl Communicate for RpcSharedEntity {
     fn register(&mut self, vfd: u64, sender: ProtoSender) {
         self.conn_map.insert(vfd, sender);
     }
@@ -33,6 +34,14 @@ impl RpcSharedEntity {
         }
     }
 
+    pub fn outbound_stats(&self) -> OutboundStats {
+        self.inner.outbound_stats()
+    }
+
+    pub fn compress_stats(&self) -> Option<CompressStats> {
+        self.inner.compress_stats()
+    }
+
     pub fn send2host(&mut self, hostid: u64, proto_id: u32, pto: ProtoType) {
         self.inner.send2host(hostid, proto_id, pto);
     }
@@ -40,4 +49,9 @@ impl RpcSharedEntity {
     pub fn send2db(&mut self, proto_id: u32, pto: ProtoType) {
         self.inner.send2db(proto_id, pto);
     }
+
+    // 见 RpcSender::flush
+    pub async fn flush(&mut self) {
+        self.inner.flush().await;
+    }
 }
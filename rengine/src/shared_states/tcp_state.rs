@@ -1,4 +1,4 @@
-use net::{Communicate, ProtoMsgType, ProtoSender};
+use net::{Communicate, ProtoSender};
 use std::collections::HashMap;
 
 #[derive(Default, Debug)]
@@ -6,7 +6,7 @@ pub struct TcpSharedEntity {
     pub conn_map: HashMap<u64, ProtoSender>,
 }
 
-impl Communicate<ProtoMsgType> for TcpSharedEntity {
+impl Communicate for TcpSharedEntity {
     fn register(&mut self, vfd: u64, sender: ProtoSender) {
         self.conn_map.insert(vfd, sender);
     }
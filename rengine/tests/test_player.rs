@@ -0,0 +1,46 @@
+use conf::conf::Conf;
+use net::tcp::outbound::{self, OutboundPolicy};
+use net::{Communicate, DisconnectReason};
+use rengine::game_modules::player::{Player, Tplayer};
+use rengine::shared_states::{GameSharedEntity, RpcSharedEntity, TcpSharedEntity};
+use std::path::Path;
+use std::sync::Arc;
+
+// llog 按当前目录读取 conf/conf.toml, 日志也写到当前目录, 所以在临时目录里运行
+fn game_entity() -> GameSharedEntity {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    let dir = std::env::temp_dir().join(format!("test_player_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("conf")).unwrap();
+    std::fs::copy(root.join("conf/conf.toml"), dir.join("conf/conf.toml")).unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+    let sysconf = Conf::from_file("conf/conf.toml");
+    let rpc_entity = RpcSharedEntity::new(sysconf.clone());
+    GameSharedEntity::new(
+        sysconf,
+        TcpSharedEntity::default(),
+        rpc_entity,
+        Default::default(),
+    )
+}
+
+// 同一个账号在新的连接上重新登录后, 旧的连接才断开
+#[test]
+fn testrelogin() {
+    let mut game = game_entity();
+    let (uid, old_vfd, new_vfd) = (1, 10001, 10002);
+    for vfd in [old_vfd, new_vfd] {
+        let (sender, _) = outbound::channel(Arc::new(OutboundPolicy::new(1)));
+        game.tcp_entity.register(vfd, sender.clone());
+        let mut player = Player::new("acc".to_string(), uid, "name".to_string());
+        player.rebind(vfd, sender);
+        game.add_player(player);
+    }
+
+    game.on_tcp_disconnected(old_vfd, &DisconnectReason::Closed);
+    assert!(!game.is_vfd_validated(old_vfd));
+    assert!(game.is_vfd_validated(new_vfd));
+    assert!(!game.offline_players.contains_key(&uid));
+    let player = game.get_player_by_uid(uid).unwrap();
+    assert_eq!(player.get_vfd(), new_vfd);
+    assert!(player.get_sender().is_some());
+}